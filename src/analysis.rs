@@ -0,0 +1,148 @@
+use crate::error::DmiError;
+use crate::icon::{DirIndex, Frame, Icon, IconState};
+use image::{DynamicImage, GenericImageView, Rgba};
+use std::collections::HashMap;
+
+/// How many pixels use each color, see [IconState::unique_colors].
+pub type ColorCounts = HashMap<Rgba<u8>, u64>;
+
+/// A rectangle of pixels within a sprite, see [IconState::content_bbox].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BoundingBox {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+}
+
+/// Color usage across an icon, see [Icon::color_census].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ColorCensus {
+	/// How many pixels use each color, over every state.
+	pub counts: ColorCounts,
+	/// The [IconState::unique_colors] of each state, in the order of [Icon::states].
+	pub states: Vec<ColorCounts>,
+}
+
+impl BoundingBox {
+	/// The smallest rectangle holding both `self` and `other`.
+	pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+		let x = self.x.min(other.x);
+		let y = self.y.min(other.y);
+		BoundingBox {
+			x,
+			y,
+			width: (self.x + self.width).max(other.x + other.width) - x,
+			height: (self.y + self.height).max(other.y + other.height) - y,
+		}
+	}
+}
+
+impl Icon {
+	/// Every state whose sprites are all fully transparent, in order. See [IconState::is_blank].
+	pub fn blank_states(&self) -> Vec<&IconState> {
+		self
+			.states
+			.iter()
+			.filter(|icon_state| icon_state.is_blank())
+			.collect()
+	}
+
+	/// Every color used by the icon with how many pixels use it, overall and per state, for checks
+	/// such as keeping sprites within a project palette.
+	pub fn color_census(&self) -> ColorCensus {
+		let states: Vec<ColorCounts> = self
+			.states
+			.iter()
+			.map(|icon_state| icon_state.unique_colors())
+			.collect();
+		let mut counts = ColorCounts::new();
+		for (color, count) in states.iter().flatten() {
+			*counts.entry(*color).or_default() += count;
+		}
+		ColorCensus { counts, states }
+	}
+
+	/// The union of the [IconState::content_bbox] of every sprite of every state: the area a crop
+	/// can keep without losing pixels. None if the whole icon is blank.
+	pub fn content_bbox(&self) -> Option<BoundingBox> {
+		self
+			.states
+			.iter()
+			.flat_map(|icon_state| icon_state.images.iter())
+			.filter_map(image_content_bbox)
+			.reduce(|union, bbox| union.union(&bbox))
+	}
+}
+
+impl IconState {
+	/// Whether every sprite of the state is fully transparent. Such states still take up room in
+	/// the sheet, and are usually leftovers. States without images count as blank.
+	pub fn is_blank(&self) -> bool {
+		self.images.iter().all(is_blank_image)
+	}
+
+	/// The smallest rectangle holding every pixel of the sprite for `dir` at `frame` that is not fully
+	/// transparent. None if the sprite is blank. Fails if the state has no such dir or frame.
+	pub fn content_bbox(&self, dir: DirIndex, frame: Frame) -> Result<Option<BoundingBox>, DmiError> {
+		Ok(image_content_bbox(self.image(dir, frame)?))
+	}
+
+	/// Every color used by the sprites of the state, with how many pixels use it across every dir
+	/// and frame. Fully transparent pixels all count as `Rgba([0, 0, 0, 0])`, whatever their color.
+	pub fn unique_colors(&self) -> ColorCounts {
+		let mut counts = ColorCounts::new();
+		for image in &self.images {
+			count_colors(image, &mut counts);
+		}
+		counts
+	}
+
+	/// The frames whose sprites are fully transparent in every dir, from first to last.
+	pub fn blank_frames(&self) -> Vec<Frame> {
+		let dirs = self.dirs.max(1) as usize;
+		self
+			.images
+			.chunks(dirs)
+			.enumerate()
+			.filter(|(_, frame_images)| frame_images.iter().all(is_blank_image))
+			.filter_map(|(index, _)| u32::try_from(index).ok().and_then(Frame::from_index))
+			.collect()
+	}
+}
+
+/// Whether every pixel of `image` has an alpha of 0. Images without alpha never are.
+fn is_blank_image(image: &DynamicImage) -> bool {
+	match image.as_rgba8() {
+		Some(pixels) => pixels.pixels().all(|pixel| pixel[3] == 0),
+		None => image.pixels().all(|(_, _, pixel)| pixel[3] == 0),
+	}
+}
+
+fn count_colors(image: &DynamicImage, counts: &mut ColorCounts) {
+	for (_, _, pixel) in image.pixels() {
+		let color = if pixel[3] == 0 { Rgba([0; 4]) } else { pixel };
+		*counts.entry(color).or_default() += 1;
+	}
+}
+
+fn image_content_bbox(image: &DynamicImage) -> Option<BoundingBox> {
+	let mut bounds: Option<(u32, u32, u32, u32)> = None;
+	for (x, y, pixel) in image.pixels() {
+		if pixel[3] == 0 {
+			continue;
+		}
+		bounds = Some(match bounds {
+			Some((min_x, min_y, max_x, max_y)) => {
+				(min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+			}
+			None => (x, y, x, y),
+		});
+	}
+	bounds.map(|(min_x, min_y, max_x, max_y)| BoundingBox {
+		x: min_x,
+		y: min_y,
+		width: max_x - min_x + 1,
+		height: max_y - min_y + 1,
+	})
+}
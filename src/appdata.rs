@@ -0,0 +1,51 @@
+use crate::{chunk, error};
+
+/// Type of the private ancillary chunk holding application data.
+/// Ancillary, private, and safe to copy, per the PNG chunk naming conventions.
+pub const APP_DATA_TYPE: [u8; 4] = [b'd', b'm', b'A', b'p'];
+
+/// Creates a chunk storing `data` under the application `key`.
+/// The chunk data is laid out like a tEXt chunk: the key, a null separator, then the raw payload.
+pub fn create_app_data_chunk(
+	key: &str,
+	data: &[u8],
+) -> Result<chunk::RawGenericChunk, error::DmiError> {
+	if key.is_empty() || key.len() > 79 || key.contains('\0') {
+		return Err(error::DmiError::InvalidArgument(format!(
+			"Failed to create application data chunk. Key must be 1 to 79 bytes long without null characters: {:#?}",
+			key
+		)));
+	}
+	let mut chunk_data = Vec::with_capacity(key.len() + 1 + data.len());
+	chunk_data.extend_from_slice(key.as_bytes());
+	chunk_data.push(0);
+	chunk_data.extend_from_slice(data);
+	chunk::RawGenericChunk::new(APP_DATA_TYPE, chunk_data)
+}
+
+/// Splits an application data chunk into its key and payload.
+pub fn read_app_data_chunk(
+	raw_chunk: &chunk::RawGenericChunk,
+) -> Result<(&str, &[u8]), error::DmiError> {
+	if raw_chunk.chunk_type != APP_DATA_TYPE {
+		return Err(error::DmiError::Generic(format!(
+			"Failed to read application data chunk. Wrong type: {:#?}. Expected: {:#?}.",
+			raw_chunk.chunk_type, APP_DATA_TYPE
+		)));
+	}
+	let separator = raw_chunk
+		.data
+		.iter()
+		.position(|byte| *byte == 0)
+		.ok_or_else(|| {
+			error::DmiError::Generic(
+				"Failed to read application data chunk. No null separator found after the key.".to_string(),
+			)
+		})?;
+	let key = std::str::from_utf8(&raw_chunk.data[..separator]).map_err(|_| {
+		error::DmiError::Generic(
+			"Failed to read application data chunk. Key is not valid UTF-8.".to_string(),
+		)
+	})?;
+	Ok((key, &raw_chunk.data[(separator + 1)..]))
+}
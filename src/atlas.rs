@@ -0,0 +1,163 @@
+use crate::dirs::Dirs;
+use crate::error::DmiError;
+use crate::icon::{Frame, Icon};
+use image::{imageops, DynamicImage, RgbaImage};
+
+/// Packs the sprites of many icons into texture atlases, so clients can draw them in batches.
+///
+/// Sprites are sorted from tallest to shortest, then laid out on shelves: rows filled left to
+/// right, a new row starting below the tallest sprite of the previous one. This wastes little
+/// space with DMI sprites, which mostly share a few sizes. A new page is started whenever a page
+/// would grow past the maximum size given to [AtlasBuilder::new].
+pub struct AtlasBuilder<'a> {
+	max_size: u32,
+	padding: u32,
+	icons: Vec<(String, &'a Icon)>,
+}
+
+/// A sprite placed in an [Atlas].
+#[derive(Clone, PartialEq, Debug)]
+pub struct AtlasEntry {
+	/// The name the icon was added under, usually its path.
+	pub file: String,
+	pub state: String,
+	pub dir: Dirs,
+	pub frame: Frame,
+	/// Index of the page holding the sprite in [Atlas::pages].
+	pub page: usize,
+	/// Position of the sprite on its page, in pixels.
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// The same rectangle in texture coordinates, from 0 to 1: left, top, right and bottom.
+	pub uv: [f32; 4],
+}
+
+/// Texture pages and where each sprite went, see [AtlasBuilder::build].
+#[derive(Clone, PartialEq, Debug)]
+pub struct Atlas {
+	pub pages: Vec<DynamicImage>,
+	/// Every sprite, by file then state, then in the order of [crate::icon::IconState::images].
+	pub entries: Vec<AtlasEntry>,
+}
+
+impl<'a> AtlasBuilder<'a> {
+	/// Pages are at most `max_size` pixels wide and high.
+	pub fn new(max_size: u32) -> AtlasBuilder<'a> {
+		AtlasBuilder {
+			max_size,
+			padding: 0,
+			icons: vec![],
+		}
+	}
+
+	/// Leaves `padding` transparent pixels between sprites, which avoids bleeding between
+	/// neighbours when textures are sampled with filtering.
+	pub fn padding(mut self, padding: u32) -> AtlasBuilder<'a> {
+		self.padding = padding;
+		self
+	}
+
+	/// Adds every sprite of `icon`, reported as coming from `file`.
+	pub fn add_icon<S: Into<String>>(mut self, file: S, icon: &'a Icon) -> AtlasBuilder<'a> {
+		self.icons.push((file.into(), icon));
+		self
+	}
+
+	/// Packs the sprites. Fails if a sprite is larger than the maximum page size.
+	pub fn build(self) -> Result<Atlas, DmiError> {
+		let mut sprites = vec![];
+		for (file, icon) in &self.icons {
+			for icon_state in &icon.states {
+				let positions = icon_state.sprite_positions().map_err(|_| {
+					DmiError::Generic(format!(
+						"Error building atlas: invalid number of dirs ({}) in icon_state \"{}\" of {file}.",
+						icon_state.dirs, icon_state.name
+					))
+				})?;
+				for (image, (dir, frame)) in icon_state.images.iter().zip(positions) {
+					if image.width() > self.max_size || image.height() > self.max_size {
+						return Err(DmiError::Generic(format!(
+							"Error building atlas: sprite of {}x{} in icon_state \"{}\" of {file} exceeds the maximum page size of {}.",
+							image.width(),
+							image.height(),
+							icon_state.name,
+							self.max_size
+						)));
+					}
+					sprites.push((
+						AtlasEntry {
+							file: file.clone(),
+							state: icon_state.name.clone(),
+							dir: dir.dir(),
+							frame,
+							page: 0,
+							x: 0,
+							y: 0,
+							width: image.width(),
+							height: image.height(),
+							uv: [0.0; 4],
+						},
+						image,
+					));
+				}
+			}
+		}
+
+		let mut order: Vec<usize> = (0..sprites.len()).collect();
+		order.sort_by_key(|&index| std::cmp::Reverse(sprites[index].0.height));
+		let mut page_sizes: Vec<(u32, u32)> = vec![];
+		let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+		for &index in &order {
+			let entry = &mut sprites[index].0;
+			if page_sizes.is_empty() {
+				page_sizes.push((0, 0));
+			}
+			if shelf_x + entry.width > self.max_size {
+				shelf_x = 0;
+				shelf_y += shelf_height + self.padding;
+				shelf_height = 0;
+			}
+			if shelf_y + entry.height > self.max_size {
+				page_sizes.push((0, 0));
+				(shelf_x, shelf_y, shelf_height) = (0, 0, 0);
+			}
+			entry.page = page_sizes.len() - 1;
+			entry.x = shelf_x;
+			entry.y = shelf_y;
+			shelf_x += entry.width + self.padding;
+			shelf_height = shelf_height.max(entry.height);
+			let page_size = page_sizes.last_mut().unwrap();
+			page_size.0 = page_size.0.max(entry.x + entry.width);
+			page_size.1 = page_size.1.max(entry.y + entry.height);
+		}
+
+		let mut pages: Vec<RgbaImage> = page_sizes
+			.iter()
+			.map(|&(width, height)| RgbaImage::new(width, height))
+			.collect();
+		let mut entries = Vec::with_capacity(sprites.len());
+		for (mut entry, image) in sprites {
+			imageops::replace(
+				&mut pages[entry.page],
+				&image.to_rgba8(),
+				entry.x as i64,
+				entry.y as i64,
+			);
+			let (page_width, page_height) = page_sizes[entry.page];
+			entry.uv = [
+				entry.x as f32 / page_width as f32,
+				entry.y as f32 / page_height as f32,
+				(entry.x + entry.width) as f32 / page_width as f32,
+				(entry.y + entry.height) as f32 / page_height as f32,
+			];
+			entries.push(entry);
+		}
+
+		Ok(Atlas {
+			pages: pages.into_iter().map(DynamicImage::ImageRgba8).collect(),
+			entries,
+		})
+	}
+}
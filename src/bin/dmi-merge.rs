@@ -0,0 +1,33 @@
+//! Git merge driver for DMI files, see [dmi::merge::merge_driver].
+//!
+//! Usage: `dmi-merge <base> <ours> <theirs>`. Writes the merged file over `<ours>`.
+//! Exits with 0 on a clean merge, 1 when conflicts remain, and 2 on errors.
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+	let arguments: Vec<String> = std::env::args().skip(1).collect();
+	let [base, ours, theirs] = arguments.as_slice() else {
+		eprintln!("Usage: dmi-merge <base> <ours> <theirs>");
+		return ExitCode::from(2);
+	};
+
+	match dmi::merge::merge_driver(base, ours, theirs) {
+		Ok(conflicts) if conflicts.is_empty() => ExitCode::SUCCESS,
+		Ok(conflicts) => {
+			for conflict in conflicts {
+				eprintln!(
+					"dmi-merge: conflict in state \"{}\" (#{}): {:?}",
+					conflict.name,
+					conflict.occurrence + 1,
+					conflict.kind
+				);
+			}
+			ExitCode::from(1)
+		}
+		Err(error) => {
+			eprintln!("dmi-merge: {}", error);
+			ExitCode::from(2)
+		}
+	}
+}
@@ -0,0 +1,38 @@
+//! Git textconv filter for DMI files, see [dmi::metadata::DmiMetadata::dump].
+//!
+//! Usage: `dmi-textconv <file>`. To use it for `git diff`:
+//!
+//! ```text
+//! # .git/config
+//! [diff "dmi"]
+//!     textconv = dmi-textconv
+//! # .gitattributes
+//! *.dmi diff=dmi
+//! ```
+
+use dmi::metadata::DmiMetadata;
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+	let arguments: Vec<String> = std::env::args().skip(1).collect();
+	let [path] = arguments.as_slice() else {
+		eprintln!("Usage: dmi-textconv <file>");
+		return ExitCode::from(2);
+	};
+
+	let metadata = File::open(path)
+		.map_err(dmi::error::DmiError::from)
+		.and_then(|file| DmiMetadata::load(BufReader::new(file)));
+	match metadata {
+		Ok(metadata) => {
+			print!("{}", metadata.dump());
+			ExitCode::SUCCESS
+		}
+		Err(error) => {
+			eprintln!("dmi-textconv: {}: {}", path, error);
+			ExitCode::from(2)
+		}
+	}
+}
@@ -0,0 +1,89 @@
+use crate::error::DmiError;
+use crate::icon::{Icon, IconState};
+use image::DynamicImage;
+
+/// Assembles an [Icon] state by state.
+///
+/// Any [Icon] produced by [IconBuilder::build] can be saved: each state has been run through
+/// [IconState::validate] against the builder's sprite dimensions, so problems surface when building
+/// rather than when [Icon::save] is called. Every method chains by value, as in
+/// `IconBuilder::default().size(32, 32).sprite("idle", image).build()`. [IconBuilder::add_state]
+/// checks states as they are added, the other methods leave every check to [IconBuilder::build].
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct IconBuilder {
+	width: u32,
+	height: u32,
+	states: Vec<IconState>,
+}
+
+impl IconBuilder {
+	/// Creates a builder for an icon whose sprites are `width`x`height` pixels.
+	pub fn new(width: u32, height: u32) -> IconBuilder {
+		IconBuilder {
+			width,
+			height,
+			states: vec![],
+		}
+	}
+
+	/// Adds a state to the icon being built, refusing it if it is not valid for the icon's dimensions.
+	/// The builder is dropped along with a refused state.
+	pub fn add_state(mut self, state: IconState) -> Result<IconBuilder, DmiError> {
+		state.validate(self.width, self.height)?;
+		self.states.push(state);
+		Ok(self)
+	}
+
+	/// Sets the dimensions of the sprites. Builders start at 0x0, which [IconBuilder::build] refuses,
+	/// unless made with [IconBuilder::new].
+	pub fn size(mut self, width: u32, height: u32) -> IconBuilder {
+		self.width = width;
+		self.height = height;
+		self
+	}
+
+	/// Adds `icon_state` under `name`, replacing its own name. It is checked by [IconBuilder::build].
+	pub fn state(mut self, name: &str, icon_state: IconState) -> IconBuilder {
+		self.states.push(IconState {
+			name: name.to_string(),
+			..icon_state
+		});
+		self
+	}
+
+	/// Adds a still state of a single dir showing `image`. It is checked by [IconBuilder::build].
+	pub fn sprite(self, name: &str, image: DynamicImage) -> IconBuilder {
+		self.state(
+			name,
+			IconState {
+				images: vec![image],
+				..Default::default()
+			},
+		)
+	}
+
+	/// The states added so far.
+	pub fn states(&self) -> &[IconState] {
+		&self.states
+	}
+
+	/// Produces the [Icon]. Fails if the sprite dimensions are zero, or with the error of the first
+	/// state that is not valid for them.
+	pub fn build(self) -> Result<Icon, DmiError> {
+		if self.width == 0 || self.height == 0 {
+			return Err(DmiError::Generic(format!(
+				"Error building icon: invalid width ({}) / height ({}) values.",
+				self.width, self.height
+			)));
+		}
+		for icon_state in &self.states {
+			icon_state.validate(self.width, self.height)?;
+		}
+		Ok(Icon {
+			width: self.width,
+			height: self.height,
+			states: self.states,
+			..Default::default()
+		})
+	}
+}
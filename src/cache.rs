@@ -0,0 +1,379 @@
+use crate::chunk::RawGenericChunk;
+use crate::error::DmiError;
+use crate::hash::Fnv1aHasher;
+use crate::icon::{DmiVersion, Hotspot, Icon, IconState, Looping};
+use image::{DynamicImage, RgbaImage};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+/// First bytes of a cache, see [Icon::write_cache].
+pub const CACHE_MAGIC: [u8; 8] = *b"DMICACHE";
+
+/// Version of the cache layout, bumped whenever it changes. Caches of other versions are refused.
+pub const CACHE_FORMAT_VERSION: u32 = 1;
+
+/// First bytes of a sidecar cache file, see [Icon::load_cached].
+pub const SIDECAR_MAGIC: [u8; 8] = *b"DMISIDE\0";
+
+/// Version of the sidecar layout, around the cache itself.
+pub const SIDECAR_FORMAT_VERSION: u32 = 2;
+
+/// Where [Icon::load_cached] keeps the cache of the DMI file at `path`: next to it, with `.cache`
+/// appended to its name, such as `mob.dmi.cache`.
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+	let mut sidecar: OsString = path.as_ref().as_os_str().to_owned();
+	sidecar.push(".cache");
+	PathBuf::from(sidecar)
+}
+
+impl Icon {
+	/// Writes the icon, pixels included, in a binary format meant to be read back by
+	/// [Icon::read_cache] much faster than a DMI file is decoded.
+	///
+	/// Images are stored as uncompressed RGBA8, so caches are much larger than DMI files. The
+	/// layout is specific to this library and only readable by the same [CACHE_FORMAT_VERSION].
+	pub fn write_cache<W: Write>(&self, writter: &mut W) -> Result<(), DmiError> {
+		let mut cache = CacheWriter { writter };
+		cache.bytes(&CACHE_MAGIC)?;
+		cache.u32(CACHE_FORMAT_VERSION)?;
+		cache.string(&self.version.0)?;
+		cache.u32(self.width)?;
+		cache.u32(self.height)?;
+
+		cache.length(self.app_data.len())?;
+		for (key, data) in &self.app_data {
+			cache.string(key)?;
+			cache.length(data.len())?;
+			cache.bytes(data)?;
+		}
+		cache.length(self.ancillary_chunks.len())?;
+		for chunk in &self.ancillary_chunks {
+			cache.bytes(&chunk.chunk_type)?;
+			cache.length(chunk.data.len())?;
+			cache.bytes(&chunk.data)?;
+		}
+
+		cache.length(self.states.len())?;
+		for icon_state in &self.states {
+			cache.string(&icon_state.name)?;
+			cache.bytes(&[icon_state.dirs])?;
+			cache.u32(icon_state.frames)?;
+			match &icon_state.delay {
+				Some(delay) => {
+					cache.bytes(&[1])?;
+					cache.length(delay.len())?;
+					for delay in delay {
+						cache.bytes(&delay.to_le_bytes())?;
+					}
+				}
+				None => cache.bytes(&[0])?,
+			}
+			cache.u32(match icon_state.loop_flag {
+				Looping::Indefinitely => 0,
+				Looping::NTimes(times) => times.get(),
+			})?;
+			cache.bytes(&[icon_state.rewind as u8, icon_state.movement as u8])?;
+			match icon_state.hotspot {
+				Some(Hotspot { x, y }) => {
+					cache.bytes(&[1])?;
+					cache.u32(x)?;
+					cache.u32(y)?;
+				}
+				None => cache.bytes(&[0])?,
+			}
+			match &icon_state.unknown_settings {
+				Some(unknown_settings) => {
+					cache.bytes(&[1])?;
+					cache.length(unknown_settings.len())?;
+					for (key, value) in unknown_settings {
+						cache.string(key)?;
+						cache.string(value)?;
+					}
+				}
+				None => cache.bytes(&[0])?,
+			}
+
+			cache.length(icon_state.images.len())?;
+			for image in &icon_state.images {
+				cache.u32(image.width())?;
+				cache.u32(image.height())?;
+				match image.as_rgba8() {
+					Some(pixels) => cache.bytes(pixels.as_raw())?,
+					None => cache.bytes(image.to_rgba8().as_raw())?,
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Loads the DMI file at `path` from its sidecar cache, see [sidecar_path], when the cache was
+	/// written for the current content of the file.
+	///
+	/// The cache stores the length of the file it was made from and a hash of all its bytes, so that
+	/// a change to any chunk, the palette or the text chunks included, makes it stale. The file is
+	/// read through to hash it, which is still much faster than decoding it. When the cache is
+	/// missing, stale, or unreadable, the file is decoded in full and the cache is written anew.
+	/// Failing to write the cache, for instance in a read-only directory, does not fail the load.
+	pub fn load_cached<P: AsRef<Path>>(path: P) -> Result<Icon, DmiError> {
+		let path = path.as_ref();
+		let key = FileKey::of(path)?;
+		let sidecar = sidecar_path(path);
+		if let Ok(icon) = read_sidecar(&sidecar, &key) {
+			return Ok(icon);
+		}
+		let icon = Icon::load(BufReader::new(File::open(path)?))?;
+		let _ = write_sidecar(&sidecar, &key, &icon);
+		Ok(icon)
+	}
+
+	/// Reads an icon written by [Icon::write_cache].
+	pub fn read_cache<R: Read>(reader: &mut R) -> Result<Icon, DmiError> {
+		let mut cache = CacheReader { reader };
+		if cache.array::<8>()? != CACHE_MAGIC {
+			return Err(DmiError::Generic(
+				"Failed to read icon cache. Missing cache header.".to_string(),
+			));
+		}
+		let format_version = cache.u32()?;
+		if format_version != CACHE_FORMAT_VERSION {
+			return Err(DmiError::Generic(format!(
+				"Failed to read icon cache. Format version {format_version} is not the supported {CACHE_FORMAT_VERSION}."
+			)));
+		}
+		let version = DmiVersion(cache.string()?);
+		let width = cache.u32()?;
+		let height = cache.u32()?;
+
+		let mut app_data = BTreeMap::new();
+		for _ in 0..cache.u32()? {
+			let key = cache.string()?;
+			let length = cache.u32()?;
+			app_data.insert(key, cache.bytes(length as u64)?);
+		}
+		let mut ancillary_chunks = vec![];
+		for _ in 0..cache.u32()? {
+			let chunk_type = cache.array::<4>()?;
+			let length = cache.u32()?;
+			ancillary_chunks.push(RawGenericChunk::new(
+				chunk_type,
+				cache.bytes(length as u64)?,
+			)?);
+		}
+
+		let mut states = vec![];
+		for _ in 0..cache.u32()? {
+			let name = cache.string()?;
+			let [dirs] = cache.array::<1>()?;
+			let frames = cache.u32()?;
+			let delay = match cache.flag()? {
+				true => {
+					let mut delay = vec![];
+					for _ in 0..cache.u32()? {
+						delay.push(f32::from_le_bytes(cache.array::<4>()?));
+					}
+					Some(delay)
+				}
+				false => None,
+			};
+			let loop_flag = match NonZeroU32::new(cache.u32()?) {
+				Some(times) => Looping::NTimes(times),
+				None => Looping::Indefinitely,
+			};
+			let rewind = cache.flag()?;
+			let movement = cache.flag()?;
+			let hotspot = match cache.flag()? {
+				true => Some(Hotspot {
+					x: cache.u32()?,
+					y: cache.u32()?,
+				}),
+				false => None,
+			};
+			let unknown_settings = match cache.flag()? {
+				true => {
+					let mut unknown_settings = HashMap::new();
+					for _ in 0..cache.u32()? {
+						let key = cache.string()?;
+						unknown_settings.insert(key, cache.string()?);
+					}
+					Some(unknown_settings)
+				}
+				false => None,
+			};
+
+			let mut images = vec![];
+			for _ in 0..cache.u32()? {
+				let image_width = cache.u32()?;
+				let image_height = cache.u32()?;
+				let pixels = cache.bytes(image_width as u64 * image_height as u64 * 4)?;
+				let Some(image) = RgbaImage::from_raw(image_width, image_height, pixels) else {
+					return Err(DmiError::Generic(format!(
+						"Failed to read icon cache. Truncated {image_width}x{image_height} image in icon_state \"{name}\"."
+					)));
+				};
+				images.push(DynamicImage::ImageRgba8(image));
+			}
+
+			states.push(IconState {
+				name,
+				dirs,
+				frames,
+				images,
+				delay,
+				loop_flag,
+				rewind,
+				movement,
+				hotspot,
+				unknown_settings,
+			});
+		}
+
+		Ok(Icon {
+			version,
+			width,
+			height,
+			states,
+			app_data,
+			ancillary_chunks,
+			source: Default::default(),
+		})
+	}
+}
+
+/// What a sidecar cache is checked against, see [Icon::load_cached].
+#[derive(PartialEq, Eq)]
+struct FileKey {
+	length: u64,
+	/// [Fnv1aHasher] hash of every byte of the file.
+	hash: u64,
+}
+
+impl FileKey {
+	fn of(path: &Path) -> Result<FileKey, DmiError> {
+		let mut reader = File::open(path)?;
+		let mut hasher = Fnv1aHasher::new();
+		let mut buffer = vec![0u8; 64 * 1024];
+		let mut length = 0;
+		loop {
+			let read = match reader.read(&mut buffer) {
+				Ok(0) => break,
+				Ok(read) => read,
+				Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+				Err(error) => return Err(error.into()),
+			};
+			hasher.update(&buffer[..read]);
+			length += read as u64;
+		}
+		Ok(FileKey {
+			length,
+			hash: hasher.finish(),
+		})
+	}
+}
+
+/// Reads the icon cached in `sidecar`, failing if it was made for another version of the file.
+fn read_sidecar(sidecar: &Path, key: &FileKey) -> Result<Icon, DmiError> {
+	let mut reader = BufReader::new(File::open(sidecar)?);
+	let mut cache = CacheReader {
+		reader: &mut reader,
+	};
+	if cache.array::<8>()? != SIDECAR_MAGIC || cache.u32()? != SIDECAR_FORMAT_VERSION {
+		return Err(DmiError::Generic(
+			"Failed to read sidecar cache. Unknown format.".to_string(),
+		));
+	}
+	let cached_key = FileKey {
+		length: u64::from_le_bytes(cache.array::<8>()?),
+		hash: u64::from_le_bytes(cache.array::<8>()?),
+	};
+	if cached_key != *key {
+		return Err(DmiError::Generic(format!(
+			"Failed to read sidecar cache. Stale cache of a {} bytes file hashed {:016x}, the file is now {} bytes hashed {:016x}.",
+			cached_key.length, cached_key.hash, key.length, key.hash
+		)));
+	}
+	Icon::read_cache(&mut reader)
+}
+
+fn write_sidecar(sidecar: &Path, key: &FileKey, icon: &Icon) -> Result<(), DmiError> {
+	let mut writter = BufWriter::new(File::create(sidecar)?);
+	let mut cache = CacheWriter {
+		writter: &mut writter,
+	};
+	cache.bytes(&SIDECAR_MAGIC)?;
+	cache.u32(SIDECAR_FORMAT_VERSION)?;
+	cache.bytes(&key.length.to_le_bytes())?;
+	cache.bytes(&key.hash.to_le_bytes())?;
+	icon.write_cache(&mut writter)?;
+	Ok(writter.flush()?)
+}
+
+struct CacheWriter<'a, W: Write> {
+	writter: &'a mut W,
+}
+
+impl<W: Write> CacheWriter<'_, W> {
+	fn bytes(&mut self, bytes: &[u8]) -> Result<(), DmiError> {
+		Ok(self.writter.write_all(bytes)?)
+	}
+
+	fn u32(&mut self, value: u32) -> Result<(), DmiError> {
+		self.bytes(&value.to_le_bytes())
+	}
+
+	fn length(&mut self, length: usize) -> Result<(), DmiError> {
+		match u32::try_from(length) {
+			Ok(length) => self.u32(length),
+			Err(_) => Err(DmiError::Generic(format!(
+				"Failed to write icon cache. Length of {length} is too large."
+			))),
+		}
+	}
+
+	fn string(&mut self, string: &str) -> Result<(), DmiError> {
+		self.length(string.len())?;
+		self.bytes(string.as_bytes())
+	}
+}
+
+struct CacheReader<'a, R: Read> {
+	reader: &'a mut R,
+}
+
+impl<R: Read> CacheReader<'_, R> {
+	/// Reads `length` bytes, without trusting `length` enough to allocate it upfront.
+	fn bytes(&mut self, length: u64) -> Result<Vec<u8>, DmiError> {
+		let mut bytes = vec![];
+		self.reader.take(length).read_to_end(&mut bytes)?;
+		if (bytes.len() as u64) < length {
+			return Err(DmiError::Generic(
+				"Failed to read icon cache. Unexpected end of the cache.".to_string(),
+			));
+		}
+		Ok(bytes)
+	}
+
+	fn array<const N: usize>(&mut self) -> Result<[u8; N], DmiError> {
+		let mut bytes = [0u8; N];
+		self.reader.read_exact(&mut bytes)?;
+		Ok(bytes)
+	}
+
+	fn u32(&mut self) -> Result<u32, DmiError> {
+		Ok(u32::from_le_bytes(self.array::<4>()?))
+	}
+
+	fn flag(&mut self) -> Result<bool, DmiError> {
+		let [flag] = self.array::<1>()?;
+		Ok(flag != 0)
+	}
+
+	fn string(&mut self) -> Result<String, DmiError> {
+		let length = self.u32()?;
+		Ok(String::from_utf8(self.bytes(length as u64)?)?)
+	}
+}
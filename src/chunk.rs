@@ -1,116 +1,459 @@
-use crate::{crc, error};
-use std::io::prelude::*;
-
-#[derive(Clone, PartialEq, Eq, Debug, Default)]
-pub struct RawGenericChunk {
-	pub data_length: [u8; 4],
-	pub chunk_type: [u8; 4],
-	pub data: Vec<u8>,
-	pub crc: [u8; 4],
-}
-
-impl RawGenericChunk {
-	pub fn load<R: Read>(reader: &mut R) -> Result<RawGenericChunk, error::DmiError> {
-		let mut chunk_bytes = Vec::new();
-		reader.read_to_end(&mut chunk_bytes)?;
-
-		// 4 bytes for the length.
-		// 4 bytes for the type.
-		// Data can be 0 bytes.
-		// 4 bytes for the CRC.
-
-		// Total minimum size for an undetermined PNG chunk: 12 bytes.
-		let chunk_length = chunk_bytes.len();
-
-		if chunk_length < 12 {
-			return Err(error::DmiError::Generic(format!("Failed to load Chunk. Supplied reader contained size of {} bytes, lower than the required 12.", chunk_length)));
-		};
-
-		let data_length = [
-			chunk_bytes[0],
-			chunk_bytes[1],
-			chunk_bytes[2],
-			chunk_bytes[3],
-		];
-
-		let chunk_type = [
-			chunk_bytes[4],
-			chunk_bytes[5],
-			chunk_bytes[6],
-			chunk_bytes[7],
-		];
-
-		// The chunk type is made of four ascii characters. The valid ranges are A-Z and a-z.
-		if !chunk_type
-			.iter()
-			.all(|c| (b'A' <= *c && *c <= b'Z') || (b'a' <= *c && *c <= b'z'))
-		{
-			return Err(error::DmiError::Generic(format!(
-				"Failed to load Chunk. Type contained unlawful characters: {:#?}",
-				chunk_type
-			)));
-		};
-
-		let data: Vec<u8> = chunk_bytes[8..(chunk_length - 4)].to_vec();
-
-		let crc = [
-			chunk_bytes[chunk_length - 4],
-			chunk_bytes[chunk_length - 3],
-			chunk_bytes[chunk_length - 2],
-			chunk_bytes[chunk_length - 1],
-		];
-
-		let recalculated_crc = crc::calculate_crc(chunk_type.iter().chain(data.iter()));
-		if u32::from_be_bytes(crc) != recalculated_crc {
-			let chunk_name = String::from_utf8(chunk_type.to_vec())?;
-			return Err(error::DmiError::Generic(format!("Failed to load Chunk of type {}. Supplied CRC invalid: {:#?}. Its value ({}) does not match the recalculated one ({}).", chunk_name, crc, u32::from_be_bytes(crc), recalculated_crc)));
-		}
-
-		Ok(RawGenericChunk {
-			data_length,
-			chunk_type,
-			data,
-			crc,
-		})
-	}
-
-	pub fn save<W: Write>(&self, writter: &mut W) -> Result<usize, error::DmiError> {
-		let bytes_written = writter.write(&self.data_length)?;
-		let mut total_bytes_written = bytes_written;
-		if bytes_written < self.data_length.len() {
-			return Err(error::DmiError::Generic(format!(
-				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
-				total_bytes_written
-			)));
-		};
-
-		let bytes_written = writter.write(&self.chunk_type)?;
-		total_bytes_written += bytes_written;
-		if bytes_written < self.chunk_type.len() {
-			return Err(error::DmiError::Generic(format!(
-				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
-				total_bytes_written
-			)));
-		};
-
-		let bytes_written = writter.write(&self.data)?;
-		total_bytes_written += bytes_written;
-		if bytes_written < self.data.len() {
-			return Err(error::DmiError::Generic(format!(
-				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
-				total_bytes_written
-			)));
-		};
-
-		let bytes_written = writter.write(&self.crc)?;
-		total_bytes_written += bytes_written;
-		if bytes_written < self.crc.len() {
-			return Err(error::DmiError::Generic(format!(
-				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
-				total_bytes_written
-			)));
-		};
-
-		Ok(total_bytes_written)
-	}
-}
+use crate::appdata::APP_DATA_TYPE;
+use crate::iend::{RawIendChunk, IEND_TYPE};
+use crate::text::TEXT_TYPE;
+use crate::ztxt::{RawZtxtChunk, ZTXT_TYPE};
+use crate::{crc, error};
+use std::io::prelude::*;
+
+pub const IHDR_TYPE: [u8; 4] = *b"IHDR";
+pub const PLTE_TYPE: [u8; 4] = *b"PLTE";
+pub const IDAT_TYPE: [u8; 4] = *b"IDAT";
+pub const ITXT_TYPE: [u8; 4] = *b"iTXt";
+
+/// The chunk types this library knows about, to match on instead of raw bytes.
+///
+/// Converting from bytes never yields [ChunkType::Other] for a known type, so comparisons between
+/// converted values are reliable.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ChunkType {
+	Ihdr,
+	Plte,
+	Idat,
+	Iend,
+	Ztxt,
+	Text,
+	Itxt,
+	Other([u8; 4]),
+}
+
+impl ChunkType {
+	/// The four bytes identifying the type in a file.
+	pub fn bytes(self) -> [u8; 4] {
+		match self {
+			ChunkType::Ihdr => IHDR_TYPE,
+			ChunkType::Plte => PLTE_TYPE,
+			ChunkType::Idat => IDAT_TYPE,
+			ChunkType::Iend => IEND_TYPE,
+			ChunkType::Ztxt => ZTXT_TYPE,
+			ChunkType::Text => TEXT_TYPE,
+			ChunkType::Itxt => ITXT_TYPE,
+			ChunkType::Other(bytes) => bytes,
+		}
+	}
+
+	// Per the PNG spec, the properties of a chunk are given by the case of each letter of its type.
+
+	/// Whether decoders must understand the chunk to read the image. Uppercase first letter.
+	pub fn is_critical(self) -> bool {
+		self.bytes()[0].is_ascii_uppercase()
+	}
+
+	/// Whether the chunk can be ignored by decoders that do not know it. Lowercase first letter.
+	pub fn is_ancillary(self) -> bool {
+		!self.is_critical()
+	}
+
+	/// Whether the chunk is defined by the PNG spec or registered, rather than private to an
+	/// application. Uppercase second letter.
+	pub fn is_public(self) -> bool {
+		self.bytes()[1].is_ascii_uppercase()
+	}
+
+	/// Whether editors that do not know the chunk may copy it to a modified image, as it does not
+	/// depend on the image data. Lowercase fourth letter.
+	pub fn is_safe_to_copy(self) -> bool {
+		self.bytes()[3].is_ascii_lowercase()
+	}
+
+	/// Whether loading an `Icon` keeps chunks of this type as they are, in its `ancillary_chunks`.
+	///
+	/// The image data is re-encoded on save, so only ancillary chunks marked safe to copy are kept.
+	/// zTXt and application data chunks are parsed instead.
+	pub fn is_kept_by_icon(self) -> bool {
+		self.is_ancillary()
+			&& self.is_safe_to_copy()
+			&& self != ChunkType::Ztxt
+			&& self != ChunkType::Other(APP_DATA_TYPE)
+	}
+}
+
+impl From<[u8; 4]> for ChunkType {
+	fn from(bytes: [u8; 4]) -> Self {
+		match bytes {
+			IHDR_TYPE => ChunkType::Ihdr,
+			PLTE_TYPE => ChunkType::Plte,
+			IDAT_TYPE => ChunkType::Idat,
+			IEND_TYPE => ChunkType::Iend,
+			ZTXT_TYPE => ChunkType::Ztxt,
+			TEXT_TYPE => ChunkType::Text,
+			ITXT_TYPE => ChunkType::Itxt,
+			bytes => ChunkType::Other(bytes),
+		}
+	}
+}
+
+impl From<ChunkType> for [u8; 4] {
+	fn from(chunk_type: ChunkType) -> Self {
+		chunk_type.bytes()
+	}
+}
+
+impl std::fmt::Display for ChunkType {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}", String::from_utf8_lossy(&self.bytes()))
+	}
+}
+
+/// Largest data length the PNG spec allows in a chunk, 2^31 - 1 bytes.
+pub const MAX_DATA_LENGTH: u32 = i32::MAX as u32;
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct RawGenericChunk {
+	pub data_length: [u8; 4],
+	pub chunk_type: [u8; 4],
+	pub data: Vec<u8>,
+	pub crc: [u8; 4],
+}
+
+impl RawGenericChunk {
+	/// Creates a chunk of type `chunk_type` holding `data`, with its length and CRC filled in.
+	/// Fails if the type is not made of ASCII letters, or if the data is too long for a chunk.
+	pub fn new(chunk_type: [u8; 4], data: Vec<u8>) -> Result<RawGenericChunk, error::DmiError> {
+		if !chunk_type.iter().all(u8::is_ascii_alphabetic) {
+			return Err(error::DmiError::InvalidChunkType { chunk_type });
+		}
+		let data_length = match u32::try_from(data.len()) {
+			Ok(data_length) if data_length <= MAX_DATA_LENGTH => data_length.to_be_bytes(),
+			_ => {
+				return Err(error::DmiError::InvalidArgument(format!(
+					"Failed to create Chunk. Data of {} bytes exceeds the maximum of {}.",
+					data.len(),
+					MAX_DATA_LENGTH
+				)))
+			}
+		};
+		let crc = crc::ChunkCrcHasher::checksum(chunk_type, &data).to_be_bytes();
+		Ok(RawGenericChunk {
+			data_length,
+			chunk_type,
+			data,
+			crc,
+		})
+	}
+
+	/// A chunk of the same type holding `data` instead, with its length and CRC updated.
+	pub fn with_data(&self, data: Vec<u8>) -> Result<RawGenericChunk, error::DmiError> {
+		RawGenericChunk::new(self.chunk_type, data)
+	}
+
+	pub fn load<R: Read>(reader: &mut R) -> Result<RawGenericChunk, error::DmiError> {
+		let mut chunk_bytes = Vec::new();
+		reader.read_to_end(&mut chunk_bytes)?;
+
+		// 4 bytes for the length.
+		// 4 bytes for the type.
+		// Data can be 0 bytes.
+		// 4 bytes for the CRC.
+
+		// Total minimum size for an undetermined PNG chunk: 12 bytes.
+		let chunk_length = chunk_bytes.len();
+
+		if chunk_length < 12 {
+			return Err(error::DmiError::Generic(format!("Failed to load Chunk. Supplied reader contained size of {} bytes, lower than the required 12.", chunk_length)));
+		};
+
+		let data_length = [
+			chunk_bytes[0],
+			chunk_bytes[1],
+			chunk_bytes[2],
+			chunk_bytes[3],
+		];
+
+		let chunk_type = [
+			chunk_bytes[4],
+			chunk_bytes[5],
+			chunk_bytes[6],
+			chunk_bytes[7],
+		];
+
+		// The chunk type is made of four ascii characters. The valid ranges are A-Z and a-z.
+		if !chunk_type
+			.iter()
+			.all(|c| (b'A' <= *c && *c <= b'Z') || (b'a' <= *c && *c <= b'z'))
+		{
+			return Err(error::DmiError::Generic(format!(
+				"Failed to load Chunk. Type contained unlawful characters: {:#?}",
+				chunk_type
+			)));
+		};
+
+		let data: Vec<u8> = chunk_bytes[8..(chunk_length - 4)].to_vec();
+
+		let crc = [
+			chunk_bytes[chunk_length - 4],
+			chunk_bytes[chunk_length - 3],
+			chunk_bytes[chunk_length - 2],
+			chunk_bytes[chunk_length - 1],
+		];
+
+		let recalculated_crc = crc::ChunkCrcHasher::checksum(chunk_type, &data);
+		if u32::from_be_bytes(crc) != recalculated_crc {
+			let chunk_name = String::from_utf8(chunk_type.to_vec())?;
+			return Err(error::DmiError::Generic(format!("Failed to load Chunk of type {}. Supplied CRC invalid: {:#?}. Its value ({}) does not match the recalculated one ({}).", chunk_name, crc, u32::from_be_bytes(crc), recalculated_crc)));
+		}
+
+		Ok(RawGenericChunk {
+			data_length,
+			chunk_type,
+			data,
+			crc,
+		})
+	}
+
+	pub fn save<W: Write>(&self, writter: &mut W) -> Result<usize, error::DmiError> {
+		let bytes_written = writter.write(&self.data_length)?;
+		let mut total_bytes_written = bytes_written;
+		if bytes_written < self.data_length.len() {
+			return Err(error::DmiError::Generic(format!(
+				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
+				total_bytes_written
+			)));
+		};
+
+		let bytes_written = writter.write(&self.chunk_type)?;
+		total_bytes_written += bytes_written;
+		if bytes_written < self.chunk_type.len() {
+			return Err(error::DmiError::Generic(format!(
+				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
+				total_bytes_written
+			)));
+		};
+
+		let bytes_written = writter.write(&self.data)?;
+		total_bytes_written += bytes_written;
+		if bytes_written < self.data.len() {
+			return Err(error::DmiError::Generic(format!(
+				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
+				total_bytes_written
+			)));
+		};
+
+		let bytes_written = writter.write(&self.crc)?;
+		total_bytes_written += bytes_written;
+		if bytes_written < self.crc.len() {
+			return Err(error::DmiError::Generic(format!(
+				"Failed to save Chunk. Buffer unable to hold the data, only {} bytes written.",
+				total_bytes_written
+			)));
+		};
+
+		Ok(total_bytes_written)
+	}
+}
+
+/// Decides which ancillary chunks are kept, see [crate::RawDmi::prune_chunks].
+/// Critical chunks are always kept, as the image cannot be read without them.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum ChunkPolicy {
+	/// Every chunk is kept.
+	#[default]
+	KeepAll,
+	/// Only the ancillary chunks whose type is listed are kept.
+	KeepListed(Vec<[u8; 4]>),
+	/// Every ancillary chunk is dropped.
+	DropAll,
+}
+
+impl ChunkPolicy {
+	/// Whether a chunk of the given type is kept under this policy.
+	pub fn keeps(&self, chunk_type: &[u8; 4]) -> bool {
+		if ChunkType::from(*chunk_type).is_critical() {
+			return true;
+		}
+		match self {
+			ChunkPolicy::KeepAll => true,
+			ChunkPolicy::KeepListed(allowed) => allowed.contains(chunk_type),
+			ChunkPolicy::DropAll => false,
+		}
+	}
+}
+
+/// What to do with a file holding more than one of a chunk that should be unique, such as IHDR,
+/// zTXt or PLTE, see [crate::RawDmi::load_with_policy].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum DuplicateChunkPolicy {
+	/// Fail with [error::DmiError::DuplicateChunk].
+	Error,
+	/// Keep the first chunk of the file.
+	First,
+	/// Keep the last chunk of the file.
+	#[default]
+	Last,
+}
+
+impl DuplicateChunkPolicy {
+	/// Stores `chunk`, of type `chunk_type`, in `slot` as the policy says, `slot` holding the chunk
+	/// of the same type met earlier, if any.
+	pub(crate) fn resolve<C>(
+		self,
+		slot: &mut Option<C>,
+		chunk: C,
+		chunk_type: [u8; 4],
+	) -> Result<(), error::DmiError> {
+		match (self, &slot) {
+			(DuplicateChunkPolicy::Error, Some(_)) => Err(error::DmiError::DuplicateChunk { chunk_type }),
+			(DuplicateChunkPolicy::First, Some(_)) => Ok(()),
+			_ => {
+				*slot = Some(chunk);
+				Ok(())
+			}
+		}
+	}
+}
+
+/// A chunk of a [crate::RawDmi], borrowed, see [crate::RawDmi::chunks].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChunkRef<'a> {
+	Generic(&'a RawGenericChunk),
+	Ztxt(&'a RawZtxtChunk),
+	Iend(&'a RawIendChunk),
+}
+
+impl ChunkRef<'_> {
+	pub fn chunk_type(&self) -> [u8; 4] {
+		match self {
+			ChunkRef::Generic(chunk) => chunk.chunk_type,
+			ChunkRef::Ztxt(chunk) => chunk.chunk_type,
+			ChunkRef::Iend(chunk) => chunk.chunk_type,
+		}
+	}
+
+	/// Length of the chunk data, as stated by its length field.
+	pub fn data_length(&self) -> u32 {
+		u32::from_be_bytes(match self {
+			ChunkRef::Generic(chunk) => chunk.data_length,
+			ChunkRef::Ztxt(chunk) => chunk.data_length,
+			ChunkRef::Iend(chunk) => chunk.data_length,
+		})
+	}
+}
+
+/// The bytes a chunk took in a file, see [crate::RawDmi::source_map].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChunkSpan {
+	pub chunk_type: [u8; 4],
+	/// Offset of the chunk from the start of the file, at its length field.
+	pub offset: u64,
+	/// Length of the whole chunk: its length, type, data and CRC.
+	pub length: u64,
+}
+
+impl ChunkSpan {
+	/// Offset of the chunk data from the start of the file.
+	pub fn data_offset(&self) -> u64 {
+		self.offset + 8
+	}
+
+	/// Length of the chunk data.
+	pub fn data_length(&self) -> u64 {
+		self.length - 12
+	}
+}
+
+/// What a load did with a chunk of the file, see [ChunkRecord].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ChunkHandling {
+	/// Read and turned into the loaded structure.
+	Parsed,
+	/// Kept as is, to be written back on save.
+	Preserved,
+	/// Ignored, and absent from what a save would write.
+	Dropped,
+}
+
+/// A chunk met while loading a file, and how it was handled.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChunkRecord {
+	pub chunk_type: [u8; 4],
+	pub data_length: u32,
+	pub handling: ChunkHandling,
+}
+
+/// Ancillary chunks describing how the image data is shown: transparency, color space and
+/// significant bits. Changing them changes the pixels without touching the image data.
+pub const PIXEL_CHUNK_TYPES: [[u8; 4]; 6] =
+	[*b"tRNS", *b"gAMA", *b"cHRM", *b"sRGB", *b"iCCP", *b"sBIT"];
+
+/// Ancillary chunks the PNG spec requires before PLTE. [crate::RawDmi::save] writes the other
+/// chunks after it, so these cannot be added to indexed files.
+const BEFORE_PLTE_TYPES: [[u8; 4]; 5] = [*b"cHRM", *b"gAMA", *b"iCCP", *b"sBIT", *b"sRGB"];
+
+/// Editing [crate::RawDmi::other_chunks], which [crate::RawDmi::save] writes between PLTE and the
+/// image data, while keeping the file valid.
+impl crate::RawDmi {
+	/// Adds `chunk` after the other chunks, right before the image data. Fails for critical
+	/// chunks, which have their own fields, and for chunks that must come before PLTE when there is
+	/// one.
+	pub fn insert_chunk_before_idat(
+		&mut self,
+		chunk: RawGenericChunk,
+	) -> Result<(), error::DmiError> {
+		self.check_insertable(&chunk)?;
+		self.other_chunks.get_or_insert_with(Vec::new).push(chunk);
+		Ok(())
+	}
+
+	/// Removes the other chunks of type `chunk_type`. Returns how many there were.
+	pub fn remove_chunks_of_type(&mut self, chunk_type: [u8; 4]) -> usize {
+		let Some(other_chunks) = &mut self.other_chunks else {
+			return 0;
+		};
+		let previous_count = other_chunks.len();
+		other_chunks.retain(|chunk| chunk.chunk_type != chunk_type);
+		let removed = previous_count - other_chunks.len();
+		if other_chunks.is_empty() {
+			self.other_chunks = None;
+		}
+		removed
+	}
+
+	/// Replaces the other chunk of the same type as `chunk`, keeping its position, and removes any
+	/// further chunk of that type. Inserts it like [crate::RawDmi::insert_chunk_before_idat] if
+	/// there was none, failing in the same cases.
+	pub fn upsert_chunk(&mut self, chunk: RawGenericChunk) -> Result<(), error::DmiError> {
+		self.check_insertable(&chunk)?;
+		let other_chunks = self.other_chunks.get_or_insert_with(Vec::new);
+		match other_chunks
+			.iter()
+			.position(|other_chunk| other_chunk.chunk_type == chunk.chunk_type)
+		{
+			Some(position) => {
+				let chunk_type = chunk.chunk_type;
+				other_chunks[position] = chunk;
+				let following = other_chunks.split_off(position + 1);
+				other_chunks.extend(
+					following
+						.into_iter()
+						.filter(|other_chunk| other_chunk.chunk_type != chunk_type),
+				);
+			}
+			None => other_chunks.push(chunk),
+		}
+		Ok(())
+	}
+
+	fn check_insertable(&self, chunk: &RawGenericChunk) -> Result<(), error::DmiError> {
+		let chunk_type = ChunkType::from(chunk.chunk_type);
+		if chunk_type.is_critical() {
+			return Err(error::DmiError::InvalidArgument(format!(
+				"Failed to insert chunk. {chunk_type} is critical, and goes in its own field."
+			)));
+		}
+		if self.chunk_plte.is_some() && BEFORE_PLTE_TYPES.contains(&chunk.chunk_type) {
+			return Err(error::DmiError::InvalidArgument(format!(
+				"Failed to insert chunk. {chunk_type} has to come before the PLTE chunk of the file."
+			)));
+		}
+		Ok(())
+	}
+}
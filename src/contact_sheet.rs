@@ -0,0 +1,158 @@
+use crate::error::DmiError;
+use crate::icon::{sheet_layout, Icon};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::num::NonZeroU32;
+
+/// Options for [Icon::render_contact_sheet].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ContactSheetOptions {
+	/// Number of cells per row. By default, the grid is as square as possible.
+	pub columns: Option<NonZeroU32>,
+	/// Factor the sprites are scaled up by, with nearest neighbour sampling.
+	pub scale: NonZeroU32,
+	/// Pixels between cells, and around the grid.
+	pub spacing: u32,
+	/// Color of the space not covered by sprites.
+	pub background: Rgba<u8>,
+}
+
+impl Default for ContactSheetOptions {
+	fn default() -> Self {
+		ContactSheetOptions {
+			columns: None,
+			scale: NonZeroU32::MIN,
+			spacing: 1,
+			background: Rgba([255, 0, 255, 255]),
+		}
+	}
+}
+
+impl ContactSheetOptions {
+	pub fn new() -> ContactSheetOptions {
+		ContactSheetOptions {
+			..Default::default()
+		}
+	}
+
+	/// Sets [ContactSheetOptions::columns].
+	pub fn columns(mut self, columns: NonZeroU32) -> ContactSheetOptions {
+		self.columns = Some(columns);
+		self
+	}
+
+	/// Sets [ContactSheetOptions::scale].
+	pub fn scale(mut self, scale: NonZeroU32) -> ContactSheetOptions {
+		self.scale = scale;
+		self
+	}
+
+	/// Sets [ContactSheetOptions::spacing].
+	pub fn spacing(mut self, spacing: u32) -> ContactSheetOptions {
+		self.spacing = spacing;
+		self
+	}
+
+	/// Sets [ContactSheetOptions::background].
+	pub fn background(mut self, background: Rgba<u8>) -> ContactSheetOptions {
+		self.background = background;
+		self
+	}
+}
+
+/// An image of every state of an icon, see [Icon::render_contact_sheet].
+#[derive(Clone, PartialEq, Debug)]
+pub struct ContactSheet {
+	pub image: DynamicImage,
+	/// The cell of each state, in state order. Meant to label the sheet, for instance with an HTML
+	/// image map or an overlay in a review comment.
+	pub cells: Vec<ContactSheetCell>,
+}
+
+/// Where a state was drawn on a [ContactSheet].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ContactSheetCell {
+	pub state_name: String,
+	/// Position of the top left corner of the cell, in pixels.
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+}
+
+impl Icon {
+	/// Draws the first frame of every state, facing south, on a grid read left to right then top to
+	/// bottom. The image holds no text: the state of each cell is listed in [ContactSheet::cells].
+	/// States without images leave their cell empty.
+	///
+	/// Fails if the sheet would be too large for its dimensions to fit in a `u32`, or for its pixels
+	/// to fit in memory.
+	pub fn render_contact_sheet(
+		&self,
+		options: &ContactSheetOptions,
+	) -> Result<ContactSheet, DmiError> {
+		let too_large = || {
+			DmiError::Generic(format!(
+				"Error rendering contact sheet: {} states of {}x{} pixels scaled by {} do not fit in an image.",
+				self.states.len(),
+				self.width,
+				self.height,
+				options.scale
+			))
+		};
+		let (columns, rows) = match options.columns {
+			Some(columns) => {
+				let columns = columns.get();
+				let states = u32::try_from(self.states.len()).map_err(|_| too_large())?;
+				(columns, states.div_ceil(columns))
+			}
+			None => sheet_layout(self.states.len()),
+		};
+		let cell_width = self
+			.width
+			.checked_mul(options.scale.get())
+			.ok_or_else(too_large)?;
+		let cell_height = self
+			.height
+			.checked_mul(options.scale.get())
+			.ok_or_else(too_large)?;
+		let spacing = options.spacing;
+		let sheet_side = |cells: u32, cell_side: u32| {
+			cell_side
+				.checked_add(spacing)
+				.and_then(|stride| stride.checked_mul(cells))
+				.and_then(|side| side.checked_add(spacing))
+				.ok_or_else(too_large)
+		};
+		let sheet_width = sheet_side(columns, cell_width)?;
+		let sheet_height = sheet_side(rows, cell_height)?;
+		(sheet_width as usize)
+			.checked_mul(sheet_height as usize)
+			.and_then(|pixels| pixels.checked_mul(4))
+			.ok_or_else(too_large)?;
+		let mut image = RgbaImage::from_pixel(sheet_width, sheet_height, options.background);
+
+		let mut cells = Vec::with_capacity(self.states.len());
+		for (index, icon_state) in self.states.iter().enumerate() {
+			let index = index as u32;
+			let x = spacing + (index % columns) * (cell_width + spacing);
+			let y = spacing + (index / columns) * (cell_height + spacing);
+			if let Some(sprite) = icon_state.images.first() {
+				let sprite = imageops::resize(sprite, cell_width, cell_height, FilterType::Nearest);
+				imageops::overlay(&mut image, &sprite, x as i64, y as i64);
+			}
+			cells.push(ContactSheetCell {
+				state_name: icon_state.name.clone(),
+				x,
+				y,
+				width: cell_width,
+				height: cell_height,
+			});
+		}
+
+		Ok(ContactSheet {
+			image: DynamicImage::ImageRgba8(image),
+			cells,
+		})
+	}
+}
@@ -0,0 +1,227 @@
+use crate::error::DmiError;
+use crate::frames::{states_from_sprites, Sprites};
+use crate::icon::{DirIndex, Frame, Icon, IconState};
+use crate::naming::NamingScheme;
+use crate::progress::Progress;
+use image::GenericImageView;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Options for [convert_png_dir].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConvertOptions {
+	/// How file names map to sprites, without the `.png` extension. `{state}` by default, so that
+	/// every file becomes a state of its own name.
+	pub naming: NamingScheme,
+}
+
+impl Default for ConvertOptions {
+	fn default() -> Self {
+		ConvertOptions {
+			naming: NamingScheme::new("{state}"),
+		}
+	}
+}
+
+impl ConvertOptions {
+	pub fn new() -> ConvertOptions {
+		ConvertOptions {
+			..Default::default()
+		}
+	}
+
+	/// Sets [ConvertOptions::naming].
+	pub fn naming(mut self, naming: NamingScheme) -> ConvertOptions {
+		self.naming = naming;
+		self
+	}
+}
+
+/// The icon built out of one directory by [convert_png_dir].
+#[derive(Debug)]
+pub struct ConvertedIcon {
+	/// The directory the PNG files were found in.
+	pub source: PathBuf,
+	/// Where the icon belongs, relative to the output directory: the path of [ConvertedIcon::source]
+	/// relative to the converted root, with a `.dmi` extension. PNG files right in the root give an
+	/// icon named after the root itself.
+	pub output: PathBuf,
+	/// The icon, or why the directory could not be converted.
+	pub icon: Result<Icon, DmiError>,
+}
+
+impl ConvertedIcon {
+	/// Saves the icon at [ConvertedIcon::output] under `output_root`, creating directories as
+	/// needed. Returns the path written. Fails with the conversion error if there was one.
+	pub fn save_to<P: AsRef<Path>>(&self, output_root: P) -> Result<PathBuf, DmiError> {
+		let icon = self.icon.as_ref().map_err(|error| {
+			DmiError::Generic(format!(
+				"Error saving {:?}: conversion failed: {error}",
+				self.output
+			))
+		})?;
+		let path = output_root.as_ref().join(&self.output);
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		icon.save(&mut BufWriter::new(File::create(&path)?))?;
+		Ok(path)
+	}
+}
+
+/// Converts the PNG files found under `root` into icons, one per directory holding any, sorted by
+/// directory. This is the usual way to import an asset pack.
+///
+/// Files are turned into sprites as [ConvertOptions::naming] says, files that do not follow it
+/// being ignored. Animated PNGs (APNG) provide every frame of their sprite starting from the frame
+/// in their name, with their frame durations as delays. Still sprites of animated states get a
+/// delay of one tick. States are sorted by name.
+///
+/// A directory fails to convert, without stopping the others, if its sprites do not all share the
+/// same dimensions, if two files provide the same sprite, if a state does not have 1, 4 or 8 dirs
+/// or if a frame is missing for any of its dirs. Only errors reading the directories themselves are
+/// returned.
+pub fn convert_png_dir<P: AsRef<Path>>(
+	root: P,
+	options: &ConvertOptions,
+) -> Result<Vec<ConvertedIcon>, DmiError> {
+	convert_png_dir_with_progress(root, options, |_| {})
+}
+
+/// Like [convert_png_dir], calling `on_progress` after each directory is converted, with the PNG
+/// files found so far as the files done and the directory as the path.
+pub fn convert_png_dir_with_progress<P: AsRef<Path>, F: FnMut(&Progress)>(
+	root: P,
+	options: &ConvertOptions,
+	mut on_progress: F,
+) -> Result<Vec<ConvertedIcon>, DmiError> {
+	let root = root.as_ref();
+	let root_name = root
+		.file_name()
+		.map_or_else(|| PathBuf::from("icon"), PathBuf::from);
+	let mut directories = vec![];
+	let mut pending = vec![root.to_path_buf()];
+	while let Some(directory) = pending.pop() {
+		let mut png_paths = vec![];
+		for entry in fs::read_dir(&directory)? {
+			let entry = entry?;
+			let path = entry.path();
+			// Symbolic links to directories are not followed, as they may loop.
+			if entry.file_type()?.is_dir() {
+				pending.push(path);
+			} else if path.is_file()
+				&& path
+					.extension()
+					.is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+			{
+				png_paths.push(path);
+			}
+		}
+		if png_paths.is_empty() {
+			continue;
+		}
+		png_paths.sort();
+		directories.push((directory, png_paths));
+	}
+	directories.sort();
+
+	let files_total = directories
+		.iter()
+		.map(|(_, png_paths)| png_paths.len())
+		.sum();
+	let mut files_done = 0;
+	let mut bytes_done = 0;
+	let mut converted = vec![];
+	for (directory, png_paths) in directories {
+		let relative = directory.strip_prefix(root).unwrap_or(&directory);
+		let output = if relative.as_os_str().is_empty() {
+			&root_name
+		} else {
+			relative
+		};
+		converted.push(ConvertedIcon {
+			output: output.with_extension("dmi"),
+			icon: convert_pngs(&png_paths, &options.naming),
+			source: directory,
+		});
+		files_done += png_paths.len();
+		bytes_done += png_paths
+			.iter()
+			.map(|path| fs::metadata(path).map_or(0, |metadata| metadata.len()))
+			.sum::<u64>();
+		on_progress(&Progress {
+			files_done,
+			files_total,
+			bytes_done,
+			path: &converted[converted.len() - 1].source,
+		});
+	}
+	Ok(converted)
+}
+
+fn convert_pngs(paths: &[PathBuf], naming: &NamingScheme) -> Result<Icon, DmiError> {
+	let mut found = Sprites::new();
+	let mut dimensions = None;
+	for path in paths {
+		let Some(sprite_name) = path
+			.file_stem()
+			.and_then(|stem| stem.to_str())
+			.and_then(|stem| naming.parse(stem))
+		else {
+			continue;
+		};
+		let Some(dir) = DirIndex::of(&sprite_name.dir) else {
+			continue;
+		};
+
+		let animation = IconState::from_apng(&sprite_name.state, BufReader::new(File::open(path)?))?;
+		let sprites = found.entry(sprite_name.state).or_default();
+		for (index, image) in animation.images.into_iter().enumerate() {
+			match dimensions {
+				None => dimensions = Some(image.dimensions()),
+				Some((width, height)) if (width, height) != image.dimensions() => {
+					return Err(DmiError::Generic(format!(
+						"Error converting PNGs: {:?} is {}x{}, other sprites are {}x{}.",
+						path,
+						image.width(),
+						image.height(),
+						width,
+						height
+					)))
+				}
+				Some(_) => (),
+			}
+			let frame = u32::try_from(index)
+				.ok()
+				.and_then(|index| sprite_name.frame.index().checked_add(index))
+				.and_then(Frame::from_index)
+				.ok_or_else(|| {
+					DmiError::Generic(format!(
+						"Error converting PNGs: {:?} has frames past the largest frame number.",
+						path
+					))
+				})?;
+			let delay = animation.delay.as_ref().map_or(1.0, |delay| delay[index]);
+			if sprites.insert((frame, dir), (image, delay)).is_some() {
+				return Err(DmiError::Generic(format!(
+					"Error converting PNGs: {:?} provides frame {frame} facing {}, which another file already provides.",
+					path,
+					dir.dir()
+				)));
+			}
+		}
+	}
+
+	let Some((width, height)) = dimensions else {
+		return Err(DmiError::Generic(
+			"Error converting PNGs: no file follows the naming scheme.".to_string(),
+		));
+	};
+	Ok(Icon {
+		width,
+		height,
+		states: states_from_sprites(found)?,
+		..Default::default()
+	})
+}
@@ -1,17 +1,126 @@
-pub(crate) fn calculate_crc<'a, I: IntoIterator<Item = &'a u8>>(buffer: I) -> u32 {
-	const CRC_POLYNOMIAL: u32 = 0xedb8_8320;
-
-	fn update_crc(crc: u32, message: u8) -> u32 {
-		let message: u32 = u32::from(message);
-		let mut crc = crc ^ message;
-		for _ in 0..8 {
-			crc = (if crc & 1 != 0 { CRC_POLYNOMIAL } else { 0 }) ^ (crc >> 1);
-		}
-		crc
-	}
-
-	buffer
-		.into_iter()
-		.fold(u32::MAX, |crc, message| update_crc(crc, *message))
-		^ u32::MAX
-}
+const CRC_POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// Streaming computation of the CRC of a chunk, as stored after its data.
+///
+/// The CRC covers the chunk type followed by the data, but the type only has to be known once all
+/// the data went through [ChunkCrcHasher::update], so the data can be hashed as it is produced.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChunkCrcHasher {
+	register: u32,
+	length: u64,
+}
+
+impl Default for ChunkCrcHasher {
+	fn default() -> Self {
+		ChunkCrcHasher {
+			register: u32::MAX,
+			length: 0,
+		}
+	}
+}
+
+impl ChunkCrcHasher {
+	pub fn new() -> ChunkCrcHasher {
+		ChunkCrcHasher {
+			..Default::default()
+		}
+	}
+
+	/// Feeds the next bytes of the chunk data.
+	pub fn update(&mut self, data: &[u8]) {
+		self.register = data
+			.iter()
+			.fold(self.register, |crc, message| update_crc(crc, *message));
+		self.length += data.len() as u64;
+	}
+
+	/// The CRC of a chunk of type `chunk_type` holding the data fed so far.
+	pub fn finalize_for(&self, chunk_type: [u8; 4]) -> u32 {
+		combine_crc(
+			calculate_crc(&chunk_type),
+			self.register ^ u32::MAX,
+			self.length,
+		)
+	}
+
+	/// The CRC of a chunk of type `chunk_type` holding `data`, when the data is known at once.
+	pub fn checksum(chunk_type: [u8; 4], data: &[u8]) -> u32 {
+		let mut hasher = ChunkCrcHasher::new();
+		hasher.update(data);
+		hasher.finalize_for(chunk_type)
+	}
+}
+
+fn calculate_crc(buffer: &[u8]) -> u32 {
+	buffer
+		.iter()
+		.fold(u32::MAX, |crc, message| update_crc(crc, *message))
+		^ u32::MAX
+}
+
+fn update_crc(crc: u32, message: u8) -> u32 {
+	let message: u32 = u32::from(message);
+	let mut crc = crc ^ message;
+	for _ in 0..8 {
+		crc = (if crc & 1 != 0 { CRC_POLYNOMIAL } else { 0 }) ^ (crc >> 1);
+	}
+	crc
+}
+
+/// The CRC of two buffers put together, out of their own CRCs and the length of the second one.
+/// Works by running the first CRC through `second_length` zero bytes, in logarithmic time, as
+/// zlib's `crc32_combine` does.
+fn combine_crc(first: u32, second: u32, second_length: u64) -> u32 {
+	if second_length == 0 {
+		return first;
+	}
+
+	// Operator for one zero bit, then squared into operators for two and four zero bits.
+	let mut odd = [0u32; 32];
+	odd[0] = CRC_POLYNOMIAL;
+	for (bit, row) in odd.iter_mut().enumerate().skip(1) {
+		*row = 1 << (bit - 1);
+	}
+	let mut even = square_matrix(&odd);
+	odd = square_matrix(&even);
+
+	// Apply the operators for each set bit of the length in bytes, squaring them along the way.
+	let mut crc = first;
+	let mut length = second_length;
+	loop {
+		even = square_matrix(&odd);
+		if length & 1 != 0 {
+			crc = multiply_matrix(&even, crc);
+		}
+		length >>= 1;
+		if length == 0 {
+			break;
+		}
+		odd = square_matrix(&even);
+		if length & 1 != 0 {
+			crc = multiply_matrix(&odd, crc);
+		}
+		length >>= 1;
+		if length == 0 {
+			break;
+		}
+	}
+	crc ^ second
+}
+
+fn multiply_matrix(matrix: &[u32; 32], mut vector: u32) -> u32 {
+	let mut sum = 0;
+	let mut row = 0;
+	while vector != 0 {
+		if vector & 1 != 0 {
+			sum ^= matrix[row];
+		}
+		vector >>= 1;
+		row += 1;
+	}
+	sum
+}
+
+fn square_matrix(matrix: &[u32; 32]) -> [u32; 32] {
+	matrix.map(|row| multiply_matrix(matrix, row))
+}
@@ -0,0 +1,43 @@
+use crate::error::DmiError;
+use crate::icon::{DirIndex, Frame, IconState};
+use image::ImageFormat;
+use std::io::Cursor;
+
+const BASE64_ALPHABET: &[u8; 64] =
+	b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl IconState {
+	/// The sprite for `dir` at `frame` as a `data:image/png;base64,...` URI, which web pages can
+	/// use as the source of an image directly. Fails if the state has no such dir or frame.
+	pub fn frame_data_uri(&self, dir: DirIndex, frame: Frame) -> Result<String, DmiError> {
+		let mut png = Cursor::new(vec![]);
+		self
+			.image(dir, frame)?
+			.write_to(&mut png, ImageFormat::Png)?;
+		Ok(format!(
+			"data:image/png;base64,{}",
+			base64(&png.into_inner())
+		))
+	}
+}
+
+/// Standard base64 with padding, as data URIs expect.
+fn base64(bytes: &[u8]) -> String {
+	let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+	for group in bytes.chunks(3) {
+		let value = group
+			.iter()
+			.enumerate()
+			.fold(0u32, |value, (index, &byte)| {
+				value | (byte as u32) << (16 - 8 * index)
+			});
+		for index in 0..4 {
+			if index <= group.len() {
+				encoded.push(BASE64_ALPHABET[(value >> (18 - 6 * index) & 0x3F) as usize] as char);
+			} else {
+				encoded.push('=');
+			}
+		}
+	}
+	encoded
+}
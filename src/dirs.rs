@@ -1,3 +1,4 @@
+use crate::error::DmiError;
 use bitflags::bitflags;
 
 bitflags! {
@@ -15,12 +16,75 @@ bitflags! {
 	}
 }
 
+impl Dirs {
+	/// The name of the DM constant for this direction, such as `SOUTHWEST`. None for sets of flags
+	/// that are not a direction.
+	pub fn name(&self) -> Option<&'static str> {
+		DIR_NAMES
+			.iter()
+			.find(|(dir, _)| dir == self)
+			.map(|(_, name)| *name)
+	}
+
+	/// The direction facing along a movement of `dx` tiles east and `dy` tiles north, y growing
+	/// upwards as in BYOND. Like DM's `get_dir`, any movement on both axes is diagonal. None when
+	/// there is no movement.
+	pub fn from_delta(dx: i32, dy: i32) -> Option<Dirs> {
+		let mut dir = Dirs::empty();
+		match dx.signum() {
+			1 => dir |= Dirs::EAST,
+			-1 => dir |= Dirs::WEST,
+			_ => (),
+		}
+		match dy.signum() {
+			1 => dir |= Dirs::NORTH,
+			-1 => dir |= Dirs::SOUTH,
+			_ => (),
+		}
+		(!dir.is_empty()).then_some(dir)
+	}
+}
+
+/// Writes the name of the direction, see [Dirs::name], or its numeric value if it has none.
 impl std::fmt::Display for Dirs {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{} ({:?})", self.bits(), self)
+		match self.name() {
+			Some(name) => write!(f, "{name}"),
+			None => write!(f, "{}", self.bits()),
+		}
 	}
 }
 
+/// Parses the name of a direction, in any case, or its numeric value as in DM: `NORTH`, `southwest`
+/// and `2` are all accepted.
+impl std::str::FromStr for Dirs {
+	type Err = DmiError;
+
+	fn from_str(text: &str) -> Result<Self, Self::Err> {
+		let text = text.trim();
+		let dir = match text.parse::<u8>() {
+			Ok(bits) => Dirs::from_bits(bits).filter(|dir| dir.name().is_some()),
+			Err(_) => DIR_NAMES
+				.iter()
+				.find(|(_, name)| name.eq_ignore_ascii_case(text))
+				.map(|(dir, _)| *dir),
+		};
+		dir.ok_or_else(|| DmiError::Generic(format!("Invalid direction: \"{text}\"")))
+	}
+}
+
+/// The DM constant name of each direction.
+const DIR_NAMES: [(Dirs, &str); 8] = [
+	(Dirs::NORTH, "NORTH"),
+	(Dirs::SOUTH, "SOUTH"),
+	(Dirs::EAST, "EAST"),
+	(Dirs::WEST, "WEST"),
+	(Dirs::NORTHEAST, "NORTHEAST"),
+	(Dirs::NORTHWEST, "NORTHWEST"),
+	(Dirs::SOUTHEAST, "SOUTHEAST"),
+	(Dirs::SOUTHWEST, "SOUTHWEST"),
+];
+
 /// A list of every cardinal direction.
 pub const CARDINAL_DIRS: [Dirs; 4] = [Dirs::NORTH, Dirs::SOUTH, Dirs::EAST, Dirs::WEST];
 
@@ -0,0 +1,103 @@
+use crate::error::DmiError;
+use crate::icon::IconState;
+use image::{DynamicImage, GenericImageView};
+use std::ops::Deref;
+
+/// A guarded mutable view over the images and layout of an [IconState], see [IconState::edit].
+///
+/// Changes can break the state's invariants while the handle is alive. Once it is dropped the state
+/// must pass [IconState::validate], all of its images having the dimensions of the first one: exactly
+/// `dirs * frames` images, 1, 4 or 8 dirs, at least one frame and one delay entry per frame when
+/// animated. If it does not, every change made through the handle is rolled back.
+/// [IconStateEdit::finish] performs the same check but reports the error.
+#[derive(Debug)]
+pub struct IconStateEdit<'a> {
+	state: &'a mut IconState,
+	original: Option<Layout>,
+}
+
+/// The dirs, frames, images and delays an [IconStateEdit] rolls back to.
+type Layout = (u8, u32, Vec<DynamicImage>, Option<Vec<f32>>);
+
+impl IconState {
+	/// Opens a guarded handle to change the images, dirs, frames and delays of this state together.
+	///
+	/// The `images`, `dirs`, `frames` and `delay` fields remain public for compatibility, but nothing
+	/// keeps them consistent. The handle checks them when it is dropped, see [IconStateEdit].
+	pub fn edit(&mut self) -> IconStateEdit<'_> {
+		let original = Some((
+			self.dirs,
+			self.frames,
+			self.images.clone(),
+			self.delay.clone(),
+		));
+		IconStateEdit {
+			state: self,
+			original,
+		}
+	}
+}
+
+impl IconStateEdit<'_> {
+	/// Mutable access to the images, laid out as in [IconState::images].
+	pub fn images_mut(&mut self) -> &mut Vec<DynamicImage> {
+		&mut self.state.images
+	}
+
+	/// Changes the number of dirs and frames. The images must be updated to match before the handle
+	/// is dropped.
+	pub fn set_layout(&mut self, dirs: u8, frames: u32) -> &mut Self {
+		self.state.dirs = dirs;
+		self.state.frames = frames;
+		self
+	}
+
+	/// Changes the delay of each frame, see [IconState::delay]. Animated states need one entry per
+	/// frame by the time the handle is dropped.
+	pub fn set_delay(&mut self, delay: Option<Vec<f32>>) -> &mut Self {
+		self.state.delay = delay;
+		self
+	}
+
+	/// Checks the state and closes the handle. On error, the changes are rolled back.
+	pub fn finish(mut self) -> Result<(), DmiError> {
+		let result = self.check();
+		if result.is_ok() {
+			self.original = None;
+		}
+		result
+	}
+
+	fn check(&self) -> Result<(), DmiError> {
+		// The icon the state belongs to is not known here, so the images only have to agree with
+		// the first one.
+		let (width, height) = self
+			.state
+			.images
+			.first()
+			.map_or((0, 0), |image| image.dimensions());
+		self.state.validate(width, height)
+	}
+}
+
+impl Deref for IconStateEdit<'_> {
+	type Target = IconState;
+
+	fn deref(&self) -> &IconState {
+		self.state
+	}
+}
+
+impl Drop for IconStateEdit<'_> {
+	fn drop(&mut self) {
+		if self.check().is_ok() {
+			return;
+		}
+		if let Some((dirs, frames, images, delay)) = self.original.take() {
+			self.state.dirs = dirs;
+			self.state.frames = frames;
+			self.state.images = images;
+			self.state.delay = delay;
+		}
+	}
+}
@@ -0,0 +1,553 @@
+use crate::chunk::{RawGenericChunk, IDAT_TYPE, IHDR_TYPE, PLTE_TYPE};
+use crate::error::DmiError;
+use crate::quantize::{quantize, QuantizeOptions};
+use crate::{iend, RawDmi, PNG_HEADER};
+use image::codecs::png;
+use image::RgbaImage;
+use std::collections::HashSet;
+use std::num::NonZeroU32;
+
+pub const TRNS_TYPE: [u8; 4] = [b't', b'R', b'N', b'S'];
+
+/// Pixel format of the saved sheet, see [crate::options::SaveOptions::color_type].
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum OutputColorType {
+	/// 8 bits per channel, alpha included. Handles every icon.
+	#[default]
+	Rgba8,
+	/// 8 bits per channel without alpha, fully transparent pixels being marked by a tRNS chunk
+	/// holding a color no opaque pixel uses. Fails for icons with partially transparent pixels.
+	Rgb8Trns,
+	/// A palette of up to 256 colors, with a tRNS chunk holding their alpha. Icons with more colors
+	/// are quantized as configured first. Uses the smallest bit depth that fits the palette.
+	Indexed(QuantizeOptions),
+	/// A palette like [OutputColorType::Indexed] when the sheet has at most 256 colors, so that no
+	/// pixel changes, and RGBA8 otherwise. Fully transparent pixels count as a single color.
+	IndexedIfLossless,
+	/// The color type and bit depth of the file the icon was loaded from, see
+	/// [crate::source::SourceSheet::format], when the sheet can still be written in it without
+	/// changing any pixel, and RGBA8 otherwise. 16-bit formats and greyscale below 8 bits are always
+	/// written as RGBA8.
+	Source,
+}
+
+/// Color type and bit depth of a PNG, as stored in its IHDR chunk.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SourceFormat {
+	pub color_type: u8,
+	pub bit_depth: u8,
+}
+
+impl SourceFormat {
+	/// Whether `sheet` can be encoded in this format without changing any pixel, fully transparent
+	/// pixels all counting as one.
+	fn fits(self, sheet: &RgbaImage) -> bool {
+		let visible = || sheet.pixels().filter(|pixel| pixel[3] != 0);
+		let translucent = || visible().any(|pixel| pixel[3] != 255);
+		let grey = || visible().all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+		match (self.color_type, self.bit_depth) {
+			(COLOR_TYPE_RGBA, 8) => true,
+			(COLOR_TYPE_RGB, 8) => !translucent(),
+			(COLOR_TYPE_GREY, 8) => !translucent() && grey() && grey_key(sheet).is_some(),
+			(COLOR_TYPE_GREY_ALPHA, 8) => grey(),
+			(COLOR_TYPE_INDEXED, bit_depth @ (1 | 2 | 4 | 8)) => fits_palette(sheet, 1 << bit_depth),
+			_ => false,
+		}
+	}
+}
+
+impl OutputColorType {
+	/// The color type `sheet` is encoded as, which only differs from this one for
+	/// [OutputColorType::IndexedIfLossless].
+	pub(crate) fn for_sheet(
+		&self,
+		sheet: &RgbaImage,
+		source: Option<SourceFormat>,
+	) -> OutputColorType {
+		match self {
+			OutputColorType::IndexedIfLossless if fits_palette(sheet, 256) => {
+				OutputColorType::Indexed(QuantizeOptions::default())
+			}
+			OutputColorType::IndexedIfLossless => OutputColorType::Rgba8,
+			OutputColorType::Source => match source {
+				Some(format) if format.color_type != COLOR_TYPE_RGBA && format.fits(sheet) => {
+					OutputColorType::Source
+				}
+				_ => OutputColorType::Rgba8,
+			},
+			color_type => color_type.clone(),
+		}
+	}
+}
+
+/// Whether `sheet` has at most `max_colors` colors, fully transparent pixels counting as one.
+fn fits_palette(sheet: &RgbaImage, max_colors: usize) -> bool {
+	let mut colors = HashSet::new();
+	for pixel in sheet.pixels() {
+		let color = if pixel[3] == 0 { [0; 4] } else { pixel.0 };
+		if colors.insert(color) && colors.len() > max_colors {
+			return false;
+		}
+	}
+	true
+}
+
+/// A grey level no opaque pixel of `sheet` uses, to mark transparent pixels with. None if every
+/// level is taken.
+fn grey_key(sheet: &RgbaImage) -> Option<u8> {
+	let mut used = [false; 256];
+	for pixel in sheet.pixels().filter(|pixel| pixel[3] != 0) {
+		used[pixel[0] as usize] = true;
+	}
+	(0..=255).find(|&level| !used[level as usize])
+}
+
+/// How hard the image data is compressed, see [crate::options::SaveOptions::compression].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum CompressionLevel {
+	Fast,
+	#[default]
+	Default,
+	Best,
+}
+
+impl CompressionLevel {
+	pub(crate) fn png(self) -> png::CompressionType {
+		match self {
+			CompressionLevel::Fast => png::CompressionType::Fast,
+			CompressionLevel::Default => png::CompressionType::Default,
+			CompressionLevel::Best => png::CompressionType::Best,
+		}
+	}
+
+	pub(crate) fn deflate(self) -> deflate::Compression {
+		match self {
+			CompressionLevel::Fast => deflate::Compression::Fast,
+			CompressionLevel::Default => deflate::Compression::Default,
+			CompressionLevel::Best => deflate::Compression::Best,
+		}
+	}
+}
+
+/// The PNG filter applied to each row of the sheet before compression, see
+/// [crate::options::SaveOptions::filter].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum FilterStrategy {
+	None,
+	Sub,
+	Up,
+	Average,
+	Paeth,
+	/// Picks the filter of each row with a heuristic. Indexed sheets are left unfiltered instead, as
+	/// the PNG spec recommends.
+	#[default]
+	Adaptive,
+}
+
+impl FilterStrategy {
+	pub(crate) fn png(self) -> png::FilterType {
+		match self {
+			FilterStrategy::None => png::FilterType::NoFilter,
+			FilterStrategy::Sub => png::FilterType::Sub,
+			FilterStrategy::Up => png::FilterType::Up,
+			FilterStrategy::Average => png::FilterType::Avg,
+			FilterStrategy::Paeth => png::FilterType::Paeth,
+			FilterStrategy::Adaptive => png::FilterType::Adaptive,
+		}
+	}
+
+	/// The PNG filter type byte of a fixed filter. None for [FilterStrategy::Adaptive].
+	fn filter_type(self) -> Option<u8> {
+		match self {
+			FilterStrategy::None => Some(0),
+			FilterStrategy::Sub => Some(1),
+			FilterStrategy::Up => Some(2),
+			FilterStrategy::Average => Some(3),
+			FilterStrategy::Paeth => Some(4),
+			FilterStrategy::Adaptive => None,
+		}
+	}
+}
+
+/// How sprites are arranged in the sheet, see [crate::options::SaveOptions::layout]. Sprites
+/// always fill the sheet row by row, in order.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SheetLayout {
+	/// As close to a square as possible, as BYOND does. See [crate::icon::sheet_layout].
+	#[default]
+	Square,
+	/// A fixed number of sprites per row: 1 stacks every sprite in a single column.
+	Columns(NonZeroU32),
+}
+
+impl SheetLayout {
+	/// The number of columns and rows of sprites used to lay out `sprite_count` sprites.
+	pub fn cells(self, sprite_count: usize) -> (u32, u32) {
+		match self {
+			SheetLayout::Square => crate::icon::sheet_layout(sprite_count),
+			SheetLayout::Columns(columns) => {
+				let rows = (sprite_count as u32).div_ceil(columns.get());
+				(columns.get(), rows)
+			}
+		}
+	}
+}
+
+/// PNG color types, as stored in the IHDR chunk.
+const COLOR_TYPE_GREY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_INDEXED: u8 = 3;
+const COLOR_TYPE_GREY_ALPHA: u8 = 4;
+pub(crate) const COLOR_TYPE_RGBA: u8 = 6;
+
+/// Encodes `sheet` as a PNG of `color_type`, without metadata. [OutputColorType::Rgba8] is left
+/// to the image crate and is not handled here, nor is [OutputColorType::IndexedIfLossless], which
+/// [OutputColorType::for_sheet] resolves first. [OutputColorType::Source] is encoded as `source`.
+pub(crate) fn encode_sheet(
+	sheet: &RgbaImage,
+	color_type: &OutputColorType,
+	source: Option<SourceFormat>,
+	compression: CompressionLevel,
+	filter: FilterStrategy,
+) -> Result<RawDmi, DmiError> {
+	match color_type {
+		OutputColorType::Rgba8 => Err(DmiError::Generic(
+			"Error encoding sheet: RGBA8 sheets are encoded by the image crate.".to_string(),
+		)),
+		OutputColorType::Rgb8Trns => encode_rgb8_trns(sheet, compression, filter),
+		OutputColorType::Indexed(options) => encode_indexed(sheet, options, 1, compression, filter),
+		OutputColorType::Source => match source {
+			Some(SourceFormat {
+				color_type: COLOR_TYPE_RGB,
+				..
+			}) => encode_rgb8_trns(sheet, compression, filter),
+			Some(SourceFormat {
+				color_type: COLOR_TYPE_GREY,
+				..
+			}) => encode_grey8(sheet, false, compression, filter),
+			Some(SourceFormat {
+				color_type: COLOR_TYPE_GREY_ALPHA,
+				..
+			}) => encode_grey8(sheet, true, compression, filter),
+			Some(SourceFormat {
+				color_type: COLOR_TYPE_INDEXED,
+				bit_depth,
+			}) => encode_indexed(
+				sheet,
+				&QuantizeOptions::default(),
+				bit_depth,
+				compression,
+				filter,
+			),
+			source => Err(DmiError::Generic(format!(
+				"Error encoding sheet: cannot encode in the source format {source:?}."
+			))),
+		},
+		OutputColorType::IndexedIfLossless => Err(DmiError::Generic(
+			"Error encoding sheet: the color type has to be picked for the sheet first.".to_string(),
+		)),
+	}
+}
+
+fn encode_rgb8_trns(
+	sheet: &RgbaImage,
+	compression: CompressionLevel,
+	filter: FilterStrategy,
+) -> Result<RawDmi, DmiError> {
+	let mut has_transparent = false;
+	let mut used_colors = HashSet::new();
+	for pixel in sheet.pixels() {
+		match pixel[3] {
+			0 => has_transparent = true,
+			255 => {
+				used_colors.insert([pixel[0], pixel[1], pixel[2]]);
+			}
+			alpha => {
+				return Err(DmiError::Generic(format!(
+					"Error encoding sheet as RGB8 with tRNS: partially transparent pixel of alpha {alpha}."
+				)))
+			}
+		}
+	}
+	// Sheets of 4096x4096 pixels or more can use every color, leaving none to mark transparency.
+	let key = (0..=0xFFFFFFu32)
+		.map(|color| {
+			let [_, red, green, blue] = color.to_be_bytes();
+			[red, green, blue]
+		})
+		.find(|color| !used_colors.contains(color));
+	let key = match key {
+		Some(key) => key,
+		// No tRNS chunk is written without transparent pixels, so any color does.
+		None if !has_transparent => [0, 0, 0],
+		None => {
+			return Err(DmiError::Generic(
+				"Error encoding sheet as RGB8 with tRNS: no color is free to mark transparent pixels."
+					.to_string(),
+			))
+		}
+	};
+
+	let rows: Vec<Vec<u8>> = sheet
+		.rows()
+		.map(|row| {
+			row
+				.flat_map(|pixel| match pixel[3] {
+					0 => key,
+					_ => [pixel[0], pixel[1], pixel[2]],
+				})
+				.collect()
+		})
+		.collect();
+	let filtered = filter_rows(&rows, 3, filter);
+	let mut raw_dmi = new_raw_dmi(sheet, 8, COLOR_TYPE_RGB, filtered, compression)?;
+	if has_transparent {
+		let trns = key.iter().flat_map(|&channel| [0, channel]).collect();
+		raw_dmi.other_chunks = Some(vec![RawGenericChunk::new(TRNS_TYPE, trns)?]);
+	}
+	Ok(raw_dmi)
+}
+
+/// Greyscale with 8 bits per channel, with an alpha channel if `alpha` is set. Fully transparent
+/// pixels are marked by a tRNS chunk otherwise, like [OutputColorType::Rgb8Trns]. The color of
+/// pixels is taken from their red channel.
+fn encode_grey8(
+	sheet: &RgbaImage,
+	alpha: bool,
+	compression: CompressionLevel,
+	filter: FilterStrategy,
+) -> Result<RawDmi, DmiError> {
+	let key = if alpha {
+		0
+	} else {
+		grey_key(sheet).ok_or_else(|| {
+			DmiError::Generic(
+				"Error encoding sheet as greyscale: no grey level is free to mark transparent pixels."
+					.to_string(),
+			)
+		})?
+	};
+	let has_transparent = sheet.pixels().any(|pixel| pixel[3] == 0);
+	let rows: Vec<Vec<u8>> = sheet
+		.rows()
+		.map(|row| {
+			row
+				.flat_map(|pixel| {
+					let level = if !alpha && pixel[3] == 0 {
+						key
+					} else {
+						pixel[0]
+					};
+					if alpha {
+						vec![level, pixel[3]]
+					} else {
+						vec![level]
+					}
+				})
+				.collect()
+		})
+		.collect();
+	let bytes_per_pixel = if alpha { 2 } else { 1 };
+	let color_type = if alpha {
+		COLOR_TYPE_GREY_ALPHA
+	} else {
+		COLOR_TYPE_GREY
+	};
+	let filtered = filter_rows(&rows, bytes_per_pixel, filter);
+	let mut raw_dmi = new_raw_dmi(sheet, 8, color_type, filtered, compression)?;
+	if !alpha && has_transparent {
+		raw_dmi.other_chunks = Some(vec![RawGenericChunk::new(TRNS_TYPE, vec![0, key])?]);
+	}
+	Ok(raw_dmi)
+}
+
+/// Indexed color with at least `min_bit_depth` bits per pixel.
+fn encode_indexed(
+	sheet: &RgbaImage,
+	options: &QuantizeOptions,
+	min_bit_depth: u8,
+	compression: CompressionLevel,
+	filter: FilterStrategy,
+) -> Result<RawDmi, DmiError> {
+	let quantized = quantize(sheet, options)?;
+	// tRNS only lists alpha up to the last translucent entry, so those go first.
+	let mut order: Vec<usize> = (0..quantized.palette.len()).collect();
+	order.sort_by_key(|&index| quantized.palette[index][3] == 255);
+	let mut new_index = vec![0u8; order.len()];
+	for (position, &index) in order.iter().enumerate() {
+		new_index[index] = position as u8;
+	}
+	let palette: Vec<_> = order
+		.iter()
+		.map(|&index| quantized.palette[index])
+		.collect();
+
+	let bit_depth = match palette.len() {
+		0..=2 => 1,
+		3..=4 => 2,
+		5..=16 => 4,
+		_ => 8,
+	}
+	.max(min_bit_depth);
+	let per_byte = 8 / bit_depth as usize;
+	let rows: Vec<Vec<u8>> = quantized
+		.indices
+		.chunks(sheet.width().max(1) as usize)
+		.map(|row| {
+			row
+				.chunks(per_byte)
+				.map(|indices| {
+					indices
+						.iter()
+						.enumerate()
+						.fold(0u8, |byte, (position, &index)| {
+							let shift = 8 - bit_depth as usize * (position + 1);
+							byte | new_index[index as usize] << shift
+						})
+				})
+				.collect()
+		})
+		.collect();
+	// Filtering rarely helps indexed images, the PNG spec recommends leaving them unfiltered.
+	let filter = match filter {
+		FilterStrategy::Adaptive => FilterStrategy::None,
+		filter => filter,
+	};
+	let filtered = filter_rows(&rows, 1, filter);
+	let mut raw_dmi = new_raw_dmi(sheet, bit_depth, COLOR_TYPE_INDEXED, filtered, compression)?;
+	raw_dmi.chunk_plte = Some(RawGenericChunk::new(
+		PLTE_TYPE,
+		palette
+			.iter()
+			.flat_map(|color| [color[0], color[1], color[2]])
+			.collect(),
+	)?);
+	let trns: Vec<u8> = palette
+		.iter()
+		.map(|color| color[3])
+		.take_while(|&alpha| alpha != 255)
+		.collect();
+	if !trns.is_empty() {
+		raw_dmi.other_chunks = Some(vec![RawGenericChunk::new(TRNS_TYPE, trns)?]);
+	}
+	Ok(raw_dmi)
+}
+
+fn new_raw_dmi(
+	sheet: &RgbaImage,
+	bit_depth: u8,
+	color_type: u8,
+	filtered: Vec<u8>,
+	compression: CompressionLevel,
+) -> Result<RawDmi, DmiError> {
+	Ok(RawDmi {
+		header: PNG_HEADER,
+		chunk_ihdr: ihdr_chunk(sheet.width(), sheet.height(), bit_depth, color_type)?,
+		chunks_idat: vec![RawGenericChunk::new(
+			IDAT_TYPE,
+			deflate::deflate_bytes_zlib_conf(&filtered, compression.deflate()),
+		)?],
+		chunk_iend: iend::RawIendChunk::new(),
+		..Default::default()
+	})
+}
+
+/// An IHDR chunk for a non-interlaced image of `width` by `height` pixels.
+pub(crate) fn ihdr_chunk(
+	width: u32,
+	height: u32,
+	bit_depth: u8,
+	color_type: u8,
+) -> Result<RawGenericChunk, DmiError> {
+	let mut ihdr = Vec::with_capacity(13);
+	ihdr.extend(width.to_be_bytes());
+	ihdr.extend(height.to_be_bytes());
+	// Bit depth and color type, then the default compression, filter and interlace methods.
+	ihdr.extend([bit_depth, color_type, 0, 0, 0]);
+	RawGenericChunk::new(IHDR_TYPE, ihdr)
+}
+
+/// Filters each row as `filter` says, [filter_best] picking the filter of adaptive rows, and
+/// prefixes it with the filter type.
+fn filter_rows(rows: &[Vec<u8>], bytes_per_pixel: usize, filter: FilterStrategy) -> Vec<u8> {
+	let mut filtered = vec![];
+	let empty_row = vec![0; rows.first().map_or(0, Vec::len)];
+	for (index, row) in rows.iter().enumerate() {
+		let previous = if index == 0 {
+			&empty_row
+		} else {
+			&rows[index - 1]
+		};
+		let (filter_type, output) = match filter.filter_type() {
+			Some(filter_type) => (
+				filter_type,
+				filter_row(filter_type, row, previous, bytes_per_pixel),
+			),
+			None => filter_best(row, previous, bytes_per_pixel),
+		};
+		filtered.push(filter_type);
+		filtered.extend(output);
+	}
+	filtered
+}
+
+/// Filters `row` with the filter whose output has the smallest sum of absolute values, the
+/// heuristic suggested by the PNG spec. `previous` is the row above, all zeroes for the first one.
+/// Returns the filter type and the filtered row.
+pub(crate) fn filter_best(row: &[u8], previous: &[u8], bytes_per_pixel: usize) -> (u8, Vec<u8>) {
+	(0..5u8)
+		.map(|filter_type| {
+			(
+				filter_type,
+				filter_row(filter_type, row, previous, bytes_per_pixel),
+			)
+		})
+		.min_by_key(|(_, output)| {
+			output
+				.iter()
+				.map(|&byte| (byte as i8).unsigned_abs() as u64)
+				.sum::<u64>()
+		})
+		.unwrap()
+}
+
+pub(crate) fn filter_row(
+	filter_type: u8,
+	row: &[u8],
+	previous: &[u8],
+	bytes_per_pixel: usize,
+) -> Vec<u8> {
+	(0..row.len())
+		.map(|index| {
+			let left = index
+				.checked_sub(bytes_per_pixel)
+				.map_or(0, |left| row[left]);
+			let up = previous[index];
+			let up_left = index
+				.checked_sub(bytes_per_pixel)
+				.map_or(0, |left| previous[left]);
+			let predictor = match filter_type {
+				0 => 0,
+				1 => left,
+				2 => up,
+				3 => ((left as u16 + up as u16) / 2) as u8,
+				_ => paeth(left, up, up_left),
+			};
+			row[index].wrapping_sub(predictor)
+		})
+		.collect()
+}
+
+pub(crate) fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+	let estimate = left as i16 + up as i16 - up_left as i16;
+	let distance_left = (estimate - left as i16).abs();
+	let distance_up = (estimate - up as i16).abs();
+	let distance_up_left = (estimate - up_left as i16).abs();
+	if distance_left <= distance_up && distance_left <= distance_up_left {
+		left
+	} else if distance_up <= distance_up_left {
+		up
+	} else {
+		up_left
+	}
+}
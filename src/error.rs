@@ -5,6 +5,7 @@ use thiserror::Error;
 pub enum DmiError {
 	#[error("IO error")]
 	Io(#[from] io::Error),
+	#[cfg(feature = "images")]
 	#[error("Image-processing error")]
 	Image(#[from] image::error::ImageError),
 	#[error("FromUtf8 error")]
@@ -17,6 +18,26 @@ pub enum DmiError {
 	InvalidChunkType { chunk_type: [u8; 4] },
 	#[error("CRC mismatch (stated {stated:?}, calculated {calculated:?})")]
 	CrcMismatch { stated: u32, calculated: u32 },
+	#[error("Duplicate {} chunk", String::from_utf8_lossy(chunk_type))]
+	DuplicateChunk { chunk_type: [u8; 4] },
+	#[error("Truncated chunk at offset {offset}: stated data length of {data_length} bytes, but only {available} bytes remain")]
+	TruncatedChunk {
+		offset: usize,
+		data_length: u32,
+		available: usize,
+	},
+	#[error("Tile slicing error: image of {image_width}x{image_height} holds fewer than the {required} states of {tile_width}x{tile_height} stated in the metadata")]
+	TileSlicing {
+		image_width: u32,
+		image_height: u32,
+		tile_width: u32,
+		tile_height: u32,
+		required: u64,
+	},
+	#[error("Signature error: {0}")]
+	Signature(String),
+	#[error("Invalid argument: {0}")]
+	InvalidArgument(String),
 	#[error("Dmi error: {0}")]
 	Generic(String),
 	#[error("Dmi IconState error: {0}")]
@@ -26,3 +47,52 @@ pub enum DmiError {
 	#[error("Conversion error: {0}")]
 	Conversion(String),
 }
+
+impl DmiError {
+	/// Whether the error stems from malformed input data, as opposed to I/O failures or misuse of the API.
+	/// [DmiError::Generic] is mostly raised while parsing malformed files and is counted as corruption,
+	/// while misuse is reported as [DmiError::InvalidArgument].
+	pub fn is_data_corruption(&self) -> bool {
+		match self {
+			#[cfg(feature = "images")]
+			DmiError::Image(image::error::ImageError::Decoding(_)) => true,
+			DmiError::Io(error) => matches!(
+				error.kind(),
+				io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+			),
+			DmiError::FromUtf8(_)
+			| DmiError::ParseInt(_)
+			| DmiError::ParseFloat(_)
+			| DmiError::InvalidChunkType { .. }
+			| DmiError::CrcMismatch { .. }
+			| DmiError::TruncatedChunk { .. }
+			| DmiError::DuplicateChunk { .. }
+			| DmiError::TileSlicing { .. }
+			| DmiError::Signature(_)
+			| DmiError::Generic(_) => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the error only concerns the file or icon being processed, so that a batch job can skip
+	/// it (or retry it more leniently) and carry on. Errors coming from the environment, such as a full
+	/// disk or exhausted memory limits, are not recoverable.
+	pub fn is_recoverable(&self) -> bool {
+		match self {
+			DmiError::Io(error) => matches!(
+				error.kind(),
+				io::ErrorKind::NotFound
+					| io::ErrorKind::PermissionDenied
+					| io::ErrorKind::InvalidData
+					| io::ErrorKind::InvalidInput
+					| io::ErrorKind::UnexpectedEof
+			),
+			#[cfg(feature = "images")]
+			DmiError::Image(error) => !matches!(
+				error,
+				image::error::ImageError::Limits(_) | image::error::ImageError::IoError(_)
+			),
+			_ => true,
+		}
+	}
+}
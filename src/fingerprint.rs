@@ -0,0 +1,161 @@
+use crate::chunk::{ChunkType, MAX_DATA_LENGTH};
+use crate::error::DmiError;
+use crate::hash::Fnv1aHasher;
+use crate::ihdr::IhdrData;
+use crate::reader::ChunkReader;
+use crate::PNG_HEADER;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// A cheap summary of a DMI file, see [quick_fingerprint].
+///
+/// Two fingerprints being equal does not guarantee the pixels are: it only tells that the
+/// dimensions, the metadata and the size of the compressed image data are all the same, which is
+/// what build systems need to decide if derived artifacts are stale.
+///
+/// The other chunks are left out: a change to the palette (PLTE), the transparency (tRNS) or the
+/// tEXt and iTXt chunks, metadata included when it is stored there, goes unnoticed, as does image
+/// data rewritten to the same length. It is no content check.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Fingerprint {
+	/// Dimensions of the whole sheet, from the IHDR chunk.
+	pub width: u32,
+	pub height: u32,
+	/// [Fnv1aHasher] hash of the zTXt chunks, as stored.
+	pub metadata_hash: u64,
+	/// Total length of the IDAT chunks' data.
+	pub idat_length: u64,
+}
+
+/// Written as `{width}x{height}-{metadata_hash}-{idat_length}`, the hash in hexadecimal.
+impl std::fmt::Display for Fingerprint {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(
+			f,
+			"{}x{}-{:016x}-{}",
+			self.width, self.height, self.metadata_hash, self.idat_length
+		)
+	}
+}
+
+/// Fingerprints the DMI file at `path` without loading it.
+///
+/// Only the IHDR and zTXt chunks are read. Every other chunk, starting with the image data, is
+/// skipped over without being read, and nothing is decompressed.
+pub fn quick_fingerprint<P: AsRef<Path>>(path: P) -> Result<Fingerprint, DmiError> {
+	let mut reader = BufReader::new(File::open(path)?);
+
+	let mut header = [0u8; 8];
+	reader.read_exact(&mut header)?;
+	if header != PNG_HEADER {
+		return Err(DmiError::Generic(format!(
+			"PNG header mismatch (expected {:#?}, found {:#?})",
+			PNG_HEADER, header
+		)));
+	}
+
+	let mut dimensions = None;
+	let mut hasher = Fnv1aHasher::new();
+	let mut idat_length = 0;
+	loop {
+		let mut chunk_header = [0u8; 8];
+		reader.read_exact(&mut chunk_header)?;
+		let data_length = u32::from_be_bytes([
+			chunk_header[0],
+			chunk_header[1],
+			chunk_header[2],
+			chunk_header[3],
+		]);
+		let chunk_type = ChunkType::from([
+			chunk_header[4],
+			chunk_header[5],
+			chunk_header[6],
+			chunk_header[7],
+		]);
+
+		if data_length > MAX_DATA_LENGTH {
+			return Err(DmiError::Generic(format!(
+				"Failed to fingerprint DMI. Chunk {chunk_type} states a length of {data_length} bytes, more than the maximum of {MAX_DATA_LENGTH}."
+			)));
+		}
+
+		match chunk_type {
+			ChunkType::Ihdr | ChunkType::Ztxt => {
+				// Read through take() so that a forged length cannot make us allocate more than the
+				// file holds.
+				let mut data = vec![];
+				(&mut reader)
+					.take(data_length as u64)
+					.read_to_end(&mut data)?;
+				if data.len() < data_length as usize {
+					return Err(DmiError::Generic(format!(
+						"Failed to fingerprint DMI. Chunk {chunk_type} states {data_length} bytes of data, only {} are left.",
+						data.len()
+					)));
+				}
+				if chunk_type == ChunkType::Ztxt {
+					hasher.update(&data);
+				} else if let Ok(ihdr) = IhdrData::try_from(&data[..]) {
+					dimensions = Some((ihdr.width, ihdr.height));
+				}
+				// CRC.
+				reader.seek_relative(4)?;
+			}
+			ChunkType::Iend => break,
+			_ => {
+				if chunk_type == ChunkType::Idat {
+					idat_length += data_length as u64;
+				}
+				reader.seek_relative(data_length as i64 + 4)?;
+			}
+		}
+	}
+
+	fingerprint(dimensions, hasher, idat_length)
+}
+
+/// Like [quick_fingerprint], from a reader that cannot seek, such as a network stream or a
+/// decompressor. The chunks other than IHDR and zTXt are still not kept, but their bytes have to be
+/// read through to skip them, see [ChunkReader::skip_data].
+pub fn quick_fingerprint_reader<R: Read>(reader: R) -> Result<Fingerprint, DmiError> {
+	let mut chunks = ChunkReader::new(reader)?;
+	let mut dimensions = None;
+	let mut hasher = Fnv1aHasher::new();
+	let mut idat_length = 0;
+	while let Some(span) = chunks.next_header()? {
+		match ChunkType::from(span.chunk_type) {
+			ChunkType::Ihdr => {
+				if let Ok(ihdr) = IhdrData::try_from(&chunks.read_data()?) {
+					dimensions = Some((ihdr.width, ihdr.height));
+				}
+			}
+			ChunkType::Ztxt => hasher.update(&chunks.read_data()?.data),
+			chunk_type => {
+				if chunk_type == ChunkType::Idat {
+					idat_length += span.data_length();
+				}
+				chunks.skip_data()?;
+			}
+		}
+	}
+	fingerprint(dimensions, hasher, idat_length)
+}
+
+fn fingerprint(
+	dimensions: Option<(u32, u32)>,
+	hasher: Fnv1aHasher,
+	idat_length: u64,
+) -> Result<Fingerprint, DmiError> {
+	let Some((width, height)) = dimensions else {
+		return Err(DmiError::Generic(
+			"Failed to fingerprint DMI. No valid IHDR chunk found.".to_string(),
+		));
+	};
+	Ok(Fingerprint {
+		width,
+		height,
+		metadata_hash: hasher.finish(),
+		idat_length,
+	})
+}
@@ -0,0 +1,184 @@
+use crate::error::DmiError;
+use crate::icon::{DirIndex, Frame, Icon, IconState};
+use crate::naming::NamingScheme;
+use image::{DynamicImage, GenericImageView};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+impl Icon {
+	/// Writes every sprite of the icon as its own PNG file in `dir_path`, which is created if needed.
+	/// Files are named by `naming`, with a `.png` extension. Returns the paths written, in state, frame
+	/// then dir order.
+	///
+	/// Only the pixels are exported: delays and the other state settings are not part of the files.
+	/// Fails before writing anything if two sprites would share a file name, or if a name is not a
+	/// plain file name, such as state names containing a `/`.
+	pub fn export_frames<P: AsRef<Path>>(
+		&self,
+		dir_path: P,
+		naming: &NamingScheme,
+	) -> Result<Vec<PathBuf>, DmiError> {
+		let dir_path = dir_path.as_ref();
+		let mut sprites = vec![];
+		let mut file_names = HashSet::new();
+
+		for icon_state in &self.states {
+			for frame in (0..icon_state.frames).filter_map(Frame::from_index) {
+				for dir in (0..icon_state.dirs as usize).filter_map(DirIndex::new) {
+					let file_name = format!("{}.png", naming.name(&icon_state.name, &dir.dir(), frame));
+					if Path::new(&file_name).file_name() != Some(file_name.as_ref()) {
+						return Err(DmiError::Generic(format!(
+							"Error exporting frames: {file_name:?} is not a valid file name."
+						)));
+					}
+					if !file_names.insert(file_name.clone()) {
+						return Err(DmiError::Generic(format!(
+							"Error exporting frames: more than one sprite would be written to {file_name:?}."
+						)));
+					}
+					sprites.push((dir_path.join(file_name), icon_state.image(dir, frame)?));
+				}
+			}
+		}
+
+		fs::create_dir_all(dir_path)?;
+		let mut written = Vec::with_capacity(sprites.len());
+		for (path, image) in sprites {
+			image.save_with_format(&path, image::ImageFormat::Png)?;
+			written.push(path);
+		}
+		Ok(written)
+	}
+
+	/// Assembles an icon out of a directory of PNG files named by `naming`, the inverse of
+	/// [Icon::export_frames]. Files that are not PNGs or do not follow the scheme are ignored.
+	///
+	/// States are ordered by name. Animated states get a delay of one tick per frame. Fails if the
+	/// sprites do not all share the same dimensions, if a state does not have 1, 4 or 8 dirs, or if a
+	/// frame is missing for any of its dirs.
+	pub fn import_frames<P: AsRef<Path>>(
+		dir_path: P,
+		naming: &NamingScheme,
+	) -> Result<Icon, DmiError> {
+		let mut found = Sprites::new();
+		let mut dimensions = None;
+
+		let mut paths = vec![];
+		for entry in fs::read_dir(dir_path)? {
+			paths.push(entry?.path());
+		}
+		paths.sort();
+
+		for path in paths {
+			if !path
+				.extension()
+				.is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+			{
+				continue;
+			}
+			let Some(sprite_name) = path
+				.file_stem()
+				.and_then(|stem| stem.to_str())
+				.and_then(|stem| naming.parse(stem))
+			else {
+				continue;
+			};
+			let Some(dir) = DirIndex::of(&sprite_name.dir) else {
+				continue;
+			};
+
+			let image = image::open(&path)?;
+			match dimensions {
+				None => dimensions = Some(image.dimensions()),
+				Some((width, height)) if (width, height) != image.dimensions() => {
+					return Err(DmiError::Generic(format!(
+						"Error importing frames: {:?} is {}x{}, other sprites are {}x{}.",
+						path,
+						image.width(),
+						image.height(),
+						width,
+						height
+					)))
+				}
+				Some(_) => (),
+			}
+			found
+				.entry(sprite_name.state)
+				.or_default()
+				.insert((sprite_name.frame, dir), (image, 1.0));
+		}
+
+		let Some((width, height)) = dimensions else {
+			return Err(DmiError::Generic(
+				"Error importing frames: no sprite found following the naming scheme.".to_string(),
+			));
+		};
+
+		let states = states_from_sprites(found)?;
+		Ok(Icon {
+			width,
+			height,
+			states,
+			..Default::default()
+		})
+	}
+}
+
+/// Sprites found in separate files, by state name then frame and dir, with their delay in ticks.
+pub(crate) type Sprites = BTreeMap<String, BTreeMap<(Frame, DirIndex), (DynamicImage, f32)>>;
+
+/// Builds a state out of each entry of `found`, in name order. The delay of each frame is taken
+/// from its first dir. Fails if a state does not have 1, 4 or 8 dirs, or if a frame is missing for
+/// any of its dirs.
+pub(crate) fn states_from_sprites(found: Sprites) -> Result<Vec<IconState>, DmiError> {
+	let mut states = vec![];
+	for (name, mut sprites) in found {
+		let dirs = sprites
+			.keys()
+			.map(|(_, dir)| dir.get() + 1)
+			.max()
+			.unwrap_or(1);
+		let frames = sprites
+			.keys()
+			.map(|(frame, _)| frame.get())
+			.max()
+			.unwrap_or(1);
+		if !matches!(dirs, 1 | 4 | 8) {
+			return Err(DmiError::IconState(format!(
+				"Sprites of icon_state \"{name}\" span {dirs} dirs, should be 1, 4 or 8"
+			)));
+		}
+
+		let mut images = Vec::with_capacity(dirs * frames as usize);
+		let mut delay = Vec::with_capacity(frames as usize);
+		for frame in (0..frames).filter_map(Frame::from_index) {
+			for dir in (0..dirs).filter_map(DirIndex::new) {
+				match sprites.remove(&(frame, dir)) {
+					Some((image, frame_delay)) => {
+						if dir.get() == 0 {
+							delay.push(frame_delay);
+						}
+						images.push(image);
+					}
+					None => {
+						return Err(DmiError::IconState(format!(
+							"icon_state \"{name}\" is missing frame {frame} facing {}",
+							dir.dir()
+						)))
+					}
+				}
+			}
+		}
+
+		states.push(IconState {
+			name,
+			dirs: dirs as u8,
+			frames,
+			images,
+			delay: (frames > 1).then_some(delay),
+			..Default::default()
+		});
+	}
+	Ok(states)
+}
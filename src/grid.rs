@@ -0,0 +1,139 @@
+use crate::dirs::Dirs;
+use crate::error::DmiError;
+use crate::icon::{DirIndex, Frame, IconState, SpritePositions};
+use image::DynamicImage;
+
+/// The sprites of a state along with their layout, addressed by dir and frame rather than by
+/// position within a flat vector. A grid always holds one sprite for every dir of every frame.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FrameGrid {
+	dirs: u8,
+	frames: u32,
+	images: Vec<DynamicImage>,
+}
+
+impl FrameGrid {
+	/// Builds a grid out of `images` laid out like [IconState::images]: every dir of frame 1, then
+	/// every dir of frame 2, and so on. Fails if `dirs` is not 1, 4 or 8, or if there is not exactly
+	/// one image per dir and frame.
+	pub fn new(dirs: u8, frames: u32, images: Vec<DynamicImage>) -> Result<FrameGrid, DmiError> {
+		if !matches!(dirs, 1 | 4 | 8) {
+			return Err(DmiError::Generic(format!(
+				"Error building frame grid: invalid number of dirs ({dirs}), expected 1, 4 or 8."
+			)));
+		}
+		if frames == 0 || images.len() as u64 != dirs as u64 * frames as u64 {
+			return Err(DmiError::Generic(format!(
+				"Error building frame grid: {} images for {dirs} dirs and {frames} frames.",
+				images.len()
+			)));
+		}
+		Ok(FrameGrid {
+			dirs,
+			frames,
+			images,
+		})
+	}
+
+	/// A grid of `dirs` dirs and `frames` frames, every sprite a copy of `image`.
+	pub fn filled(dirs: u8, frames: u32, image: DynamicImage) -> Result<FrameGrid, DmiError> {
+		let images = vec![image; dirs as usize * frames as usize];
+		FrameGrid::new(dirs, frames, images)
+	}
+
+	pub fn dirs(&self) -> u8 {
+		self.dirs
+	}
+
+	pub fn frames(&self) -> u32 {
+		self.frames
+	}
+
+	/// The sprite for `dir` at `frame`. None if the grid has no such dir or frame.
+	pub fn get(&self, dir: DirIndex, frame: Frame) -> Option<&DynamicImage> {
+		let index = self.index(dir, frame)?;
+		self.images.get(index)
+	}
+
+	/// The sprite for `dir` at `frame`, mutably. None if the grid has no such dir or frame.
+	pub fn get_mut(&mut self, dir: DirIndex, frame: Frame) -> Option<&mut DynamicImage> {
+		let index = self.index(dir, frame)?;
+		self.images.get_mut(index)
+	}
+
+	/// Every sprite with its dir and frame, in the order of [FrameGrid::images].
+	pub fn iter(&self) -> impl Iterator<Item = (DirIndex, Frame, &DynamicImage)> {
+		self
+			.positions()
+			.zip(&self.images)
+			.map(|((dir, frame), image)| (dir, frame, image))
+	}
+
+	/// The sprites, in the order of [IconState::images].
+	pub fn images(&self) -> &[DynamicImage] {
+		&self.images
+	}
+
+	pub fn into_images(self) -> Vec<DynamicImage> {
+		self.images
+	}
+
+	fn positions(&self) -> SpritePositions {
+		SpritePositions::new(self.dirs, self.frames).expect("grids have 1, 4 or 8 dirs")
+	}
+
+	fn index(&self, dir: DirIndex, frame: Frame) -> Option<usize> {
+		if dir.get() >= self.dirs as usize || frame.get() > self.frames {
+			return None;
+		}
+		Some(frame.index() as usize * self.dirs as usize + dir.get())
+	}
+
+	/// Like [FrameGrid::index], for the `Index` implementations, which panic on missing sprites.
+	fn expect_index(&self, dir: &Dirs, frame: Frame) -> usize {
+		DirIndex::of(dir)
+			.and_then(|dir_index| self.index(dir_index, frame))
+			.unwrap_or_else(|| {
+				panic!(
+					"No sprite facing {dir} at frame {frame} in a grid of {} dirs and {} frames",
+					self.dirs, self.frames
+				)
+			})
+	}
+}
+
+impl std::ops::Index<(Dirs, Frame)> for FrameGrid {
+	type Output = DynamicImage;
+
+	fn index(&self, (dir, frame): (Dirs, Frame)) -> &DynamicImage {
+		&self.images[self.expect_index(&dir, frame)]
+	}
+}
+
+impl std::ops::IndexMut<(Dirs, Frame)> for FrameGrid {
+	fn index_mut(&mut self, (dir, frame): (Dirs, Frame)) -> &mut DynamicImage {
+		let index = self.expect_index(&dir, frame);
+		&mut self.images[index]
+	}
+}
+
+impl IconState {
+	/// A copy of the sprites of the state as a [FrameGrid]. Fails if the state is inconsistent, see
+	/// [FrameGrid::new].
+	pub fn to_grid(&self) -> Result<FrameGrid, DmiError> {
+		FrameGrid::new(self.dirs, self.frames, self.images.clone())
+			.map_err(|error| DmiError::IconState(format!("icon_state \"{}\": {error}", self.name)))
+	}
+
+	/// Replaces the sprites of the state with those of `grid`, updating [IconState::dirs] and
+	/// [IconState::frames] to match. When the number of frames changes, an existing
+	/// [IconState::delay] is cut or extended with delays of 1 tick.
+	pub fn set_grid(&mut self, grid: FrameGrid) {
+		self.dirs = grid.dirs;
+		self.frames = grid.frames;
+		self.images = grid.images;
+		if let Some(delay) = &mut self.delay {
+			delay.resize(self.frames as usize, 1.0);
+		}
+	}
+}
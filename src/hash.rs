@@ -0,0 +1,108 @@
+use crate::{error, RawDmi};
+use sha2::{Digest, Sha256};
+
+/// tEXt keyword under which [RawDmi::stamp_content_hash] stores the hash.
+pub const CONTENT_HASH_KEYWORD: &str = "ContentHash";
+
+/// Prefix naming the algorithm in the stored hash, so that it can be changed without ambiguity.
+const CONTENT_HASH_ALGORITHM: &str = "sha256";
+
+/// SHA-256 digest of the content of a file, see [RawDmi::content_hash].
+pub type ContentHash = [u8; 32];
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64 bits FNV-1a hasher. Unlike the standard library hashers, its output is stable across
+/// platforms and compiler versions, so it can be stored in files.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fnv1aHasher(u64);
+
+impl Fnv1aHasher {
+	pub fn new() -> Fnv1aHasher {
+		Fnv1aHasher(FNV_OFFSET_BASIS)
+	}
+
+	pub fn update(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.0 ^= u64::from(*byte);
+			self.0 = self.0.wrapping_mul(FNV_PRIME);
+		}
+	}
+
+	pub fn finish(&self) -> u64 {
+		self.0
+	}
+}
+
+impl Default for Fnv1aHasher {
+	fn default() -> Self {
+		Fnv1aHasher::new()
+	}
+}
+
+impl RawDmi {
+	/// Hashes the content of the file with SHA-256: the IHDR, PLTE, zTXt and IDAT chunks.
+	/// Ancillary chunks, including a previously stamped hash, do not take part.
+	pub fn content_hash(&self) -> Result<ContentHash, error::DmiError> {
+		let mut hasher = Sha256::new();
+		let mut feed_chunk = |chunk_type: &[u8; 4], data: &[u8]| {
+			hasher.update(chunk_type);
+			hasher.update((data.len() as u32).to_be_bytes());
+			hasher.update(data);
+		};
+
+		feed_chunk(&self.chunk_ihdr.chunk_type, &self.chunk_ihdr.data);
+		if let Some(chunk_plte) = &self.chunk_plte {
+			feed_chunk(&chunk_plte.chunk_type, &chunk_plte.data);
+		}
+		if let Some(chunk_ztxt) = &self.chunk_ztxt {
+			let mut ztxt_data = vec![];
+			chunk_ztxt.data.save(&mut ztxt_data)?;
+			feed_chunk(&chunk_ztxt.chunk_type, &ztxt_data);
+		}
+		for chunk in &self.chunks_idat {
+			feed_chunk(&chunk.chunk_type, &chunk.data);
+		}
+
+		Ok(hasher.finalize().into())
+	}
+
+	/// Stores the [RawDmi::content_hash] in a tEXt chunk, replacing any previous one.
+	/// Returns the hash.
+	pub fn stamp_content_hash(&mut self) -> Result<ContentHash, error::DmiError> {
+		let hash = self.content_hash()?;
+		let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+		self.set_text(
+			CONTENT_HASH_KEYWORD,
+			&format!("{}:{}", CONTENT_HASH_ALGORITHM, hex),
+		)?;
+		Ok(hash)
+	}
+
+	/// Gets the hash stored by [RawDmi::stamp_content_hash], without recomputing anything.
+	/// Returns `None` if there is none, or if it was made with an unknown algorithm.
+	pub fn stamped_content_hash(&self) -> Option<ContentHash> {
+		let stamp = self.text(CONTENT_HASH_KEYWORD)?;
+		let hex = stamp
+			.strip_prefix(CONTENT_HASH_ALGORITHM)?
+			.strip_prefix(':')?;
+		if hex.len() != 64 || !hex.is_ascii() {
+			return None;
+		}
+		let mut hash = [0; 32];
+		for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+			*byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+		}
+		Some(hash)
+	}
+
+	/// Checks the stamped hash against the current contents.
+	/// Returns `None` if the file was never stamped, otherwise whether the contents still match.
+	pub fn check_content_hash(&self) -> Result<Option<bool>, error::DmiError> {
+		match self.stamped_content_hash() {
+			Some(stamped) => Ok(Some(stamped == self.content_hash()?)),
+			None => Ok(None),
+		}
+	}
+}
@@ -1,13 +1,37 @@
-use crate::dirs::{Dirs, ALL_DIRS, CARDINAL_DIRS};
-use crate::{error::DmiError, ztxt, RawDmi};
+#[cfg(feature = "images")]
+use crate::chunk::{ChunkPolicy, ChunkType, DuplicateChunkPolicy, RawGenericChunk};
+use crate::dirs::Dirs;
+#[cfg(feature = "images")]
+use crate::encode::{self, OutputColorType};
+#[cfg(feature = "images")]
+use crate::metadata::{quote_state_name, Description, StateMetadata};
+#[cfg(feature = "images")]
+use crate::options::SaveOptions;
+#[cfg(feature = "images")]
+use crate::pattern::StatePattern;
+#[cfg(feature = "images")]
+use crate::source::SourceSheet;
+#[cfg(feature = "images")]
+use crate::text::is_description_text;
+#[cfg(feature = "images")]
+use crate::warning::DmiWarning;
+#[cfg(feature = "images")]
+use crate::{appdata, error::DmiError, RawDmi};
+#[cfg(feature = "images")]
 use image::codecs::png;
+#[cfg(feature = "images")]
 use image::GenericImageView;
-use image::{imageops, DynamicImage};
-use std::collections::HashMap;
+#[cfg(feature = "images")]
+use image::{imageops, DynamicImage, ImageBuffer, ImageDecoder};
+#[cfg(feature = "images")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "images")]
 use std::io::prelude::*;
+#[cfg(feature = "images")]
 use std::io::Cursor;
 use std::num::NonZeroU32;
 
+#[cfg(feature = "images")]
 #[derive(Clone, Default, PartialEq, Debug)]
 /// A DMI Icon, which is a collection of [IconState]s.
 pub struct Icon {
@@ -15,6 +39,15 @@ pub struct Icon {
 	pub width: u32,
 	pub height: u32,
 	pub states: Vec<IconState>,
+	/// Application data payloads, keyed by application. Stored in their own chunks, see [crate::appdata].
+	pub app_data: BTreeMap<String, Vec<u8>>,
+	/// Other ancillary chunks of the loaded file, such as pHYs or private chunks, written back on save.
+	/// Only chunks marked safe to copy are kept, see [ChunkType::is_kept_by_icon].
+	pub ancillary_chunks: Vec<RawGenericChunk>,
+	/// The image data of the loaded file, written back by [Icon::save] while the sprites are
+	/// unchanged. Empty for icons that were not loaded. It takes about as much memory as the file,
+	/// see [SourceSheet].
+	pub source: SourceSheet,
 }
 
 /// The ordering of directions within a DMI file.
@@ -44,95 +77,269 @@ pub fn dir_to_dmi_index(dir: &Dirs) -> Option<usize> {
 	}
 }
 
+/// Given an order within a DMI file, gives its dir, for a state of `dir_count` dirs (inverse of
+/// [dir_to_dmi_index]). None if `index` is not one of the dirs of such a state.
+pub fn dmi_index_to_dir(index: usize, dir_count: u8) -> Option<Dirs> {
+	if index >= dir_count as usize {
+		return None;
+	}
+	DIR_ORDERING.get(index).copied()
+}
+
+/// Gives the dir and frame of the sprite at `index` within [IconState::images], for a state of
+/// `dir_count` dirs. None if `dir_count` is not between 1 and 8, or if the frame number would not
+/// fit a `u32`.
+pub fn image_position(index: usize, dir_count: u8) -> Option<(DirIndex, Frame)> {
+	if !(1..=DIR_ORDERING.len()).contains(&(dir_count as usize)) {
+		return None;
+	}
+	let dirs = dir_count as usize;
+	let frame = u32::try_from(index / dirs)
+		.ok()
+		.and_then(Frame::from_index)?;
+	Some((DirIndex(index % dirs), frame))
+}
+
+/// Iterator over the dir and frame of each sprite of a state, in the order of [IconState::images]:
+/// every dir of frame 1, then every dir of frame 2, and so on. Zip it with the images to walk them
+/// without deriving positions by hand.
+#[derive(Clone, Debug)]
+pub struct SpritePositions {
+	dirs: u8,
+	next: usize,
+	len: usize,
+}
+
+impl SpritePositions {
+	/// The positions of a state of `dirs` dirs and `frames` frames. None if `dirs` is not between 1
+	/// and 8.
+	pub fn new(dirs: u8, frames: u32) -> Option<SpritePositions> {
+		image_position(0, dirs)?;
+		Some(SpritePositions {
+			dirs,
+			next: 0,
+			len: dirs as usize * frames as usize,
+		})
+	}
+}
+
+impl Iterator for SpritePositions {
+	type Item = (DirIndex, Frame);
+
+	fn next(&mut self) -> Option<(DirIndex, Frame)> {
+		if self.next >= self.len {
+			return None;
+		}
+		let position = image_position(self.next, self.dirs);
+		self.next += 1;
+		position
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let remaining = self.len - self.next;
+		(remaining, Some(remaining))
+	}
+}
+
+impl ExactSizeIterator for SpritePositions {}
+
+/// A frame number as DMI counts them: the first frame of an animation is frame 1.
+///
+/// Kept apart from the zero-based indices of [IconState::images] so the two cannot be mixed up.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct Frame(u32);
+
+impl Frame {
+	/// The first frame of every state.
+	pub const FIRST: Frame = Frame(1);
+
+	/// Returns None for 0, which is not a valid frame number.
+	pub fn new(frame: u32) -> Option<Frame> {
+		match frame {
+			0 => None,
+			frame => Some(Frame(frame)),
+		}
+	}
+
+	/// The frame at a zero-based position within an animation. Returns None for `u32::MAX`, whose
+	/// frame number would not fit a `u32`.
+	pub fn from_index(index: u32) -> Option<Frame> {
+		index.checked_add(1).map(Frame)
+	}
+
+	/// The one-based frame number.
+	pub fn get(self) -> u32 {
+		self.0
+	}
+
+	/// The zero-based position of this frame within an animation.
+	pub fn index(self) -> u32 {
+		self.0 - 1
+	}
+}
+
+impl std::fmt::Display for Frame {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// The zero-based position of a dir within [DIR_ORDERING], which is how dirs are laid out in a DMI.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct DirIndex(usize);
+
+impl DirIndex {
+	/// Returns None for dirs that are not part of [DIR_ORDERING], such as combinations of opposite dirs.
+	pub fn of(dir: &Dirs) -> Option<DirIndex> {
+		dir_to_dmi_index(dir).map(DirIndex)
+	}
+
+	/// Returns None if `index` is past the eight dirs of [DIR_ORDERING].
+	pub fn new(index: usize) -> Option<DirIndex> {
+		(index < DIR_ORDERING.len()).then_some(DirIndex(index))
+	}
+
+	pub fn get(self) -> usize {
+		self.0
+	}
+
+	/// The dir at this position.
+	pub fn dir(self) -> Dirs {
+		DIR_ORDERING[self.0]
+	}
+}
+
+impl std::fmt::Display for DirIndex {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// Gives the number of columns and rows of sprites used by [Icon::save] to lay out `sprite_count` sprites.
+pub fn sheet_layout(sprite_count: usize) -> (u32, u32) {
+	// We try to make a square png as output
+	let states_rooted = (sprite_count as f64).sqrt().ceil();
+	// Then if it turns out we would have empty rows, we remove them
+	let columns = states_rooted as u32;
+	let rows = ((sprite_count as f64) / states_rooted).ceil() as u32;
+	(columns, rows)
+}
+
+/// Gets a state by name like [Icon::get_state], panicking if there is none.
+#[cfg(feature = "images")]
+impl std::ops::Index<&str> for Icon {
+	type Output = IconState;
+
+	fn index(&self, name: &str) -> &IconState {
+		self
+			.get_state(name)
+			.unwrap_or_else(|| panic!("No icon_state named \"{name}\""))
+	}
+}
+
+/// Loads a whole file held in memory, like [Icon::load].
+#[cfg(feature = "images")]
+impl TryFrom<&[u8]> for Icon {
+	type Error = DmiError;
+
+	fn try_from(bytes: &[u8]) -> Result<Icon, DmiError> {
+		Icon::load_internal(RawDmi::try_from(bytes)?, &mut DecodeBuffers::default())
+	}
+}
+
+#[cfg(all(feature = "images", feature = "bytes"))]
+impl TryFrom<bytes::Bytes> for Icon {
+	type Error = DmiError;
+
+	fn try_from(bytes: bytes::Bytes) -> Result<Icon, DmiError> {
+		Icon::try_from(&bytes[..])
+	}
+}
+
+#[cfg(feature = "images")]
+impl std::fmt::Display for Icon {
+	/// Writes a summary of the icon: its dimensions and, on the following lines, each of its states.
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(
+			f,
+			"DMI v{}, {}x{}, {} state{}",
+			self.version,
+			self.width,
+			self.height,
+			self.states.len(),
+			if self.states.len() == 1 { "" } else { "s" }
+		)?;
+		for icon_state in &self.states {
+			write!(f, "\n\t{}", icon_state)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(feature = "images")]
 impl Icon {
 	pub fn load<R: Read>(reader: R) -> Result<Icon, DmiError> {
 		let raw_dmi = RawDmi::load(reader)?;
-		let chunk_ztxt = match &raw_dmi.chunk_ztxt {
-			Some(chunk) => chunk.clone(),
-			None => {
-				return Err(DmiError::Generic(
-					"Error loading icon: no zTXt chunk found.".to_string(),
-				))
-			}
-		};
-		let decompressed_text = chunk_ztxt.data.decode()?;
-		let decompressed_text = String::from_utf8(decompressed_text)?;
-		let mut decompressed_text = decompressed_text.lines();
-
-		let current_line = decompressed_text.next();
-		if current_line != Some("# BEGIN DMI") {
-			return Err(DmiError::Generic(format!(
-				"Error loading icon: no DMI header found. Beginning: {:#?}",
-				current_line
-			)));
-		};
+		Icon::load_internal(raw_dmi, &mut DecodeBuffers::default())
+	}
 
-		let current_line = match decompressed_text.next() {
-			Some(thing) => thing,
-			None => {
-				return Err(DmiError::Generic(
-					"Error loading icon: no version header found.".to_string(),
-				))
-			}
-		};
-		let split_version: Vec<&str> = current_line.split_terminator(" = ").collect();
-		if split_version.len() != 2 || split_version[0] != "version" {
-			return Err(DmiError::Generic(format!(
-				"Error loading icon: improper version header found: {:#?}",
-				split_version
-			)));
-		};
-		let version = split_version[1].to_string();
+	/// Like [Icon::load], but carries on past malformed states, see
+	/// [crate::metadata::DmiMetadata::parse_collecting].
+	/// The icon holds every state that could be loaded, and the errors describe the others.
+	///
+	/// The sprites of a malformed state are skipped when its dirs and frames could still be read.
+	/// When they could not, the position of the following sprites is unknown: loading stops there
+	/// and the remaining states are left out.
+	pub fn load_collecting<R: Read>(reader: R) -> Result<(Icon, Vec<DmiError>), DmiError> {
+		let raw_dmi = RawDmi::load(reader)?;
+		Icon::assemble(raw_dmi, &mut DecodeBuffers::default(), true, None)
+	}
 
-		let current_line = match decompressed_text.next() {
-			Some(thing) => thing,
-			None => {
-				return Err(DmiError::Generic(
-					"Error loading icon: no width found.".to_string(),
-				))
-			}
-		};
-		let split_version: Vec<&str> = current_line.split_terminator(" = ").collect();
-		if split_version.len() != 2 || split_version[0] != "\twidth" {
-			return Err(DmiError::Generic(format!(
-				"Error loading icon: improper width found: {:#?}",
-				split_version
-			)));
-		};
-		let width = split_version[1].parse::<u32>()?;
+	/// Builds an [Icon] out of an already parsed [RawDmi].
+	/// `buffers` is used as scratch space to re-assemble and decode the PNG.
+	pub(crate) fn load_internal(
+		raw_dmi: RawDmi,
+		buffers: &mut DecodeBuffers,
+	) -> Result<Icon, DmiError> {
+		let (icon, _) = Icon::assemble(raw_dmi, buffers, false, None)?;
+		Ok(icon)
+	}
 
-		let current_line = match decompressed_text.next() {
-			Some(thing) => thing,
-			None => {
-				return Err(DmiError::Generic(
-					"Error loading icon: no height found.".to_string(),
-				))
-			}
-		};
-		let split_version: Vec<&str> = current_line.split_terminator(" = ").collect();
-		if split_version.len() != 2 || split_version[0] != "\theight" {
-			return Err(DmiError::Generic(format!(
-				"Error loading icon: improper height found: {:#?}",
-				split_version
-			)));
-		};
-		let height = split_version[1].parse::<u32>()?;
+	/// Slices the sheet of `raw_dmi` into states. Errors are returned as soon as they are met, unless
+	/// `collect_errors` is set, in which case the errors concerning single states are collected.
+	/// With `quirks`, nonstandard metadata is accepted and reported there, see
+	/// [crate::quirks::Quirk].
+	pub(crate) fn assemble(
+		raw_dmi: RawDmi,
+		buffers: &mut DecodeBuffers,
+		collect_errors: bool,
+		quirks: Option<&mut Vec<DmiWarning>>,
+	) -> Result<(Icon, Vec<DmiError>), DmiError> {
+		// Sprites are only in sheet order when no state is skipped or reshaped.
+		let keep_source = !collect_errors && quirks.is_none();
+		let description = Description::from_raw_dmi(&raw_dmi, quirks)?;
+		let width = description.width;
+		let height = description.height;
 
-		if width == 0 || height == 0 {
-			return Err(DmiError::Generic(format!(
-				"Error loading icon: invalid width ({}) / height ({}) values.",
-				width, height
-			)));
-		};
+		let mut errors = vec![];
+		let mut blocks = Vec::with_capacity(description.blocks.len());
+		for block in description.blocks {
+			match block.state {
+				Ok(state_metadata) => blocks.push((Some(state_metadata), block.image_count)),
+				Err(error) if collect_errors => {
+					errors.push(error);
+					blocks.push((None, block.image_count));
+				}
+				Err(error) => return Err(error),
+			}
+		}
 
 		// Image time.
-		let mut reader = vec![];
-		raw_dmi.save(&mut reader)?;
-		let base_image = image::load_from_memory_with_format(&reader, image::ImageFormat::Png)?;
-
-		let dimensions = base_image.dimensions();
-		let img_width = dimensions.0;
-		let img_height = dimensions.1;
+		buffers.png_bytes.clear();
+		raw_dmi.save(&mut buffers.png_bytes)?;
+		let base_image = decode_sheet(&buffers.png_bytes, &mut buffers.pixels)?;
+		let img_width = base_image.width;
+		let img_height = base_image.height;
 
 		if img_width == 0 || img_height == 0 || img_width % width != 0 || img_height % height != 0 {
 			return Err(DmiError::Generic(format!("Error loading icon: invalid image width ({}) / height ({}) values. Missmatch with metadata width ({}) / height ({}).", img_width, img_height, width, height)));
@@ -140,171 +347,189 @@ impl Icon {
 
 		let width_in_states = img_width / width;
 		let height_in_states = img_height / height;
-		let max_possible_states = width_in_states * height_in_states;
+		let max_possible_states = width_in_states as u64 * height_in_states as u64;
 
-		let mut index = 0;
-
-		let mut current_line = match decompressed_text.next() {
-			Some(thing) => thing,
-			None => {
-				return Err(DmiError::Generic(
-					"Error loading icon: no DMI trailer nor states found.".to_string(),
-				))
-			}
-		};
+		let mut index: u32 = 0;
 
 		let mut states = vec![];
 
-		loop {
-			if current_line.contains("# END DMI") {
+		for (state_metadata, image_count) in blocks {
+			let Some(image_count) = image_count else {
+				errors.push(DmiError::Generic(
+					"Error loading icon: the sprites of the states following a malformed state cannot be located.".to_string(),
+				));
 				break;
 			};
-
-			let split_version: Vec<&str> = current_line.split_terminator(" = ").collect();
-			if split_version.len() != 2 || split_version[0] != "state" {
-				return Err(DmiError::Generic(format!(
-					"Error loading icon: improper state found: {:#?}",
-					split_version
-				)));
-			};
-
-			let name = split_version[1].as_bytes();
-			if !name.starts_with(b"\"") || !name.ends_with(b"\"") {
-				return Err(DmiError::Generic(format!("Error loading icon: invalid name icon_state found in metadata, should be preceded and succeeded by double-quotes (\"): {:#?}", name)));
-			};
-			let name = match name.len() {
-				0 | 1 => {
-					return Err(DmiError::Generic(format!(
-						"Error loading icon: invalid name icon_state found in metadata, improper size: {:#?}",
-						name
-					)))
+			let required_states = index as u64 + image_count;
+			if required_states > max_possible_states {
+				let error = DmiError::TileSlicing {
+					image_width: img_width,
+					image_height: img_height,
+					tile_width: width,
+					tile_height: height,
+					required: required_states,
+				};
+				if !collect_errors {
+					return Err(error);
 				}
-				2 => String::new(), //Only the quotes, empty name otherwise.
-				length => String::from_utf8(name[1..(length - 1)].to_vec())?, //Hacky way to trim. Blame the cool methods being nightly experimental.
+				errors.push(error);
+				break;
 			};
-
-			let mut dirs = None;
-			let mut frames = None;
-			let mut delay = None;
-			let mut loop_flag = Looping::Indefinitely;
-			let mut rewind = false;
-			let mut movement = false;
-			let mut hotspot = None;
-			let mut unknown_settings = None;
-
-			loop {
-				current_line = match decompressed_text.next() {
-					Some(thing) => thing,
-					None => {
-						return Err(DmiError::Generic(
-							"Error loading icon: no DMI trailer found.".to_string(),
-						))
-					}
-				};
-
-				if current_line.contains("# END DMI") || current_line.contains("state = \"") {
-					break;
-				};
-				let split_version: Vec<&str> = current_line.split_terminator(" = ").collect();
-				if split_version.len() != 2 {
-					return Err(DmiError::Generic(format!(
-						"Error loading icon: improper state found: {:#?}",
-						split_version
-					)));
-				};
-
-				match split_version[0] {
-					"\tdirs" => dirs = Some(split_version[1].parse::<u8>()?),
-					"\tframes" => frames = Some(split_version[1].parse::<u32>()?),
-					"\tdelay" => {
-						let mut delay_vector = vec![];
-						let text_delays = split_version[1].split_terminator(',');
-						for text_entry in text_delays {
-							delay_vector.push(text_entry.parse::<f32>()?);
-						}
-						delay = Some(delay_vector);
-					}
-					"\tloop" => loop_flag = Looping::new(split_version[1].parse::<u32>()?),
-					"\trewind" => rewind = split_version[1].parse::<u8>()? != 0,
-					"\tmovement" => movement = split_version[1].parse::<u8>()? != 0,
-					"\thotspot" => {
-						let text_coordinates: Vec<&str> = split_version[1].split_terminator(',').collect();
-						// Hotspot includes a mysterious 3rd parameter that always seems to be 1.
-						if text_coordinates.len() != 3 {
-							return Err(DmiError::Generic(format!(
-								"Error loading icon: improper hotspot found: {:#?}",
-								split_version
-							)));
-						};
-						hotspot = Some(Hotspot {
-							x: text_coordinates[0].parse::<u32>()?,
-							y: text_coordinates[1].parse::<u32>()?,
-						});
-					}
-					_ => {
-						unknown_settings = match unknown_settings {
-							None => {
-								let mut new_map = HashMap::new();
-								new_map.insert(split_version[0].to_string(), split_version[1].to_string());
-								Some(new_map)
-							}
-							Some(mut thing) => {
-								thing.insert(split_version[0].to_string(), split_version[1].to_string());
-								Some(thing)
-							}
-						};
-					}
-				};
-			}
-
-			if dirs.is_none() || frames.is_none() {
-				return Err(DmiError::Generic(format!(
-					"Error loading icon: state lacks essential settings. dirs: {:#?}. frames: {:#?}.",
-					dirs, frames
-				)));
+			// Sheets of more than u32::MAX sprites fit the check above, but not the sprite indices.
+			let Some(next_index) = u32::try_from(image_count)
+				.ok()
+				.and_then(|image_count| index.checked_add(image_count))
+			else {
+				let error = DmiError::Generic(format!(
+					"Error loading icon: the sheet holds more than {} sprites.",
+					u32::MAX
+				));
+				if !collect_errors {
+					return Err(error);
+				}
+				errors.push(error);
+				break;
 			};
-			let dirs = dirs.unwrap();
-			let frames = frames.unwrap();
 
-			if index + (dirs as u32 * frames) > max_possible_states {
-				return Err(DmiError::Generic(format!("Error loading icon: metadata settings exceeded the maximum number of states possible ({}).", max_possible_states)));
+			let Some(state_metadata) = state_metadata else {
+				index = next_index;
+				continue;
 			};
 
 			let mut images = vec![];
 
-			for _frame in 0..frames {
-				for _dir in 0..dirs {
+			// Rounded dirs (see Quirk::DirsRounded) differ from the number of sprites in the sheet
+			// per frame: missing dirs are copies of the first one, extra ones are dropped.
+			let dirs = state_metadata.dirs as u64;
+			let sheet_dirs = match state_metadata.frames {
+				0 => dirs,
+				frames => image_count / frames as u64,
+			};
+			for _frame in 0..state_metadata.frames {
+				let first_dir = images.len();
+				for dir in 0..sheet_dirs {
 					let x = (index % width_in_states) * width;
 					//This operation rounds towards zero, truncating any fractional part of the exact result, essentially a floor() function.
 					let y = (index / width_in_states) * height;
-					images.push(base_image.crop_imm(x, y, width, height));
+					if dir < dirs {
+						images.push(base_image.crop(x, y, width, height));
+					}
 					index += 1;
 				}
+				for _dir in sheet_dirs..dirs {
+					images.push(match images.get(first_dir) {
+						Some(image) => image.clone(),
+						None => DynamicImage::new_rgba8(width, height),
+					});
+				}
 			}
 
-			states.push(IconState {
-				name,
-				dirs,
-				frames,
-				images,
-				delay,
-				loop_flag,
-				rewind,
-				movement,
-				hotspot,
-				unknown_settings,
-			});
-		}
-
-		Ok(Icon {
-			version: DmiVersion(version),
+			states.push(IconState::from_metadata(state_metadata, images));
+		}
+
+		let source = if keep_source {
+			let sprites = states.iter().flat_map(|icon_state| &icon_state.images);
+			SourceSheet::new(&raw_dmi, width, height, sprites)
+		} else {
+			SourceSheet::default()
+		};
+
+		let mut app_data = BTreeMap::new();
+		let mut ancillary_chunks = vec![];
+		for chunk in raw_dmi.other_chunks.into_iter().flatten() {
+			if chunk.chunk_type == appdata::APP_DATA_TYPE {
+				let (key, data) = appdata::read_app_data_chunk(&chunk)?;
+				app_data.insert(key.to_string(), data.to_vec());
+			} else if is_description_text(&chunk) {
+				// Metadata written as tEXt or iTXt, which saving writes again as configured.
+				continue;
+			} else if ChunkType::from(chunk.chunk_type).is_kept_by_icon() {
+				ancillary_chunks.push(chunk);
+			}
+		}
+
+		let icon = Icon {
+			version: description.version,
 			width,
 			height,
 			states,
-		})
+			app_data,
+			ancillary_chunks,
+			source,
+		};
+		Ok((icon, errors))
+	}
+
+	/// Saves the icon as a DMI file. While the sprites are those of the loaded file, its image data is
+	/// written back as it is rather than encoded again, see [Icon::pixels_modified].
+	pub fn save<W: Write>(&self, writter: &mut W) -> Result<usize, DmiError> {
+		self.save_with(writter, &SaveOptions::default())
+	}
+
+	/// Encodes the sheet into a [RawDmi] as configured by `options`, along with the
+	/// [Icon::ancillary_chunks] that [SaveOptions::chunk_policy] keeps.
+	pub(crate) fn encode_raw_dmi(&self, options: &SaveOptions) -> Result<RawDmi, DmiError> {
+		let (signature, sprites) = self.save_metadata_with(options.hotspot_third_value)?;
+
+		let (cell_width, cell_height) = options.layout.cells(sprites.len());
+		let mut new_png = image::RgbaImage::new(cell_width * self.width, cell_height * self.height);
+
+		for image in sprites.iter().enumerate() {
+			let index = image.0 as u32;
+			let image = image.1;
+			imageops::replace(
+				&mut new_png,
+				*image,
+				(self.width * (index % cell_width)).into(),
+				(self.height * (index / cell_width)).into(),
+			);
+		}
+
+		let mut new_dmi = match options.color_type.for_sheet(&new_png, self.source.format()) {
+			OutputColorType::Rgba8 => {
+				let mut dmi_data = Cursor::new(vec![]);
+				// The default compression of SaveOptions is 'Default' - the actual default for the
+				// library is 'Fast'
+				let encoder = png::PngEncoder::new_with_quality(
+					&mut dmi_data,
+					options.compression.png(),
+					options.filter.png(),
+				);
+				new_png.write_with_encoder(encoder)?;
+				RawDmi::load(&dmi_data.into_inner()[..])?
+			}
+			color_type => encode::encode_sheet(
+				&new_png,
+				&color_type,
+				self.source.format(),
+				options.compression,
+				options.filter,
+			)?,
+		};
+
+		new_dmi
+			.other_chunks
+			.get_or_insert_with(Vec::new)
+			.extend(self.extra_chunks(&options.chunk_policy)?);
+		options.write_metadata(&mut new_dmi, &signature)?;
+		if new_dmi.other_chunks.as_ref().is_some_and(Vec::is_empty) {
+			new_dmi.other_chunks = None;
+		}
+		Ok(new_dmi)
+	}
+
+	/// The zTXt metadata describing the icon, and every sprite in sheet order. Fails if a state's
+	/// images or delays do not match its dirs and frames.
+	pub(crate) fn save_metadata(&self) -> Result<(String, Vec<&DynamicImage>), DmiError> {
+		self.save_metadata_with(true)
 	}
 
-	pub fn save<W: Write>(&self, mut writter: &mut W) -> Result<usize, DmiError> {
+	/// Like [Icon::save_metadata], leaving out the third value of hotspots unless
+	/// `hotspot_third_value` is set, see [SaveOptions::hotspot_third_value].
+	pub(crate) fn save_metadata_with(
+		&self,
+		hotspot_third_value: bool,
+	) -> Result<(String, Vec<&DynamicImage>), DmiError> {
 		let mut sprites = vec![];
 		let mut signature = format!(
 			"# BEGIN DMI\nversion = {}\n\twidth = {}\n\theight = {}\n",
@@ -312,13 +537,19 @@ impl Icon {
 		);
 
 		for icon_state in &self.states {
+			// Names are quoted and escaped, but the description is read line by line.
+			if icon_state.name.contains('\n') {
+				return Err(DmiError::InvalidArgument(format!("Error saving Icon: state name {:?} contains a line break, which cannot be stored in the metadata.", icon_state.name)));
+			}
 			if icon_state.images.len() as u32 != icon_state.dirs as u32 * icon_state.frames {
 				return Err(DmiError::Generic(format!("Error saving Icon: number of images ({}) differs from the stated metadata. Dirs: {}. Frames: {}. Name: \"{}\".", icon_state.images.len(), icon_state.dirs, icon_state.frames, icon_state.name)));
 			};
 
 			signature.push_str(&format!(
-				"state = \"{}\"\n\tdirs = {}\n\tframes = {}\n",
-				icon_state.name, icon_state.dirs, icon_state.frames
+				"state = {}\n\tdirs = {}\n\tframes = {}\n",
+				quote_state_name(&icon_state.name),
+				icon_state.dirs,
+				icon_state.frames
 			));
 
 			if icon_state.frames > 1 {
@@ -344,11 +575,15 @@ impl Icon {
 			};
 
 			if let Some(Hotspot { x, y }) = icon_state.hotspot {
-				signature.push_str(&format!(
-					// Mysterious third parameter here doesn't seem to do anything. Unable to find
-					// any example of it not being 1.
-					"\thotspot = {x},{y},1\n"
-				))
+				if hotspot_third_value {
+					signature.push_str(&format!(
+						// Mysterious third parameter here doesn't seem to do anything. Unable to find
+						// any example of it not being 1.
+						"\thotspot = {x},{y},1\n"
+					))
+				} else {
+					signature.push_str(&format!("\thotspot = {x},{y}\n"))
+				}
 			};
 
 			if let Some(hashmap) = &icon_state.unknown_settings {
@@ -362,40 +597,310 @@ impl Icon {
 
 		signature.push_str("# END DMI\n");
 
-		// We try to make a square png as output
-		let states_rooted = (sprites.len() as f64).sqrt().ceil();
-		// Then if it turns out we would have empty rows, we remove them
-		let cell_width = states_rooted as u32;
-		let cell_height = ((sprites.len() as f64) / states_rooted).ceil() as u32;
-		let mut new_png =
-			image::DynamicImage::new_rgba8(cell_width * self.width, cell_height * self.height);
+		Ok((signature, sprites))
+	}
 
-		for image in sprites.iter().enumerate() {
-			let index = image.0 as u32;
-			let image = image.1;
-			imageops::replace(
-				&mut new_png,
-				*image,
-				(self.width * (index % cell_width)).into(),
-				(self.height * (index / cell_width)).into(),
-			);
+	/// The chunks written after the metadata on save: the [Icon::app_data], then the
+	/// [Icon::ancillary_chunks] that `chunk_policy` keeps.
+	pub(crate) fn extra_chunks(
+		&self,
+		chunk_policy: &ChunkPolicy,
+	) -> Result<Vec<RawGenericChunk>, DmiError> {
+		if let Some(chunk) = self
+			.ancillary_chunks
+			.iter()
+			.find(|chunk| ChunkType::from(chunk.chunk_type).is_critical())
+		{
+			return Err(DmiError::InvalidArgument(format!(
+				"Error saving Icon: critical chunk {} cannot be carried as an ancillary chunk.",
+				ChunkType::from(chunk.chunk_type)
+			)));
 		}
-
-		let mut dmi_data = Cursor::new(vec![]);
-		// Use the 'Default' compression - the actual default for the library is 'Fast'
-		let encoder = png::PngEncoder::new_with_quality(
-			&mut dmi_data,
-			png::CompressionType::Default,
-			png::FilterType::Adaptive,
+		let mut chunks = vec![];
+		for (key, data) in &self.app_data {
+			chunks.push(appdata::create_app_data_chunk(key, data)?);
+		}
+		chunks.extend(
+			self
+				.ancillary_chunks
+				.iter()
+				.filter(|chunk| chunk_policy.keeps(&chunk.chunk_type))
+				.cloned(),
 		);
-		new_png.write_with_encoder(encoder)?;
-		let mut new_dmi = RawDmi::load(&dmi_data.into_inner()[..])?;
+		Ok(chunks)
+	}
+
+	/// The first state named `name`, in file order. See [Icon::get_state_variant] to pick between a
+	/// still and a movement state sharing the name.
+	pub fn get_state(&self, name: &str) -> Option<&IconState> {
+		let index = self.state_index(name)?;
+		Some(&self.states[index])
+	}
+
+	/// Like [Icon::get_state], mutably.
+	pub fn get_state_mut(&mut self, name: &str) -> Option<&mut IconState> {
+		let index = self.state_index(name)?;
+		Some(&mut self.states[index])
+	}
+
+	/// Every state named `name`, in file order. BYOND allows several states to share a name, of
+	/// which [Icon::get_state] returns the first.
+	pub fn all_states_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a IconState> {
+		self.states.iter().filter(move |state| state.name == name)
+	}
+
+	/// The first state named `name` whose [IconState::movement] flag is `movement`. Unlike
+	/// [Icon::get_state], this tells apart the still and movement states sharing a name.
+	pub fn get_state_variant(&self, name: &str, movement: bool) -> Option<&IconState> {
+		self
+			.states
+			.iter()
+			.find(|state| state.name == name && state.movement == movement)
+	}
+
+	/// Like [Icon::get_state_variant], mutably.
+	pub fn get_state_variant_mut(&mut self, name: &str, movement: bool) -> Option<&mut IconState> {
+		self
+			.states
+			.iter_mut()
+			.find(|state| state.name == name && state.movement == movement)
+	}
+
+	/// The name and movement flag of every state that shares both with an earlier state, in order.
+	/// Only the first of such states can ever be displayed by BYOND, so they are usually mistakes,
+	/// unlike a still and a movement state sharing a name.
+	pub fn duplicate_states(&self) -> Vec<(&str, bool)> {
+		let mut seen = std::collections::HashSet::new();
+		self
+			.states
+			.iter()
+			.map(|state| (state.name.as_str(), state.movement))
+			.filter(|key| !seen.insert(*key))
+			.collect()
+	}
+
+	/// Removes and returns the first state named `name`, the one [Icon::get_state] returns. Other
+	/// states sharing the name are kept.
+	pub fn remove_state(&mut self, name: &str) -> Option<IconState> {
+		let index = self.state_index(name)?;
+		Some(self.states.remove(index))
+	}
+
+	/// Replaces the sprite for `dir` at `frame` of the state named `state`, see
+	/// [IconState::set_image]. Fails if there is no such state, or if `image` does not have the
+	/// dimensions of the icon.
+	pub fn set_image(
+		&mut self,
+		state: &str,
+		dir: &Dirs,
+		frame: u32,
+		image: DynamicImage,
+	) -> Result<(), DmiError> {
+		if image.dimensions() != (self.width, self.height) {
+			return Err(DmiError::IconState(format!(
+				"Sprite of {}x{} does not fit the {}x{} icon, for icon_state \"{state}\"",
+				image.width(),
+				image.height(),
+				self.width,
+				self.height
+			)));
+		}
+		match self.get_state_mut(state) {
+			Some(icon_state) => *icon_state.get_image_mut(dir, frame)? = image,
+			None => {
+				return Err(DmiError::IconState(format!(
+					"No icon_state named \"{state}\""
+				)))
+			}
+		}
+		Ok(())
+	}
+
+	/// Every state whose name matches `pattern`, in order. Plain strings are treated as globs:
+	/// `icon.find_states("muzzle_*")`. See [StatePattern] for regular expressions.
+	pub fn find_states<P: Into<StatePattern>>(&self, pattern: P) -> Vec<&IconState> {
+		let pattern = pattern.into();
+		self
+			.states
+			.iter()
+			.filter(|state| pattern.matches(&state.name))
+			.collect()
+	}
+
+	/// Keeps only the states for which `keep` returns true, in order, like [Vec::retain].
+	pub fn retain_states<F: FnMut(&IconState) -> bool>(&mut self, keep: F) {
+		self.states.retain(keep);
+	}
+
+	/// A copy of the icon with only the states named in `names`, keeping their order in the icon.
+	/// States sharing a listed name are all kept.
+	pub fn filtered<S: AsRef<str>>(&self, names: &[S]) -> Icon {
+		Icon {
+			version: self.version.clone(),
+			width: self.width,
+			height: self.height,
+			states: self
+				.states
+				.iter()
+				.filter(|state| names.iter().any(|name| name.as_ref() == state.name))
+				.cloned()
+				.collect(),
+			app_data: self.app_data.clone(),
+			ancillary_chunks: self.ancillary_chunks.clone(),
+			source: SourceSheet::default(),
+		}
+	}
 
-		let new_ztxt = ztxt::create_ztxt_chunk(signature.as_bytes())?;
+	fn state_index(&self, name: &str) -> Option<usize> {
+		self.states.iter().position(|state| state.name == name)
+	}
+
+	/// Rough number of bytes the icon takes in memory: mostly its decoded images, plus its metadata
+	/// and the data of its chunks. Allocator overhead and spare capacity are not counted.
+	pub fn approx_memory_bytes(&self) -> usize {
+		let app_data_bytes: usize = self
+			.app_data
+			.iter()
+			.map(|(key, data)| key.len() + data.len())
+			.sum();
+		let chunk_bytes: usize = self
+			.ancillary_chunks
+			.iter()
+			.map(|chunk| std::mem::size_of::<RawGenericChunk>() + chunk.data.len())
+			.sum();
+		let state_bytes: usize = self.states.iter().map(IconState::approx_memory_bytes).sum();
+		let source_bytes = self.source.approx_memory_bytes();
+		std::mem::size_of::<Icon>() + app_data_bytes + chunk_bytes + state_bytes + source_bytes
+	}
+}
+
+/// Builds an icon out of a parsed file, like [Icon::load].
+#[cfg(feature = "images")]
+impl TryFrom<RawDmi> for Icon {
+	type Error = DmiError;
+
+	fn try_from(raw_dmi: RawDmi) -> Result<Icon, DmiError> {
+		Icon::load_internal(raw_dmi, &mut DecodeBuffers::default())
+	}
+}
+
+/// The file [Icon::save] writes, see [Icon::to_raw_dmi_with].
+#[cfg(feature = "images")]
+impl TryFrom<&Icon> for RawDmi {
+	type Error = DmiError;
+
+	fn try_from(icon: &Icon) -> Result<RawDmi, DmiError> {
+		icon.to_raw_dmi_with(&SaveOptions::default())
+	}
+}
+
+#[cfg(feature = "images")]
+/// Loads many DMI files in a row, reusing its scratch buffers between them.
+///
+/// Scanning an entire icon tree through [Icon::load] allocates fresh buffers for every file, for
+/// the file contents, the re-assembled PNG and its decoded pixels. A single `Decoder` keeps those
+/// around and only grows them when a bigger file comes along. Only the sprites themselves are
+/// allocated for each file.
+#[derive(Clone, Default, Debug)]
+pub struct Decoder {
+	file_bytes: Vec<u8>,
+	buffers: DecodeBuffers,
+}
+
+/// Scratch space of [Icon::assemble], see [Decoder].
+#[cfg(feature = "images")]
+#[derive(Clone, Default, Debug)]
+pub(crate) struct DecodeBuffers {
+	/// The file re-assembled as a PNG with only the chunks the image decoder needs.
+	png_bytes: Vec<u8>,
+	/// The decoded pixels of the whole sheet.
+	pixels: Vec<u8>,
+}
+
+/// The decoded sheet of a file, borrowing the pixels of [DecodeBuffers].
+#[cfg(feature = "images")]
+struct SheetPixels<'a> {
+	color_type: image::ColorType,
+	width: u32,
+	height: u32,
+	pixels: &'a [u8],
+}
+
+#[cfg(feature = "images")]
+impl SheetPixels<'_> {
+	/// Copies the `width`x`height` sprite whose top left corner is at `x`, `y`, which must lie
+	/// within the sheet.
+	fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> DynamicImage {
+		let pixel_size = self.color_type.bytes_per_pixel() as usize;
+		let row_length = width as usize * pixel_size;
+		let mut data = Vec::with_capacity(row_length * height as usize);
+		for row in y..y + height {
+			let start = (row as usize * self.width as usize + x as usize) * pixel_size;
+			data.extend_from_slice(&self.pixels[start..start + row_length]);
+		}
+		let image = match self.color_type {
+			image::ColorType::L8 => {
+				ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+			}
+			image::ColorType::La8 => {
+				ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+			}
+			image::ColorType::Rgb8 => {
+				ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+			}
+			_ => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+		};
+		image.expect("Sprite buffers hold exactly width * height pixels")
+	}
+}
 
-		new_dmi.chunk_ztxt = Some(new_ztxt);
+/// Decodes the PNG in `png_bytes` into `pixels`, converting sheets of 16 bits per channel to 8.
+#[cfg(feature = "images")]
+fn decode_sheet<'a>(
+	png_bytes: &[u8],
+	pixels: &'a mut Vec<u8>,
+) -> Result<SheetPixels<'a>, DmiError> {
+	let decoder = png::PngDecoder::new(std::io::Cursor::new(png_bytes))?;
+	let (width, height) = decoder.dimensions();
+	let color_type = match decoder.color_type() {
+		color_type @ (image::ColorType::L8
+		| image::ColorType::La8
+		| image::ColorType::Rgb8
+		| image::ColorType::Rgba8) => {
+			pixels.resize(decoder.total_bytes() as usize, 0);
+			decoder.read_image(pixels)?;
+			color_type
+		}
+		// Sprites have 8 bits per channel, see LoadOptions::reject_16_bit.
+		_ => {
+			let image = DynamicImage::from_decoder(decoder)?.into_rgba8();
+			pixels.clear();
+			pixels.extend_from_slice(image.as_raw());
+			image::ColorType::Rgba8
+		}
+	};
+	Ok(SheetPixels {
+		color_type,
+		width,
+		height,
+		pixels,
+	})
+}
 
-		new_dmi.save(&mut writter)
+#[cfg(feature = "images")]
+impl Decoder {
+	pub fn new() -> Decoder {
+		Decoder {
+			..Default::default()
+		}
+	}
+
+	/// Equivalent to [Icon::load], using the buffers held by this decoder.
+	pub fn load<R: Read>(&mut self, mut reader: R) -> Result<Icon, DmiError> {
+		self.file_bytes.clear();
+		reader.read_to_end(&mut self.file_bytes)?;
+		let raw_dmi = RawDmi::load_bytes(&self.file_bytes, DuplicateChunkPolicy::default())?;
+		Icon::load_internal(raw_dmi, &mut self.buffers)
 	}
 }
 
@@ -483,6 +988,7 @@ pub struct Hotspot {
 	pub y: u32,
 }
 
+#[cfg(feature = "images")]
 #[derive(Clone, PartialEq, Debug)]
 pub struct IconState {
 	pub name: String,
@@ -497,29 +1003,259 @@ pub struct IconState {
 	pub unknown_settings: Option<HashMap<String, String>>,
 }
 
+#[cfg(feature = "images")]
 impl IconState {
-	/// Gets a specific DynamicImage from `images`, given a dir and frame.
-	/// If the dir or frame is invalid, returns a DmiError.
-	pub fn get_image(&self, dir: &Dirs, frame: u32) -> Result<&DynamicImage, DmiError> {
-		if self.frames < frame {
+	/// Rough number of bytes the state takes in memory, see [Icon::approx_memory_bytes].
+	pub fn approx_memory_bytes(&self) -> usize {
+		let image_bytes: usize = self
+			.images
+			.iter()
+			.map(|image| std::mem::size_of::<DynamicImage>() + image.as_bytes().len())
+			.sum();
+		let delay_bytes = self
+			.delay
+			.as_ref()
+			.map_or(0, |delay| delay.len() * std::mem::size_of::<f32>());
+		let unknown_settings_bytes: usize = self
+			.unknown_settings
+			.iter()
+			.flatten()
+			.map(|(key, value)| key.len() + value.len())
+			.sum();
+		std::mem::size_of::<IconState>()
+			+ self.name.len()
+			+ image_bytes
+			+ delay_bytes
+			+ unknown_settings_bytes
+	}
+
+	/// Builds a state out of its parsed settings and its images.
+	pub fn from_metadata(metadata: StateMetadata, images: Vec<DynamicImage>) -> IconState {
+		let StateMetadata {
+			name,
+			dirs,
+			frames,
+			delay,
+			loop_flag,
+			rewind,
+			movement,
+			hotspot,
+			unknown_settings,
+		} = metadata;
+		IconState {
+			name,
+			dirs,
+			frames,
+			images,
+			delay,
+			loop_flag,
+			rewind,
+			movement,
+			hotspot,
+			unknown_settings,
+		}
+	}
+
+	/// The settings of this state, without its images.
+	pub fn metadata(&self) -> StateMetadata {
+		StateMetadata {
+			name: self.name.clone(),
+			dirs: self.dirs,
+			frames: self.frames,
+			delay: self.delay.clone(),
+			loop_flag: self.loop_flag,
+			rewind: self.rewind,
+			movement: self.movement,
+			hotspot: self.hotspot,
+			unknown_settings: self.unknown_settings.clone(),
+		}
+	}
+
+	/// Checks that this state can be saved as part of an [Icon] of the given sprite dimensions.
+	/// Verifies the name, the number of dirs, that `images` holds exactly `dirs * frames` images of
+	/// `width`x`height`, and that animated states have one delay entry per frame.
+	pub fn validate(&self, width: u32, height: u32) -> Result<(), DmiError> {
+		if self.name.contains('\n') {
+			return Err(DmiError::IconState(format!(
+				"Name \"{}\" contains characters that cannot be stored in DMI metadata",
+				self.name
+			)));
+		}
+
+		if !matches!(self.dirs, 1 | 4 | 8) {
+			return Err(DmiError::IconState(format!(
+				"Invalid number of dirs ({}) for icon_state \"{}\", should be 1, 4 or 8",
+				self.dirs, self.name
+			)));
+		}
+
+		if self.frames == 0 {
+			return Err(DmiError::IconState(format!(
+				"icon_state \"{}\" has no frames",
+				self.name
+			)));
+		}
+
+		if self.images.len() as u64 != self.dirs as u64 * self.frames as u64 {
+			return Err(DmiError::IconState(format!(
+				"Number of images ({}) differs from the stated metadata for icon_state \"{}\". Dirs: {}. Frames: {}.",
+				self.images.len(),
+				self.name,
+				self.dirs,
+				self.frames
+			)));
+		}
+
+		if let Some((index, image)) = self
+			.images
+			.iter()
+			.enumerate()
+			.find(|(_, image)| image.dimensions() != (width, height))
+		{
+			return Err(DmiError::IconState(format!(
+				"Image {index} of icon_state \"{}\" is {}x{}, expected {width}x{height}",
+				self.name,
+				image.width(),
+				image.height()
+			)));
+		}
+
+		if self.frames > 1 {
+			match &self.delay {
+				Some(delay) if delay.len() as u32 == self.frames => (),
+				Some(delay) => {
+					return Err(DmiError::IconState(format!(
+						"Number of frames ({}) differs from the delay entry ({:3?}) for icon_state \"{}\"",
+						self.frames, delay, self.name
+					)))
+				}
+				None => {
+					return Err(DmiError::IconState(format!(
+						"Number of frames ({}) larger than one without a delay entry for icon_state \"{}\"",
+						self.frames, self.name
+					)))
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Position within `images` of the sprite for `dir` at `frame`.
+	///
+	/// Sprites are stored frame-major, in [DIR_ORDERING] within each frame: every dir of frame 1,
+	/// then every dir of frame 2, and so on. Fails if the state has no such dir or frame.
+	pub fn image_index(&self, dir: DirIndex, frame: Frame) -> Result<usize, DmiError> {
+		if frame.get() > self.frames {
 			return Err(DmiError::IconState(format!(
 				"Specified frame \"{frame}\" is larger than the number of frames ({}) for icon_state \"{}\"",
 				self.frames, self.name
 			)));
 		}
 
-		if (self.dirs == 1 && *dir != Dirs::SOUTH)
-			|| (self.dirs == 4 && !CARDINAL_DIRS.contains(dir))
-			|| (self.dirs == 8 && !ALL_DIRS.contains(dir))
-		{
+		// The first four dirs of DIR_ORDERING are the cardinals, and south comes first.
+		if dir.get() >= self.dirs as usize {
 			return Err(DmiError::IconState(format!(
-				"Dir specified {dir} is not in the set of valid dirs ({} dirs) for icon_state \"{}\"",
+				"Dir specified {} is not in the set of valid dirs ({} dirs) for icon_state \"{}\"",
+				dir.dir(),
+				self.dirs,
+				self.name
+			)));
+		}
+
+		Ok(frame.index() as usize * self.dirs as usize + dir.get())
+	}
+
+	/// The dir and frame of each sprite, in the order of [IconState::images]. Fails if the state
+	/// does not have between 1 and 8 dirs.
+	pub fn sprite_positions(&self) -> Result<SpritePositions, DmiError> {
+		SpritePositions::new(self.dirs, self.frames).ok_or_else(|| {
+			DmiError::IconState(format!(
+				"Invalid number of dirs ({}) for icon_state \"{}\"",
 				self.dirs, self.name
+			))
+		})
+	}
+
+	/// Gets the sprite for `dir` at `frame`, see [IconState::image_index].
+	pub fn image(&self, dir: DirIndex, frame: Frame) -> Result<&DynamicImage, DmiError> {
+		let image_idx = self.image_index(dir, frame)?;
+		match self.images.get(image_idx) {
+			Some(image) => Ok(image),
+			None => Err(self.out_of_bounds(image_idx, dir, frame)),
+		}
+	}
+
+	/// Gets the sprite for `dir` at `frame` mutably, see [IconState::image_index].
+	pub fn image_mut(&mut self, dir: DirIndex, frame: Frame) -> Result<&mut DynamicImage, DmiError> {
+		let image_idx = self.image_index(dir, frame)?;
+		if image_idx >= self.images.len() {
+			return Err(self.out_of_bounds(image_idx, dir, frame));
+		}
+		Ok(&mut self.images[image_idx])
+	}
+
+	/// Gets every frame of the animation facing `dir`, in the order they are played: from the first
+	/// frame to the last, then, for [IconState::rewind] states, back down to the second one, before
+	/// the animation loops to the first frame again.
+	pub fn frames_for_dir(&self, dir: &Dirs) -> Result<Vec<&DynamicImage>, DmiError> {
+		let dir_index = match DirIndex::of(dir) {
+			Some(dir_index) => dir_index,
+			None => {
+				return Err(DmiError::IconState(format!(
+					"Dir specified {dir} is not a valid dir within DMI ordering! (icon_state: {})",
+					self.name
+				)));
+			}
+		};
+
+		let mut frames = (0..self.frames)
+			.filter_map(Frame::from_index)
+			.map(|frame| self.image(dir_index, frame))
+			.collect::<Result<Vec<_>, DmiError>>()?;
+		if self.rewind && frames.len() > 2 {
+			let inner_frames = frames[1..frames.len() - 1].to_vec();
+			frames.extend(inner_frames.into_iter().rev());
+		}
+		Ok(frames)
+	}
+
+	/// Gets a specific DynamicImage from `images`, given a dir and frame.
+	/// Frames start at 1. If the dir or frame is invalid, returns a DmiError.
+	pub fn get_image(&self, dir: &Dirs, frame: u32) -> Result<&DynamicImage, DmiError> {
+		let (dir_index, frame) = self.position_of(dir, frame)?;
+		self.image(dir_index, frame)
+	}
+
+	/// Like [IconState::get_image], mutably.
+	pub fn get_image_mut(&mut self, dir: &Dirs, frame: u32) -> Result<&mut DynamicImage, DmiError> {
+		let (dir_index, frame) = self.position_of(dir, frame)?;
+		self.image_mut(dir_index, frame)
+	}
+
+	/// Replaces the sprite for `dir` at `frame`, frames starting at 1. Fails if the dir or frame is
+	/// invalid, or if `image` does not have the dimensions of the sprite it replaces, which are
+	/// those of the icon. See [Icon::set_image] to check against the icon itself.
+	pub fn set_image(&mut self, dir: &Dirs, frame: u32, image: DynamicImage) -> Result<(), DmiError> {
+		let name = self.name.clone();
+		let sprite = self.get_image_mut(dir, frame)?;
+		if sprite.dimensions() != image.dimensions() {
+			return Err(DmiError::IconState(format!(
+				"Sprite of {}x{} does not fit icon_state \"{name}\", whose sprites are {}x{}",
+				image.width(),
+				image.height(),
+				sprite.width(),
+				sprite.height()
 			)));
 		}
+		*sprite = image;
+		Ok(())
+	}
 
-		let image_idx = match dir_to_dmi_index(dir) {
-			Some(idx) => (idx + 1) * frame as usize - 1,
+	/// Converts the dir and one-based frame number taken by [IconState::get_image].
+	fn position_of(&self, dir: &Dirs, frame: u32) -> Result<(DirIndex, Frame), DmiError> {
+		let dir_index = match DirIndex::of(dir) {
+			Some(dir_index) => dir_index,
 			None => {
 				return Err(DmiError::IconState(format!(
 					"Dir specified {dir} is not a valid dir within DMI ordering! (icon_state: {})",
@@ -528,16 +1264,51 @@ impl IconState {
 			}
 		};
 
-		match self.images.get(image_idx) {
-			Some(image) => Ok(image),
-			None => Err(DmiError::IconState(format!(
-				"Out of bounds index {image_idx} in icon_state \"{}\" (images len: {} dirs: {}, frames: {} - dir: {dir}, frame: {frame})",
-				self.name, self.images.len(), self.dirs, self.frames
-			))),
+		let frame = match Frame::new(frame) {
+			Some(frame) => frame,
+			None => {
+				return Err(DmiError::IconState(format!(
+					"Frame 0 requested for icon_state \"{}\", frames start at 1",
+					self.name
+				)))
+			}
+		};
+
+		Ok((dir_index, frame))
+	}
+
+	fn out_of_bounds(&self, image_idx: usize, dir: DirIndex, frame: Frame) -> DmiError {
+		DmiError::IconState(format!(
+			"Out of bounds index {image_idx} in icon_state \"{}\" (images len: {} dirs: {}, frames: {} - dir: {}, frame: {frame})",
+			self.name,
+			self.images.len(),
+			self.dirs,
+			self.frames,
+			dir.dir()
+		))
+	}
+}
+
+#[cfg(feature = "images")]
+impl std::fmt::Display for IconState {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(
+			f,
+			"\"{}\": {} dir{}, {} frame{}",
+			self.name,
+			self.dirs,
+			if self.dirs == 1 { "" } else { "s" },
+			self.frames,
+			if self.frames == 1 { "" } else { "s" }
+		)?;
+		if self.movement {
+			write!(f, ", movement")?;
 		}
+		Ok(())
 	}
 }
 
+#[cfg(feature = "images")]
 impl Default for IconState {
 	fn default() -> Self {
 		Self {
@@ -556,7 +1327,13 @@ impl Default for IconState {
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
-pub struct DmiVersion(String);
+pub struct DmiVersion(pub(crate) String);
+
+impl std::fmt::Display for DmiVersion {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
 
 impl Default for DmiVersion {
 	fn default() -> Self {
@@ -0,0 +1,101 @@
+use crate::chunk::{RawGenericChunk, IHDR_TYPE};
+use crate::{error, RawDmi};
+
+/// Length of the data of a valid IHDR chunk.
+pub const IHDR_DATA_LENGTH: usize = 13;
+
+/// The fields of an IHDR chunk, parsed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct IhdrData {
+	/// Width of the whole sheet, in pixels.
+	pub width: u32,
+	/// Height of the whole sheet, in pixels.
+	pub height: u32,
+	/// Number of bits per sample, or per palette index for indexed images.
+	pub bit_depth: u8,
+	/// The PNG color type, see [RawDmi::color_type].
+	pub color_type: u8,
+	/// 0 for none, 1 for Adam7 interlacing.
+	pub interlace_method: u8,
+}
+
+/// Parses the data of an IHDR chunk. Fails if it is too short, extra bytes being ignored.
+impl TryFrom<&[u8]> for IhdrData {
+	type Error = error::DmiError;
+
+	fn try_from(data: &[u8]) -> Result<IhdrData, error::DmiError> {
+		let Some(data) = data.get(..IHDR_DATA_LENGTH) else {
+			return Err(error::DmiError::Generic(format!(
+				"IHDR chunk too short: {} bytes of data, expected {}.",
+				data.len(),
+				IHDR_DATA_LENGTH
+			)));
+		};
+		Ok(IhdrData {
+			width: u32::from_be_bytes(data[0..4].try_into().unwrap()),
+			height: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+			bit_depth: data[8],
+			color_type: data[9],
+			interlace_method: data[12],
+		})
+	}
+}
+
+impl TryFrom<&RawGenericChunk> for IhdrData {
+	type Error = error::DmiError;
+
+	fn try_from(chunk: &RawGenericChunk) -> Result<IhdrData, error::DmiError> {
+		if chunk.chunk_type != IHDR_TYPE {
+			return Err(error::DmiError::Generic(format!(
+				"Failed to read IHDR chunk. Wrong type: {:#?}. Expected: {:#?}.",
+				chunk.chunk_type, IHDR_TYPE
+			)));
+		}
+		IhdrData::try_from(&chunk.data[..])
+	}
+}
+
+/// Accessors for the fields of the IHDR chunk, which describes the PNG image as a whole. Each fails
+/// if the chunk is too short to hold the field.
+impl RawDmi {
+	/// Every field of the IHDR chunk at once.
+	pub fn ihdr(&self) -> Result<IhdrData, error::DmiError> {
+		IhdrData::try_from(&self.chunk_ihdr)
+	}
+
+	/// Width of the whole sheet, in pixels.
+	pub fn image_width(&self) -> Result<u32, error::DmiError> {
+		let bytes = self.ihdr_bytes::<4>(0)?;
+		Ok(u32::from_be_bytes(bytes))
+	}
+
+	/// Height of the whole sheet, in pixels.
+	pub fn image_height(&self) -> Result<u32, error::DmiError> {
+		let bytes = self.ihdr_bytes::<4>(4)?;
+		Ok(u32::from_be_bytes(bytes))
+	}
+
+	/// Number of bits per sample, or per palette index for indexed images.
+	pub fn bit_depth(&self) -> Result<u8, error::DmiError> {
+		let [bit_depth] = self.ihdr_bytes::<1>(8)?;
+		Ok(bit_depth)
+	}
+
+	/// The PNG color type: 0 for grayscale, 2 for RGB, 3 for indexed, 4 for grayscale with alpha and
+	/// 6 for RGBA.
+	pub fn color_type(&self) -> Result<u8, error::DmiError> {
+		let [color_type] = self.ihdr_bytes::<1>(9)?;
+		Ok(color_type)
+	}
+
+	fn ihdr_bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N], error::DmiError> {
+		match self.chunk_ihdr.data.get(offset..offset + N) {
+			Some(bytes) => Ok(bytes.try_into().unwrap()),
+			None => Err(error::DmiError::Generic(format!(
+				"IHDR chunk too short: {} bytes of data, expected {}.",
+				self.chunk_ihdr.data.len(),
+				IHDR_DATA_LENGTH
+			))),
+		}
+	}
+}
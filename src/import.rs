@@ -0,0 +1,84 @@
+use crate::error::DmiError;
+use crate::icon::{IconState, Looping};
+use image::{AnimationDecoder, DynamicImage, Frame};
+use std::io::{BufRead, Seek};
+
+/// Length of a BYOND tick in milliseconds. DMI delays are expressed in ticks.
+const TICK_MS: f32 = 100.0;
+
+impl IconState {
+	/// Builds a single dir state out of an animated PNG (APNG), one frame per APNG frame.
+	///
+	/// Frame durations are converted to ticks. Plain PNG files produce a state with a single frame.
+	pub fn from_apng<R: BufRead + Seek>(name: &str, reader: R) -> Result<IconState, DmiError> {
+		let decoder = image::codecs::png::PngDecoder::new(reader)?;
+		if !decoder.is_apng()? {
+			let image = DynamicImage::from_decoder(decoder)?;
+			return IconState::from_animation_frames(
+				name,
+				vec![Frame::new(image.into_rgba8())],
+				Looping::Indefinitely,
+			);
+		}
+		let frames = decoder.apng()?.into_frames().collect_frames()?;
+		IconState::from_animation_frames(name, frames, Looping::Indefinitely)
+	}
+
+	/// Builds a single dir state out of an animated WebP, one frame per WebP frame.
+	///
+	/// Frame durations are converted to ticks and the WebP loop count becomes [IconState::loop_flag].
+	/// Still WebP files produce a state with a single frame.
+	#[cfg(feature = "webp")]
+	pub fn from_webp<R: BufRead + Seek>(name: &str, reader: R) -> Result<IconState, DmiError> {
+		let decoder = image::codecs::webp::WebPDecoder::new(reader)?;
+		if !decoder.has_animation() {
+			let image = DynamicImage::from_decoder(decoder)?;
+			return IconState::from_animation_frames(
+				name,
+				vec![Frame::new(image.into_rgba8())],
+				Looping::Indefinitely,
+			);
+		}
+		let loop_flag = match decoder.loop_count() {
+			image::metadata::LoopCount::Infinite => Looping::Indefinitely,
+			image::metadata::LoopCount::Finite(times) => Looping::NTimes(times),
+		};
+		let frames = decoder.into_frames().collect_frames()?;
+		IconState::from_animation_frames(name, frames, loop_flag)
+	}
+
+	fn from_animation_frames(
+		name: &str,
+		frames: Vec<Frame>,
+		loop_flag: Looping,
+	) -> Result<IconState, DmiError> {
+		if frames.is_empty() {
+			return Err(DmiError::Generic(format!(
+				"Error importing icon_state \"{name}\": the animation has no frames."
+			)));
+		}
+
+		let mut delay = Vec::with_capacity(frames.len());
+		let mut images = Vec::with_capacity(frames.len());
+		for frame in frames {
+			let (numerator, denominator) = frame.delay().numer_denom_ms();
+			delay.push(numerator as f32 / denominator as f32 / TICK_MS);
+			images.push(DynamicImage::ImageRgba8(frame.into_buffer()));
+		}
+
+		let animated = images.len() > 1;
+		Ok(IconState {
+			name: name.to_string(),
+			dirs: 1,
+			frames: images.len() as u32,
+			images,
+			delay: animated.then_some(delay),
+			loop_flag: if animated {
+				loop_flag
+			} else {
+				Looping::Indefinitely
+			},
+			..Default::default()
+		})
+	}
+}
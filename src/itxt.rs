@@ -0,0 +1,102 @@
+use crate::chunk::{RawGenericChunk, ITXT_TYPE};
+use crate::{error, ztxt, RawDmi};
+
+/// Which chunk holds the DMI metadata of a saved file, see
+/// [crate::options::SaveOptions::metadata_chunk].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum MetadataChunk {
+	/// A zTXt chunk, as BYOND writes and reads. Its text is Latin-1 per the PNG spec, though BYOND
+	/// and this library treat it as UTF-8.
+	#[default]
+	Ztxt,
+	/// A compressed iTXt chunk, whose text is UTF-8 per the PNG spec. BYOND does not read it.
+	Itxt,
+}
+
+/// Creates an iTXt chunk holding `text` under `keyword`, zlib compressed if `compressed` is set,
+/// without language tag or translated keyword. Keywords are 1 to 79 printable ASCII characters.
+pub fn create_itxt_chunk(
+	keyword: &str,
+	text: &str,
+	compressed: bool,
+) -> Result<RawGenericChunk, error::DmiError> {
+	if keyword.is_empty()
+		|| keyword.len() > 79
+		|| !keyword.bytes().all(|byte| (b' '..=b'~').contains(&byte))
+	{
+		return Err(error::DmiError::InvalidArgument(format!(
+			"Failed to create iTXt chunk. Invalid keyword: {keyword:?}"
+		)));
+	}
+	let mut data = keyword.as_bytes().to_vec();
+	// Null separator, compression flag and method, then the empty language tag and translated
+	// keyword, each ended by a null separator.
+	data.extend([0, compressed as u8, 0, 0, 0]);
+	if compressed {
+		data.extend(ztxt::encode(text.as_bytes()));
+	} else {
+		data.extend(text.as_bytes());
+	}
+	RawGenericChunk::new(ITXT_TYPE, data)
+}
+
+/// Reads the keyword and text of an iTXt chunk, decompressing the text if needed. The language tag
+/// and translated keyword are skipped.
+pub fn read_itxt_chunk(raw_chunk: &RawGenericChunk) -> Result<(String, String), error::DmiError> {
+	if raw_chunk.chunk_type != ITXT_TYPE {
+		return Err(error::DmiError::Generic(format!(
+			"Failed to read iTXt chunk. Wrong type: {:#?}. Expected: {:#?}.",
+			raw_chunk.chunk_type, ITXT_TYPE
+		)));
+	}
+	let missing_field = || {
+		error::DmiError::Generic(
+			"Failed to read iTXt chunk. The data ends before the text.".to_string(),
+		)
+	};
+	let (keyword, rest) = split_at_null(&raw_chunk.data).ok_or_else(missing_field)?;
+	let [compression_flag, compression_method, rest @ ..] = rest else {
+		return Err(missing_field());
+	};
+	let (_language_tag, rest) = split_at_null(rest).ok_or_else(missing_field)?;
+	let (_translated_keyword, text) = split_at_null(rest).ok_or_else(missing_field)?;
+	let text = match (compression_flag, compression_method) {
+		(0, _) => text.to_vec(),
+		(1, 0) => inflate::inflate_bytes_zlib(text).map_err(|error| {
+			error::DmiError::Generic(format!(
+				"Failed to read compressed text. Error: {}",
+				error
+			))
+		})?,
+		_ => {
+			return Err(error::DmiError::Generic(format!(
+				"Failed to read iTXt chunk. Unknown compression flag {compression_flag} or method {compression_method}."
+			)))
+		}
+	};
+	Ok((
+		// Keywords are Latin-1, like those of tEXt chunks.
+		keyword.iter().map(|byte| *byte as char).collect(),
+		String::from_utf8(text)?,
+	))
+}
+
+/// Splits `bytes` at the first null byte, which is left out. None if there is no null byte.
+fn split_at_null(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+	let separator = bytes.iter().position(|byte| *byte == 0)?;
+	Some((&bytes[..separator], &bytes[separator + 1..]))
+}
+
+impl RawDmi {
+	/// The text of the first readable iTXt chunk under `keyword`, if any.
+	pub fn itxt(&self, keyword: &str) -> Option<String> {
+		self
+			.other_chunks
+			.iter()
+			.flatten()
+			.filter(|chunk| chunk.chunk_type == ITXT_TYPE)
+			.filter_map(|chunk| read_itxt_chunk(chunk).ok())
+			.find(|(chunk_keyword, _)| chunk_keyword == keyword)
+			.map(|(_, text)| text)
+	}
+}
@@ -1,11 +1,75 @@
+#[cfg(feature = "images")]
+pub mod analysis;
+pub mod appdata;
+#[cfg(feature = "images")]
+pub mod atlas;
+#[cfg(feature = "images")]
+pub mod builder;
+#[cfg(feature = "cache")]
+pub mod cache;
 pub mod chunk;
-pub(crate) mod crc;
+#[cfg(feature = "images")]
+pub mod contact_sheet;
+#[cfg(feature = "images")]
+pub mod convert;
+pub mod crc;
+#[cfg(feature = "images")]
+pub mod data_uri;
 pub mod dirs;
+#[cfg(feature = "images")]
+pub mod edit;
+#[cfg(feature = "images")]
+pub mod encode;
 pub mod error;
+pub mod fingerprint;
+#[cfg(feature = "images")]
+pub mod frames;
+#[cfg(feature = "images")]
+pub mod grid;
+pub mod hash;
 pub mod icon;
 pub mod iend;
+pub mod ihdr;
+#[cfg(feature = "images")]
+pub mod import;
+pub mod itxt;
+pub mod library;
+#[cfg(feature = "images")]
+pub mod merge;
+pub mod metadata;
+pub mod naming;
+#[cfg(feature = "optimize")]
+pub mod optimize;
+#[cfg(feature = "images")]
+pub mod options;
+pub mod patch;
+pub mod pattern;
+pub mod phys;
+pub mod progress;
+#[cfg(feature = "images")]
+pub mod quantize;
+pub mod quirks;
+pub mod reader;
+#[cfg(feature = "images")]
+pub mod recolor;
+#[cfg(feature = "signing")]
+pub mod signature;
+#[cfg(feature = "images")]
+pub mod source;
+#[cfg(feature = "images")]
+pub mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod text;
+#[cfg(feature = "images")]
+pub mod thumbnail;
+#[cfg(feature = "images")]
+pub mod tiled;
+pub mod warning;
 pub mod ztxt;
 
+pub use fingerprint::quick_fingerprint;
+
 use std::io::{Read, Write};
 
 /// The PNG magic header
@@ -20,6 +84,9 @@ pub struct RawDmi {
 	pub other_chunks: Option<Vec<chunk::RawGenericChunk>>,
 	pub chunks_idat: Vec<chunk::RawGenericChunk>,
 	pub chunk_iend: iend::RawIendChunk,
+	/// Where each chunk was in the loaded file, in file order. Set by [RawDmi::load], it is neither
+	/// updated by edits nor used by [RawDmi::save].
+	pub source_map: Option<Vec<chunk::ChunkSpan>>,
 }
 
 impl RawDmi {
@@ -29,64 +96,184 @@ impl RawDmi {
 		}
 	}
 
-	pub fn load<R: Read>(mut reader: R) -> Result<RawDmi, error::DmiError> {
-		let mut dmi_bytes = Vec::new();
-		reader.read_to_end(&mut dmi_bytes)?;
+	/// Every chunk, borrowed, in the order [RawDmi::save] writes them: IHDR, zTXt, PLTE, the other
+	/// chunks, IDAT then IEND.
+	pub fn chunks(&self) -> impl Iterator<Item = chunk::ChunkRef<'_>> {
+		let leading_chunks = std::iter::once(&self.chunk_ihdr)
+			.map(chunk::ChunkRef::Generic)
+			.chain(self.chunk_ztxt.iter().map(chunk::ChunkRef::Ztxt));
+		leading_chunks
+			.chain(
+				self
+					.chunk_plte
+					.iter()
+					.chain(self.other_chunks.iter().flatten())
+					.chain(self.chunks_idat.iter())
+					.map(chunk::ChunkRef::Generic),
+			)
+			.chain(std::iter::once(chunk::ChunkRef::Iend(&self.chunk_iend)))
+	}
+
+	/// The type and data length of every chunk, in the order [RawDmi::save] writes them.
+	pub fn chunk_types(&self) -> Vec<([u8; 4], u32)> {
+		self
+			.chunks()
+			.map(|chunk| (chunk.chunk_type(), chunk.data_length()))
+			.collect()
+	}
+
+	/// The data of every IDAT chunk put together: the zlib stream of the image.
+	pub fn idat_bytes(&self) -> Vec<u8> {
+		self
+			.chunks_idat
+			.iter()
+			.flat_map(|chunk| chunk.data.iter().copied())
+			.collect()
+	}
+
+	/// The decompressed image data: the filtered scanlines, each starting with its filter type byte.
+	pub fn inflated_idat_bytes(&self) -> Result<Vec<u8>, error::DmiError> {
+		inflate::inflate_bytes_zlib(&self.idat_bytes()).map_err(|error| {
+			error::DmiError::Generic(format!("Failed to inflate the image data. Error: {error}"))
+		})
+	}
+
+	pub fn load<R: Read>(reader: R) -> Result<RawDmi, error::DmiError> {
+		RawDmi::load_with_policy(reader, chunk::DuplicateChunkPolicy::default())
+	}
+
+	/// Like [RawDmi::load], resolving repeated IHDR, zTXt and PLTE chunks as `duplicates` says.
+	///
+	/// The file is read one chunk at a time, see [reader::ChunkReader], rather than whole first.
+	pub fn load_with_policy<R: Read>(
+		reader: R,
+		duplicates: chunk::DuplicateChunkPolicy,
+	) -> Result<RawDmi, error::DmiError> {
+		RawDmi::load_chunks(reader::ChunkReader::new(reader)?, duplicates, false, None)
+	}
+
+	/// Like [RawDmi::load_with_policy], dropping the ancillary chunks that fail to load, such as ones
+	/// with a bad CRC, rather than failing. Each one dropped is reported in `warnings`.
+	#[cfg(feature = "images")]
+	pub(crate) fn load_lenient<R: Read>(
+		reader: R,
+		duplicates: chunk::DuplicateChunkPolicy,
+		warnings: &mut Vec<warning::DmiWarning>,
+	) -> Result<RawDmi, error::DmiError> {
+		RawDmi::load_chunks(
+			reader::ChunkReader::new(reader)?,
+			duplicates,
+			false,
+			Some(warnings),
+		)
+	}
+
+	/// Loads every chunk but the image data, which is skipped over without being read: the
+	/// [RawDmi::chunks_idat] are left empty. Meant for reading the metadata, wherever it is in the
+	/// file: some optimizers move the zTXt chunk after the image data.
+	///
+	/// The result cannot be saved as a valid PNG until image data is added back.
+	pub fn load_meta<R: Read>(reader: R) -> Result<RawDmi, error::DmiError> {
+		RawDmi::load_chunks(
+			reader::ChunkReader::new(reader)?,
+			chunk::DuplicateChunkPolicy::default(),
+			true,
+			None,
+		)
+	}
+
+	pub(crate) fn load_bytes(
+		dmi_bytes: &[u8],
+		duplicates: chunk::DuplicateChunkPolicy,
+	) -> Result<RawDmi, error::DmiError> {
 		// 8 bytes for the PNG file signature.
 		// 12 + 13 bytes for the IHDR chunk.
 		// 12 for the IDAT chunk.
-		// 12 + 3 for the zTXt chunk.
 		// 12 for the IEND chunk.
+		// The zTXt chunk is optional, plain PNGs are loaded too.
 
-		// Total minimum size for a DMI file: 72 bytes.
+		// Total minimum size for a DMI file: 57 bytes.
 
-		if dmi_bytes.len() < 72 {
-			return Err(error::DmiError::Generic(format!("Failed to load DMI. Supplied reader contained size of {} bytes, lower than the required 72.", dmi_bytes.len())));
+		if dmi_bytes.len() < 57 {
+			return Err(error::DmiError::Generic(format!("Failed to load DMI. Supplied reader contained size of {} bytes, lower than the required 57.", dmi_bytes.len())));
 		};
+		RawDmi::load_chunks(
+			reader::ChunkReader::new(dmi_bytes)?,
+			duplicates,
+			false,
+			None,
+		)
+	}
 
-		let header = &dmi_bytes[0..8];
-		if dmi_bytes[0..8] != PNG_HEADER {
-			return Err(error::DmiError::Generic(format!(
-				"PNG header mismatch (expected {:#?}, found {:#?})",
-				PNG_HEADER, header
-			)));
-		};
+	/// Parses the chunks of `chunks`. With `skip_image_data`, IDAT chunks are skipped over rather
+	/// than read, leaving [RawDmi::chunks_idat] empty. With `corrupt_chunks`, invalid ancillary
+	/// chunks are dropped and reported there instead of failing.
+	fn load_chunks<R: Read>(
+		mut chunks: reader::ChunkReader<R>,
+		duplicates: chunk::DuplicateChunkPolicy,
+		skip_image_data: bool,
+		mut corrupt_chunks: Option<&mut Vec<warning::DmiWarning>>,
+	) -> Result<RawDmi, error::DmiError> {
 		let header = PNG_HEADER;
 		let mut chunk_ihdr = None;
-		let mut chunk_ztxt = None;
+		let mut chunk_ztxt: Option<ztxt::RawZtxtChunk> = None;
 		let mut chunk_plte = None;
 		let mut chunks_idat = vec![];
+		let mut found_idat = false;
 		let chunk_iend;
 		let mut other_chunks = vec![];
-
-		// Index starts after the PNG header.
-		let mut index = 8;
+		let mut source_map = vec![];
 
 		loop {
-			if index + 12 > dmi_bytes.len() {
-				return Err(error::DmiError::Generic(
-					"Failed to load DMI. Buffer end reached without finding an IEND chunk.".to_string(),
-				));
+			// Reading stops at the IEND chunk, which ends the loop.
+			let span = chunks.next_header()?.ok_or_else(|| {
+				error::DmiError::Generic("Failed to load DMI. Chunk read after IEND.".to_string())
+			})?;
+			source_map.push(span);
+			if span.chunk_type == chunk::IDAT_TYPE {
+				found_idat = true;
+				if skip_image_data {
+					chunks.skip_data()?;
+					continue;
+				}
 			}
+			let raw_chunk = match (chunks.read_data(), &mut corrupt_chunks) {
+				(Ok(raw_chunk), _) => raw_chunk,
+				// The chunk was read whole, only its content is invalid.
+				(Err(_), Some(warnings))
+					if chunk::ChunkType::from(span.chunk_type).is_ancillary()
+						&& chunks.offset() == span.offset + span.length =>
+				{
+					warnings.push(warning::DmiWarning::CorruptChunk {
+						chunk_type: span.chunk_type,
+					});
+					continue;
+				}
+				(Err(error), _) => return Err(error),
+			};
 
-			let chunk_data_length = u32::from_be_bytes([
-				dmi_bytes[index],
-				dmi_bytes[index + 1],
-				dmi_bytes[index + 2],
-				dmi_bytes[index + 3],
-			]) as usize;
-
-			// 12 minimum necessary bytes from the chunk plus the data length.
-			let chunk_bytes = dmi_bytes[index..(index + 12 + chunk_data_length)].to_vec();
-			let raw_chunk = chunk::RawGenericChunk::load(&mut &*chunk_bytes)?;
-			index += 12 + chunk_data_length;
-
-			match &raw_chunk.chunk_type {
-				b"IHDR" => chunk_ihdr = Some(raw_chunk),
-				b"zTXt" => chunk_ztxt = Some(ztxt::RawZtxtChunk::try_from(raw_chunk)?),
-				b"PLTE" => chunk_plte = Some(raw_chunk),
-				b"IDAT" => chunks_idat.push(raw_chunk),
-				b"IEND" => {
+			match chunk::ChunkType::from(raw_chunk.chunk_type) {
+				chunk::ChunkType::Ihdr => {
+					duplicates.resolve(&mut chunk_ihdr, raw_chunk, chunk::IHDR_TYPE)?
+				}
+				// Continuations are written after the zTXt chunk they continue.
+				chunk::ChunkType::Ztxt
+					if chunk_ztxt.as_ref().is_some_and(|chunk_ztxt| {
+						ztxt::is_continuation(&raw_chunk, &chunk_ztxt.data.keyword)
+					}) =>
+				{
+					other_chunks.push(raw_chunk)
+				}
+				chunk::ChunkType::Ztxt => duplicates.resolve(
+					&mut chunk_ztxt,
+					ztxt::RawZtxtChunk::try_from(raw_chunk)?,
+					ztxt::ZTXT_TYPE,
+				)?,
+				chunk::ChunkType::Plte => {
+					duplicates.resolve(&mut chunk_plte, raw_chunk, chunk::PLTE_TYPE)?
+				}
+				chunk::ChunkType::Idat => chunks_idat.push(raw_chunk),
+				chunk::ChunkType::Iend => {
 					chunk_iend = Some(iend::RawIendChunk::try_from(raw_chunk)?);
 					break;
 				}
@@ -98,7 +285,7 @@ impl RawDmi {
 				"Failed to load DMI. Buffer end reached without finding an IHDR chunk.".to_string(),
 			));
 		};
-		if chunks_idat.is_empty() {
+		if !found_idat {
 			return Err(error::DmiError::Generic(
 				"Failed to load DMI. Buffer end reached without finding an IDAT chunk.".to_string(),
 			));
@@ -118,6 +305,7 @@ impl RawDmi {
 			other_chunks,
 			chunks_idat,
 			chunk_iend,
+			source_map: Some(source_map),
 		})
 	}
 
@@ -197,4 +385,118 @@ impl RawDmi {
 
 		Ok(total_bytes_written)
 	}
+
+	/// Drops the ancillary chunks held in `other_chunks` that `policy` does not keep, such as the
+	/// pHYs, tIME or editor-specific chunks art tools like to add. The zTXt metadata, PLTE and image
+	/// data are never touched. Returns how many chunks were removed.
+	pub fn prune_chunks(&mut self, policy: &chunk::ChunkPolicy) -> usize {
+		let Some(other_chunks) = &mut self.other_chunks else {
+			return 0;
+		};
+		let previous_count = other_chunks.len();
+		other_chunks.retain(|chunk| policy.keeps(&chunk.chunk_type));
+		let removed = previous_count - other_chunks.len();
+		if other_chunks.is_empty() {
+			self.other_chunks = None;
+		}
+		removed
+	}
+
+	/// Gets the text of the first tEXt chunk with the given keyword.
+	pub fn text(&self, keyword: &str) -> Option<String> {
+		self
+			.other_chunks
+			.iter()
+			.flatten()
+			.filter(|chunk| chunk.chunk_type == text::TEXT_TYPE)
+			.filter_map(|chunk| text::read_text_chunk(chunk).ok())
+			.find(|(chunk_keyword, _)| chunk_keyword == keyword)
+			.map(|(_, text)| text)
+	}
+
+	/// Stores `text` in a tEXt chunk under `keyword`, replacing the tEXt chunks with the same keyword.
+	pub fn set_text(&mut self, keyword: &str, text: &str) -> Result<(), error::DmiError> {
+		let new_chunk = text::create_text_chunk(keyword, text)?;
+		self.remove_text(keyword);
+		self
+			.other_chunks
+			.get_or_insert_with(Vec::new)
+			.push(new_chunk);
+		Ok(())
+	}
+
+	/// Removes the tEXt chunks with the given keyword. Returns whether there were any.
+	pub fn remove_text(&mut self, keyword: &str) -> bool {
+		let Some(other_chunks) = &mut self.other_chunks else {
+			return false;
+		};
+		let previous_count = other_chunks.len();
+		other_chunks.retain(|chunk| {
+			chunk.chunk_type != text::TEXT_TYPE
+				|| !matches!(text::read_text_chunk(chunk), Ok((chunk_keyword, _)) if chunk_keyword == keyword)
+		});
+		let removed = previous_count != other_chunks.len();
+		if other_chunks.is_empty() {
+			self.other_chunks = None;
+		}
+		removed
+	}
+
+	/// Gets the application data stored under `key`, see [appdata].
+	pub fn app_data(&self, key: &str) -> Option<&[u8]> {
+		self
+			.other_chunks
+			.iter()
+			.flatten()
+			.filter(|chunk| chunk.chunk_type == appdata::APP_DATA_TYPE)
+			.filter_map(|chunk| appdata::read_app_data_chunk(chunk).ok())
+			.find(|(chunk_key, _)| *chunk_key == key)
+			.map(|(_, data)| data)
+	}
+
+	/// Stores `data` under the application `key`, replacing any previous data with the same key.
+	pub fn set_app_data(&mut self, key: &str, data: &[u8]) -> Result<(), error::DmiError> {
+		let new_chunk = appdata::create_app_data_chunk(key, data)?;
+		self.remove_app_data(key);
+		self
+			.other_chunks
+			.get_or_insert_with(Vec::new)
+			.push(new_chunk);
+		Ok(())
+	}
+
+	/// Removes the application data stored under `key`. Returns whether there was any.
+	pub fn remove_app_data(&mut self, key: &str) -> bool {
+		let Some(other_chunks) = &mut self.other_chunks else {
+			return false;
+		};
+		let previous_count = other_chunks.len();
+		other_chunks.retain(|chunk| {
+			chunk.chunk_type != appdata::APP_DATA_TYPE
+				|| !matches!(appdata::read_app_data_chunk(chunk), Ok((chunk_key, _)) if chunk_key == key)
+		});
+		let removed = previous_count != other_chunks.len();
+		if other_chunks.is_empty() {
+			self.other_chunks = None;
+		}
+		removed
+	}
+}
+
+/// Parses a whole file held in memory, like [RawDmi::load].
+impl TryFrom<&[u8]> for RawDmi {
+	type Error = error::DmiError;
+
+	fn try_from(bytes: &[u8]) -> Result<RawDmi, error::DmiError> {
+		RawDmi::load_bytes(bytes, chunk::DuplicateChunkPolicy::default())
+	}
+}
+
+#[cfg(feature = "bytes")]
+impl TryFrom<bytes::Bytes> for RawDmi {
+	type Error = error::DmiError;
+
+	fn try_from(bytes: bytes::Bytes) -> Result<RawDmi, error::DmiError> {
+		RawDmi::try_from(&bytes[..])
+	}
 }
@@ -0,0 +1,649 @@
+use crate::error::DmiError;
+#[cfg(feature = "images")]
+use crate::hash::Fnv1aHasher;
+#[cfg(feature = "images")]
+use crate::icon::{Decoder, Icon, IconState};
+use crate::metadata::{DmiMetadata, StateMetadata};
+use crate::pattern::StatePattern;
+use crate::progress::Progress;
+#[cfg(feature = "images")]
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+#[cfg(feature = "images")]
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// A DMI file indexed by a [DmiLibrary].
+#[derive(Clone, PartialEq, Debug)]
+pub struct LibraryFile {
+	pub path: PathBuf,
+	pub metadata: DmiMetadata,
+}
+
+/// Position of a state within a [DmiLibrary]: the index of its file, then its index within the file.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct StateLocation {
+	pub file: usize,
+	pub state: usize,
+}
+
+/// A state found in a [DmiLibrary], along with the file it lives in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LibraryState<'a> {
+	pub location: StateLocation,
+	pub path: &'a Path,
+	pub icon: &'a DmiMetadata,
+	pub state: &'a StateMetadata,
+}
+
+/// A set of states from different files that look the same, see [DmiLibrary::find_duplicates].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DuplicateGroup {
+	/// The duplicated states, in file order.
+	pub states: Vec<StateLocation>,
+	/// Whether every state is pixel for pixel identical. Otherwise they only differ by slight color
+	/// variations or in the color of fully transparent pixels.
+	pub exact: bool,
+}
+
+/// How many shades each channel of a pixel may differ by for [DmiLibrary::find_duplicates] to
+/// consider two states near-identical.
+pub const NEAR_DUPLICATE_TOLERANCE: u8 = 7;
+
+/// What [DmiLibrary::find_duplicates] found.
+#[derive(Default, Debug)]
+pub struct Duplicates {
+	/// Exact and near-identical groups, ordered by their first state.
+	pub groups: Vec<DuplicateGroup>,
+	/// Files that could not be decoded, whose states are not part of any group.
+	pub failures: Vec<(PathBuf, DmiError)>,
+}
+
+/// An index over the metadata of many DMI files, typically a whole icons tree.
+///
+/// Only the metadata is loaded, see [DmiMetadata]. States can then be looked up by name, file or
+/// sprite dimensions without touching the files again.
+#[derive(Default, Debug)]
+pub struct DmiLibrary {
+	files: Vec<LibraryFile>,
+	by_path: HashMap<PathBuf, usize>,
+	by_name: HashMap<String, Vec<StateLocation>>,
+	by_dimensions: HashMap<(u32, u32), Vec<usize>>,
+	failures: Vec<(PathBuf, DmiError)>,
+}
+
+impl DmiLibrary {
+	pub fn new() -> DmiLibrary {
+		DmiLibrary {
+			..Default::default()
+		}
+	}
+
+	/// Indexes every `.dmi` file found under `root`, recursively.
+	/// Files that fail to load are recorded in [DmiLibrary::failures] rather than aborting the scan.
+	pub fn load_dir<P: AsRef<Path>>(root: P) -> Result<DmiLibrary, DmiError> {
+		let mut library = DmiLibrary::new();
+		library.add_dir(root)?;
+		Ok(library)
+	}
+
+	/// Indexes every `.dmi` file found under `root`, recursively, in path order.
+	/// Files that fail to load are recorded in [DmiLibrary::failures]. Only errors reading the
+	/// directories themselves are returned.
+	pub fn add_dir<P: AsRef<Path>>(&mut self, root: P) -> Result<(), DmiError> {
+		self.add_dir_with_progress(root, |_| {})
+	}
+
+	/// Like [DmiLibrary::add_dir], calling `on_progress` after each file, so that tools scanning
+	/// large trees can show how far they got.
+	pub fn add_dir_with_progress<P: AsRef<Path>, F: FnMut(&Progress)>(
+		&mut self,
+		root: P,
+		mut on_progress: F,
+	) -> Result<(), DmiError> {
+		let paths = find_dmi_files(root.as_ref())?;
+		let mut bytes_done = 0;
+		for (index, path) in paths.iter().enumerate() {
+			bytes_done += fs::metadata(path).map_or(0, |metadata| metadata.len());
+			if let Err(error) = self.add_file(path) {
+				self.failures.push((path.clone(), error));
+			}
+			on_progress(&Progress {
+				files_done: index + 1,
+				files_total: paths.len(),
+				bytes_done,
+				path,
+			});
+		}
+		Ok(())
+	}
+
+	/// Loads the metadata of a single file and indexes it.
+	pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), DmiError> {
+		let path = path.as_ref();
+		let metadata = DmiMetadata::load(BufReader::new(File::open(path)?))?;
+		self.insert(path.to_path_buf(), metadata);
+		Ok(())
+	}
+
+	/// Indexes already loaded metadata under `path`, replacing whatever was indexed there before.
+	pub fn insert(&mut self, path: PathBuf, metadata: DmiMetadata) {
+		match self.by_path.get(&path) {
+			Some(&file_index) => {
+				self.files[file_index].metadata = metadata;
+				self.rebuild_indexes();
+			}
+			None => {
+				let file_index = self.files.len();
+				self.files.push(LibraryFile { path, metadata });
+				self.index_file(file_index);
+			}
+		}
+	}
+
+	/// Every indexed file, in the order they were added.
+	pub fn files(&self) -> &[LibraryFile] {
+		&self.files
+	}
+
+	/// The metadata indexed under `path`.
+	pub fn file<P: AsRef<Path>>(&self, path: P) -> Option<&DmiMetadata> {
+		self
+			.by_path
+			.get(path.as_ref())
+			.map(|&file_index| &self.files[file_index].metadata)
+	}
+
+	/// Files that could not be loaded while scanning directories, with the reason.
+	pub fn failures(&self) -> &[(PathBuf, DmiError)] {
+		&self.failures
+	}
+
+	/// Total number of states across every file.
+	pub fn state_count(&self) -> usize {
+		self
+			.files
+			.iter()
+			.map(|file| file.metadata.states.len())
+			.sum()
+	}
+
+	/// Gets the state at `location`.
+	pub fn state(&self, location: StateLocation) -> Option<LibraryState<'_>> {
+		let file = self.files.get(location.file)?;
+		let state = file.metadata.states.get(location.state)?;
+		Some(LibraryState {
+			location,
+			path: &file.path,
+			icon: &file.metadata,
+			state,
+		})
+	}
+
+	/// Every state of every file.
+	pub fn states(&self) -> impl Iterator<Item = LibraryState<'_>> {
+		self
+			.files
+			.iter()
+			.enumerate()
+			.flat_map(|(file_index, file)| {
+				file
+					.metadata
+					.states
+					.iter()
+					.enumerate()
+					.map(move |(state_index, state)| LibraryState {
+						location: StateLocation {
+							file: file_index,
+							state: state_index,
+						},
+						path: &file.path,
+						icon: &file.metadata,
+						state,
+					})
+			})
+	}
+
+	/// Every state named exactly `name`, across all files.
+	pub fn states_named(&self, name: &str) -> impl Iterator<Item = LibraryState<'_>> {
+		self
+			.by_name
+			.get(name)
+			.into_iter()
+			.flatten()
+			.filter_map(|&location| self.state(location))
+	}
+
+	/// Every state whose name matches `pattern`, across all files, in file order.
+	/// Plain strings are treated as globs: `library.find_states("*_broken")`.
+	pub fn find_states<P: Into<StatePattern>>(&self, pattern: P) -> Vec<LibraryState<'_>> {
+		let pattern = pattern.into();
+		if let StatePattern::Exact(name) = &pattern {
+			return self.states_named(name).collect();
+		}
+		self
+			.states()
+			.filter(|found| pattern.matches(&found.state.name))
+			.collect()
+	}
+
+	/// Every file whose sprites are `width`x`height`.
+	pub fn files_with_dimensions(
+		&self,
+		width: u32,
+		height: u32,
+	) -> impl Iterator<Item = &LibraryFile> {
+		self
+			.by_dimensions
+			.get(&(width, height))
+			.into_iter()
+			.flatten()
+			.map(|&file_index| &self.files[file_index])
+	}
+
+	/// Finds states living in different files that share the same layout and pixels.
+	///
+	/// Unlike the rest of the library, this decodes the image data of every indexed file.
+	/// Identical states are reported as exact groups. States whose colors only differ by up to
+	/// [NEAR_DUPLICATE_TOLERANCE] shades per channel, pixel for pixel, are reported as
+	/// near-identical groups, as long as they are not all identical already. The color of fully
+	/// transparent pixels is ignored for those.
+	///
+	/// Files that can no longer be read are listed in [Duplicates::failures] and left out of the
+	/// groups, the rest of the library is still searched.
+	#[cfg(feature = "images")]
+	pub fn find_duplicates(&self) -> Duplicates {
+		self.find_duplicates_with_progress(|_| {})
+	}
+
+	/// Like [DmiLibrary::find_duplicates], calling `on_progress` after each file is decoded.
+	/// Comparing the candidates afterwards only reloads the files involved, keeping the last
+	/// [RELOADED_FILES] of them decoded.
+	#[cfg(feature = "images")]
+	pub fn find_duplicates_with_progress<F: FnMut(&Progress)>(
+		&self,
+		mut on_progress: F,
+	) -> Duplicates {
+		let mut decoder = Decoder::new();
+		let mut failures = vec![];
+		let mut scanned: Vec<ScannedState> = vec![];
+
+		let mut bytes_done = 0;
+		for (file_index, file) in self.files.iter().enumerate() {
+			bytes_done += fs::metadata(&file.path).map_or(0, |metadata| metadata.len());
+			let loaded = load_icon(&mut decoder, &file.path);
+			on_progress(&Progress {
+				files_done: file_index + 1,
+				files_total: self.files.len(),
+				bytes_done,
+				path: &file.path,
+			});
+			let icon = match loaded {
+				Ok(icon) => icon,
+				Err(error) => {
+					failures.push((file.path.clone(), error));
+					continue;
+				}
+			};
+			for (state_index, icon_state) in icon.states.iter().enumerate() {
+				scanned.push(ScannedState {
+					location: StateLocation {
+						file: file_index,
+						state: state_index,
+					},
+					exact_hash: hash_state_pixels(icon_state),
+					layout_hash: hash_state_layout(icon_state),
+					mean: mean_color(icon_state),
+				});
+			}
+		}
+
+		let spans_files = |locations: &[StateLocation]| {
+			locations
+				.iter()
+				.any(|location| location.file != locations[0].file)
+		};
+
+		let mut groups = vec![];
+		let mut exact_hashes: HashMap<[u8; 32], Vec<usize>> = HashMap::new();
+		for (index, state) in scanned.iter().enumerate() {
+			exact_hashes
+				.entry(state.exact_hash)
+				.or_default()
+				.push(index);
+		}
+		for indices in exact_hashes.values() {
+			let locations: Vec<StateLocation> = indices
+				.iter()
+				.map(|&index| scanned[index].location)
+				.collect();
+			if spans_files(&locations) {
+				groups.push(DuplicateGroup {
+					states: locations,
+					exact: true,
+				});
+			}
+		}
+
+		// Near-identical states have mean colors at most the tolerance apart, so they either share
+		// a bucket or sit in neighbouring ones. Only those candidates get compared pixel by pixel.
+		let mut buckets: HashMap<(u64, [u8; 4]), Vec<usize>> = HashMap::new();
+		for (index, state) in scanned.iter().enumerate() {
+			buckets
+				.entry((state.layout_hash, state.bucket()))
+				.or_default()
+				.push(index);
+		}
+		let mut parents: Vec<usize> = (0..scanned.len()).collect();
+		let mut reloaded = ReloadCache::default();
+		for (index, state) in scanned.iter().enumerate() {
+			for neighbour in neighbouring_buckets(state.bucket()) {
+				let Some(candidates) = buckets.get(&(state.layout_hash, neighbour)) else {
+					continue;
+				};
+				for &candidate in candidates {
+					let other = &scanned[candidate];
+					if candidate <= index
+						|| other.exact_hash == state.exact_hash
+						|| find_root(&mut parents, candidate) == find_root(&mut parents, index)
+					{
+						continue;
+					}
+					let loaded = [state.location, other.location].map(|location| {
+						let path = &self.files[location.file].path;
+						reloaded.load(location.file, path, &mut decoder, &mut failures)
+					});
+					if loaded != [true, true] {
+						continue;
+					}
+					if let (Some(first), Some(second)) = (
+						reloaded.state(state.location),
+						reloaded.state(other.location),
+					) {
+						if states_are_near(first, second) {
+							let root = find_root(&mut parents, index);
+							let other_root = find_root(&mut parents, candidate);
+							parents[other_root] = root;
+						}
+					}
+				}
+			}
+		}
+		// Identical states are near whatever their twins are near.
+		for indices in exact_hashes.values() {
+			for &index in &indices[1..] {
+				let root = find_root(&mut parents, indices[0]);
+				let other_root = find_root(&mut parents, index);
+				parents[other_root] = root;
+			}
+		}
+
+		let mut components: HashMap<usize, Vec<usize>> = HashMap::new();
+		for index in 0..scanned.len() {
+			let root = find_root(&mut parents, index);
+			components.entry(root).or_default().push(index);
+		}
+		for indices in components.into_values() {
+			let first_exact_hash = scanned[indices[0]].exact_hash;
+			if indices
+				.iter()
+				.all(|&index| scanned[index].exact_hash == first_exact_hash)
+			{
+				continue;
+			}
+			let locations: Vec<StateLocation> = indices
+				.iter()
+				.map(|&index| scanned[index].location)
+				.collect();
+			if spans_files(&locations) {
+				groups.push(DuplicateGroup {
+					states: locations,
+					exact: false,
+				});
+			}
+		}
+
+		groups.sort_by_key(|group| (group.states[0].file, group.states[0].state, !group.exact));
+		Duplicates { groups, failures }
+	}
+
+	fn index_file(&mut self, file_index: usize) {
+		let file = &self.files[file_index];
+		self.by_path.insert(file.path.clone(), file_index);
+		self
+			.by_dimensions
+			.entry((file.metadata.width, file.metadata.height))
+			.or_default()
+			.push(file_index);
+		for (state_index, state) in file.metadata.states.iter().enumerate() {
+			self
+				.by_name
+				.entry(state.name.clone())
+				.or_default()
+				.push(StateLocation {
+					file: file_index,
+					state: state_index,
+				});
+		}
+	}
+
+	fn rebuild_indexes(&mut self) {
+		self.by_path.clear();
+		self.by_name.clear();
+		self.by_dimensions.clear();
+		for file_index in 0..self.files.len() {
+			self.index_file(file_index);
+		}
+	}
+}
+
+/// A state decoded by [DmiLibrary::find_duplicates], reduced to what finding candidates needs.
+#[cfg(feature = "images")]
+struct ScannedState {
+	location: StateLocation,
+	/// SHA-256 of the layout and pixels, so that equal hashes mean identical states.
+	exact_hash: [u8; 32],
+	layout_hash: u64,
+	mean: [u8; 4],
+}
+
+#[cfg(feature = "images")]
+impl ScannedState {
+	/// The mean color bucket, wide enough that near-identical states are at most one bucket apart.
+	fn bucket(&self) -> [u8; 4] {
+		self
+			.mean
+			.map(|channel| channel / (NEAR_DUPLICATE_TOLERANCE + 1))
+	}
+}
+
+/// How many reloaded files [DmiLibrary::find_duplicates] keeps decoded while comparing candidates.
+pub const RELOADED_FILES: usize = 8;
+
+/// The files reloaded by [DmiLibrary::find_duplicates], least recently used first, so that memory
+/// does not grow with the size of the library.
+#[cfg(feature = "images")]
+#[derive(Default)]
+struct ReloadCache {
+	icons: VecDeque<(usize, Icon)>,
+	failed: HashSet<usize>,
+}
+
+#[cfg(feature = "images")]
+impl ReloadCache {
+	/// Makes sure the icon of the file at `file_index` is decoded, evicting the least recently used
+	/// one if needed. Returns false, recording the error once, if the file can no longer be loaded.
+	fn load(
+		&mut self,
+		file_index: usize,
+		path: &Path,
+		decoder: &mut Decoder,
+		failures: &mut Vec<(PathBuf, DmiError)>,
+	) -> bool {
+		if self.failed.contains(&file_index) {
+			return false;
+		}
+		if let Some(position) = self
+			.icons
+			.iter()
+			.position(|(index, _)| *index == file_index)
+		{
+			let entry = self.icons.remove(position).unwrap();
+			self.icons.push_back(entry);
+			return true;
+		}
+		match load_icon(decoder, path) {
+			Ok(icon) => {
+				if self.icons.len() == RELOADED_FILES {
+					self.icons.pop_front();
+				}
+				self.icons.push_back((file_index, icon));
+				true
+			}
+			Err(error) => {
+				failures.push((path.to_path_buf(), error));
+				self.failed.insert(file_index);
+				false
+			}
+		}
+	}
+
+	fn state(&self, location: StateLocation) -> Option<&IconState> {
+		self
+			.icons
+			.iter()
+			.find(|(index, _)| *index == location.file)
+			.and_then(|(_, icon)| icon.states.get(location.state))
+	}
+}
+
+#[cfg(feature = "images")]
+fn load_icon(decoder: &mut Decoder, path: &Path) -> Result<Icon, DmiError> {
+	decoder.load(BufReader::new(File::open(path)?))
+}
+
+/// The bucket itself and every bucket one step away on any channel.
+#[cfg(feature = "images")]
+fn neighbouring_buckets(bucket: [u8; 4]) -> impl Iterator<Item = [u8; 4]> {
+	(0..81).filter_map(move |mut offsets: u32| {
+		let mut neighbour = bucket;
+		for channel in &mut neighbour {
+			*channel = match offsets % 3 {
+				0 => *channel,
+				1 => channel.checked_sub(1)?,
+				_ => channel.checked_add(1)?,
+			};
+			offsets /= 3;
+		}
+		Some(neighbour)
+	})
+}
+
+#[cfg(feature = "images")]
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+	while parents[index] != index {
+		parents[index] = parents[parents[index]];
+		index = parents[index];
+	}
+	index
+}
+
+/// Fully transparent pixels all count as the same color when looking for near duplicates.
+#[cfg(feature = "images")]
+fn visible_color(pixel: [u8; 4]) -> [u8; 4] {
+	match pixel {
+		[_, _, _, 0] => [0; 4],
+		pixel => pixel,
+	}
+}
+
+/// The mean of every pixel of every image of a state, per channel.
+#[cfg(feature = "images")]
+fn mean_color(icon_state: &IconState) -> [u8; 4] {
+	let mut sums = [0u64; 4];
+	let mut count = 0u64;
+	for image in &icon_state.images {
+		for pixel in image.to_rgba8().pixels() {
+			for (sum, channel) in sums.iter_mut().zip(visible_color(pixel.0)) {
+				*sum += channel as u64;
+			}
+			count += 1;
+		}
+	}
+	sums.map(|sum| sum.checked_div(count).unwrap_or(0) as u8)
+}
+
+/// Whether two states of the same layout differ by at most [NEAR_DUPLICATE_TOLERANCE] on every
+/// channel of every pixel.
+#[cfg(feature = "images")]
+fn states_are_near(first: &IconState, second: &IconState) -> bool {
+	first.images.len() == second.images.len()
+		&& first
+			.images
+			.iter()
+			.zip(&second.images)
+			.all(|(first, second)| {
+				let (first, second) = (first.to_rgba8(), second.to_rgba8());
+				first.dimensions() == second.dimensions()
+					&& first.pixels().zip(second.pixels()).all(|(first, second)| {
+						visible_color(first.0)
+							.iter()
+							.zip(visible_color(second.0))
+							.all(|(first, second)| first.abs_diff(second) <= NEAR_DUPLICATE_TOLERANCE)
+					})
+			})
+}
+
+/// Hashes the directions, frames and image dimensions of a state.
+#[cfg(feature = "images")]
+fn hash_state_layout(icon_state: &IconState) -> u64 {
+	let mut hasher = Fnv1aHasher::new();
+	hasher.update(&[icon_state.dirs]);
+	hasher.update(&icon_state.frames.to_be_bytes());
+	for image in &icon_state.images {
+		hasher.update(&image.width().to_be_bytes());
+		hasher.update(&image.height().to_be_bytes());
+	}
+	hasher.finish()
+}
+
+/// Hashes the layout and RGBA pixels of a state with SHA-256.
+#[cfg(feature = "images")]
+fn hash_state_pixels(icon_state: &IconState) -> [u8; 32] {
+	let mut hasher = Sha256::new();
+	hasher.update([icon_state.dirs]);
+	hasher.update(icon_state.frames.to_be_bytes());
+	for image in &icon_state.images {
+		let image = image.to_rgba8();
+		hasher.update(image.width().to_be_bytes());
+		hasher.update(image.height().to_be_bytes());
+		hasher.update(image.as_raw());
+	}
+	hasher.finalize().into()
+}
+
+/// Recursively lists the `.dmi` files under `root`, sorted by path.
+fn find_dmi_files(root: &Path) -> Result<Vec<PathBuf>, DmiError> {
+	let mut found = vec![];
+	let mut pending = vec![root.to_path_buf()];
+	while let Some(directory) = pending.pop() {
+		for entry in fs::read_dir(&directory)? {
+			let entry = entry?;
+			let path = entry.path();
+			// Symbolic links to directories are not followed, as they may loop.
+			if entry.file_type()?.is_dir() {
+				pending.push(path);
+			} else if path.is_file()
+				&& path
+					.extension()
+					.is_some_and(|extension| extension.eq_ignore_ascii_case("dmi"))
+			{
+				found.push(path);
+			}
+		}
+	}
+	found.sort();
+	Ok(found)
+}
@@ -0,0 +1,274 @@
+use crate::error::DmiError;
+use crate::icon::{Icon, IconState};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// Which side of a merge a change came from.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum MergeSide {
+	Ours,
+	Theirs,
+}
+
+/// Why a state could not be merged automatically.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ConflictKind {
+	/// Both sides changed the state's images (or its dirs/frames) in different ways.
+	BothModified,
+	/// One side removed the state while the other changed it.
+	ModifiedAndDeleted { deleted_in: MergeSide },
+	/// Both sides added a state with this name, with different contents.
+	BothAdded,
+	/// The merged settings do not fit the merged images, such as a delay list of the wrong length.
+	InconsistentMetadata,
+}
+
+/// A state that could not be merged. The merged [Icon] holds our version of it, if we have one.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MergeConflict {
+	pub name: String,
+	/// Which of the states sharing this name is concerned, starting at 0.
+	pub occurrence: usize,
+	pub kind: ConflictKind,
+}
+
+/// The outcome of [Icon::merge3].
+#[derive(Clone, PartialEq, Debug)]
+pub struct MergeResult {
+	pub icon: Icon,
+	pub conflicts: Vec<MergeConflict>,
+}
+
+impl MergeResult {
+	pub fn is_clean(&self) -> bool {
+		self.conflicts.is_empty()
+	}
+}
+
+/// States are matched across versions by name, and by order among the states sharing a name.
+type StateKey = (String, usize);
+
+fn keyed_states(icon: &Icon) -> Vec<(StateKey, &IconState)> {
+	let mut occurrences: HashMap<&str, usize> = HashMap::new();
+	icon
+		.states
+		.iter()
+		.map(|icon_state| {
+			let occurrence = occurrences.entry(&icon_state.name).or_default();
+			let key = (icon_state.name.clone(), *occurrence);
+			*occurrence += 1;
+			(key, icon_state)
+		})
+		.collect()
+}
+
+fn same_pixels(first: &IconState, second: &IconState) -> bool {
+	first.dirs == second.dirs && first.frames == second.frames && first.images == second.images
+}
+
+/// Takes the side that changed the value, or ours if both did.
+fn merge_field<T: PartialEq + Clone>(base: &T, ours: &T, theirs: &T) -> T {
+	if ours == base {
+		theirs.clone()
+	} else {
+		ours.clone()
+	}
+}
+
+impl Icon {
+	/// Merges the changes made to `base` in `ours` and in `theirs`.
+	///
+	/// Added and removed states are carried over from either side. For states present on every side,
+	/// the images are taken from whichever side changed them, and the other settings are merged one by
+	/// one, ours winning when both sides changed the same setting. Conflicts are only raised when the
+	/// images of a state changed on both sides, when a state was changed on one side and removed on the
+	/// other, or when both sides added different states under the same name.
+	///
+	/// Fails if `ours` and `theirs` do not have the same sprite dimensions.
+	pub fn merge3(base: &Icon, ours: &Icon, theirs: &Icon) -> Result<MergeResult, DmiError> {
+		if ours.width != theirs.width || ours.height != theirs.height {
+			return Err(DmiError::Generic(format!(
+				"Error merging icons: dimensions differ between ours ({}x{}) and theirs ({}x{}).",
+				ours.width, ours.height, theirs.width, theirs.height
+			)));
+		}
+
+		let base_states: HashMap<StateKey, &IconState> = keyed_states(base).into_iter().collect();
+		let our_states = keyed_states(ours);
+		let their_states = keyed_states(theirs);
+		let their_lookup: HashMap<&StateKey, &IconState> = their_states
+			.iter()
+			.map(|(key, state)| (key, *state))
+			.collect();
+
+		let mut states = vec![];
+		let mut conflicts = vec![];
+		let mut conflict = |key: &StateKey, kind| {
+			conflicts.push(MergeConflict {
+				name: key.0.clone(),
+				occurrence: key.1,
+				kind,
+			})
+		};
+
+		for (key, our_state) in &our_states {
+			match (base_states.get(key), their_lookup.get(key)) {
+				(Some(base_state), Some(their_state)) => {
+					let merged = merge_state(base_state, our_state, their_state);
+					match merged {
+						Ok(merged) => states.push(merged),
+						Err(kind) => {
+							conflict(key, kind);
+							states.push((*our_state).clone());
+						}
+					}
+				}
+				// Removed on their side.
+				(Some(base_state), None) => {
+					if base_state != our_state {
+						conflict(
+							key,
+							ConflictKind::ModifiedAndDeleted {
+								deleted_in: MergeSide::Theirs,
+							},
+						);
+						states.push((*our_state).clone());
+					}
+				}
+				// Added on our side.
+				(None, their_state) => {
+					if their_state.is_some_and(|their_state| their_state != our_state) {
+						conflict(key, ConflictKind::BothAdded);
+					}
+					states.push((*our_state).clone());
+				}
+			}
+		}
+
+		let our_keys: HashSet<&StateKey> = our_states.iter().map(|(key, _)| key).collect();
+		for (key, their_state) in &their_states {
+			if our_keys.contains(key) {
+				continue;
+			}
+			match base_states.get(key) {
+				// Removed on our side.
+				Some(base_state) => {
+					if base_state != their_state {
+						conflict(
+							key,
+							ConflictKind::ModifiedAndDeleted {
+								deleted_in: MergeSide::Ours,
+							},
+						);
+					}
+				}
+				// Added on their side.
+				None => states.push((*their_state).clone()),
+			}
+		}
+
+		let mut app_data = BTreeMap::new();
+		let keys = base
+			.app_data
+			.keys()
+			.chain(ours.app_data.keys())
+			.chain(theirs.app_data.keys());
+		for key in keys {
+			let merged = merge_field(
+				&base.app_data.get(key),
+				&ours.app_data.get(key),
+				&theirs.app_data.get(key),
+			);
+			if let Some(data) = merged {
+				app_data.insert(key.clone(), data.clone());
+			}
+		}
+
+		Ok(MergeResult {
+			icon: Icon {
+				version: ours.version.clone(),
+				width: ours.width,
+				height: ours.height,
+				states,
+				app_data,
+				ancillary_chunks: ours.ancillary_chunks.clone(),
+				source: Default::default(),
+			},
+			conflicts,
+		})
+	}
+}
+
+/// Entry point for a git merge driver: merges the DMI files at `base`, `ours` and `theirs`, then writes
+/// the result over `ours`, as git expects. Returns the conflicts, if any.
+///
+/// On conflicts the merged file is still written, holding our version of each conflicted state, so
+/// that it remains a valid DMI to resolve from. To register it, with the `dmi-merge` binary:
+///
+/// ```text
+/// # .git/config
+/// [merge "dmi"]
+///     name = DMI three-way merge
+///     driver = dmi-merge %O %A %B
+/// # .gitattributes
+/// *.dmi merge=dmi
+/// ```
+pub fn merge_driver<P: AsRef<Path>>(
+	base: P,
+	ours: P,
+	theirs: P,
+) -> Result<Vec<MergeConflict>, DmiError> {
+	let load =
+		|path: &Path| -> Result<Icon, DmiError> { Icon::load(BufReader::new(File::open(path)?)) };
+	let base_icon = load(base.as_ref())?;
+	let our_icon = load(ours.as_ref())?;
+	let their_icon = load(theirs.as_ref())?;
+
+	let merged = Icon::merge3(&base_icon, &our_icon, &their_icon)?;
+	let mut writter = BufWriter::new(File::create(ours.as_ref())?);
+	merged.icon.save(&mut writter)?;
+	Ok(merged.conflicts)
+}
+
+fn merge_state(
+	base: &IconState,
+	ours: &IconState,
+	theirs: &IconState,
+) -> Result<IconState, ConflictKind> {
+	let pixels_source = match (same_pixels(base, ours), same_pixels(base, theirs)) {
+		(true, _) => theirs,
+		(false, true) => ours,
+		(false, false) if same_pixels(ours, theirs) => ours,
+		(false, false) => return Err(ConflictKind::BothModified),
+	};
+
+	let merged = IconState {
+		name: ours.name.clone(),
+		dirs: pixels_source.dirs,
+		frames: pixels_source.frames,
+		images: pixels_source.images.clone(),
+		delay: merge_field(&base.delay, &ours.delay, &theirs.delay),
+		loop_flag: merge_field(&base.loop_flag, &ours.loop_flag, &theirs.loop_flag),
+		rewind: merge_field(&base.rewind, &ours.rewind, &theirs.rewind),
+		movement: merge_field(&base.movement, &ours.movement, &theirs.movement),
+		hotspot: merge_field(&base.hotspot, &ours.hotspot, &theirs.hotspot),
+		unknown_settings: merge_field(
+			&base.unknown_settings,
+			&ours.unknown_settings,
+			&theirs.unknown_settings,
+		),
+	};
+
+	if merged.frames > 1
+		&& merged
+			.delay
+			.as_ref()
+			.is_none_or(|delay| delay.len() as u32 != merged.frames)
+	{
+		return Err(ConflictKind::InconsistentMetadata);
+	}
+
+	Ok(merged)
+}
@@ -0,0 +1,577 @@
+use crate::error::DmiError;
+use crate::icon::{DmiVersion, Hotspot, Looping};
+use crate::quirks::{round_dirs, Quirk};
+use crate::warning::DmiWarning;
+use crate::{ztxt, RawDmi};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::prelude::*;
+use std::iter::Peekable;
+
+/// Icon size BYOND assumes when none is given, that of `world.icon_size` by default.
+const DEFAULT_ICON_SIZE: u32 = 32;
+
+/// The description stored in the zTXt chunk of a DMI file, without any of the image data.
+///
+/// Loading this is much cheaper than a full [crate::icon::Icon], as the pixels are never decoded,
+/// which makes it the right tool to inspect whole icon trees.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct DmiMetadata {
+	pub version: DmiVersion,
+	pub width: u32,
+	pub height: u32,
+	pub states: Vec<StateMetadata>,
+}
+
+/// The settings of a single icon_state, as stated in the DMI description.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StateMetadata {
+	pub name: String,
+	pub dirs: u8,
+	pub frames: u32,
+	pub delay: Option<Vec<f32>>,
+	pub loop_flag: Looping,
+	pub rewind: bool,
+	pub movement: bool,
+	pub hotspot: Option<Hotspot>,
+	pub unknown_settings: Option<HashMap<String, String>>,
+}
+
+impl StateMetadata {
+	/// Number of sprites the state takes on the sheet, one per dir and frame.
+	pub fn image_count(&self) -> u64 {
+		self.dirs as u64 * self.frames as u64
+	}
+}
+
+impl Default for StateMetadata {
+	fn default() -> Self {
+		Self {
+			name: String::new(),
+			dirs: 1,
+			frames: 1,
+			delay: None,
+			loop_flag: Looping::Indefinitely,
+			rewind: false,
+			movement: false,
+			hotspot: None,
+			unknown_settings: None,
+		}
+	}
+}
+
+impl DmiMetadata {
+	/// Loads the metadata of a DMI file, skipping over its image data, see [RawDmi::load_meta].
+	pub fn load<R: Read>(reader: R) -> Result<DmiMetadata, DmiError> {
+		let raw_dmi = RawDmi::load_meta(reader)?;
+		DmiMetadata::from_raw_dmi(&raw_dmi)
+	}
+
+	/// Decompresses and parses the zTXt chunk of `raw_dmi`.
+	pub fn from_raw_dmi(raw_dmi: &RawDmi) -> Result<DmiMetadata, DmiError> {
+		DmiMetadata::parse(&description_text(raw_dmi)?)
+	}
+
+	/// Writes a stable, diff-friendly listing of the metadata, meant for `git diff` through a textconv
+	/// filter. States are sorted by name, keeping the file order between states sharing a name, and
+	/// every setting is written explicitly, in a fixed order. Unknown settings are sorted by key.
+	pub fn dump(&self) -> String {
+		let mut dump = format!(
+			"version = {}\nwidth = {}\nheight = {}\nstates = {}\n",
+			self.version,
+			self.width,
+			self.height,
+			self.states.len()
+		);
+
+		let mut states: Vec<&StateMetadata> = self.states.iter().collect();
+		states.sort_by(|first, second| first.name.cmp(&second.name));
+		for state in states {
+			dump.push_str(&format!(
+				"\nstate \"{}\"\n\tdirs = {}\n\tframes = {}\n",
+				state.name, state.dirs, state.frames
+			));
+			if let Some(delay) = &state.delay {
+				let delay: Vec<String> = delay.iter().map(|delay| delay.to_string()).collect();
+				dump.push_str(&format!("\tdelay = {}\n", delay.join(",")));
+			}
+			match state.loop_flag {
+				Looping::Indefinitely => dump.push_str("\tloop = indefinitely\n"),
+				Looping::NTimes(times) => dump.push_str(&format!("\tloop = {}\n", times)),
+			}
+			dump.push_str(&format!(
+				"\trewind = {}\n\tmovement = {}\n",
+				state.rewind as u8, state.movement as u8
+			));
+			if let Some(Hotspot { x, y }) = state.hotspot {
+				dump.push_str(&format!("\thotspot = {},{}\n", x, y));
+			}
+			if let Some(unknown_settings) = &state.unknown_settings {
+				let mut unknown_settings: Vec<(&String, &String)> = unknown_settings.iter().collect();
+				unknown_settings.sort();
+				for (setting, value) in unknown_settings {
+					dump.push_str(&format!("\t{} = {}\n", setting, value));
+				}
+			}
+		}
+		dump
+	}
+
+	/// Parses a DMI description, the text starting with `# BEGIN DMI` and ending with `# END DMI`.
+	///
+	/// The text is read through [DescriptionLine], which borrows from it: allocations are limited to
+	/// the values kept in the result.
+	pub fn parse(text: &str) -> Result<DmiMetadata, DmiError> {
+		let description = Description::parse(text, None)?;
+		let mut states = Vec::with_capacity(description.blocks.len());
+		for block in description.blocks {
+			states.push(block.state?);
+		}
+		Ok(DmiMetadata {
+			version: description.version,
+			width: description.width,
+			height: description.height,
+			states,
+		})
+	}
+
+	/// Like [DmiMetadata::parse], but carries on past malformed state blocks. The states that could
+	/// be parsed are returned along with the errors of the others, so that every problem of a file
+	/// can be reported at once. Problems with the header or trailer of the description are still
+	/// returned as an error.
+	pub fn parse_collecting(text: &str) -> Result<(DmiMetadata, Vec<DmiError>), DmiError> {
+		let description = Description::parse(text, None)?;
+		let mut states = vec![];
+		let mut errors = vec![];
+		for block in description.blocks {
+			match block.state {
+				Ok(state) => states.push(state),
+				Err(error) => errors.push(error),
+			}
+		}
+		let metadata = DmiMetadata {
+			version: description.version,
+			width: description.width,
+			height: description.height,
+			states,
+		};
+		Ok((metadata, errors))
+	}
+}
+
+/// A parsed description, keeping the outcome of each state block apart.
+pub(crate) struct Description {
+	pub version: DmiVersion,
+	pub width: u32,
+	pub height: u32,
+	pub blocks: Vec<StateBlock>,
+}
+
+/// A `state = "..."` line and the settings following it.
+pub(crate) struct StateBlock {
+	pub state: Result<StateMetadata, DmiError>,
+	/// Number of sprites the state takes on the sheet, if its dirs and frames could be read even
+	/// though the rest of the block is malformed. Only needed to slice the sheet.
+	#[cfg_attr(not(feature = "images"), allow(dead_code))]
+	pub image_count: Option<u64>,
+}
+
+impl Description {
+	#[cfg_attr(not(feature = "images"), allow(dead_code))]
+	pub fn from_raw_dmi(
+		raw_dmi: &RawDmi,
+		quirks: Option<&mut Vec<DmiWarning>>,
+	) -> Result<Description, DmiError> {
+		Description::parse(&description_text(raw_dmi)?, quirks)
+	}
+
+	/// Parses the header and splits the states into blocks. Only errors that prevent finding the
+	/// state blocks are returned, the others are kept in their [StateBlock].
+	///
+	/// With `quirks`, the deviations listed in [Quirk] are accepted and reported there, and a missing
+	/// width or height is read as 32, see [DmiWarning::MissingSize].
+	pub fn parse(
+		text: &str,
+		mut quirks: Option<&mut Vec<DmiWarning>>,
+	) -> Result<Description, DmiError> {
+		let mut lines = text.lines().peekable();
+
+		let current_line = lines.next();
+		if current_line != Some("# BEGIN DMI") {
+			return Err(DmiError::Generic(format!(
+				"Error loading icon: no DMI header found. Beginning: {:#?}",
+				current_line
+			)));
+		};
+
+		let version = header_setting(lines.next(), "version", false)?.to_string();
+		let width = header_size(&mut lines, "width", &mut quirks)?;
+		let height = header_size(&mut lines, "height", &mut quirks)?;
+
+		if width == 0 || height == 0 {
+			return Err(DmiError::Generic(format!(
+				"Error loading icon: invalid width ({}) / height ({}) values.",
+				width, height
+			)));
+		};
+
+		let mut current_line = match lines.next() {
+			Some(thing) => thing,
+			None => {
+				return Err(DmiError::Generic(
+					"Error loading icon: no DMI trailer nor states found.".to_string(),
+				))
+			}
+		};
+
+		let mut blocks = vec![];
+
+		loop {
+			let name = match DescriptionLine::parse(current_line)? {
+				DescriptionLine::End => break,
+				DescriptionLine::Setting {
+					key: "state",
+					value,
+					indented: false,
+				} => unquote_state_name(value).map(Cow::into_owned),
+				_ => {
+					return Err(DmiError::Generic(format!(
+						"Error loading icon: improper state found: {:#?}",
+						current_line
+					)))
+				}
+			};
+
+			let mut state = StateMetadata::default();
+			let mut dirs = None;
+			let mut frames = None;
+			// The first problem of the block. Parsing goes on to find where the next block starts.
+			let mut block_error = None;
+			let mut block_quirks = vec![];
+			let mut seen_keys = HashSet::new();
+
+			loop {
+				current_line = match lines.next() {
+					Some(thing) => thing,
+					None => {
+						return Err(DmiError::Generic(
+							"Error loading icon: no DMI trailer found.".to_string(),
+						))
+					}
+				};
+
+				let setting = match DescriptionLine::parse(current_line) {
+					Ok(
+						DescriptionLine::End
+						| DescriptionLine::Setting {
+							key: "state",
+							indented: false,
+							..
+						},
+					) => break,
+					Ok(DescriptionLine::Setting { key, value, .. }) => {
+						if quirks.is_some() && !seen_keys.insert(key) {
+							block_quirks.push(Quirk::DuplicateSetting {
+								key: key.to_string(),
+							});
+						}
+						parse_state_setting(
+							&mut state,
+							&mut dirs,
+							&mut frames,
+							key,
+							value,
+							quirks.is_some(),
+						)
+						.map(|quirk| block_quirks.extend(quirk))
+					}
+					Ok(DescriptionLine::Begin) => Err(DmiError::Generic(format!(
+						"Error loading icon: improper state found: {:#?}",
+						current_line
+					))),
+					Err(error) => Err(error),
+				};
+				if let Err(error) = setting {
+					block_error.get_or_insert(error);
+				}
+			}
+
+			let image_count = match (dirs, frames) {
+				(Some(dirs), Some(frames)) => Some(dirs as u64 * frames as u64),
+				_ => None,
+			};
+			// The sheet still holds the sprites of the written dirs, see Icon::assemble.
+			if let (Some(_), Some(from)) = (&quirks, dirs) {
+				let to = round_dirs(from);
+				if to != from {
+					block_quirks.push(Quirk::DirsRounded { from, to });
+					dirs = Some(to);
+				}
+			}
+			if let (Some(warnings), Ok(name)) = (&mut quirks, &name) {
+				warnings.extend(
+					block_quirks
+						.into_iter()
+						.map(|quirk| DmiWarning::MetadataQuirk {
+							state: name.clone(),
+							quirk,
+						}),
+				);
+			}
+			let state = match (name, block_error, dirs, frames) {
+				(Err(error), ..) | (Ok(_), Some(error), ..) => Err(error),
+				(Ok(name), None, Some(dirs), Some(frames)) => Ok(StateMetadata {
+					name,
+					dirs,
+					frames,
+					..state
+				}),
+				(Ok(_), None, ..) => Err(DmiError::Generic(format!(
+					"Error loading icon: state lacks essential settings. dirs: {:#?}. frames: {:#?}.",
+					dirs, frames
+				))),
+			};
+			blocks.push(StateBlock { state, image_count });
+		}
+
+		Ok(Description {
+			version: DmiVersion(version),
+			width,
+			height,
+			blocks,
+		})
+	}
+}
+
+/// Decompresses the zTXt chunk of `raw_dmi`, followed by the chunks continuing it, see
+/// [ztxt::create_split_ztxt_chunks]. Files without one fall back to an iTXt or tEXt chunk
+/// under the same keyword, see [crate::itxt::MetadataChunk], as some tools write the metadata
+/// uncompressed.
+fn description_text(raw_dmi: &RawDmi) -> Result<String, DmiError> {
+	let chunk_ztxt = match &raw_dmi.chunk_ztxt {
+		Some(chunk) => chunk,
+		None => {
+			return raw_dmi
+				.itxt(ztxt::DESCRIPTION_KEYWORD)
+				.or_else(|| raw_dmi.text(ztxt::DESCRIPTION_KEYWORD))
+				.ok_or_else(|| {
+					DmiError::Generic(
+						"Error loading icon: no zTXt chunk or Description iTXt or tEXt chunk found."
+							.to_string(),
+					)
+				})
+		}
+	};
+	let mut decompressed_text = chunk_ztxt.data.decode()?;
+	for chunk in raw_dmi.other_chunks.iter().flatten() {
+		if ztxt::is_continuation(chunk, &chunk_ztxt.data.keyword) {
+			let continuation = ztxt::RawZtxtChunk::try_from(chunk.clone())?;
+			decompressed_text.extend(continuation.data.decode()?);
+		}
+	}
+	let decompressed_text = String::from_utf8(decompressed_text)?;
+	Ok(decompressed_text)
+}
+
+/// Applies one setting of a state block to `state`. `dirs` and `frames` are tracked apart, as they
+/// are required. With `quirks`, values otherwise rejected may be accepted, returning which [Quirk]
+/// was.
+fn parse_state_setting(
+	state: &mut StateMetadata,
+	dirs: &mut Option<u8>,
+	frames: &mut Option<u32>,
+	key: &str,
+	value: &str,
+	quirks: bool,
+) -> Result<Option<Quirk>, DmiError> {
+	match key {
+		"dirs" => *dirs = Some(value.parse::<u8>()?),
+		"frames" => *frames = Some(value.parse::<u32>()?),
+		"delay" => {
+			let mut delay_vector = vec![];
+			for text_entry in value.split_terminator(',') {
+				delay_vector.push(text_entry.parse::<f32>()?);
+			}
+			state.delay = Some(delay_vector);
+		}
+		"loop" => match value.parse::<u32>() {
+			Ok(times) => state.loop_flag = Looping::new(times),
+			Err(_) if quirks && value.parse::<i64>().is_ok_and(|times| times < 0) => {
+				state.loop_flag = Looping::Indefinitely;
+				return Ok(Some(Quirk::NegativeLoop));
+			}
+			Err(error) => return Err(error.into()),
+		},
+		"rewind" => state.rewind = value.parse::<u8>()? != 0,
+		"movement" => state.movement = value.parse::<u8>()? != 0,
+		"hotspot" => {
+			let mut text_coordinates = value.split_terminator(',');
+			// Hotspot includes a mysterious 3rd parameter that always seems to be 1.
+			match (
+				text_coordinates.next(),
+				text_coordinates.next(),
+				text_coordinates.next(),
+				text_coordinates.next(),
+			) {
+				(Some(x), Some(y), Some(_), None) => {
+					state.hotspot = Some(Hotspot {
+						x: x.parse::<u32>()?,
+						y: y.parse::<u32>()?,
+					})
+				}
+				(Some(x), Some(y), None, None) if quirks => {
+					state.hotspot = Some(Hotspot {
+						x: x.parse::<u32>()?,
+						y: y.parse::<u32>()?,
+					});
+					return Ok(Some(Quirk::TwoValueHotspot));
+				}
+				_ => {
+					return Err(DmiError::Generic(format!(
+						"Error loading icon: improper hotspot found: {:#?}",
+						value
+					)))
+				}
+			}
+		}
+		_ => {
+			state
+				.unknown_settings
+				.get_or_insert_with(HashMap::new)
+				.insert(key.to_string(), value.to_string());
+		}
+	};
+	Ok(None)
+}
+
+/// A line of a DMI description, borrowed from the description text.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DescriptionLine<'a> {
+	/// The `# BEGIN DMI` header.
+	Begin,
+	/// The `# END DMI` trailer.
+	End,
+	/// A `key = value` setting. Settings of the sheet and of each state are indented with a tab,
+	/// which is not part of `key`, nor is any other leading tab or space. Values are left as written, quotes included.
+	Setting {
+		key: &'a str,
+		value: &'a str,
+		indented: bool,
+	},
+}
+
+impl<'a> DescriptionLine<'a> {
+	pub fn parse(line: &'a str) -> Result<DescriptionLine<'a>, DmiError> {
+		if line == "# BEGIN DMI" {
+			return Ok(DescriptionLine::Begin);
+		}
+		if line.contains("# END DMI") {
+			return Ok(DescriptionLine::End);
+		}
+		let (key, value) = match line.split_once(" = ") {
+			Some(setting) => setting,
+			None => {
+				return Err(DmiError::Generic(format!(
+					"Error loading icon: improper line found in metadata: {:#?}",
+					line
+				)))
+			}
+		};
+		// Some tools indent with several tabs or with spaces.
+		let unindented = key.trim_start_matches(['\t', ' ']);
+		Ok(DescriptionLine::Setting {
+			key: unindented,
+			value,
+			indented: unindented.len() != key.len(),
+		})
+	}
+}
+
+/// The value of the `width` or `height` header line. With `quirks`, a missing line is read as
+/// [DEFAULT_ICON_SIZE] and reported there.
+fn header_size<'a, I: Iterator<Item = &'a str>>(
+	lines: &mut Peekable<I>,
+	key: &str,
+	quirks: &mut Option<&mut Vec<DmiWarning>>,
+) -> Result<u32, DmiError> {
+	if let Some(warnings) = quirks {
+		if header_setting(lines.peek().copied(), key, true).is_err() {
+			warnings.push(DmiWarning::MissingSize {
+				key: key.to_string(),
+				assumed: DEFAULT_ICON_SIZE,
+			});
+			return Ok(DEFAULT_ICON_SIZE);
+		}
+	}
+	Ok(header_setting(lines.next(), key, true)?.parse::<u32>()?)
+}
+
+/// Reads one of the settings at the top of the description, which must come in a fixed order.
+fn header_setting<'a>(
+	line: Option<&'a str>,
+	expected_key: &str,
+	expected_indented: bool,
+) -> Result<&'a str, DmiError> {
+	let line = match line {
+		Some(thing) => thing,
+		None => {
+			return Err(DmiError::Generic(format!(
+				"Error loading icon: no {} found.",
+				expected_key
+			)))
+		}
+	};
+	match DescriptionLine::parse(line) {
+		Ok(DescriptionLine::Setting {
+			key,
+			value,
+			indented,
+		}) if key == expected_key && indented == expected_indented => Ok(value),
+		_ => Err(DmiError::Generic(format!(
+			"Error loading icon: improper {} found: {:#?}",
+			expected_key, line
+		))),
+	}
+}
+
+/// Strips the quotes around a state name, resolving the `\"` and `\\` escapes. Only allocates if
+/// the name holds escapes.
+pub fn unquote_state_name(value: &str) -> Result<Cow<'_, str>, DmiError> {
+	let name = match value
+		.strip_prefix('"')
+		.and_then(|value| value.strip_suffix('"'))
+	{
+		Some(name) => name,
+		None => return Err(DmiError::Generic(format!("Error loading icon: invalid name icon_state found in metadata, should be preceded and succeeded by double-quotes (\"): {:#?}", value))),
+	};
+	if !name.contains('\\') {
+		return Ok(Cow::Borrowed(name));
+	}
+	let mut unescaped = String::with_capacity(name.len());
+	let mut characters = name.chars();
+	while let Some(character) = characters.next() {
+		match (character, characters.clone().next()) {
+			('\\', Some(escaped @ ('"' | '\\'))) => {
+				unescaped.push(escaped);
+				characters.next();
+			}
+			(character, _) => unescaped.push(character),
+		}
+	}
+	Ok(Cow::Owned(unescaped))
+}
+
+/// Quotes a state name for the description, escaping `"` and `\`, the inverse of
+/// [unquote_state_name].
+pub fn quote_state_name(name: &str) -> String {
+	let mut quoted = String::with_capacity(name.len() + 2);
+	quoted.push('"');
+	for character in name.chars() {
+		if matches!(character, '"' | '\\') {
+			quoted.push('\\');
+		}
+		quoted.push(character);
+	}
+	quoted.push('"');
+	quoted
+}
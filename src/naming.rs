@@ -0,0 +1,218 @@
+use crate::dirs::Dirs;
+use crate::icon::{Frame, DIR_ORDERING};
+
+/// How individual sprites are named when an icon is exploded into separate files.
+///
+/// The template is any text with placeholders: `{state}` for the icon_state name, `{dir}` for the
+/// direction, written according to the [DirStyle], and `{frame}` for the one-based frame number.
+/// The default is `{state}_{dir}_{frame}` with lowercase dir names, such as `idle_south_1`.
+///
+/// Names can be parsed back with [NamingScheme::parse]. Placeholders missing from the template are
+/// parsed as an empty state name, south, and the first frame respectively.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NamingScheme {
+	template: String,
+	dir_style: DirStyle,
+}
+
+/// How the `{dir}` placeholder of a [NamingScheme] is written.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum DirStyle {
+	/// `south`, `northeast`...
+	#[default]
+	Lowercase,
+	/// `SOUTH`, `NORTHEAST`...
+	Uppercase,
+	/// `s`, `ne`...
+	Abbreviated,
+	/// The numeric DM direction: `2` for south, `5` for northeast...
+	Byond,
+	/// The zero-based position in [DIR_ORDERING]: `0` for south, `6` for northeast...
+	Index,
+}
+
+impl DirStyle {
+	/// Writes `dir` in this style. Dirs outside of [DIR_ORDERING] are written as their numeric value.
+	pub fn dir_name(self, dir: &Dirs) -> String {
+		let Some(index) = DIR_ORDERING.iter().position(|ordered| ordered == dir) else {
+			return dir.bits().to_string();
+		};
+		match self {
+			DirStyle::Lowercase => DIR_NAMES[index].to_string(),
+			DirStyle::Uppercase => DIR_NAMES[index].to_uppercase(),
+			DirStyle::Abbreviated => DIR_ABBREVIATIONS[index].to_string(),
+			DirStyle::Byond => dir.bits().to_string(),
+			DirStyle::Index => index.to_string(),
+		}
+	}
+}
+
+/// Names of the dirs of [DIR_ORDERING], in the same order.
+const DIR_NAMES: [&str; 8] = [
+	"south",
+	"north",
+	"east",
+	"west",
+	"southeast",
+	"southwest",
+	"northeast",
+	"northwest",
+];
+
+const DIR_ABBREVIATIONS: [&str; 8] = ["s", "n", "e", "w", "se", "sw", "ne", "nw"];
+
+/// The sprite a name refers to, see [NamingScheme::parse].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SpriteName {
+	pub state: String,
+	pub dir: Dirs,
+	pub frame: Frame,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Token<'a> {
+	Literal(&'a str),
+	State,
+	Dir,
+	Frame,
+}
+
+impl NamingScheme {
+	pub fn new(template: &str) -> NamingScheme {
+		NamingScheme {
+			template: template.to_string(),
+			dir_style: DirStyle::default(),
+		}
+	}
+
+	/// Uses `dir_style` for the `{dir}` placeholder.
+	pub fn with_dir_style(mut self, dir_style: DirStyle) -> NamingScheme {
+		self.dir_style = dir_style;
+		self
+	}
+
+	pub fn template(&self) -> &str {
+		&self.template
+	}
+
+	pub fn dir_style(&self) -> DirStyle {
+		self.dir_style
+	}
+
+	/// The name of the sprite facing `dir` at `frame` of the state called `state`.
+	pub fn name(&self, state: &str, dir: &Dirs, frame: Frame) -> String {
+		self
+			.template
+			.replace("{dir}", &self.dir_style.dir_name(dir))
+			.replace("{frame}", &frame.to_string())
+			// Last, so that placeholders within the state name are left alone.
+			.replace("{state}", state)
+	}
+
+	/// Recovers the state, dir and frame out of a name produced by [NamingScheme::name].
+	/// Returns None if `name` does not follow the template.
+	///
+	/// State names may contain the text separating the placeholders: `a_b_south_1` is parsed as
+	/// state `a_b` with the default template.
+	pub fn parse(&self, name: &str) -> Option<SpriteName> {
+		let mut sprite_name = SpriteName {
+			state: String::new(),
+			dir: Dirs::SOUTH,
+			frame: Frame::FIRST,
+		};
+		match_tokens(&self.tokens(), self.dir_style, name, &mut sprite_name).then_some(sprite_name)
+	}
+
+	fn tokens(&self) -> Vec<Token<'_>> {
+		let mut tokens = vec![];
+		let mut rest = self.template.as_str();
+		while !rest.is_empty() {
+			let placeholder = [
+				("{state}", Token::State),
+				("{dir}", Token::Dir),
+				("{frame}", Token::Frame),
+			]
+			.into_iter()
+			.filter_map(|(text, token)| rest.find(text).map(|position| (position, text, token)))
+			.min_by_key(|(position, _, _)| *position);
+			match placeholder {
+				Some((position, text, token)) => {
+					if position > 0 {
+						tokens.push(Token::Literal(&rest[..position]));
+					}
+					tokens.push(token);
+					rest = &rest[position + text.len()..];
+				}
+				None => {
+					tokens.push(Token::Literal(rest));
+					rest = "";
+				}
+			}
+		}
+		tokens
+	}
+}
+
+impl Default for NamingScheme {
+	fn default() -> Self {
+		NamingScheme::new("{state}_{dir}_{frame}")
+	}
+}
+
+/// Matches `name` against `tokens`, backtracking over the possible lengths of each placeholder.
+fn match_tokens(
+	tokens: &[Token],
+	dir_style: DirStyle,
+	name: &str,
+	sprite_name: &mut SpriteName,
+) -> bool {
+	let Some((token, rest_tokens)) = tokens.split_first() else {
+		return name.is_empty();
+	};
+	match token {
+		Token::Literal(literal) => name
+			.strip_prefix(literal)
+			.is_some_and(|rest| match_tokens(rest_tokens, dir_style, rest, sprite_name)),
+		Token::State => {
+			// Longest first, the separators usually come after the state name.
+			for split in (0..=name.len())
+				.rev()
+				.filter(|&split| name.is_char_boundary(split))
+			{
+				if match_tokens(rest_tokens, dir_style, &name[split..], sprite_name) {
+					sprite_name.state = name[..split].to_string();
+					return true;
+				}
+			}
+			false
+		}
+		Token::Dir => {
+			for dir in DIR_ORDERING {
+				if let Some(rest) = name.strip_prefix(&dir_style.dir_name(&dir)) {
+					if match_tokens(rest_tokens, dir_style, rest, sprite_name) {
+						sprite_name.dir = dir;
+						return true;
+					}
+				}
+			}
+			false
+		}
+		Token::Frame => {
+			// Frame numbers are written without leading zeros, which also disambiguates numeric dirs.
+			if name.starts_with('0') {
+				return false;
+			}
+			let digits = name.bytes().take_while(u8::is_ascii_digit).count();
+			for split in (1..=digits).rev() {
+				let Some(frame) = name[..split].parse().ok().and_then(Frame::new) else {
+					continue;
+				};
+				if match_tokens(rest_tokens, dir_style, &name[split..], sprite_name) {
+					sprite_name.frame = frame;
+					return true;
+				}
+			}
+			false
+		}
+	}
+}
@@ -0,0 +1,144 @@
+use crate::chunk::{RawGenericChunk, IDAT_TYPE};
+use crate::encode::{filter_best, filter_row, paeth};
+use crate::error::DmiError;
+use crate::icon::Icon;
+use crate::RawDmi;
+use std::io::Write;
+
+/// The highest compression level of miniz_oxide, slower than the levels of the encoders used to
+/// save.
+const BEST_LEVEL: u8 = 10;
+
+impl RawDmi {
+	/// Compresses the image data again as small as possible: the scanlines are filtered with each
+	/// fixed PNG filter and with adaptive filtering, every candidate is compressed at the highest
+	/// level, and the smallest one is kept as a single IDAT chunk. The pixels are unchanged.
+	///
+	/// Returns whether the image data shrank. It is left as it is otherwise, as it was already
+	/// smaller. Fails for interlaced images.
+	pub fn optimize(&mut self) -> Result<bool, DmiError> {
+		let ihdr = self.ihdr()?;
+		let width = ihdr.width as usize;
+		let height = ihdr.height as usize;
+		let bits_per_pixel = channels(ihdr.color_type)? * ihdr.bit_depth as usize;
+		if ihdr.interlace_method != 0 {
+			return Err(DmiError::Generic(
+				"Failed to optimize DMI. Interlaced images are not supported.".to_string(),
+			));
+		}
+		let row_length = (width * bits_per_pixel).div_ceil(8);
+		let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+
+		let filtered = self.inflated_idat_bytes()?;
+		if filtered.len() != (row_length + 1) * height {
+			return Err(DmiError::Generic(format!(
+				"Failed to optimize DMI. The image data is {} bytes long, {} expected.",
+				filtered.len(),
+				(row_length + 1) * height
+			)));
+		}
+		let rows = unfilter(&filtered, row_length, bytes_per_pixel)?;
+
+		let current_length: usize = self.chunks_idat.iter().map(|chunk| chunk.data.len()).sum();
+		let smallest = [Some(0), Some(1), Some(2), Some(3), Some(4), None]
+			.into_iter()
+			.map(|filter_type| {
+				let filtered = refilter(&rows, bytes_per_pixel, filter_type);
+				miniz_oxide::deflate::compress_to_vec_zlib(&filtered, BEST_LEVEL)
+			})
+			.min_by_key(Vec::len)
+			.unwrap();
+		if smallest.len() >= current_length {
+			return Ok(false);
+		}
+		self.chunks_idat = vec![RawGenericChunk::new(IDAT_TYPE, smallest)?];
+		Ok(true)
+	}
+}
+
+impl Icon {
+	/// Saves the icon like [Icon::save], then shrinks the file with [RawDmi::optimize]. Meant for
+	/// files stored in version control, where size matters more than the time taken to save.
+	pub fn save_optimized<W: Write>(&self, writter: &mut W) -> Result<usize, DmiError> {
+		let mut saved = vec![];
+		self.save(&mut saved)?;
+		let mut raw_dmi = RawDmi::load(&saved[..])?;
+		raw_dmi.optimize()?;
+		raw_dmi.save(writter)
+	}
+}
+
+/// Number of samples per pixel of a PNG color type.
+fn channels(color_type: u8) -> Result<usize, DmiError> {
+	match color_type {
+		0 | 3 => Ok(1),
+		2 => Ok(3),
+		4 => Ok(2),
+		6 => Ok(4),
+		color_type => Err(DmiError::Generic(format!(
+			"Failed to optimize DMI. Unknown color type {color_type}."
+		))),
+	}
+}
+
+/// Reverts the filtering of each scanline of `filtered`, each prefixed with its filter type.
+fn unfilter(
+	filtered: &[u8],
+	row_length: usize,
+	bytes_per_pixel: usize,
+) -> Result<Vec<Vec<u8>>, DmiError> {
+	let mut rows: Vec<Vec<u8>> = Vec::with_capacity(filtered.len() / (row_length + 1));
+	let empty_row = vec![0; row_length];
+	for line in filtered.chunks(row_length + 1) {
+		let (filter_type, data) = (line[0], &line[1..]);
+		let previous = rows.last().unwrap_or(&empty_row);
+		let mut row = Vec::with_capacity(row_length);
+		for index in 0..row_length {
+			let left = index
+				.checked_sub(bytes_per_pixel)
+				.map_or(0, |left| row[left]);
+			let up = previous[index];
+			let up_left = index
+				.checked_sub(bytes_per_pixel)
+				.map_or(0, |left| previous[left]);
+			let predictor = match filter_type {
+				0 => 0,
+				1 => left,
+				2 => up,
+				3 => ((left as u16 + up as u16) / 2) as u8,
+				4 => paeth(left, up, up_left),
+				filter_type => {
+					return Err(DmiError::Generic(format!(
+						"Failed to optimize DMI. Unknown filter type {filter_type}."
+					)))
+				}
+			};
+			row.push(data[index].wrapping_add(predictor));
+		}
+		rows.push(row);
+	}
+	Ok(rows)
+}
+
+/// Filters every row with `filter_type`, or the best filter for each row if None.
+fn refilter(rows: &[Vec<u8>], bytes_per_pixel: usize, filter_type: Option<u8>) -> Vec<u8> {
+	let mut filtered = vec![];
+	let empty_row = vec![0; rows.first().map_or(0, Vec::len)];
+	for (index, row) in rows.iter().enumerate() {
+		let previous = if index == 0 {
+			&empty_row
+		} else {
+			&rows[index - 1]
+		};
+		let (filter_type, output) = match filter_type {
+			Some(filter_type) => (
+				filter_type,
+				filter_row(filter_type, row, previous, bytes_per_pixel),
+			),
+			None => filter_best(row, previous, bytes_per_pixel),
+		};
+		filtered.push(filter_type);
+		filtered.extend(output);
+	}
+	filtered
+}
@@ -0,0 +1,344 @@
+use crate::appdata::APP_DATA_TYPE;
+use crate::chunk::{ChunkHandling, ChunkPolicy, ChunkRecord, ChunkType, DuplicateChunkPolicy};
+use crate::encode::{CompressionLevel, FilterStrategy, OutputColorType, SheetLayout};
+use crate::error::DmiError;
+use crate::icon::{DecodeBuffers, Icon};
+use crate::itxt::{self, MetadataChunk};
+use crate::warning::DmiWarning;
+use crate::{ztxt, RawDmi};
+use std::io::{Read, Write};
+
+/// Callback receiving each chunk of a loaded file, see [LoadOptions::on_chunk].
+pub type ChunkCallback<'a> = Box<dyn FnMut(&ChunkRecord) + 'a>;
+
+/// Options for [Icon::load_with].
+#[derive(Default)]
+pub struct LoadOptions<'a> {
+	/// Called for every chunk of the file with how the load handled it, in the order
+	/// [RawDmi::save] writes them: IHDR, zTXt, PLTE, ancillary chunks, IDAT then IEND.
+	pub on_chunk: Option<ChunkCallback<'a>>,
+	/// Rejects files whose zTXt metadata comes after the image data. Off by default: some encoders
+	/// write it there, and the PNG spec allows it.
+	pub strict_chunk_order: bool,
+	/// What to do with repeated IHDR, zTXt or PLTE chunks. Keeps the last one by default.
+	pub duplicate_chunks: DuplicateChunkPolicy,
+	/// Rejects files with 16 bits per channel. Off by default, in which case their sprites are
+	/// rounded to RGBA8, losing precision.
+	pub reject_16_bit: bool,
+	/// Turns on lenient mode when set, the default being strict. Lenient mode tolerates recoverable
+	/// problems rather than failing: ancillary chunks with a bad CRC are dropped, a missing width or
+	/// height is read as 32 and the nonstandard metadata listed in [crate::quirks::Quirk], such as
+	/// odd dir counts, is accepted. Each of them is pushed here, along with the warnings of
+	/// [Icon::load_with_warnings].
+	pub lenient: Option<&'a mut Vec<DmiWarning>>,
+}
+
+impl<'a> LoadOptions<'a> {
+	pub fn new() -> LoadOptions<'a> {
+		LoadOptions {
+			..Default::default()
+		}
+	}
+
+	/// Sets [LoadOptions::on_chunk].
+	pub fn on_chunk<F: FnMut(&ChunkRecord) + 'a>(mut self, on_chunk: F) -> LoadOptions<'a> {
+		self.on_chunk = Some(Box::new(on_chunk));
+		self
+	}
+
+	/// Sets [LoadOptions::duplicate_chunks].
+	pub fn duplicate_chunks(mut self, duplicate_chunks: DuplicateChunkPolicy) -> LoadOptions<'a> {
+		self.duplicate_chunks = duplicate_chunks;
+		self
+	}
+
+	/// Sets [LoadOptions::lenient].
+	pub fn lenient(mut self, warnings: &'a mut Vec<DmiWarning>) -> LoadOptions<'a> {
+		self.lenient = Some(warnings);
+		self
+	}
+
+	/// Sets [LoadOptions::reject_16_bit].
+	pub fn reject_16_bit(mut self, reject_16_bit: bool) -> LoadOptions<'a> {
+		self.reject_16_bit = reject_16_bit;
+		self
+	}
+
+	/// Sets [LoadOptions::strict_chunk_order].
+	pub fn strict_chunk_order(mut self, strict_chunk_order: bool) -> LoadOptions<'a> {
+		self.strict_chunk_order = strict_chunk_order;
+		self
+	}
+}
+
+/// Options for [Icon::save_with].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SaveOptions {
+	/// Which of the [Icon::ancillary_chunks] are written. Keeps them all by default, like [Icon::save].
+	pub chunk_policy: ChunkPolicy,
+	/// Pixel format of the sheet. RGBA8 by default, like [Icon::save]. With RGBA8 or
+	/// [OutputColorType::Source], the image data of a loaded icon whose sprites are unchanged is
+	/// written back as it is, whatever its format.
+	pub color_type: OutputColorType,
+	/// How hard the image data is compressed. The default level by default, like [Icon::save].
+	pub compression: CompressionLevel,
+	/// The PNG filter applied to the rows of the sheet. Adaptive by default, like [Icon::save].
+	pub filter: FilterStrategy,
+	/// How sprites are arranged in the sheet. As square as possible by default, like [Icon::save].
+	pub layout: SheetLayout,
+	/// Writes hotspots as `x,y,1`, as BYOND does, rather than `x,y`. On by default. Files without the
+	/// third value are only read back in quirks mode, see [crate::quirks::Quirk::TwoValueHotspot].
+	pub hotspot_third_value: bool,
+	/// Keyword of the zTXt chunk holding the metadata. [ztxt::DESCRIPTION_KEYWORD] by default, the
+	/// only one BYOND reads. iTXt metadata, see [SaveOptions::metadata_chunk], is only found again
+	/// under [ztxt::DESCRIPTION_KEYWORD], so saving fails with any other keyword.
+	pub ztxt_keyword: String,
+	/// Which chunk holds the metadata. zTXt by default, the only one BYOND reads.
+	pub metadata_chunk: MetadataChunk,
+	/// Splits zTXt metadata longer than this many bytes across several zTXt chunks, for tools that
+	/// choke on large chunks, see [ztxt::create_split_ztxt_chunks]. Loading joins them back. None by
+	/// default, as BYOND only reads the first chunk. iTXt metadata is never split.
+	pub split_metadata_above: Option<usize>,
+	/// Always encodes the sheet again, rather than writing back unchanged image data, see
+	/// [Icon::pixels_modified]. Off by default. Unchanged image data is only written back with the
+	/// default [SaveOptions::color_type], [SaveOptions::compression], [SaveOptions::filter] and
+	/// [SaveOptions::layout].
+	pub reencode: bool,
+	/// Fails instead of saving states that share both their name and movement flag with another,
+	/// see [Icon::duplicate_states]. Off by default, as BYOND accepts them.
+	pub reject_duplicate_states: bool,
+}
+
+impl Default for SaveOptions {
+	fn default() -> Self {
+		SaveOptions {
+			chunk_policy: ChunkPolicy::default(),
+			color_type: OutputColorType::default(),
+			compression: CompressionLevel::default(),
+			filter: FilterStrategy::default(),
+			layout: SheetLayout::default(),
+			hotspot_third_value: true,
+			ztxt_keyword: ztxt::DESCRIPTION_KEYWORD.to_string(),
+			metadata_chunk: MetadataChunk::default(),
+			split_metadata_above: None,
+			reencode: false,
+			reject_duplicate_states: false,
+		}
+	}
+}
+
+impl SaveOptions {
+	pub fn new() -> SaveOptions {
+		SaveOptions {
+			..Default::default()
+		}
+	}
+
+	/// Sets [SaveOptions::chunk_policy].
+	pub fn chunk_policy(mut self, chunk_policy: ChunkPolicy) -> SaveOptions {
+		self.chunk_policy = chunk_policy;
+		self
+	}
+
+	/// Sets [SaveOptions::color_type].
+	pub fn color_type(mut self, color_type: OutputColorType) -> SaveOptions {
+		self.color_type = color_type;
+		self
+	}
+
+	/// Sets [SaveOptions::compression].
+	pub fn compression(mut self, compression: CompressionLevel) -> SaveOptions {
+		self.compression = compression;
+		self
+	}
+
+	/// Sets [SaveOptions::filter].
+	pub fn filter(mut self, filter: FilterStrategy) -> SaveOptions {
+		self.filter = filter;
+		self
+	}
+
+	/// Sets [SaveOptions::layout].
+	pub fn layout(mut self, layout: SheetLayout) -> SaveOptions {
+		self.layout = layout;
+		self
+	}
+
+	/// Sets [SaveOptions::hotspot_third_value].
+	pub fn hotspot_third_value(mut self, hotspot_third_value: bool) -> SaveOptions {
+		self.hotspot_third_value = hotspot_third_value;
+		self
+	}
+
+	/// Sets [SaveOptions::ztxt_keyword].
+	pub fn ztxt_keyword(mut self, ztxt_keyword: &str) -> SaveOptions {
+		self.ztxt_keyword = ztxt_keyword.to_string();
+		self
+	}
+
+	/// Sets [SaveOptions::metadata_chunk].
+	pub fn metadata_chunk(mut self, metadata_chunk: MetadataChunk) -> SaveOptions {
+		self.metadata_chunk = metadata_chunk;
+		self
+	}
+
+	/// Sets [SaveOptions::split_metadata_above].
+	pub fn split_metadata_above(mut self, split_metadata_above: Option<usize>) -> SaveOptions {
+		self.split_metadata_above = split_metadata_above;
+		self
+	}
+
+	/// Sets [SaveOptions::reencode].
+	pub fn reencode(mut self, reencode: bool) -> SaveOptions {
+		self.reencode = reencode;
+		self
+	}
+
+	/// Sets [SaveOptions::reject_duplicate_states].
+	pub fn reject_duplicate_states(mut self, reject_duplicate_states: bool) -> SaveOptions {
+		self.reject_duplicate_states = reject_duplicate_states;
+		self
+	}
+
+	/// Stores `signature` in `raw_dmi`, in the chunk given by [SaveOptions::metadata_chunk]. iTXt
+	/// chunks and zTXt continuation chunks come first among the ancillary chunks.
+	pub(crate) fn write_metadata(
+		&self,
+		raw_dmi: &mut RawDmi,
+		signature: &str,
+	) -> Result<(), DmiError> {
+		match self.metadata_chunk {
+			MetadataChunk::Ztxt => match self.split_metadata_above {
+				Some(max_length) if signature.len() > max_length => {
+					let (first, continuations) =
+						ztxt::create_split_ztxt_chunks(&self.ztxt_keyword, signature, max_length)?;
+					raw_dmi.chunk_ztxt = Some(first);
+					raw_dmi
+						.other_chunks
+						.get_or_insert_with(Vec::new)
+						.splice(0..0, continuations);
+				}
+				_ => {
+					raw_dmi.chunk_ztxt = Some(ztxt::create_ztxt_chunk_with_keyword(
+						&self.ztxt_keyword,
+						signature.as_bytes(),
+					)?);
+				}
+			},
+			MetadataChunk::Itxt => {
+				if self.ztxt_keyword != ztxt::DESCRIPTION_KEYWORD {
+					return Err(DmiError::InvalidArgument(format!(
+						"Error saving Icon: iTXt metadata has to be under the {:?} keyword to be loaded again, not {:?}.",
+						ztxt::DESCRIPTION_KEYWORD,
+						self.ztxt_keyword
+					)));
+				}
+				raw_dmi.chunk_ztxt = None;
+				raw_dmi.other_chunks.get_or_insert_with(Vec::new).insert(
+					0,
+					itxt::create_itxt_chunk(&self.ztxt_keyword, signature, true)?,
+				);
+			}
+		}
+		Ok(())
+	}
+
+	/// Whether unchanged image data can be written back as it is, rather than encoded as configured.
+	pub(crate) fn reuses_image_data(&self) -> bool {
+		!self.reencode
+			&& matches!(
+				self.color_type,
+				OutputColorType::Rgba8 | OutputColorType::Source
+			) && self.compression == CompressionLevel::Default
+			&& self.filter == FilterStrategy::Adaptive
+			&& self.layout == SheetLayout::Square
+	}
+}
+
+impl Icon {
+	/// Loads an icon like [Icon::load], as configured by `options`.
+	pub fn load_with<R: Read>(reader: R, mut options: LoadOptions) -> Result<Icon, DmiError> {
+		let raw_dmi = match &mut options.lenient {
+			Some(warnings) => RawDmi::load_lenient(reader, options.duplicate_chunks, warnings)?,
+			None => RawDmi::load_with_policy(reader, options.duplicate_chunks)?,
+		};
+		if options.strict_chunk_order && metadata_after_image_data(&raw_dmi) {
+			return Err(DmiError::Generic(
+				"Failed to load DMI. The zTXt chunk comes after the image data.".to_string(),
+			));
+		}
+		if options.reject_16_bit && raw_dmi.bit_depth()? == 16 {
+			return Err(DmiError::Generic(
+				"Failed to load DMI. The image has 16 bits per channel.".to_string(),
+			));
+		}
+		if let Some(on_chunk) = &mut options.on_chunk {
+			for (chunk_type, data_length) in raw_dmi.chunk_types() {
+				on_chunk(&ChunkRecord {
+					chunk_type,
+					data_length,
+					handling: icon_chunk_handling(&chunk_type),
+				});
+			}
+		}
+		let Some(warnings) = options.lenient else {
+			return Icon::load_internal(raw_dmi, &mut DecodeBuffers::default());
+		};
+		Icon::load_reporting(raw_dmi, true, warnings)
+	}
+
+	/// Saves the icon like [Icon::save], as configured by `options`.
+	pub fn save_with<W: Write>(
+		&self,
+		writter: &mut W,
+		options: &SaveOptions,
+	) -> Result<usize, DmiError> {
+		self.to_raw_dmi_with(options)?.save(writter)
+	}
+
+	/// The [RawDmi] that [Icon::save_with] writes, without encoding it to bytes.
+	pub fn to_raw_dmi_with(&self, options: &SaveOptions) -> Result<RawDmi, DmiError> {
+		if options.reject_duplicate_states {
+			if let Some((name, movement)) = self.duplicate_states().first() {
+				return Err(DmiError::InvalidArgument(format!(
+					"Error saving Icon: more than one {}state is named \"{name}\".",
+					if *movement { "movement " } else { "" }
+				)));
+			}
+		}
+		if options.reuses_image_data() {
+			if let Some(raw_dmi) = self.unmodified_raw_dmi(options)? {
+				return Ok(raw_dmi);
+			}
+		}
+		self.encode_raw_dmi(options)
+	}
+}
+
+/// Whether the zTXt chunk of `raw_dmi` came after its first IDAT chunk in the loaded file.
+fn metadata_after_image_data(raw_dmi: &RawDmi) -> bool {
+	let Some(source_map) = &raw_dmi.source_map else {
+		return false;
+	};
+	let position = |chunk_type| {
+		source_map
+			.iter()
+			.position(|span| ChunkType::from(span.chunk_type) == chunk_type)
+	};
+	matches!(
+		(position(ChunkType::Ztxt), position(ChunkType::Idat)),
+		(Some(ztxt), Some(idat)) if ztxt > idat
+	)
+}
+
+/// What loading an [Icon] does with chunks of type `chunk_type`.
+fn icon_chunk_handling(chunk_type: &[u8; 4]) -> ChunkHandling {
+	match ChunkType::from(*chunk_type) {
+		ChunkType::Ihdr | ChunkType::Plte | ChunkType::Idat | ChunkType::Iend | ChunkType::Ztxt => {
+			ChunkHandling::Parsed
+		}
+		ChunkType::Other(APP_DATA_TYPE) => ChunkHandling::Parsed,
+		chunk_type if chunk_type.is_kept_by_icon() => ChunkHandling::Preserved,
+		_ => ChunkHandling::Dropped,
+	}
+}
@@ -0,0 +1,192 @@
+#[cfg(feature = "images")]
+use crate::chunk::ChunkPolicy;
+use crate::chunk::{ChunkType, RawGenericChunk};
+use crate::error::DmiError;
+#[cfg(feature = "images")]
+use crate::icon::Icon;
+use crate::metadata::DmiMetadata;
+use crate::{ztxt, RawDmi, PNG_HEADER};
+#[cfg(feature = "images")]
+use image::{DynamicImage, GenericImageView};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Type of the chunk filling the space left by a smaller zTXt chunk, see [patch_metadata_in_place].
+/// Private and not safe to copy, so it is dropped as soon as the file is saved again.
+pub const PADDING_TYPE: [u8; 4] = *b"dmPD";
+
+/// Replaces the metadata of the DMI file in `file` by `new_text`, rewriting only the bytes of its
+/// zTXt chunk instead of the whole file.
+///
+/// This is only possible if the new compressed chunk is as long as the old one, or shorter by at
+/// least 12 bytes, which are then filled with a [PADDING_TYPE] chunk, and if the file has a single
+/// zTXt chunk: metadata split across continuation chunks, see [ztxt::continuation_keyword], is not
+/// patched. Returns `false` without touching the file otherwise, leaving it to the caller to save
+/// the file in full. `new_text` must be valid metadata.
+pub fn patch_metadata_in_place<F: Read + Write + Seek>(
+	file: &mut F,
+	new_text: &str,
+) -> Result<bool, DmiError> {
+	DmiMetadata::parse(new_text)?;
+	let (offset, old_length) = match find_ztxt(file)?[..] {
+		[] => {
+			return Err(DmiError::Generic(
+				"Failed to patch DMI metadata. No zTXt chunk found.".to_string(),
+			))
+		}
+		[chunk] => chunk,
+		// Continuation chunks would be left holding the old text.
+		_ => return Ok(false),
+	};
+
+	let mut new_bytes = vec![];
+	ztxt::create_ztxt_chunk(new_text.as_bytes())?.save(&mut new_bytes)?;
+	let new_length = new_bytes.len() as u64;
+	if new_length < old_length {
+		let gap = old_length - new_length;
+		if gap < 12 {
+			return Ok(false);
+		}
+		RawGenericChunk::new(PADDING_TYPE, vec![0; gap as usize - 12])?.save(&mut new_bytes)?;
+	} else if new_length > old_length {
+		return Ok(false);
+	}
+
+	file.seek(SeekFrom::Start(offset))?;
+	file.write_all(&new_bytes)?;
+	file.flush()?;
+	Ok(true)
+}
+
+impl RawDmi {
+	/// Replaces the metadata by `text`, leaving every other chunk, and so the image data, as it is.
+	/// Fails if `text` is not valid metadata, or if it does not fit the sheet: its sprite dimensions
+	/// must divide those of the sheet, which must hold every sprite it describes.
+	pub fn replace_metadata(&mut self, text: &str) -> Result<(), DmiError> {
+		let metadata = DmiMetadata::parse(text)?;
+		let image_width = self.image_width()?;
+		let image_height = self.image_height()?;
+		if image_width % metadata.width != 0 || image_height % metadata.height != 0 {
+			return Err(DmiError::Generic(format!(
+				"Failed to replace DMI metadata. Sprites of {}x{} do not divide the {}x{} sheet.",
+				metadata.width, metadata.height, image_width, image_height
+			)));
+		}
+		let capacity = (image_width / metadata.width) as u64 * (image_height / metadata.height) as u64;
+		let required: u64 = metadata
+			.states
+			.iter()
+			.map(|state| state.dirs as u64 * state.frames as u64)
+			.sum();
+		if required > capacity {
+			return Err(DmiError::Generic(format!(
+				"Failed to replace DMI metadata. It describes {required} sprites, the sheet holds {capacity}."
+			)));
+		}
+		let new_ztxt = ztxt::create_ztxt_chunk(text.as_bytes())?;
+		if let (Some(old_ztxt), Some(other_chunks)) = (&self.chunk_ztxt, &mut self.other_chunks) {
+			other_chunks.retain(|chunk| !ztxt::is_continuation(chunk, &old_ztxt.data.keyword));
+		}
+		self.chunk_ztxt = Some(new_ztxt);
+		Ok(())
+	}
+}
+
+#[cfg(feature = "images")]
+impl Icon {
+	/// Saves the icon by rewriting the metadata and other small chunks of `original`, the file it
+	/// was loaded from, keeping its image data byte for byte. This leaves the pixels untouched in
+	/// version control when only names or settings changed.
+	///
+	/// Fails without writing anything if the sprites of the icon are not those of the `original`
+	/// sheet, in the same order: the icon then has to be saved in full. When [Icon::source] holds
+	/// the image data of `original`, sprites are compared through its hashes, otherwise `original`
+	/// is decoded to compare them.
+	pub fn save_metadata_only<W: Write>(
+		&self,
+		original: &RawDmi,
+		writter: &mut W,
+	) -> Result<usize, DmiError> {
+		let (signature, sprites) = self.save_metadata()?;
+		let mut new_dmi = original.clone();
+		new_dmi.replace_metadata(&signature)?;
+
+		if self.source.is_sheet_of(original) {
+			if self.pixels_modified() {
+				return Err(DmiError::Generic(
+					"Error saving Icon: the sprites differ from the original sheet, the icon has to be saved in full.".to_string(),
+				));
+			}
+		} else {
+			self.check_sprites_of(original, &sprites)?;
+		}
+
+		let extra_chunks = self.extra_chunks(&ChunkPolicy::KeepAll)?;
+		new_dmi.other_chunks = (!extra_chunks.is_empty()).then_some(extra_chunks);
+		new_dmi.save(writter)
+	}
+
+	/// Decodes the sheet of `original` to check that `sprites` are its sprites, in order.
+	fn check_sprites_of(&self, original: &RawDmi, sprites: &[&DynamicImage]) -> Result<(), DmiError> {
+		let mut png_bytes = vec![];
+		original.save(&mut png_bytes)?;
+		let sheet = image::load_from_memory_with_format(&png_bytes, image::ImageFormat::Png)?;
+		let columns = sheet.width() / self.width;
+		for (index, sprite) in sprites.iter().enumerate() {
+			let index = index as u32;
+			let cell = sheet.view(
+				(index % columns) * self.width,
+				(index / columns) * self.height,
+				self.width,
+				self.height,
+			);
+			if sprite.to_rgba8() != cell.to_image() {
+				return Err(DmiError::Generic(format!(
+					"Error saving Icon: sprite {index} differs from the original sheet, the icon has to be saved in full."
+				)));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Offset and total length of every zTXt chunk of `file`, in order.
+fn find_ztxt<F: Read + Seek>(file: &mut F) -> Result<Vec<(u64, u64)>, DmiError> {
+	file.seek(SeekFrom::Start(0))?;
+	let mut header = [0u8; 8];
+	file.read_exact(&mut header)?;
+	if header != PNG_HEADER {
+		return Err(DmiError::Generic(format!(
+			"PNG header mismatch (expected {:#?}, found {:#?})",
+			PNG_HEADER, header
+		)));
+	}
+
+	let mut offset = 8;
+	let mut found = vec![];
+	loop {
+		let mut chunk_header = [0u8; 8];
+		file.read_exact(&mut chunk_header)?;
+		let data_length = u32::from_be_bytes([
+			chunk_header[0],
+			chunk_header[1],
+			chunk_header[2],
+			chunk_header[3],
+		]) as u64;
+		let chunk_type = ChunkType::from([
+			chunk_header[4],
+			chunk_header[5],
+			chunk_header[6],
+			chunk_header[7],
+		]);
+		match chunk_type {
+			ChunkType::Iend => return Ok(found),
+			chunk_type => {
+				if chunk_type == ChunkType::Ztxt {
+					found.push((offset, data_length + 12));
+				}
+				file.seek(SeekFrom::Current(data_length as i64 + 4))?;
+				offset += data_length + 12;
+			}
+		}
+	}
+}
@@ -0,0 +1,91 @@
+#[cfg(feature = "regex")]
+use crate::error::DmiError;
+
+/// A pattern matched against icon_state names.
+///
+/// Globs support `*` (any sequence of characters, including none) and `?` (any single character).
+/// Regular expressions require the `regex` feature.
+#[derive(Clone, Debug)]
+pub enum StatePattern {
+	/// Matches the given name only.
+	Exact(String),
+	/// Matches names against a glob.
+	Glob(String),
+	/// Matches names against a regular expression. Unanchored, like [regex::Regex::is_match].
+	#[cfg(feature = "regex")]
+	Regex(regex::Regex),
+}
+
+impl StatePattern {
+	pub fn exact(name: &str) -> StatePattern {
+		StatePattern::Exact(name.to_string())
+	}
+
+	pub fn glob(glob: &str) -> StatePattern {
+		StatePattern::Glob(glob.to_string())
+	}
+
+	#[cfg(feature = "regex")]
+	pub fn regex(regex: &str) -> Result<StatePattern, DmiError> {
+		match regex::Regex::new(regex) {
+			Ok(regex) => Ok(StatePattern::Regex(regex)),
+			Err(error) => Err(DmiError::Generic(format!(
+				"Failed to compile state pattern {:#?}: {}",
+				regex, error
+			))),
+		}
+	}
+
+	/// Whether `name` matches the pattern.
+	pub fn matches(&self, name: &str) -> bool {
+		match self {
+			StatePattern::Exact(exact) => exact == name,
+			StatePattern::Glob(glob) => glob_matches(glob, name),
+			#[cfg(feature = "regex")]
+			StatePattern::Regex(regex) => regex.is_match(name),
+		}
+	}
+}
+
+/// Plain strings are treated as globs.
+impl From<&str> for StatePattern {
+	fn from(glob: &str) -> Self {
+		StatePattern::glob(glob)
+	}
+}
+
+fn glob_matches(glob: &str, name: &str) -> bool {
+	let glob: Vec<char> = glob.chars().collect();
+	let name: Vec<char> = name.chars().collect();
+	let (mut glob_index, mut name_index) = (0, 0);
+	// Position of the last `*` seen, and the name position it is currently matched up to.
+	let mut backtrack = None;
+
+	while name_index < name.len() {
+		match glob.get(glob_index) {
+			Some('*') => {
+				backtrack = Some((glob_index, name_index));
+				glob_index += 1;
+			}
+			Some('?') => {
+				glob_index += 1;
+				name_index += 1;
+			}
+			Some(character) if *character == name[name_index] => {
+				glob_index += 1;
+				name_index += 1;
+			}
+			_ => match backtrack {
+				// Let the last `*` swallow one more character and try again.
+				Some((star_index, star_name_index)) => {
+					backtrack = Some((star_index, star_name_index + 1));
+					glob_index = star_index + 1;
+					name_index = star_name_index + 1;
+				}
+				None => return false,
+			},
+		}
+	}
+
+	glob[glob_index..].iter().all(|character| *character == '*')
+}
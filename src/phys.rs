@@ -0,0 +1,149 @@
+#[cfg(feature = "images")]
+use crate::icon::Icon;
+use crate::{chunk, error, RawDmi};
+
+pub const PHYS_TYPE: [u8; 4] = [b'p', b'H', b'Y', b's'];
+
+/// Centimeters in an inch, to convert between pixels per meter and DPI.
+const CENTIMETERS_PER_INCH: f64 = 2.54;
+
+/// The intended pixel size of the image, stored in the pHYs chunk.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct PhysicalDimensions {
+	pub pixels_per_unit_x: u32,
+	pub pixels_per_unit_y: u32,
+	pub unit: PhysicalUnit,
+}
+
+/// Unit of [PhysicalDimensions].
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PhysicalUnit {
+	/// Only the aspect ratio of the pixels is known.
+	Unknown,
+	Meter,
+}
+
+impl PhysicalDimensions {
+	/// Square pixels at `dpi` dots per inch, rounded to the nearest pixel per meter.
+	pub fn from_dpi(dpi: f64) -> PhysicalDimensions {
+		let pixels_per_meter = (dpi * 100.0 / CENTIMETERS_PER_INCH).round() as u32;
+		PhysicalDimensions {
+			pixels_per_unit_x: pixels_per_meter,
+			pixels_per_unit_y: pixels_per_meter,
+			unit: PhysicalUnit::Meter,
+		}
+	}
+
+	/// Horizontal and vertical dots per inch. None if the unit is unknown.
+	pub fn dpi(&self) -> Option<(f64, f64)> {
+		match self.unit {
+			PhysicalUnit::Unknown => None,
+			PhysicalUnit::Meter => Some((
+				self.pixels_per_unit_x as f64 * CENTIMETERS_PER_INCH / 100.0,
+				self.pixels_per_unit_y as f64 * CENTIMETERS_PER_INCH / 100.0,
+			)),
+		}
+	}
+}
+
+/// Creates a pHYs chunk holding `dimensions`.
+pub fn create_phys_chunk(
+	dimensions: &PhysicalDimensions,
+) -> Result<chunk::RawGenericChunk, error::DmiError> {
+	let mut data = Vec::with_capacity(9);
+	data.extend(dimensions.pixels_per_unit_x.to_be_bytes());
+	data.extend(dimensions.pixels_per_unit_y.to_be_bytes());
+	data.push(match dimensions.unit {
+		PhysicalUnit::Unknown => 0,
+		PhysicalUnit::Meter => 1,
+	});
+	chunk::RawGenericChunk::new(PHYS_TYPE, data)
+}
+
+/// Reads the dimensions held by a pHYs chunk.
+pub fn read_phys_chunk(
+	raw_chunk: &chunk::RawGenericChunk,
+) -> Result<PhysicalDimensions, error::DmiError> {
+	if raw_chunk.chunk_type != PHYS_TYPE {
+		return Err(error::DmiError::Generic(format!(
+			"Failed to read pHYs chunk. Wrong type: {:#?}. Expected: {:#?}.",
+			raw_chunk.chunk_type, PHYS_TYPE
+		)));
+	}
+	let data: &[u8; 9] = raw_chunk.data.as_slice().try_into().map_err(|_| {
+		error::DmiError::Generic(format!(
+			"Failed to read pHYs chunk. Data of {} bytes, expected 9.",
+			raw_chunk.data.len()
+		))
+	})?;
+	let unit = match data[8] {
+		0 => PhysicalUnit::Unknown,
+		1 => PhysicalUnit::Meter,
+		unit => {
+			return Err(error::DmiError::Generic(format!(
+				"Failed to read pHYs chunk. Unknown unit: {unit}."
+			)))
+		}
+	};
+	Ok(PhysicalDimensions {
+		pixels_per_unit_x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+		pixels_per_unit_y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+		unit,
+	})
+}
+
+impl RawDmi {
+	/// The dimensions stored in the pHYs chunk, if there is one.
+	pub fn physical_dimensions(&self) -> Result<Option<PhysicalDimensions>, error::DmiError> {
+		find_physical_dimensions(self.other_chunks.iter().flatten())
+	}
+
+	/// Stores `dimensions` in a pHYs chunk, replacing the previous one, or removes it for None.
+	pub fn set_physical_dimensions(
+		&mut self,
+		dimensions: Option<&PhysicalDimensions>,
+	) -> Result<(), error::DmiError> {
+		let other_chunks = self.other_chunks.get_or_insert_with(Vec::new);
+		replace_physical_dimensions(other_chunks, dimensions)?;
+		if other_chunks.is_empty() {
+			self.other_chunks = None;
+		}
+		Ok(())
+	}
+}
+
+/// The pHYs chunk is safe to copy, so an [Icon] carries it in [Icon::ancillary_chunks].
+#[cfg(feature = "images")]
+impl Icon {
+	/// The dimensions stored in the pHYs chunk, if there is one.
+	pub fn physical_dimensions(&self) -> Result<Option<PhysicalDimensions>, error::DmiError> {
+		find_physical_dimensions(self.ancillary_chunks.iter())
+	}
+
+	/// Stores `dimensions` in a pHYs chunk written on save, or removes it for None.
+	pub fn set_physical_dimensions(
+		&mut self,
+		dimensions: Option<&PhysicalDimensions>,
+	) -> Result<(), error::DmiError> {
+		replace_physical_dimensions(&mut self.ancillary_chunks, dimensions)
+	}
+}
+
+fn find_physical_dimensions<'a, I: Iterator<Item = &'a chunk::RawGenericChunk>>(
+	mut chunks: I,
+) -> Result<Option<PhysicalDimensions>, error::DmiError> {
+	chunks
+		.find(|chunk| chunk.chunk_type == PHYS_TYPE)
+		.map(read_phys_chunk)
+		.transpose()
+}
+
+fn replace_physical_dimensions(
+	chunks: &mut Vec<chunk::RawGenericChunk>,
+	dimensions: Option<&PhysicalDimensions>,
+) -> Result<(), error::DmiError> {
+	let new_chunk = dimensions.map(create_phys_chunk).transpose()?;
+	chunks.retain(|chunk| chunk.chunk_type != PHYS_TYPE);
+	chunks.extend(new_chunk);
+	Ok(())
+}
@@ -0,0 +1,23 @@
+use std::path::Path;
+
+/// How far an operation over many files got, passed to progress callbacks such as the one of
+/// [crate::library::DmiLibrary::add_dir_with_progress].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Progress<'a> {
+	/// Files processed so far, the current one included, whether they succeeded or not.
+	pub files_done: usize,
+	pub files_total: usize,
+	/// Size on disk of the files processed so far.
+	pub bytes_done: u64,
+	/// The file just processed, or the directory when converting directories of PNG files.
+	pub path: &'a Path,
+}
+
+/// How far saving a single icon got, passed to [crate::icon::Icon::save_streaming_with_progress].
+#[cfg(feature = "images")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SaveProgress {
+	/// Rows of pixels of the sprite sheet compressed so far.
+	pub rows_done: u32,
+	pub rows_total: u32,
+}
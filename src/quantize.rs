@@ -0,0 +1,376 @@
+use crate::error::DmiError;
+use crate::icon::Icon;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::collections::HashMap;
+
+/// Smallest [QuantizeOptions::max_colors] [QuantizeMethod::NeuQuant] accepts.
+pub const NEUQUANT_MIN_COLORS: u16 = 64;
+
+/// Share of the pixels NeuQuant learns from, one in this many: from 1, the slowest and most
+/// accurate, to 30. Sprite sheets are small enough to learn from every pixel.
+const NEUQUANT_SAMPLE_FACTOR: i32 = 1;
+
+/// Bayer threshold matrix used by [Dithering::Ordered], in sixteenths.
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// How [quantize] picks the colors of the palette.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[non_exhaustive]
+pub enum QuantizeMethod {
+	/// Splits the colors of the image into boxes along their widest channel, at the median pixel,
+	/// until there are as many boxes as allowed colors, then averages each box.
+	#[default]
+	MedianCut,
+	/// Trains a Kohonen neural network on the pixels, as the NeuQuant algorithm does. Slower, but
+	/// often closer on smooth gradients. Needs a palette of at least [NEUQUANT_MIN_COLORS] colors,
+	/// below which most of the network never learns.
+	NeuQuant,
+}
+
+/// How [quantize] spreads the error between a pixel and its palette color.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Dithering {
+	/// Every pixel takes the nearest palette color. Keeps flat areas flat, as sprites usually want.
+	#[default]
+	None,
+	/// Offsets pixels by a 4x4 Bayer matrix before picking their color, for a regular pattern that
+	/// stays stable from one frame of an animation to the next.
+	Ordered,
+	/// Pushes the error of each pixel onto its unvisited neighbours, for smoother gradients.
+	FloydSteinberg,
+}
+
+/// Options for [quantize] and [Icon::quantize].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QuantizeOptions {
+	/// Number of colors of the palette, from 1 to 256. 256 by default.
+	pub max_colors: u16,
+	pub method: QuantizeMethod,
+	/// No dithering by default.
+	pub dithering: Dithering,
+}
+
+impl Default for QuantizeOptions {
+	fn default() -> Self {
+		QuantizeOptions {
+			max_colors: 256,
+			method: QuantizeMethod::default(),
+			dithering: Dithering::default(),
+		}
+	}
+}
+
+impl QuantizeOptions {
+	pub fn new() -> QuantizeOptions {
+		QuantizeOptions {
+			..Default::default()
+		}
+	}
+
+	/// Sets [QuantizeOptions::max_colors].
+	pub fn max_colors(mut self, max_colors: u16) -> QuantizeOptions {
+		self.max_colors = max_colors;
+		self
+	}
+
+	/// Sets [QuantizeOptions::method].
+	pub fn method(mut self, method: QuantizeMethod) -> QuantizeOptions {
+		self.method = method;
+		self
+	}
+
+	/// Sets [QuantizeOptions::dithering].
+	pub fn dithering(mut self, dithering: Dithering) -> QuantizeOptions {
+		self.dithering = dithering;
+		self
+	}
+}
+
+/// An image reduced to a palette, see [quantize].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct QuantizedImage {
+	pub width: u32,
+	pub height: u32,
+	/// At most [QuantizeOptions::max_colors] colors. Fully transparent pixels all share one entry.
+	pub palette: Vec<Rgba<u8>>,
+	/// Index in the palette of each pixel, row by row.
+	pub indices: Vec<u8>,
+}
+
+impl QuantizedImage {
+	/// The image with every pixel replaced by its palette color.
+	pub fn to_rgba8(&self) -> RgbaImage {
+		RgbaImage::from_fn(self.width, self.height, |x, y| {
+			self.palette[self.indices[(y * self.width + x) as usize] as usize]
+		})
+	}
+}
+
+/// Reduces `image` to a palette of at most [QuantizeOptions::max_colors] colors. Images that
+/// already fit keep their exact colors.
+pub fn quantize(image: &RgbaImage, options: &QuantizeOptions) -> Result<QuantizedImage, DmiError> {
+	let palette = build_palette(std::iter::once(image), options)?;
+	Ok(QuantizedImage {
+		width: image.width(),
+		height: image.height(),
+		indices: remap(image, &palette, options.dithering),
+		palette,
+	})
+}
+
+impl Icon {
+	/// Reduces every image of every state to one palette of at most [QuantizeOptions::max_colors]
+	/// colors, shared by the whole icon as it is once saved to a single sheet. Returns the palette.
+	///
+	/// Images are converted to RGBA8.
+	pub fn quantize(&mut self, options: &QuantizeOptions) -> Result<Vec<Rgba<u8>>, DmiError> {
+		let images: Vec<RgbaImage> = self
+			.states
+			.iter()
+			.flat_map(|state| state.images.iter())
+			.map(|image| image.to_rgba8())
+			.collect();
+		let palette = build_palette(images.iter(), options)?;
+		let mut images = images.into_iter();
+		for image in self
+			.states
+			.iter_mut()
+			.flat_map(|state| state.images.iter_mut())
+		{
+			let pixels = images.next().unwrap();
+			let indices = remap(&pixels, &palette, options.dithering);
+			let quantized = QuantizedImage {
+				width: pixels.width(),
+				height: pixels.height(),
+				palette: palette.clone(),
+				indices,
+			};
+			*image = DynamicImage::ImageRgba8(quantized.to_rgba8());
+		}
+		Ok(palette)
+	}
+}
+
+/// A set of colors weighted by how many pixels use them.
+type ColorBox = Vec<(Rgba<u8>, u64)>;
+
+fn build_palette<'a, I: Iterator<Item = &'a RgbaImage>>(
+	images: I,
+	options: &QuantizeOptions,
+) -> Result<Vec<Rgba<u8>>, DmiError> {
+	if !(1..=256).contains(&options.max_colors) {
+		return Err(DmiError::Generic(format!(
+			"Error quantizing: palettes hold from 1 to 256 colors, {} requested.",
+			options.max_colors
+		)));
+	}
+	if options.method == QuantizeMethod::NeuQuant && options.max_colors < NEUQUANT_MIN_COLORS {
+		return Err(DmiError::Generic(format!(
+			"Error quantizing: NeuQuant needs at least {NEUQUANT_MIN_COLORS} colors, {} requested.",
+			options.max_colors
+		)));
+	}
+	let mut has_transparent = false;
+	let mut counts: HashMap<Rgba<u8>, u64> = HashMap::new();
+	for pixel in images.flat_map(|image| image.pixels()) {
+		if pixel[3] == 0 {
+			has_transparent = true;
+		} else {
+			*counts.entry(*pixel).or_default() += 1;
+		}
+	}
+	let mut palette = vec![];
+	if has_transparent {
+		palette.push(Rgba([0, 0, 0, 0]));
+	}
+	let max_colors = (options.max_colors as usize)
+		.saturating_sub(palette.len())
+		.max(1);
+	let mut colors: ColorBox = counts.into_iter().collect();
+	// Sorted so that the palette does not depend on the iteration order of the map.
+	colors.sort_unstable_by_key(|&(color, _)| color.0);
+	if colors.len() <= max_colors {
+		palette.extend(colors.into_iter().map(|(color, _)| color));
+		palette.truncate(options.max_colors as usize);
+		return Ok(palette);
+	}
+	match options.method {
+		QuantizeMethod::MedianCut => palette.extend(median_cut(colors, max_colors)),
+		QuantizeMethod::NeuQuant => palette.extend(neuquant(&colors, max_colors)),
+	}
+	palette.truncate(options.max_colors as usize);
+	Ok(palette)
+}
+
+fn median_cut(colors: ColorBox, max_colors: usize) -> Vec<Rgba<u8>> {
+	let mut boxes = vec![colors];
+	while boxes.len() < max_colors {
+		let widest = boxes
+			.iter()
+			.enumerate()
+			.filter(|(_, color_box)| color_box.len() > 1)
+			.map(|(index, color_box)| (index, widest_channel(color_box)))
+			.max_by_key(|&(_, (_, range))| range);
+		let Some((index, (channel, _))) = widest else {
+			break;
+		};
+		let mut color_box = boxes.swap_remove(index);
+		color_box.sort_unstable_by_key(|&(color, _)| color[channel]);
+		let total: u64 = color_box.iter().map(|&(_, count)| count).sum();
+		let mut seen = 0;
+		let median = color_box
+			.iter()
+			.position(|&(_, count)| {
+				seen += count;
+				seen * 2 >= total
+			})
+			.unwrap_or(0);
+		let upper = color_box.split_off((median + 1).min(color_box.len() - 1));
+		boxes.push(color_box);
+		boxes.push(upper);
+	}
+	boxes.iter().map(average).collect()
+}
+
+fn neuquant(colors: &ColorBox, max_colors: usize) -> Vec<Rgba<u8>> {
+	// Each color is repeated as many times as it is used, so that the network learns it as often
+	// as it would from the images.
+	let pixels: Vec<u8> = colors
+		.iter()
+		.flat_map(|&(color, count)| std::iter::repeat_n(color.0, count as usize))
+		.flatten()
+		.collect();
+	let network = color_quant::NeuQuant::new(NEUQUANT_SAMPLE_FACTOR, max_colors, &pixels);
+	// Only the neurons some color maps to are kept, the others never got close to any pixel.
+	let mut palette: Vec<Rgba<u8>> = colors
+		.iter()
+		.map(|(color, _)| {
+			let index = network.index_of(&color.0);
+			let entry = network.lookup(index).unwrap();
+			Rgba(entry)
+		})
+		.collect();
+	palette.sort_unstable_by_key(|color| color.0);
+	palette.dedup();
+	palette
+}
+
+/// The channel over which the colors of `color_box` spread the most, and that spread.
+fn widest_channel(color_box: &ColorBox) -> (usize, u8) {
+	(0..4)
+		.map(|channel| {
+			let (min, max) = color_box
+				.iter()
+				.fold((u8::MAX, u8::MIN), |(min, max), (color, _)| {
+					(min.min(color[channel]), max.max(color[channel]))
+				});
+			(channel, max - min)
+		})
+		.max_by_key(|&(_, range)| range)
+		.unwrap()
+}
+
+fn average(color_box: &ColorBox) -> Rgba<u8> {
+	let total: u64 = color_box.iter().map(|&(_, count)| count).sum();
+	let mut sums = [0u64; 4];
+	for (color, count) in color_box {
+		for (sum, channel) in sums.iter_mut().zip(color.0) {
+			*sum += channel as u64 * count;
+		}
+	}
+	Rgba(sums.map(|sum| ((sum + total / 2) / total) as u8))
+}
+
+/// Index of the color of `palette` closest to `color`.
+fn nearest(palette: &[Rgba<u8>], color: [f32; 4]) -> u8 {
+	if color[3] < 0.5 {
+		if let Some(index) = palette.iter().position(|entry| entry[3] == 0) {
+			return index as u8;
+		}
+	}
+	let distance = |entry: &Rgba<u8>| -> f32 {
+		entry
+			.0
+			.iter()
+			.zip(color)
+			.map(|(&channel, target)| (channel as f32 - target).powi(2))
+			.sum()
+	};
+	palette
+		.iter()
+		.enumerate()
+		.min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+		.map_or(0, |(index, _)| index as u8)
+}
+
+fn remap(image: &RgbaImage, palette: &[Rgba<u8>], dithering: Dithering) -> Vec<u8> {
+	let (width, height) = image.dimensions();
+	let to_f32 = |pixel: &Rgba<u8>| pixel.0.map(|channel| channel as f32);
+	match dithering {
+		Dithering::None => {
+			let mut cache: HashMap<Rgba<u8>, u8> = HashMap::new();
+			image
+				.pixels()
+				.map(|pixel| {
+					*cache
+						.entry(*pixel)
+						.or_insert_with(|| nearest(palette, to_f32(pixel)))
+				})
+				.collect()
+		}
+		Dithering::Ordered => {
+			// Roughly the distance between neighbouring colors of an evenly spread palette.
+			let spread = 255.0 / (palette.len() as f32).cbrt();
+			image
+				.enumerate_pixels()
+				.map(|(x, y, pixel)| {
+					let mut color = to_f32(pixel);
+					if pixel[3] != 0 {
+						let threshold = BAYER_4X4[y as usize % 4][x as usize % 4] as f32;
+						let offset = ((threshold + 0.5) / 16.0 - 0.5) * spread;
+						for channel in &mut color[..3] {
+							*channel = (*channel + offset).clamp(0.0, 255.0);
+						}
+					}
+					nearest(palette, color)
+				})
+				.collect()
+		}
+		Dithering::FloydSteinberg => {
+			let mut colors: Vec<[f32; 4]> = image.pixels().map(to_f32).collect();
+			let mut indices = Vec::with_capacity(colors.len());
+			for y in 0..height as usize {
+				for x in 0..width as usize {
+					let color = colors[y * width as usize + x];
+					let index = nearest(palette, color);
+					indices.push(index);
+					if color[3] == 0.0 {
+						continue;
+					}
+					let chosen = palette[index as usize];
+					let error: [f32; 3] = [0, 1, 2].map(|channel| color[channel] - chosen[channel] as f32);
+					let mut spread_error = |dx: isize, dy: usize, weight: f32| {
+						let (Some(x), y) = (x.checked_add_signed(dx), y + dy) else {
+							return;
+						};
+						if x >= width as usize || y >= height as usize {
+							return;
+						}
+						let neighbour = &mut colors[y * width as usize + x];
+						if neighbour[3] == 0.0 {
+							return;
+						}
+						for channel in 0..3 {
+							neighbour[channel] = (neighbour[channel] + error[channel] * weight).clamp(0.0, 255.0);
+						}
+					};
+					spread_error(1, 0, 7.0 / 16.0);
+					spread_error(-1, 1, 3.0 / 16.0);
+					spread_error(0, 1, 5.0 / 16.0);
+					spread_error(1, 1, 1.0 / 16.0);
+				}
+			}
+			indices
+		}
+	}
+}
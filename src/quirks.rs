@@ -0,0 +1,81 @@
+use crate::error::DmiError;
+#[cfg(feature = "images")]
+use crate::icon::Icon;
+use crate::metadata::DmiMetadata;
+#[cfg(feature = "images")]
+use crate::options::LoadOptions;
+use crate::warning::DmiWarning;
+#[cfg(feature = "images")]
+use std::io::Read;
+
+/// A deviation from the DMI format written by some third-party tools, which quirks mode accepts
+/// rather than failing. Each one found is reported as a [DmiWarning::MetadataQuirk].
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Quirk {
+	/// A `hotspot = x,y` line, without the third value BYOND writes. Read as the same hotspot.
+	TwoValueHotspot,
+	/// A `loop` setting below 0, read as [crate::icon::Looping::Indefinitely].
+	NegativeLoop,
+	/// A number of dirs other than 1, 4 or 8, rounded up to the next of these, 8 at most. Missing
+	/// dirs are filled with copies of the first dir of each frame, extra ones are dropped.
+	DirsRounded { from: u8, to: u8 },
+	/// A setting given more than once in the same state. The last value wins.
+	DuplicateSetting { key: String },
+}
+
+impl std::fmt::Display for Quirk {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Quirk::TwoValueHotspot => write!(f, "hotspot has two values instead of three"),
+			Quirk::NegativeLoop => write!(f, "negative loop read as looping indefinitely"),
+			Quirk::DirsRounded { from, to } => write!(f, "{from} dirs rounded to {to}"),
+			Quirk::DuplicateSetting { key } => {
+				write!(f, "setting \"{key}\" is repeated, the last value is kept")
+			}
+		}
+	}
+}
+
+/// The closest valid number of dirs, see [Quirk::DirsRounded].
+pub(crate) fn round_dirs(dirs: u8) -> u8 {
+	match dirs {
+		0 | 1 => 1,
+		2..=4 => 4,
+		_ => 8,
+	}
+}
+
+impl DmiMetadata {
+	/// Like [DmiMetadata::parse], but accepts the nonstandard metadata listed in [Quirk], reporting
+	/// each use of it.
+	pub fn parse_with_quirks(text: &str) -> Result<(DmiMetadata, Vec<DmiWarning>), DmiError> {
+		let mut warnings = vec![];
+		let description = crate::metadata::Description::parse(text, Some(&mut warnings))?;
+		let mut states = Vec::with_capacity(description.blocks.len());
+		for block in description.blocks {
+			states.push(block.state?);
+		}
+		let metadata = DmiMetadata {
+			version: description.version,
+			width: description.width,
+			height: description.height,
+			states,
+		};
+		Ok((metadata, warnings))
+	}
+}
+
+#[cfg(feature = "images")]
+impl Icon {
+	/// Like [Icon::load_with_warnings], but accepts the nonstandard metadata listed in [Quirk],
+	/// reporting each use of it along with the other warnings. Real icon repositories hold files
+	/// written by tools that BYOND itself would not produce.
+	///
+	/// Short for [Icon::load_with] in lenient mode, see [LoadOptions::lenient].
+	pub fn load_with_quirks<R: Read>(reader: R) -> Result<(Icon, Vec<DmiWarning>), DmiError> {
+		let mut warnings = vec![];
+		let icon = Icon::load_with(reader, LoadOptions::new().lenient(&mut warnings))?;
+		Ok((icon, warnings))
+	}
+}
@@ -0,0 +1,183 @@
+use crate::chunk::{ChunkSpan, ChunkType, RawGenericChunk};
+use crate::error::DmiError;
+use crate::PNG_HEADER;
+use std::io::{self, Read};
+
+/// Reads the chunks of a PNG file one at a time from any [Read], so that only the current chunk is
+/// held in memory. [crate::RawDmi::load] builds on it.
+///
+/// Reading stops after the IEND chunk, whatever follows it. As an [Iterator], it yields the chunks
+/// read by [ChunkReader::next_chunk], and stops after the first error.
+pub struct ChunkReader<R: Read> {
+	reader: R,
+	offset: u64,
+	finished: bool,
+	/// The header of the chunk whose data is next, see [ChunkReader::next_header].
+	pending: Option<[u8; 8]>,
+}
+
+impl<R: Read> ChunkReader<R> {
+	/// Reads and checks the PNG signature at the start of `reader`.
+	pub fn new(mut reader: R) -> Result<ChunkReader<R>, DmiError> {
+		let mut header = [0u8; 8];
+		let header_length = read_up_to(&mut reader, &mut header)?;
+		if header != PNG_HEADER {
+			return Err(DmiError::Generic(format!(
+				"PNG header mismatch (expected {:#?}, found {:#?})",
+				PNG_HEADER,
+				&header[..header_length]
+			)));
+		}
+		Ok(ChunkReader {
+			reader,
+			offset: PNG_HEADER.len() as u64,
+			finished: false,
+			pending: None,
+		})
+	}
+
+	/// Offset from the start of the file of the next chunk to be read.
+	pub fn offset(&self) -> u64 {
+		self.offset
+	}
+
+	/// Reads the next chunk, checking its type and CRC. None once the IEND chunk was read. Fails if
+	/// the file ends before it.
+	pub fn next_chunk(&mut self) -> Result<Option<RawGenericChunk>, DmiError> {
+		if self.next_header()?.is_none() {
+			return Ok(None);
+		}
+		self.read_data().map(Some)
+	}
+
+	/// Reads the type and length of the next chunk, skipping over its data without checking it.
+	/// None once the IEND chunk was read. Cheaper than [ChunkReader::next_chunk] to inspect the
+	/// structure of a file.
+	pub fn next_span(&mut self) -> Result<Option<ChunkSpan>, DmiError> {
+		let Some(span) = self.next_header()? else {
+			return Ok(None);
+		};
+		self.skip_data()?;
+		Ok(Some(span))
+	}
+
+	/// Reads the length and type of the next chunk, leaving its data to [ChunkReader::read_data] or
+	/// [ChunkReader::skip_data]. If neither is called, the data is skipped on the next call. None once
+	/// the IEND chunk was read.
+	pub fn next_header(&mut self) -> Result<Option<ChunkSpan>, DmiError> {
+		if self.pending.is_some() {
+			self.skip_data()?;
+		}
+		if self.finished {
+			return Ok(None);
+		}
+		let mut header = [0u8; 8];
+		if read_up_to(&mut self.reader, &mut header)? < header.len() {
+			return Err(end_without_iend());
+		}
+		self.pending = Some(header);
+		let (data_length, chunk_type) = split_header(&header);
+		Ok(Some(ChunkSpan {
+			chunk_type,
+			offset: self.offset,
+			length: data_length as u64 + 12,
+		}))
+	}
+
+	/// Reads the data of the chunk whose header [ChunkReader::next_header] returned, checking its
+	/// type and CRC. A chunk read whole but invalid is still moved past, so that reading can go on
+	/// with the next one.
+	pub fn read_data(&mut self) -> Result<RawGenericChunk, DmiError> {
+		let header = self.take_pending()?;
+		let (data_length, chunk_type) = split_header(&header);
+		let mut chunk_bytes = header.to_vec();
+		let read = (&mut self.reader)
+			.take(data_length as u64 + 4)
+			.read_to_end(&mut chunk_bytes)?;
+		self.check_complete(data_length, read as u64)?;
+		self.advance(&chunk_type, data_length);
+		RawGenericChunk::load(&mut &*chunk_bytes)
+	}
+
+	/// Skips over the data of the chunk whose header [ChunkReader::next_header] returned, without
+	/// checking it.
+	pub fn skip_data(&mut self) -> Result<(), DmiError> {
+		let header = self.take_pending()?;
+		let (data_length, chunk_type) = split_header(&header);
+		let skipped = io::copy(
+			&mut (&mut self.reader).take(data_length as u64 + 4),
+			&mut io::sink(),
+		)?;
+		self.check_complete(data_length, skipped)?;
+		self.advance(&chunk_type, data_length);
+		Ok(())
+	}
+
+	fn take_pending(&mut self) -> Result<[u8; 8], DmiError> {
+		self.pending.take().ok_or_else(|| {
+			DmiError::Generic("Failed to read chunk data. No chunk header was read.".to_string())
+		})
+	}
+
+	/// Fails unless the data and CRC of a chunk stating `data_length` bytes of data were read whole,
+	/// `read` being how many bytes of them were.
+	fn check_complete(&self, data_length: u32, read: u64) -> Result<(), DmiError> {
+		if read == data_length as u64 + 4 {
+			return Ok(());
+		}
+		// Chunks are at least 12 bytes long, shorter leftovers cannot be one.
+		if read < 4 {
+			return Err(end_without_iend());
+		}
+		Err(DmiError::TruncatedChunk {
+			offset: self.offset as usize,
+			data_length,
+			available: read as usize - 4,
+		})
+	}
+
+	fn advance(&mut self, chunk_type: &[u8; 4], data_length: u32) {
+		self.offset += data_length as u64 + 12;
+		self.finished = ChunkType::from(*chunk_type) == ChunkType::Iend;
+	}
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+	type Item = Result<RawGenericChunk, DmiError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let chunk = self.next_chunk();
+		if chunk.is_err() {
+			self.finished = true;
+		}
+		chunk.transpose()
+	}
+}
+
+/// The data length and chunk type of a chunk header.
+fn split_header(header: &[u8; 8]) -> (u32, [u8; 4]) {
+	(
+		u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+		[header[4], header[5], header[6], header[7]],
+	)
+}
+
+fn end_without_iend() -> DmiError {
+	DmiError::Generic(
+		"Failed to load DMI. Buffer end reached without finding an IEND chunk.".to_string(),
+	)
+}
+
+/// Reads into `buffer` until it is full or the reader ends, returning how many bytes were read.
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+	let mut read = 0;
+	while read < buffer.len() {
+		match reader.read(&mut buffer[read..]) {
+			Ok(0) => break,
+			Ok(length) => read += length,
+			Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+			Err(error) => return Err(error),
+		}
+	}
+	Ok(read)
+}
@@ -0,0 +1,257 @@
+use crate::error::DmiError;
+use crate::icon::{Icon, IconState};
+use image::{DynamicImage, GenericImageView, Rgb, RgbImage, Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// Colors given to the regions of a mask, keyed by the color of the region in the mask, see
+/// [Icon::mask_recolor].
+pub type MaskColors = HashMap<Rgba<u8>, Rgba<u8>>;
+
+/// Rec. 709 luma weights of the red, green and blue channels, as used by [image::imageops::grayscale].
+const LUMA_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+impl Icon {
+	/// Replaces `target` by `replacement` in every image of every state. Pixels match when each of
+	/// their channels, alpha included, is within `tolerance` of `target`, which catches the slightly
+	/// off shades of anti-aliased edges. Returns how many pixels were replaced.
+	///
+	/// Images that are not RGBA8 are converted to it.
+	pub fn replace_color(&mut self, target: Rgba<u8>, replacement: Rgba<u8>, tolerance: u8) -> usize {
+		let mut replaced = 0;
+		for image in self
+			.states
+			.iter_mut()
+			.flat_map(|state| state.images.iter_mut())
+		{
+			for pixel in rgba8_mut(image).pixels_mut() {
+				let matches = pixel
+					.0
+					.iter()
+					.zip(target.0)
+					.all(|(channel, target_channel)| channel.abs_diff(target_channel) <= tolerance);
+				if matches {
+					*pixel = replacement;
+					replaced += 1;
+				}
+			}
+		}
+		replaced
+	}
+
+	/// Applies `transform` to every image of every state, in order.
+	///
+	/// Images that are not RGBA8 are converted to it.
+	pub fn map_images<F: FnMut(&mut RgbaImage)>(&mut self, mut transform: F) {
+		for image in self
+			.states
+			.iter_mut()
+			.flat_map(|state| state.images.iter_mut())
+		{
+			transform(rgba8_mut(image));
+		}
+	}
+
+	/// Like [Icon::map_images], spreading the images over as many threads as the available
+	/// parallelism, in no particular order.
+	pub fn par_map_images<F: Fn(&mut RgbaImage) + Sync>(&mut self, transform: F) {
+		let mut images: Vec<&mut DynamicImage> = self
+			.states
+			.iter_mut()
+			.flat_map(|state| state.images.iter_mut())
+			.collect();
+		let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+		let chunk_size = images.len().div_ceil(threads).max(1);
+		let transform = &transform;
+		thread::scope(|scope| {
+			for chunk in images.chunks_mut(chunk_size) {
+				scope.spawn(move || {
+					for image in chunk {
+						transform(rgba8_mut(image));
+					}
+				});
+			}
+		});
+	}
+
+	/// Shifts the colors of every state, see [IconState::shift_hsv].
+	pub fn shift_hsv(&mut self, hue: f32, saturation: f32, value: f32) {
+		for icon_state in &mut self.states {
+			icon_state.shift_hsv(hue, saturation, value);
+		}
+	}
+
+	/// A copy of the icon recolored through the `mask_state` state, which is left out of it.
+	///
+	/// The mask splits the sprites into regions of one color each, and `colors` tints each region:
+	/// pixels take the color of their region, scaled by their own luma to keep the shading of the
+	/// base sprite, and keep their alpha. Pixels of the mask that are transparent or of a color
+	/// absent from `colors` are left as they are. The mask either has a single image, used for every
+	/// sprite, or as many images as each state, matched one to one.
+	pub fn mask_recolor(&self, mask_state: &str, colors: &MaskColors) -> Result<Icon, DmiError> {
+		let Some(mask) = self.states.iter().find(|state| state.name == mask_state) else {
+			return Err(DmiError::IconState(format!(
+				"Mask icon_state \"{mask_state}\" not found."
+			)));
+		};
+		let mut icon = self.clone();
+		icon.states.retain(|state| state.name != mask_state);
+		for icon_state in &mut icon.states {
+			if mask.images.len() != 1 && mask.images.len() != icon_state.images.len() {
+				return Err(DmiError::IconState(format!(
+					"Mask icon_state \"{mask_state}\" has {} images, expected 1 or {} to match icon_state \"{}\".",
+					mask.images.len(),
+					icon_state.images.len(),
+					icon_state.name
+				)));
+			}
+			for (index, image) in icon_state.images.iter_mut().enumerate() {
+				let mask_image = &mask.images[index.min(mask.images.len() - 1)];
+				for (x, y, pixel) in rgba8_mut(image).enumerate_pixels_mut() {
+					if !mask_image.in_bounds(x, y) {
+						continue;
+					}
+					let mask_pixel = mask_image.get_pixel(x, y);
+					if mask_pixel[3] == 0 {
+						continue;
+					}
+					let Some(color) = colors.get(&mask_pixel) else {
+						continue;
+					};
+					let shade = luma(*pixel) / 255.0;
+					let [red, green, blue] =
+						[color[0], color[1], color[2]].map(|channel| (channel as f32 * shade).round() as u8);
+					*pixel = Rgba([red, green, blue, pixel[3]]);
+				}
+			}
+		}
+		Ok(icon)
+	}
+
+	/// One [Icon::mask_recolor] variant of the icon for each entry of `variants`, in order.
+	pub fn mask_variants(
+		&self,
+		mask_state: &str,
+		variants: &[MaskColors],
+	) -> Result<Vec<Icon>, DmiError> {
+		variants
+			.iter()
+			.map(|colors| self.mask_recolor(mask_state, colors))
+			.collect()
+	}
+
+	/// A copy of the icon with every state desaturated, see [IconState::desaturate].
+	pub fn to_grayscale(&self) -> Icon {
+		let mut icon = self.clone();
+		for icon_state in &mut icon.states {
+			icon_state.desaturate();
+		}
+		icon
+	}
+}
+
+impl IconState {
+	/// Turns every image of the state to shades of gray of the same luma, keeping their alpha.
+	///
+	/// Images that are not RGBA8 are converted to it.
+	pub fn desaturate(&mut self) {
+		for image in &mut self.images {
+			for pixel in rgba8_mut(image).pixels_mut() {
+				let luma = luma(*pixel).round().clamp(0.0, 255.0) as u8;
+				*pixel = Rgba([luma, luma, luma, pixel[3]]);
+			}
+		}
+	}
+
+	/// Shifts the colors of every image of the state in the HSV space, keeping their alpha: `hue`
+	/// is added to the hue, in degrees, and wraps around, while `saturation` and `value` are added
+	/// to their counterparts, which range from 0 to 1.
+	///
+	/// Images that are not RGBA8 are converted to it.
+	pub fn shift_hsv(&mut self, hue: f32, saturation: f32, value: f32) {
+		for image in &mut self.images {
+			for pixel in rgba8_mut(image).pixels_mut() {
+				let [red, green, blue, alpha] = pixel.0;
+				let [pixel_hue, pixel_saturation, pixel_value] = rgb_to_hsv([red, green, blue]);
+				let [red, green, blue] = hsv_to_rgb([
+					(pixel_hue + hue).rem_euclid(360.0),
+					(pixel_saturation + saturation).clamp(0.0, 1.0),
+					(pixel_value + value).clamp(0.0, 1.0),
+				]);
+				*pixel = Rgba([red, green, blue, alpha]);
+			}
+		}
+	}
+
+	/// Composites every image of the state over an opaque `background`, whose alpha is ignored,
+	/// leaving RGB8 images without transparency.
+	pub fn flatten(&mut self, background: Rgba<u8>) {
+		for image in &mut self.images {
+			let pixels = rgba8_mut(image);
+			let flattened = RgbImage::from_fn(pixels.width(), pixels.height(), |x, y| {
+				let [red, green, blue, alpha] = pixels.get_pixel(x, y).0;
+				let alpha = alpha as u32;
+				let blend = |channel: u8, background: u8| {
+					((channel as u32 * alpha + background as u32 * (255 - alpha) + 127) / 255) as u8
+				};
+				Rgb([
+					blend(red, background[0]),
+					blend(green, background[1]),
+					blend(blue, background[2]),
+				])
+			});
+			*image = DynamicImage::ImageRgb8(flattened);
+		}
+	}
+}
+
+/// Luma of the color of `pixel`, from 0 to 255.
+fn luma(pixel: Rgba<u8>) -> f32 {
+	let [red, green, blue, _] = pixel.0;
+	LUMA_WEIGHTS[0] * red as f32 + LUMA_WEIGHTS[1] * green as f32 + LUMA_WEIGHTS[2] * blue as f32
+}
+
+/// Hue in degrees, saturation and value from 0 to 1.
+fn rgb_to_hsv(rgb: [u8; 3]) -> [f32; 3] {
+	let [red, green, blue] = rgb.map(|channel| channel as f32 / 255.0);
+	let max = red.max(green).max(blue);
+	let min = red.min(green).min(blue);
+	let delta = max - min;
+	let hue = if delta == 0.0 {
+		0.0
+	} else if max == red {
+		60.0 * ((green - blue) / delta).rem_euclid(6.0)
+	} else if max == green {
+		60.0 * ((blue - red) / delta + 2.0)
+	} else {
+		60.0 * ((red - green) / delta + 4.0)
+	};
+	let saturation = if max == 0.0 { 0.0 } else { delta / max };
+	[hue, saturation, max]
+}
+
+/// The inverse of [rgb_to_hsv].
+fn hsv_to_rgb(hsv: [f32; 3]) -> [u8; 3] {
+	let [hue, saturation, value] = hsv;
+	let chroma = value * saturation;
+	let x = chroma * (1.0 - ((hue / 60.0).rem_euclid(2.0) - 1.0).abs());
+	let (red, green, blue) = match (hue / 60.0) as u32 {
+		0 => (chroma, x, 0.0),
+		1 => (x, chroma, 0.0),
+		2 => (0.0, chroma, x),
+		3 => (0.0, x, chroma),
+		4 => (x, 0.0, chroma),
+		_ => (chroma, 0.0, x),
+	};
+	let offset = value - chroma;
+	[red, green, blue].map(|channel| ((channel + offset) * 255.0).round().clamp(0.0, 255.0) as u8)
+}
+
+/// The pixels of `image`, converting it to RGBA8 first if needed.
+fn rgba8_mut(image: &mut DynamicImage) -> &mut RgbaImage {
+	if image.as_rgba8().is_none() {
+		*image = DynamicImage::ImageRgba8(image.to_rgba8());
+	}
+	image.as_mut_rgba8().unwrap()
+}
@@ -0,0 +1,100 @@
+use crate::text::is_description_text;
+use crate::{chunk, error, ztxt, RawDmi};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::Read;
+
+/// Type of the private ancillary chunk holding the signature.
+/// Marked unsafe to copy, as editors changing the image invalidate it.
+pub const SIGNATURE_TYPE: [u8; 4] = [b'd', b'm', b'S', b'G'];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Computes the HMAC-SHA256 of the chunks of `raw_dmi` that make up its sprites and metadata, with
+/// the given key: IHDR, zTXt, PLTE, those of [chunk::PIXEL_CHUNK_TYPES], zTXt continuations,
+/// Description iTXt and tEXt chunks, then IDAT. Each chunk contributes its type, its data length
+/// and its data, in file order.
+pub fn compute_signature(raw_dmi: &RawDmi, key: &[u8]) -> Result<[u8; 32], error::DmiError> {
+	Ok(signature_mac(raw_dmi, key)?.finalize().into_bytes().into())
+}
+
+fn signature_mac(raw_dmi: &RawDmi, key: &[u8]) -> Result<HmacSha256, error::DmiError> {
+	let mut mac = HmacSha256::new_from_slice(key).map_err(|_| {
+		error::DmiError::Signature("Failed to create HMAC from the supplied key.".to_string())
+	})?;
+
+	let mut feed_chunk = |chunk_type: &[u8; 4], data: &[u8]| {
+		mac.update(chunk_type);
+		mac.update(&(data.len() as u32).to_be_bytes());
+		mac.update(data);
+	};
+
+	feed_chunk(&raw_dmi.chunk_ihdr.chunk_type, &raw_dmi.chunk_ihdr.data);
+	if let Some(chunk_ztxt) = &raw_dmi.chunk_ztxt {
+		let mut ztxt_data = vec![];
+		chunk_ztxt.data.save(&mut ztxt_data)?;
+		feed_chunk(&chunk_ztxt.chunk_type, &ztxt_data);
+	}
+	if let Some(chunk_plte) = &raw_dmi.chunk_plte {
+		feed_chunk(&chunk_plte.chunk_type, &chunk_plte.data);
+	}
+	let ztxt_keyword = raw_dmi
+		.chunk_ztxt
+		.as_ref()
+		.map(|chunk_ztxt| &chunk_ztxt.data.keyword);
+	for chunk in raw_dmi.other_chunks.iter().flatten() {
+		let signed = chunk::PIXEL_CHUNK_TYPES.contains(&chunk.chunk_type)
+			|| is_description_text(chunk)
+			|| ztxt_keyword.is_some_and(|keyword| ztxt::is_continuation(chunk, keyword));
+		if signed {
+			feed_chunk(&chunk.chunk_type, &chunk.data);
+		}
+	}
+	for chunk in &raw_dmi.chunks_idat {
+		feed_chunk(&chunk.chunk_type, &chunk.data);
+	}
+
+	Ok(mac)
+}
+
+impl RawDmi {
+	/// Signs the file with `key`, replacing any previous signature.
+	/// Any later change to the dimensions, metadata, palette, transparency or image data invalidates
+	/// the signature.
+	pub fn sign(&mut self, key: &[u8]) -> Result<(), error::DmiError> {
+		let signature = compute_signature(self, key)?;
+		let signature_chunk = chunk::RawGenericChunk::new(SIGNATURE_TYPE, signature.to_vec())?;
+
+		let other_chunks = self.other_chunks.get_or_insert_with(Vec::new);
+		other_chunks.retain(|chunk| chunk.chunk_type != SIGNATURE_TYPE);
+		other_chunks.push(signature_chunk);
+		Ok(())
+	}
+
+	/// Checks the embedded signature against `key`.
+	/// Fails if the file is unsigned or if its contents were changed after signing.
+	pub fn verify_signature(&self, key: &[u8]) -> Result<(), error::DmiError> {
+		let signature_chunk = self
+			.other_chunks
+			.iter()
+			.flatten()
+			.find(|chunk| chunk.chunk_type == SIGNATURE_TYPE)
+			.ok_or_else(|| error::DmiError::Signature("No signature chunk found.".to_string()))?;
+
+		let mac = signature_mac(self, key)?;
+		// Constant-time comparison.
+		if mac.verify_slice(&signature_chunk.data).is_err() {
+			return Err(error::DmiError::Signature(
+				"Signature does not match the file contents.".to_string(),
+			));
+		}
+		Ok(())
+	}
+
+	/// Loads a DMI, failing unless it carries a valid signature for `key`.
+	pub fn load_verified<R: Read>(reader: R, key: &[u8]) -> Result<RawDmi, error::DmiError> {
+		let raw_dmi = RawDmi::load(reader)?;
+		raw_dmi.verify_signature(key)?;
+		Ok(raw_dmi)
+	}
+}
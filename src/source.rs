@@ -0,0 +1,181 @@
+use crate::chunk::{RawGenericChunk, PIXEL_CHUNK_TYPES};
+use crate::encode::SourceFormat;
+use crate::error::DmiError;
+use crate::icon::Icon;
+use crate::ihdr::IhdrData;
+use crate::options::SaveOptions;
+use crate::{iend, RawDmi, PNG_HEADER};
+use image::DynamicImage;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// The image data of the file an [Icon] was loaded from, kept so that [Icon::save] can write it
+/// back as it is when no sprite changed, see [Icon::pixels_modified].
+///
+/// Besides the compressed image data, which takes about as much memory as the file itself, only a
+/// SHA-256 hash of each sprite is kept, and clones share both. Replace it with an empty source to
+/// free that memory when the icon will be saved with new pixels anyway. Icons that were not loaded
+/// have an empty source. Every source compares equal, so that comparing icons only compares what
+/// they hold.
+#[derive(Clone, Debug, Default)]
+pub struct SourceSheet(Option<Arc<SheetData>>);
+
+#[derive(Debug)]
+struct SheetData {
+	chunk_ihdr: RawGenericChunk,
+	chunk_plte: Option<RawGenericChunk>,
+	/// Ancillary chunks describing the pixels, see [PIXEL_CHUNK_TYPES], which are only valid with
+	/// the image data. Other chunks are written as the chunk policy says, like for any icon.
+	image_chunks: Vec<RawGenericChunk>,
+	chunks_idat: Vec<RawGenericChunk>,
+	width: u32,
+	height: u32,
+	sprite_hashes: Vec<SpriteHash>,
+}
+
+impl PartialEq for SourceSheet {
+	fn eq(&self, _other: &SourceSheet) -> bool {
+		true
+	}
+}
+
+impl SourceSheet {
+	/// Keeps the image data of `raw_dmi`, whose sheet was sliced into `sprites` of `width`x`height`
+	/// pixels, in sheet order.
+	pub(crate) fn new<'a, I: IntoIterator<Item = &'a DynamicImage>>(
+		raw_dmi: &RawDmi,
+		width: u32,
+		height: u32,
+		sprites: I,
+	) -> SourceSheet {
+		let image_chunks = raw_dmi
+			.other_chunks
+			.iter()
+			.flatten()
+			.filter(|chunk| PIXEL_CHUNK_TYPES.contains(&chunk.chunk_type))
+			.cloned()
+			.collect();
+		SourceSheet(Some(Arc::new(SheetData {
+			chunk_ihdr: raw_dmi.chunk_ihdr.clone(),
+			chunk_plte: raw_dmi.chunk_plte.clone(),
+			image_chunks,
+			chunks_idat: raw_dmi.chunks_idat.clone(),
+			width,
+			height,
+			sprite_hashes: sprites.into_iter().map(sprite_hash).collect(),
+		})))
+	}
+
+	/// Whether `raw_dmi` holds this very image data, so that its sprites are those hashed here.
+	pub(crate) fn is_sheet_of(&self, raw_dmi: &RawDmi) -> bool {
+		let Some(data) = &self.0 else {
+			return false;
+		};
+		let pixel_chunks = raw_dmi
+			.other_chunks
+			.iter()
+			.flatten()
+			.filter(|chunk| PIXEL_CHUNK_TYPES.contains(&chunk.chunk_type));
+		data.chunk_ihdr == raw_dmi.chunk_ihdr
+			&& data.chunk_plte == raw_dmi.chunk_plte
+			&& data.chunks_idat == raw_dmi.chunks_idat
+			&& data.image_chunks.iter().eq(pixel_chunks)
+	}
+
+	/// Whether there is no image data, as the icon was not loaded from a file.
+	pub fn is_empty(&self) -> bool {
+		self.0.is_none()
+	}
+
+	/// The color type and bit depth of the loaded file. None if the icon was not loaded.
+	pub fn format(&self) -> Option<SourceFormat> {
+		let ihdr = IhdrData::try_from(&self.0.as_ref()?.chunk_ihdr).ok()?;
+		Some(SourceFormat {
+			color_type: ihdr.color_type,
+			bit_depth: ihdr.bit_depth,
+		})
+	}
+
+	/// Rough number of bytes the source takes in memory, see [Icon::approx_memory_bytes].
+	pub(crate) fn approx_memory_bytes(&self) -> usize {
+		let Some(data) = &self.0 else {
+			return 0;
+		};
+		let chunk_bytes: usize = data
+			.chunks_idat
+			.iter()
+			.chain(&data.image_chunks)
+			.chain(&data.chunk_plte)
+			.map(|chunk| std::mem::size_of::<RawGenericChunk>() + chunk.data.len())
+			.sum();
+		std::mem::size_of::<SheetData>()
+			+ chunk_bytes
+			+ data.sprite_hashes.len() * std::mem::size_of::<SpriteHash>()
+	}
+}
+
+impl Icon {
+	/// Whether the sprites differ from those of the file the icon was loaded from, in which case
+	/// [Icon::save] has to encode the sheet again. Always true for icons that were not loaded, or
+	/// whose states are not valid.
+	///
+	/// Sprites are compared through a SHA-256 hash of their pixels, so that an edit going unnoticed
+	/// would take a hash collision.
+	pub fn pixels_modified(&self) -> bool {
+		let Some(source) = &self.source.0 else {
+			return true;
+		};
+		let Ok((_, sprites)) = self.save_metadata() else {
+			return true;
+		};
+		(self.width, self.height) != (source.width, source.height)
+			|| sprites.len() != source.sprite_hashes.len()
+			|| sprites
+				.iter()
+				.zip(&source.sprite_hashes)
+				.any(|(sprite, hash)| sprite_hash(sprite) != *hash)
+	}
+
+	/// The file of the icon with the image data of [Icon::source], if the pixels were not modified.
+	/// None otherwise.
+	pub(crate) fn unmodified_raw_dmi(
+		&self,
+		options: &SaveOptions,
+	) -> Result<Option<RawDmi>, DmiError> {
+		let Some(source) = &self.source.0 else {
+			return Ok(None);
+		};
+		if self.pixels_modified() {
+			return Ok(None);
+		}
+		let (signature, _) = self.save_metadata_with(options.hotspot_third_value)?;
+		let mut other_chunks = source.image_chunks.clone();
+		other_chunks.extend(self.extra_chunks(&options.chunk_policy)?);
+		let mut raw_dmi = RawDmi {
+			header: PNG_HEADER,
+			chunk_ihdr: source.chunk_ihdr.clone(),
+			chunk_ztxt: None,
+			chunk_plte: source.chunk_plte.clone(),
+			other_chunks: (!other_chunks.is_empty()).then_some(other_chunks),
+			chunks_idat: source.chunks_idat.clone(),
+			chunk_iend: iend::RawIendChunk::new(),
+			source_map: None,
+		};
+		options.write_metadata(&mut raw_dmi, &signature)?;
+		Ok(Some(raw_dmi))
+	}
+}
+
+type SpriteHash = [u8; 32];
+
+/// Hash of the dimensions and RGBA pixels of `sprite`.
+fn sprite_hash(sprite: &DynamicImage) -> SpriteHash {
+	let mut hasher = Sha256::new();
+	hasher.update(sprite.width().to_be_bytes());
+	hasher.update(sprite.height().to_be_bytes());
+	match sprite.as_rgba8() {
+		Some(pixels) => hasher.update(pixels.as_raw()),
+		None => hasher.update(sprite.to_rgba8().as_raw()),
+	}
+	hasher.finalize().into()
+}
@@ -0,0 +1,139 @@
+use crate::chunk::{ChunkPolicy, RawGenericChunk, IDAT_TYPE};
+use crate::encode::{filter_best, ihdr_chunk, COLOR_TYPE_RGBA};
+use crate::error::DmiError;
+use crate::icon::{sheet_layout, Icon};
+use crate::progress::SaveProgress;
+use crate::{iend, ztxt, PNG_HEADER};
+use deflate::write::ZlibEncoder;
+use deflate::Compression;
+use image::{DynamicImage, RgbaImage};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+/// Largest IDAT chunk written by [Icon::save_streaming]. The compressed stream is cut into chunks of
+/// this size, so that it never has to be held whole.
+const IDAT_CHUNK_SIZE: usize = 1 << 16;
+
+impl Icon {
+	/// Saves the icon like [Icon::save], composing the sheet and compressing it one row of pixels at
+	/// a time as it goes to `writter`.
+	///
+	/// [Icon::save] builds the whole sheet, then its PNG encoding, then parses that back, which
+	/// triples the memory a large icon takes. Here, on top of the icon itself, only one row of
+	/// sprites and the compressor state are held. The file differs from the one [Icon::save] writes,
+	/// as it is not encoded by the image crate, but loads back to the same icon.
+	pub fn save_streaming<W: Write>(&self, writter: &mut W) -> Result<usize, DmiError> {
+		self.save_streaming_with_progress(writter, |_| {})
+	}
+
+	/// Like [Icon::save_streaming], calling `on_progress` after each row of sprites is compressed,
+	/// so that tools saving very large icons can show how far they got.
+	pub fn save_streaming_with_progress<W: Write, F: FnMut(&SaveProgress)>(
+		&self,
+		writter: &mut W,
+		mut on_progress: F,
+	) -> Result<usize, DmiError> {
+		let (signature, sprites) = self.save_metadata()?;
+		let (cell_width, cell_height) = sheet_layout(sprites.len());
+		let sheet_width = cell_width * self.width;
+		let sheet_height = cell_height * self.height;
+
+		writter.write_all(&PNG_HEADER)?;
+		let mut total_bytes_written = PNG_HEADER.len();
+		total_bytes_written +=
+			ihdr_chunk(sheet_width, sheet_height, 8, COLOR_TYPE_RGBA)?.save(writter)?;
+		total_bytes_written += ztxt::create_ztxt_chunk(signature.as_bytes())?.save(writter)?;
+		for chunk in self.extra_chunks(&ChunkPolicy::KeepAll)? {
+			total_bytes_written += chunk.save(writter)?;
+		}
+
+		let idat = IdatWriter {
+			writter: &mut *writter,
+			buffer: Vec::with_capacity(IDAT_CHUNK_SIZE),
+			bytes_written: 0,
+		};
+		let mut encoder = ZlibEncoder::new(idat, Compression::Default);
+		let row_length = sheet_width as usize * 4;
+		let mut row = vec![0; row_length];
+		let mut previous = vec![0; row_length];
+		for cell_row in 0..cell_height as usize {
+			// Converted once per row of sprites rather than once per row of pixels.
+			let row_sprites: Vec<Cow<RgbaImage>> = sprites
+				.iter()
+				.skip(cell_row * cell_width as usize)
+				.take(cell_width as usize)
+				.map(|sprite| rgba8(sprite))
+				.collect();
+			for y in 0..self.height {
+				row.fill(0);
+				for (column, sprite) in row_sprites.iter().enumerate() {
+					if y >= sprite.height() {
+						continue;
+					}
+					// Like the sheet of Icon::save, oversized sprites are cropped to their cell.
+					let width = sprite.width().min(self.width) as usize * 4;
+					let source = sprite.width() as usize * 4 * y as usize;
+					let target = column * self.width as usize * 4;
+					row[target..target + width].copy_from_slice(&sprite.as_raw()[source..source + width]);
+				}
+				let (filter_type, filtered) = filter_best(&row, &previous, 4);
+				encoder.write_all(&[filter_type])?;
+				encoder.write_all(&filtered)?;
+				std::mem::swap(&mut row, &mut previous);
+			}
+			on_progress(&SaveProgress {
+				rows_done: (cell_row as u32 + 1) * self.height,
+				rows_total: sheet_height,
+			});
+		}
+		let mut idat = encoder.finish()?;
+		idat.flush_chunk()?;
+		total_bytes_written += idat.bytes_written;
+
+		total_bytes_written += iend::RawIendChunk::new().save(writter)?;
+		Ok(total_bytes_written)
+	}
+}
+
+fn rgba8(image: &DynamicImage) -> Cow<'_, RgbaImage> {
+	match image.as_rgba8() {
+		Some(pixels) => Cow::Borrowed(pixels),
+		None => Cow::Owned(image.to_rgba8()),
+	}
+}
+
+/// Cuts the compressed image data written to it into IDAT chunks of [IDAT_CHUNK_SIZE] bytes.
+struct IdatWriter<'a, W: Write> {
+	writter: &'a mut W,
+	buffer: Vec<u8>,
+	bytes_written: usize,
+}
+
+impl<W: Write> IdatWriter<'_, W> {
+	/// Writes the buffered data as an IDAT chunk, if there is any.
+	fn flush_chunk(&mut self) -> io::Result<()> {
+		if self.buffer.is_empty() {
+			return Ok(());
+		}
+		let data = std::mem::replace(&mut self.buffer, Vec::with_capacity(IDAT_CHUNK_SIZE));
+		self.bytes_written += RawGenericChunk::new(IDAT_TYPE, data)
+			.and_then(|chunk| chunk.save(self.writter))
+			.map_err(io::Error::other)?;
+		Ok(())
+	}
+}
+
+impl<W: Write> Write for IdatWriter<'_, W> {
+	fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+		let length = bytes.len().min(IDAT_CHUNK_SIZE - self.buffer.len());
+		self.buffer.extend_from_slice(&bytes[..length]);
+		if self.buffer.len() == IDAT_CHUNK_SIZE {
+			self.flush_chunk()?;
+		}
+		Ok(length)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.writter.flush()
+	}
+}
@@ -0,0 +1,122 @@
+//! [proptest] strategies generating valid DMI structures, for property testing.
+//!
+//! Every generated [Icon] can be saved and loaded back unchanged: settings that are only stored for
+//! animated states (delay, loop, rewind, movement) are only generated for them.
+
+use crate::dirs::{Dirs, ALL_DIRS};
+use crate::icon::{Hotspot, Icon, IconState, Looping};
+use image::{DynamicImage, RgbaImage};
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+
+impl Arbitrary for Dirs {
+	type Parameters = ();
+	type Strategy = BoxedStrategy<Dirs>;
+
+	fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+		proptest::sample::select(ALL_DIRS.to_vec()).boxed()
+	}
+}
+
+impl Arbitrary for Looping {
+	type Parameters = ();
+	type Strategy = BoxedStrategy<Looping>;
+
+	fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+		prop_oneof![
+			Just(Looping::Indefinitely),
+			(1..100u32).prop_map(Looping::new)
+		]
+		.boxed()
+	}
+}
+
+impl Arbitrary for IconState {
+	/// Width and height of the generated images.
+	type Parameters = (u32, u32);
+	type Strategy = BoxedStrategy<IconState>;
+
+	fn arbitrary_with((width, height): Self::Parameters) -> Self::Strategy {
+		(
+			"[a-z0-9_ \"\\\\]{0,12}",
+			proptest::sample::select(vec![1u8, 4, 8]),
+			1..4u32,
+			proptest::option::of((0..width, 0..height)),
+		)
+			.prop_flat_map(move |(name, dirs, frames, hotspot)| {
+				let animated = frames > 1;
+				(
+					Just(name),
+					Just(dirs),
+					Just(frames),
+					vec(
+						image_strategy(width, height),
+						dirs as usize * frames as usize,
+					),
+					// Delays are multiples of a tenth of a tick, which survive being written as text.
+					vec(
+						(1..50u32).prop_map(|delay| delay as f32 / 10.0),
+						frames as usize,
+					),
+					any::<Looping>(),
+					any::<bool>(),
+					any::<bool>(),
+					Just(hotspot.map(|(x, y)| Hotspot { x, y })),
+					Just(animated),
+				)
+			})
+			.prop_map(
+				|(name, dirs, frames, images, delay, loop_flag, rewind, movement, hotspot, animated)| {
+					IconState {
+						name,
+						dirs,
+						frames,
+						images,
+						delay: animated.then_some(delay),
+						loop_flag: if animated {
+							loop_flag
+						} else {
+							Looping::Indefinitely
+						},
+						rewind: animated && rewind,
+						movement: animated && movement,
+						hotspot,
+						unknown_settings: None,
+					}
+				},
+			)
+			.boxed()
+	}
+}
+
+impl Arbitrary for Icon {
+	type Parameters = ();
+	type Strategy = BoxedStrategy<Icon>;
+
+	fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+		(1..8u32, 1..8u32)
+			.prop_flat_map(|(width, height)| {
+				(
+					Just(width),
+					Just(height),
+					vec(any_with::<IconState>((width, height)), 1..5),
+					btree_map("[a-z]{1,8}", vec(any::<u8>(), 0..16), 0..2),
+				)
+			})
+			.prop_map(|(width, height, states, app_data)| Icon {
+				width,
+				height,
+				states,
+				app_data,
+				..Default::default()
+			})
+			.boxed()
+	}
+}
+
+/// Generates RGBA images of the given dimensions with random pixels.
+pub fn image_strategy(width: u32, height: u32) -> impl Strategy<Value = DynamicImage> {
+	vec(any::<u8>(), (width * height * 4) as usize).prop_map(move |pixels| {
+		DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, pixels).unwrap())
+	})
+}
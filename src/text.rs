@@ -0,0 +1,196 @@
+#[cfg(feature = "images")]
+use crate::icon::Icon;
+use crate::{chunk, error, RawDmi};
+
+pub const TEXT_TYPE: [u8; 4] = [b't', b'E', b'X', b't'];
+
+/// Creates a tEXt chunk holding `text` under `keyword`.
+/// Per the PNG spec, both are Latin-1 encoded, and the keyword is 1 to 79 characters long.
+pub fn create_text_chunk(
+	keyword: &str,
+	text: &str,
+) -> Result<chunk::RawGenericChunk, error::DmiError> {
+	if keyword.is_empty() || keyword.chars().count() > 79 || keyword.contains('\0') {
+		return Err(error::DmiError::Encoding(format!(
+			"tEXt keyword must be 1 to 79 characters long without null characters: {:#?}",
+			keyword
+		)));
+	}
+	let mut data = encode_latin1(keyword)?;
+	data.push(0);
+	data.extend(encode_latin1(text)?);
+	chunk::RawGenericChunk::new(TEXT_TYPE, data)
+}
+
+/// Reads the keyword and text of a tEXt chunk.
+pub fn read_text_chunk(
+	raw_chunk: &chunk::RawGenericChunk,
+) -> Result<(String, String), error::DmiError> {
+	if raw_chunk.chunk_type != TEXT_TYPE {
+		return Err(error::DmiError::Generic(format!(
+			"Failed to read tEXt chunk. Wrong type: {:#?}. Expected: {:#?}.",
+			raw_chunk.chunk_type, TEXT_TYPE
+		)));
+	}
+	let separator = raw_chunk
+		.data
+		.iter()
+		.position(|byte| *byte == 0)
+		.ok_or_else(|| {
+			error::DmiError::Generic(
+				"Failed to read tEXt chunk. No null separator found after the keyword.".to_string(),
+			)
+		})?;
+	Ok((
+		decode_latin1(&raw_chunk.data[..separator]),
+		decode_latin1(&raw_chunk.data[(separator + 1)..]),
+	))
+}
+
+/// Whether `raw_chunk` is a tEXt or iTXt chunk holding DMI metadata, under the keyword of the zTXt
+/// chunk that usually does.
+#[cfg(feature = "images")]
+pub(crate) fn is_description_text(raw_chunk: &chunk::RawGenericChunk) -> bool {
+	matches!(raw_chunk.chunk_type, TEXT_TYPE | chunk::ITXT_TYPE)
+		&& raw_chunk
+			.data
+			.strip_prefix(crate::ztxt::DESCRIPTION_KEYWORD.as_bytes())
+			.is_some_and(|rest| rest.first() == Some(&0))
+}
+
+fn encode_latin1(text: &str) -> Result<Vec<u8>, error::DmiError> {
+	text
+		.chars()
+		.map(|character| {
+			u8::try_from(character as u32).map_err(|_| {
+				error::DmiError::Encoding(format!(
+					"Character {:?} cannot be stored in a tEXt chunk, which is Latin-1 encoded",
+					character
+				))
+			})
+		})
+		.collect()
+}
+
+fn decode_latin1(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| *byte as char).collect()
+}
+
+/// Keywords the PNG spec predefines for provenance, see [Provenance].
+pub const AUTHOR_KEYWORD: &str = "Author";
+pub const COPYRIGHT_KEYWORD: &str = "Copyright";
+pub const SOFTWARE_KEYWORD: &str = "Software";
+
+/// Who made a file and under which terms, stored in tEXt chunks under the keywords the PNG spec
+/// predefines, which image viewers and asset tools display.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Provenance {
+	/// Name of the image's creator, the `Author` keyword.
+	pub author: Option<String>,
+	/// Copyright notice or license, the `Copyright` keyword.
+	pub copyright: Option<String>,
+	/// Software used to create the image, the `Software` keyword.
+	pub software: Option<String>,
+}
+
+impl Provenance {
+	pub fn new() -> Provenance {
+		Provenance {
+			..Default::default()
+		}
+	}
+
+	/// Sets [Provenance::author].
+	pub fn author<S: Into<String>>(mut self, author: S) -> Provenance {
+		self.author = Some(author.into());
+		self
+	}
+
+	/// Sets [Provenance::copyright].
+	pub fn copyright<S: Into<String>>(mut self, copyright: S) -> Provenance {
+		self.copyright = Some(copyright.into());
+		self
+	}
+
+	/// Sets [Provenance::software].
+	pub fn software<S: Into<String>>(mut self, software: S) -> Provenance {
+		self.software = Some(software.into());
+		self
+	}
+
+	fn fields(&self) -> [(&'static str, Option<&String>); 3] {
+		[
+			(AUTHOR_KEYWORD, self.author.as_ref()),
+			(COPYRIGHT_KEYWORD, self.copyright.as_ref()),
+			(SOFTWARE_KEYWORD, self.software.as_ref()),
+		]
+	}
+}
+
+impl RawDmi {
+	/// The author, copyright and software tEXt chunks of the file.
+	pub fn provenance(&self) -> Provenance {
+		read_provenance(self.other_chunks.iter().flatten())
+	}
+
+	/// Stores the fields of `provenance` in tEXt chunks, replacing the previous ones. Fields set
+	/// to None are removed from the file.
+	pub fn set_provenance(&mut self, provenance: &Provenance) -> Result<(), error::DmiError> {
+		for (keyword, text) in provenance.fields() {
+			match text {
+				Some(text) => self.set_text(keyword, text)?,
+				None => {
+					self.remove_text(keyword);
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+/// tEXt chunks are safe to copy, so an [Icon] carries them in [Icon::ancillary_chunks].
+#[cfg(feature = "images")]
+impl Icon {
+	/// The author, copyright and software tEXt chunks of the icon.
+	pub fn provenance(&self) -> Provenance {
+		read_provenance(self.ancillary_chunks.iter())
+	}
+
+	/// Stores the fields of `provenance` in tEXt chunks written on save, replacing the previous
+	/// ones. Fields set to None are removed.
+	pub fn set_provenance(&mut self, provenance: &Provenance) -> Result<(), error::DmiError> {
+		let mut new_chunks = vec![];
+		for (keyword, text) in provenance.fields() {
+			if let Some(text) = text {
+				new_chunks.push(create_text_chunk(keyword, text)?);
+			}
+		}
+		self.ancillary_chunks.retain(|chunk| {
+			chunk.chunk_type != TEXT_TYPE
+				|| !matches!(
+					read_text_chunk(chunk),
+					Ok((keyword, _)) if [AUTHOR_KEYWORD, COPYRIGHT_KEYWORD, SOFTWARE_KEYWORD].contains(&keyword.as_str())
+				)
+		});
+		self.ancillary_chunks.extend(new_chunks);
+		Ok(())
+	}
+}
+
+fn read_provenance<'a, I: Iterator<Item = &'a chunk::RawGenericChunk>>(chunks: I) -> Provenance {
+	let mut provenance = Provenance::new();
+	for (keyword, text) in chunks
+		.filter(|chunk| chunk.chunk_type == TEXT_TYPE)
+		.filter_map(|chunk| read_text_chunk(chunk).ok())
+	{
+		let field = match keyword.as_str() {
+			AUTHOR_KEYWORD => &mut provenance.author,
+			COPYRIGHT_KEYWORD => &mut provenance.copyright,
+			SOFTWARE_KEYWORD => &mut provenance.software,
+			_ => continue,
+		};
+		// Like RawDmi::text, the first chunk with a keyword wins.
+		field.get_or_insert(text);
+	}
+	provenance
+}
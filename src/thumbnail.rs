@@ -0,0 +1,150 @@
+use crate::error::DmiError;
+use crate::icon::Icon;
+use crate::progress::Progress;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Options for [generate_thumbnails].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ThumbnailOptions {
+	/// Thumbnails fit in a square of this side, keeping the aspect ratio of the icon. Smaller icons
+	/// are left as they are.
+	pub max_size: u32,
+	/// How many files are loaded at once, bounding the memory used. Defaults to the available
+	/// parallelism.
+	pub threads: NonZeroUsize,
+}
+
+impl Default for ThumbnailOptions {
+	fn default() -> Self {
+		ThumbnailOptions {
+			max_size: 32,
+			threads: thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+		}
+	}
+}
+
+impl ThumbnailOptions {
+	pub fn new() -> ThumbnailOptions {
+		ThumbnailOptions {
+			..Default::default()
+		}
+	}
+
+	/// Sets [ThumbnailOptions::max_size].
+	pub fn max_size(mut self, max_size: u32) -> ThumbnailOptions {
+		self.max_size = max_size;
+		self
+	}
+
+	/// Sets [ThumbnailOptions::threads].
+	pub fn threads(mut self, threads: NonZeroUsize) -> ThumbnailOptions {
+		self.threads = threads;
+		self
+	}
+}
+
+/// A preview of one state, see [generate_thumbnails].
+#[derive(Clone, PartialEq, Debug)]
+pub struct Thumbnail {
+	/// Index of the state in its icon, as names may repeat.
+	pub state_index: usize,
+	pub state_name: String,
+	/// The first image of the state, scaled down.
+	pub image: DynamicImage,
+}
+
+/// What [generate_thumbnails] got out of one file.
+type FileThumbnails = (PathBuf, Result<Vec<Thumbnail>, DmiError>);
+
+/// Generates a thumbnail of every state of every file in `paths`, loading them on
+/// [ThumbnailOptions::threads] threads.
+///
+/// `on_file` is called on the calling thread as soon as a file is done, with its thumbnails in
+/// state order or the error that stopped it, so files come in no particular order. Workers wait
+/// for `on_file` to take their results, which keeps at most a few icons in memory at once however
+/// many files there are.
+pub fn generate_thumbnails<P, F>(paths: &[P], options: &ThumbnailOptions, on_file: F)
+where
+	P: AsRef<Path> + Sync,
+	F: FnMut(&Path, Result<Vec<Thumbnail>, DmiError>),
+{
+	generate_thumbnails_with_progress(paths, options, on_file, |_| {})
+}
+
+/// Like [generate_thumbnails], calling `on_progress` after each `on_file` call, so that tools
+/// thumbnailing large trees can show how far they got.
+pub fn generate_thumbnails_with_progress<P, F, G>(
+	paths: &[P],
+	options: &ThumbnailOptions,
+	mut on_file: F,
+	mut on_progress: G,
+) where
+	P: AsRef<Path> + Sync,
+	F: FnMut(&Path, Result<Vec<Thumbnail>, DmiError>),
+	G: FnMut(&Progress),
+{
+	let pending = Mutex::new(paths.iter());
+	let (sender, receiver) = mpsc::sync_channel::<FileThumbnails>(options.threads.get());
+	thread::scope(|scope| {
+		for _ in 0..options.threads.get().min(paths.len()) {
+			let sender = sender.clone();
+			let pending = &pending;
+			scope.spawn(move || loop {
+				let Some(path) = pending.lock().unwrap().next() else {
+					break;
+				};
+				let path = path.as_ref();
+				let thumbnails = file_thumbnails(path, options.max_size);
+				if sender.send((path.to_path_buf(), thumbnails)).is_err() {
+					break;
+				}
+			});
+		}
+		drop(sender);
+		let mut bytes_done = 0;
+		for (index, (path, thumbnails)) in receiver.into_iter().enumerate() {
+			on_file(&path, thumbnails);
+			bytes_done += fs::metadata(&path).map_or(0, |metadata| metadata.len());
+			on_progress(&Progress {
+				files_done: index + 1,
+				files_total: paths.len(),
+				bytes_done,
+				path: &path,
+			});
+		}
+	});
+}
+
+fn file_thumbnails(path: &Path, max_size: u32) -> Result<Vec<Thumbnail>, DmiError> {
+	let icon = Icon::load(BufReader::new(File::open(path)?))?;
+	Ok(
+		icon
+			.states
+			.into_iter()
+			.enumerate()
+			.filter_map(|(state_index, state)| {
+				let image = state.images.into_iter().next()?;
+				Some(Thumbnail {
+					state_index,
+					state_name: state.name,
+					image: thumbnail(image, max_size),
+				})
+			})
+			.collect(),
+	)
+}
+
+/// Scales `image` down to fit in `max_size`, with nearest neighbour sampling to keep pixel art crisp.
+fn thumbnail(image: DynamicImage, max_size: u32) -> DynamicImage {
+	if image.width() <= max_size && image.height() <= max_size {
+		return image;
+	}
+	image.resize(max_size, max_size, FilterType::Nearest)
+}
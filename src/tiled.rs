@@ -0,0 +1,115 @@
+use crate::error::DmiError;
+use crate::icon::{sheet_layout, Icon, DIR_ORDERING};
+use std::io::prelude::*;
+
+/// Tiled map format version the exported tilesets declare.
+pub const TILED_FORMAT_VERSION: &str = "1.10";
+
+/// Writes a [Tiled](https://www.mapeditor.org/) tileset (`.tsx`) describing `icon`.
+///
+/// The tileset references `image_source`, which should point at the sheet produced by
+/// [Icon::save] (a DMI file is a regular PNG). Every tile carries `state`, `dir` and `frame`
+/// properties, `dir` being the numeric DM direction and `frame` starting at 1. Animated states
+/// also get a Tiled animation on the tile of their first frame, for each direction.
+pub fn export_tileset<W: Write>(
+	icon: &Icon,
+	name: &str,
+	image_source: &str,
+	writter: &mut W,
+) -> Result<(), DmiError> {
+	let sprite_count: usize = icon.states.iter().map(|state| state.images.len()).sum();
+	let (columns, rows) = sheet_layout(sprite_count);
+
+	let mut tileset = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+	tileset.push_str(&format!(
+		"<tileset version=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" tilecount=\"{}\" columns=\"{}\">\n",
+		TILED_FORMAT_VERSION,
+		escape_xml(name),
+		icon.width,
+		icon.height,
+		sprite_count,
+		columns
+	));
+	tileset.push_str(&format!(
+		" <image source=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+		escape_xml(image_source),
+		columns * icon.width,
+		rows * icon.height
+	));
+
+	let mut tile_id = 0;
+	for icon_state in &icon.states {
+		if icon_state.images.len() as u32 != icon_state.dirs as u32 * icon_state.frames {
+			return Err(DmiError::Generic(format!("Error exporting tileset: number of images ({}) differs from the stated metadata. Dirs: {}. Frames: {}. Name: \"{}\".", icon_state.images.len(), icon_state.dirs, icon_state.frames, icon_state.name)));
+		};
+		let state_name = escape_xml(&icon_state.name);
+		let first_tile_id = tile_id;
+
+		for frame in 0..icon_state.frames {
+			for dir_index in 0..icon_state.dirs as usize {
+				let dir = DIR_ORDERING.get(dir_index).ok_or_else(|| {
+					DmiError::Generic(format!(
+						"Error exporting tileset: invalid number of dirs ({}) in icon state of name \"{}\".",
+						icon_state.dirs, icon_state.name
+					))
+				})?;
+				tileset.push_str(&format!(" <tile id=\"{}\">\n  <properties>\n", tile_id));
+				tileset.push_str(&format!(
+					"   <property name=\"state\" value=\"{}\"/>\n",
+					state_name
+				));
+				tileset.push_str(&format!(
+					"   <property name=\"dir\" type=\"int\" value=\"{}\"/>\n",
+					dir.bits()
+				));
+				tileset.push_str(&format!(
+					"   <property name=\"frame\" type=\"int\" value=\"{}\"/>\n",
+					frame + 1
+				));
+				tileset.push_str("  </properties>\n");
+
+				if frame == 0 && icon_state.frames > 1 {
+					tileset.push_str("  <animation>\n");
+					for animation_frame in 0..icon_state.frames {
+						// Delays are stored in ticks, a tenth of a second each.
+						let delay = icon_state
+							.delay
+							.as_ref()
+							.and_then(|delay| delay.get(animation_frame as usize))
+							.copied()
+							.unwrap_or(1.0);
+						tileset.push_str(&format!(
+							"   <frame tileid=\"{}\" duration=\"{}\"/>\n",
+							first_tile_id + animation_frame * icon_state.dirs as u32 + dir_index as u32,
+							(delay * 100.0).round() as u32
+						));
+					}
+					tileset.push_str("  </animation>\n");
+				}
+
+				tileset.push_str(" </tile>\n");
+				tile_id += 1;
+			}
+		}
+	}
+
+	tileset.push_str("</tileset>\n");
+
+	writter.write_all(tileset.as_bytes())?;
+	Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for character in text.chars() {
+		match character {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&apos;"),
+			_ => escaped.push(character),
+		}
+	}
+	escaped
+}
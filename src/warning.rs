@@ -0,0 +1,165 @@
+use crate::appdata::APP_DATA_TYPE;
+use crate::chunk::ChunkType;
+use crate::error::DmiError;
+use crate::icon::Looping;
+#[cfg(feature = "images")]
+use crate::icon::{DecodeBuffers, Icon};
+use crate::metadata::{DmiMetadata, StateMetadata};
+use crate::quirks::Quirk;
+use crate::RawDmi;
+use std::io::Read;
+
+/// Something unusual found in a file that was nonetheless loaded. Lenient pipelines can ignore
+/// these, strict ones can turn them into failures.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DmiWarning {
+	/// A state setting this library does not know about. It is kept in `unknown_settings`.
+	UnknownSetting { state: String, key: String },
+	/// A state with a number of dirs BYOND never writes, rather than 1, 4 or 8.
+	UnusualDirs { state: String, dirs: u8 },
+	/// A single frame state with animation settings (delay, loop or rewind), which are not saved.
+	StillStateAnimationSettings { state: String },
+	/// An ancillary chunk that is dropped when the file is loaded as an [crate::icon::Icon], because it
+	/// is not safe to copy.
+	IgnoredChunk { chunk_type: [u8; 4] },
+	/// Nonstandard metadata accepted by quirks mode, see [Quirk].
+	MetadataQuirk { state: String, quirk: Quirk },
+	/// An ancillary chunk with a bad CRC or type, dropped by a lenient load, see
+	/// [crate::options::LoadOptions::lenient].
+	CorruptChunk { chunk_type: [u8; 4] },
+	/// A `width` or `height` header line missing from the metadata, which quirks mode reads as
+	/// `assumed`, BYOND's default icon size.
+	MissingSize { key: String, assumed: u32 },
+}
+
+impl std::fmt::Display for DmiWarning {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			DmiWarning::UnknownSetting { state, key } => {
+				write!(f, "Unknown setting \"{key}\" in icon_state \"{state}\"")
+			}
+			DmiWarning::UnusualDirs { state, dirs } => {
+				write!(
+					f,
+					"icon_state \"{state}\" has {dirs} dirs, expected 1, 4 or 8"
+				)
+			}
+			DmiWarning::StillStateAnimationSettings { state } => write!(
+				f,
+				"icon_state \"{state}\" has a single frame but animation settings, which will be dropped"
+			),
+			DmiWarning::IgnoredChunk { chunk_type } => {
+				write!(f, "Ignored chunk {}", String::from_utf8_lossy(chunk_type))
+			}
+			DmiWarning::MetadataQuirk { state, quirk } => {
+				write!(f, "icon_state \"{state}\": {quirk}")
+			}
+			DmiWarning::CorruptChunk { chunk_type } => {
+				write!(
+					f,
+					"Dropped corrupt chunk {}",
+					String::from_utf8_lossy(chunk_type)
+				)
+			}
+			DmiWarning::MissingSize { key, assumed } => {
+				write!(f, "No {key} found, {assumed} assumed")
+			}
+		}
+	}
+}
+
+#[cfg(feature = "images")]
+impl Icon {
+	/// Like [Icon::load], also reporting the oddities of the file, see [DmiWarning].
+	pub fn load_with_warnings<R: Read>(reader: R) -> Result<(Icon, Vec<DmiWarning>), DmiError> {
+		let mut warnings = vec![];
+		let icon = Icon::load_reporting(RawDmi::load(reader)?, false, &mut warnings)?;
+		Ok((icon, warnings))
+	}
+
+	/// Builds an [Icon] out of `raw_dmi`, pushing the oddities of its states then of its chunks to
+	/// `warnings`. With `quirks`, the nonstandard metadata listed in [Quirk] is accepted, and each
+	/// use of it is pushed first.
+	pub(crate) fn load_reporting(
+		raw_dmi: RawDmi,
+		quirks: bool,
+		warnings: &mut Vec<DmiWarning>,
+	) -> Result<Icon, DmiError> {
+		let chunk_warnings = raw_dmi.chunk_warnings();
+		let quirks = if quirks { Some(&mut *warnings) } else { None };
+		let (icon, _) = Icon::assemble(raw_dmi, &mut DecodeBuffers::default(), false, quirks)?;
+		for icon_state in &icon.states {
+			icon_state.metadata().push_warnings(warnings);
+		}
+		warnings.extend(chunk_warnings);
+		Ok(icon)
+	}
+}
+
+impl DmiMetadata {
+	/// Like [DmiMetadata::load], also reporting the oddities of the file, see [DmiWarning].
+	pub fn load_with_warnings<R: Read>(
+		reader: R,
+	) -> Result<(DmiMetadata, Vec<DmiWarning>), DmiError> {
+		let raw_dmi = RawDmi::load(reader)?;
+		let metadata = DmiMetadata::from_raw_dmi(&raw_dmi)?;
+		let mut warnings = metadata.warnings();
+		warnings.extend(raw_dmi.chunk_warnings());
+		Ok((metadata, warnings))
+	}
+
+	/// The oddities of the states, in state order.
+	pub fn warnings(&self) -> Vec<DmiWarning> {
+		let mut warnings = vec![];
+		for state in &self.states {
+			state.push_warnings(&mut warnings);
+		}
+		warnings
+	}
+}
+
+impl StateMetadata {
+	pub(crate) fn push_warnings(&self, warnings: &mut Vec<DmiWarning>) {
+		if !matches!(self.dirs, 1 | 4 | 8) {
+			warnings.push(DmiWarning::UnusualDirs {
+				state: self.name.clone(),
+				dirs: self.dirs,
+			});
+		}
+		if self.frames == 1
+			&& (self.delay.is_some() || matches!(self.loop_flag, Looping::NTimes(_)) || self.rewind)
+		{
+			warnings.push(DmiWarning::StillStateAnimationSettings {
+				state: self.name.clone(),
+			});
+		}
+		if let Some(unknown_settings) = &self.unknown_settings {
+			let mut keys: Vec<&String> = unknown_settings.keys().collect();
+			keys.sort();
+			for key in keys {
+				warnings.push(DmiWarning::UnknownSetting {
+					state: self.name.clone(),
+					key: key.clone(),
+				});
+			}
+		}
+	}
+}
+
+impl RawDmi {
+	/// Warnings for the ancillary chunks an [crate::icon::Icon] does not keep.
+	pub(crate) fn chunk_warnings(&self) -> Vec<DmiWarning> {
+		self
+			.other_chunks
+			.iter()
+			.flatten()
+			.filter(|chunk| {
+				let chunk_type = ChunkType::from(chunk.chunk_type);
+				chunk_type != ChunkType::Other(APP_DATA_TYPE) && !chunk_type.is_kept_by_icon()
+			})
+			.map(|chunk| DmiWarning::IgnoredChunk {
+				chunk_type: chunk.chunk_type,
+			})
+			.collect()
+	}
+}
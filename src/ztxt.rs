@@ -6,6 +6,9 @@ use std::io::prelude::*;
 
 pub const ZTXT_TYPE: [u8; 4] = [b'z', b'T', b'X', b't'];
 
+/// Keyword of the zTXt chunk holding the DMI metadata, as written by BYOND.
+pub const DESCRIPTION_KEYWORD: &str = "Description";
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct RawZtxtChunk {
 	pub data_length: [u8; 4],
@@ -15,8 +18,26 @@ pub struct RawZtxtChunk {
 }
 
 pub fn create_ztxt_chunk(dmi_signature: &[u8]) -> Result<RawZtxtChunk, error::DmiError> {
+	create_ztxt_chunk_with_keyword(DESCRIPTION_KEYWORD, dmi_signature)
+}
+
+/// Like [create_ztxt_chunk], under `keyword` rather than [DESCRIPTION_KEYWORD]. Keywords are 1 to 79
+/// printable ASCII characters.
+pub fn create_ztxt_chunk_with_keyword(
+	keyword: &str,
+	dmi_signature: &[u8],
+) -> Result<RawZtxtChunk, error::DmiError> {
+	if keyword.is_empty()
+		|| keyword.len() > 79
+		|| !keyword.bytes().all(|byte| (b' '..=b'~').contains(&byte))
+	{
+		return Err(error::DmiError::InvalidArgument(format!(
+			"Failed to create zTXt chunk. Invalid keyword: {keyword:?}"
+		)));
+	}
 	let compressed_text = encode(dmi_signature);
 	let data = RawZtxtData {
+		keyword: keyword.as_bytes().to_vec(),
 		compressed_text,
 		..Default::default()
 	};
@@ -24,7 +45,7 @@ pub fn create_ztxt_chunk(dmi_signature: &[u8]) -> Result<RawZtxtChunk, error::Dm
 	data.save(&mut data_bytes)?;
 	let data_length = (data_bytes.len() as u32).to_be_bytes();
 	let chunk_type = ZTXT_TYPE;
-	let crc = crc::calculate_crc(chunk_type.iter().chain(data_bytes.iter())).to_be_bytes();
+	let crc = crc::ChunkCrcHasher::checksum(chunk_type, &data_bytes).to_be_bytes();
 	Ok(RawZtxtChunk {
 		data_length,
 		chunk_type,
@@ -33,6 +54,57 @@ pub fn create_ztxt_chunk(dmi_signature: &[u8]) -> Result<RawZtxtChunk, error::Dm
 	})
 }
 
+/// Keyword of the zTXt chunks continuing metadata too long for a single chunk, held under `keyword`
+/// in the first one. See [create_split_ztxt_chunks].
+pub fn continuation_keyword(keyword: &str) -> String {
+	format!("{keyword} {CONTINUATION_SUFFIX}")
+}
+
+const CONTINUATION_SUFFIX: &str = "continued";
+
+/// Whether `raw_chunk` is a zTXt chunk continuing the metadata held under `keyword`, that is a
+/// zTXt chunk under exactly [continuation_keyword] of `keyword`. Loading keeps those among the
+/// other chunks of [crate::RawDmi] rather than as its zTXt chunk.
+pub fn is_continuation(raw_chunk: &chunk::RawGenericChunk, keyword: &[u8]) -> bool {
+	raw_chunk.chunk_type == ZTXT_TYPE
+		&& raw_chunk
+			.data
+			.split(|byte| *byte == 0)
+			.next()
+			.and_then(|chunk_keyword| chunk_keyword.strip_prefix(keyword))
+			.and_then(|rest| rest.strip_prefix(b" "))
+			.is_some_and(|suffix| suffix == CONTINUATION_SUFFIX.as_bytes())
+}
+
+/// Creates zTXt chunks holding `text` split in parts of at most `max_length` bytes, cut between
+/// lines: the first part under `keyword`, the following ones under [continuation_keyword], to be
+/// written in order. Lines are never cut, so a part is only longer when a single line is.
+pub fn create_split_ztxt_chunks(
+	keyword: &str,
+	text: &str,
+	max_length: usize,
+) -> Result<(RawZtxtChunk, Vec<chunk::RawGenericChunk>), error::DmiError> {
+	let mut parts = vec![String::new()];
+	for line in text.split_inclusive('\n') {
+		let part = parts.last_mut().unwrap();
+		if !part.is_empty() && part.len() + line.len() > max_length {
+			parts.push(line.to_string());
+		} else {
+			part.push_str(line);
+		}
+	}
+	let first = create_ztxt_chunk_with_keyword(keyword, parts[0].as_bytes())?;
+	let continuation_keyword = continuation_keyword(keyword);
+	let mut continuations = vec![];
+	for part in &parts[1..] {
+		let chunk = create_ztxt_chunk_with_keyword(&continuation_keyword, part.as_bytes())?;
+		let mut data = vec![];
+		chunk.data.save(&mut data)?;
+		continuations.push(chunk::RawGenericChunk::new(ZTXT_TYPE, data)?);
+	}
+	Ok((first, continuations))
+}
+
 impl RawZtxtChunk {
 	pub fn load<R: Read>(reader: &mut R) -> Result<RawZtxtChunk, error::DmiError> {
 		let mut raw_chunk_bytes = Vec::new();
@@ -73,7 +145,7 @@ impl RawZtxtChunk {
 			raw_chunk_bytes[total_bytes_length - 2],
 			raw_chunk_bytes[total_bytes_length - 1],
 		];
-		let calculated_crc = crc::calculate_crc(chunk_type.iter().chain(data_bytes.iter()));
+		let calculated_crc = crc::ChunkCrcHasher::checksum(chunk_type, data_bytes);
 		if u32::from_be_bytes(crc) != calculated_crc {
 			return Err(error::DmiError::Generic(format!("Failed to load RawZtxtChunk from reader. Given CRC ({}) does not match the calculated one ({}).", u32::from_be_bytes(crc), calculated_crc)));
 		}
@@ -130,7 +202,7 @@ impl RawZtxtChunk {
 		data.save(&mut data_bytes)?;
 		let data_length = (data_bytes.len() as u32).to_be_bytes();
 		let chunk_type = ZTXT_TYPE;
-		let crc = crc::calculate_crc(chunk_type.iter().chain(data_bytes.iter())).to_be_bytes();
+		let crc = crc::ChunkCrcHasher::checksum(chunk_type, &data_bytes).to_be_bytes();
 		Ok(RawZtxtChunk {
 			data_length,
 			chunk_type,
@@ -197,7 +269,7 @@ impl TryFrom<Vec<u8>> for RawZtxtChunk {
 		let data_bytes = &raw_chunk_bytes[8..(total_bytes_length - 4)];
 		let data = RawZtxtData::load(data_bytes)?;
 		let crc = [raw_chunk_bytes[total_bytes_length - 4], raw_chunk_bytes[total_bytes_length - 3], raw_chunk_bytes[total_bytes_length - 2], raw_chunk_bytes[total_bytes_length - 1]];
-		let calculated_crc = crc::calculate_crc(chunk_type.iter().chain(data_bytes.iter()));
+		let calculated_crc = crc::ChunkCrcHasher::checksum(chunk_type, &data_bytes);
 		if u32::from_be_bytes(crc) != calculated_crc {
 			bail!("Failed to convert Vec<u8> into RawZtxtChunk. Given CRC ({}) does not match the calculated one ({}).", u32::from_be_bytes(crc), calculated_crc)
 		}
@@ -299,13 +371,11 @@ impl RawZtxtData {
 	}
 
 	fn crc(&self) -> u32 {
-		crc::calculate_crc(
-			ZTXT_TYPE
-				.iter()
-				.chain(self.keyword.iter())
-				.chain([self.null_separator, self.compression_method].iter())
-				.chain(self.compressed_text.iter()),
-		)
+		let mut hasher = crc::ChunkCrcHasher::new();
+		hasher.update(&self.keyword);
+		hasher.update(&[self.null_separator, self.compression_method]);
+		hasher.update(&self.compressed_text);
+		hasher.finalize_for(ZTXT_TYPE)
 	}
 }
 
@@ -316,7 +386,7 @@ pub fn encode(text_to_compress: &[u8]) -> Vec<u8> {
 impl Default for RawZtxtData {
 	fn default() -> Self {
 		RawZtxtData {
-			keyword: "Description".as_bytes().to_vec(),
+			keyword: DESCRIPTION_KEYWORD.as_bytes().to_vec(),
 			null_separator: 0,
 			compression_method: 0,
 			compressed_text: vec![],
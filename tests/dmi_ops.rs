@@ -16,3 +16,3916 @@ fn load_and_save_dmi() {
 		.save(&mut write_file)
 		.expect("Failed to save lights dmi");
 }
+
+#[test]
+fn export_tiled_tileset() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let load_file =
+		File::open(load_path.as_path()).unwrap_or_else(|_| panic!("No lights dmi: {load_path:?}"));
+	let lights_icon = Icon::load(&load_file).expect("Unable to load lights dmi");
+	let mut tileset = vec![];
+	dmi::tiled::export_tileset(&lights_icon, "lights", "lights.dmi", &mut tileset)
+		.expect("Failed to export tileset");
+	let tileset = String::from_utf8(tileset).unwrap();
+	let sprite_count: usize = lights_icon
+		.states
+		.iter()
+		.map(|state| state.images.len())
+		.sum();
+	assert_eq!(tileset.matches("<tile id=").count(), sprite_count);
+	assert!(tileset.contains("<image source=\"lights.dmi\""));
+}
+
+#[test]
+fn decoder_reuse() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut decoder = dmi::icon::Decoder::new();
+	let first = decoder
+		.load(File::open(load_path.as_path()).unwrap())
+		.expect("Unable to load lights dmi");
+	let second = decoder
+		.load(File::open(load_path.as_path()).unwrap())
+		.expect("Unable to load lights dmi twice");
+	assert_eq!(first, second);
+	assert_eq!(
+		first,
+		Icon::load(File::open(load_path.as_path()).unwrap()).unwrap()
+	);
+
+	// A smaller sheet of another color type in between leaves stale pixels in the buffers.
+	let small = Icon {
+		width: 1,
+		height: 1,
+		states: vec![dmi::icon::IconState {
+			name: "gray".to_string(),
+			images: vec![image::DynamicImage::ImageLuma8(
+				image::GrayImage::from_pixel(1, 1, image::Luma([128])),
+			)],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	small.save(&mut saved).unwrap();
+	let loaded = decoder.load(&saved[..]).unwrap();
+	assert_eq!(loaded, Icon::load(&saved[..]).unwrap());
+	assert_eq!(
+		loaded.states[0].images[0].to_rgba8().get_pixel(0, 0).0,
+		[128, 128, 128, 255]
+	);
+	let third = decoder
+		.load(File::open(load_path.as_path()).unwrap())
+		.expect("Unable to load lights dmi after a smaller file");
+	assert_eq!(first, third);
+}
+
+#[test]
+fn builder_rejects_invalid_states() {
+	use dmi::builder::IconBuilder;
+	use dmi::icon::IconState;
+	use image::DynamicImage;
+
+	let builder = IconBuilder::new(32, 32)
+		.add_state(IconState {
+			name: "idle".to_string(),
+			images: vec![DynamicImage::new_rgba8(32, 32)],
+			..Default::default()
+		})
+		.expect("Valid state refused");
+	assert!(builder
+		.clone()
+		.add_state(IconState {
+			name: "wrong_size".to_string(),
+			images: vec![DynamicImage::new_rgba8(16, 16)],
+			..Default::default()
+		})
+		.is_err());
+	assert!(builder
+		.clone()
+		.add_state(IconState {
+			name: "missing_images".to_string(),
+			dirs: 4,
+			images: vec![DynamicImage::new_rgba8(32, 32)],
+			..Default::default()
+		})
+		.is_err());
+
+	let icon = builder
+		.sprite("walk", DynamicImage::new_rgba8(32, 32))
+		.build()
+		.expect("Failed to build icon");
+	assert_eq!(icon.states.len(), 2);
+	icon.save(&mut vec![]).expect("Built icon failed to save");
+}
+
+#[test]
+fn fluent_builder_validates_on_build() {
+	use dmi::builder::IconBuilder;
+	use dmi::icon::IconState;
+	use image::DynamicImage;
+
+	let icon = IconBuilder::default()
+		.size(32, 32)
+		.sprite("idle", DynamicImage::new_rgba8(32, 32))
+		.state(
+			"run",
+			IconState {
+				frames: 2,
+				images: vec![DynamicImage::new_rgba8(32, 32); 2],
+				delay: Some(vec![1.0, 2.0]),
+				..Default::default()
+			},
+		)
+		.build()
+		.expect("Failed to build icon");
+	assert_eq!(icon.states.len(), 2);
+	assert_eq!(icon["run"].frames, 2);
+	icon.save(&mut vec![]).expect("Built icon failed to save");
+
+	let wrong_size = IconBuilder::default()
+		.size(32, 32)
+		.sprite("idle", DynamicImage::new_rgba8(16, 16));
+	assert!(wrong_size.build().is_err());
+	assert!(IconBuilder::default()
+		.sprite("idle", DynamicImage::new_rgba8(32, 32))
+		.build()
+		.is_err());
+}
+
+#[test]
+fn app_data_round_trip() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path.as_path()).unwrap()).unwrap();
+	icon
+		.app_data
+		.insert("generator".to_string(), b"{\"size\": 32}".to_vec());
+	let mut saved = vec![];
+	icon.save(&mut saved).expect("Failed to save icon");
+
+	let raw_dmi = dmi::RawDmi::load(&saved[..]).expect("Failed to load raw dmi");
+	assert_eq!(raw_dmi.app_data("generator"), Some(&b"{\"size\": 32}"[..]));
+	let reloaded = Icon::load(&saved[..]).expect("Failed to reload icon");
+	assert_eq!(reloaded.app_data, icon.app_data);
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn signature_detects_tampering() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = dmi::RawDmi::load(File::open(load_path.as_path()).unwrap()).unwrap();
+	raw_dmi.sign(b"secret").expect("Failed to sign");
+	let mut signed = vec![];
+	raw_dmi.save(&mut signed).unwrap();
+
+	let mut loaded =
+		dmi::RawDmi::load_verified(&signed[..], b"secret").expect("Valid signature refused");
+	assert!(loaded.verify_signature(b"other secret").is_err());
+	loaded.chunks_idat[0].data[0] ^= 1;
+	assert!(loaded.verify_signature(b"secret").is_err());
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn signature_covers_palette_and_metadata_chunks() {
+	use dmi::encode::{OutputColorType, TRNS_TYPE};
+	use dmi::itxt::MetadataChunk;
+	use dmi::options::SaveOptions;
+	use dmi::quantize::QuantizeOptions;
+	use dmi::{text, RawDmi};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	let signed = |options: &SaveOptions| {
+		let mut saved = vec![];
+		icon.save_with(&mut saved, options).unwrap();
+		let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+		raw_dmi.sign(b"secret").unwrap();
+		raw_dmi.verify_signature(b"secret").unwrap();
+		raw_dmi
+	};
+	let tamper = |raw_dmi: &mut RawDmi, signed_chunk: &dyn Fn(&[u8; 4], &[u8]) -> bool| {
+		let chunk = raw_dmi
+			.other_chunks
+			.iter_mut()
+			.flatten()
+			.find(|chunk| signed_chunk(&chunk.chunk_type, &chunk.data))
+			.expect("No chunk to tamper with");
+		*chunk.data.last_mut().unwrap() ^= 1;
+	};
+
+	let indexed =
+		signed(&SaveOptions::new().color_type(OutputColorType::Indexed(QuantizeOptions::new())));
+	let mut recolored = indexed.clone();
+	recolored.chunk_plte.as_mut().unwrap().data[0] ^= 1;
+	assert!(recolored.verify_signature(b"secret").is_err());
+	let mut transparency_changed = indexed.clone();
+	tamper(&mut transparency_changed, &|chunk_type, _| {
+		*chunk_type == TRNS_TYPE
+	});
+	assert!(transparency_changed.verify_signature(b"secret").is_err());
+
+	let mut split = signed(&SaveOptions::new().split_metadata_above(Some(40)));
+	tamper(&mut split, &|chunk_type, _| chunk_type == b"zTXt");
+	assert!(split.verify_signature(b"secret").is_err());
+
+	let mut itxt = signed(&SaveOptions::new().metadata_chunk(MetadataChunk::Itxt));
+	tamper(&mut itxt, &|chunk_type, _| chunk_type == b"iTXt");
+	assert!(itxt.verify_signature(b"secret").is_err());
+
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let mut text_metadata = RawDmi::load(&saved[..]).unwrap();
+	let metadata = text_metadata
+		.chunk_ztxt
+		.take()
+		.unwrap()
+		.data
+		.decode()
+		.unwrap();
+	let metadata = String::from_utf8(metadata).unwrap();
+	text_metadata.other_chunks = Some(vec![
+		text::create_text_chunk("Description", &metadata).unwrap()
+	]);
+	text_metadata.sign(b"secret").unwrap();
+	tamper(&mut text_metadata, &|chunk_type, _| chunk_type == b"tEXt");
+	assert!(text_metadata.verify_signature(b"secret").is_err());
+
+	// Chunks that do not change the sprites or their metadata are left out.
+	let mut commented = indexed;
+	commented
+		.other_chunks
+		.as_mut()
+		.unwrap()
+		.push(text::create_text_chunk("Comment", "hello").unwrap());
+	commented.verify_signature(b"secret").unwrap();
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn reused_image_data_drops_stale_signature() {
+	use dmi::encode::{OutputColorType, TRNS_TYPE};
+	use dmi::options::SaveOptions;
+	use dmi::quantize::QuantizeOptions;
+	use dmi::signature::SIGNATURE_TYPE;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	let mut indexed = vec![];
+	let options = SaveOptions::new().color_type(OutputColorType::Indexed(QuantizeOptions::new()));
+	icon.save_with(&mut indexed, &options).unwrap();
+	let mut raw_dmi = RawDmi::load(&indexed[..]).unwrap();
+	raw_dmi.sign(b"secret").unwrap();
+	let mut signed = vec![];
+	raw_dmi.save(&mut signed).unwrap();
+
+	// Only the metadata changes, so the image data is written back as it is.
+	let mut renamed = Icon::load(&signed[..]).unwrap();
+	renamed.states[0].name = "renamed".to_string();
+	assert!(!renamed.pixels_modified());
+	let mut saved = vec![];
+	renamed.save(&mut saved).unwrap();
+	let resaved = RawDmi::load(&saved[..]).unwrap();
+	assert_eq!(resaved.chunks_idat, raw_dmi.chunks_idat);
+	assert_eq!(resaved.chunk_plte, raw_dmi.chunk_plte);
+	let chunk_types: Vec<[u8; 4]> = resaved
+		.other_chunks
+		.iter()
+		.flatten()
+		.map(|chunk| chunk.chunk_type)
+		.collect();
+	assert!(chunk_types.contains(&TRNS_TYPE));
+	assert!(!chunk_types.contains(&SIGNATURE_TYPE));
+	assert_eq!(Icon::load(&saved[..]).unwrap(), renamed);
+}
+
+#[test]
+fn content_hash_stamp() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = dmi::RawDmi::load(File::open(load_path.as_path()).unwrap()).unwrap();
+	assert_eq!(raw_dmi.check_content_hash().unwrap(), None);
+	let hash = raw_dmi.stamp_content_hash().unwrap();
+	let mut stamped = vec![];
+	raw_dmi.save(&mut stamped).unwrap();
+
+	let mut loaded = dmi::RawDmi::load(&stamped[..]).unwrap();
+	assert_eq!(loaded.stamped_content_hash(), Some(hash));
+	let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+	assert_eq!(
+		loaded.text(dmi::hash::CONTENT_HASH_KEYWORD),
+		Some(format!("sha256:{hex}"))
+	);
+	assert_eq!(loaded.check_content_hash().unwrap(), Some(true));
+	loaded.chunks_idat[0].data[0] ^= 1;
+	assert_eq!(loaded.check_content_hash().unwrap(), Some(false));
+
+	// Stamps of other algorithms are not trusted.
+	loaded
+		.set_text(dmi::hash::CONTENT_HASH_KEYWORD, "fnv1a64:0123456789abcdef")
+		.unwrap();
+	assert_eq!(loaded.stamped_content_hash(), None);
+	assert_eq!(loaded.check_content_hash().unwrap(), None);
+}
+
+#[test]
+fn library_indexes_resources() {
+	let mut resources_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	resources_path.push("tests/resources");
+	let library =
+		dmi::library::DmiLibrary::load_dir(&resources_path).expect("Failed to scan resources");
+
+	let mut load_path = resources_path.clone();
+	load_path.push("load_test.dmi");
+	let lights_icon = Icon::load(File::open(load_path.as_path()).unwrap()).unwrap();
+	let metadata = library.file(&load_path).expect("Resource not indexed");
+	assert_eq!(metadata.states.len(), lights_icon.states.len());
+
+	let first_name = &lights_icon.states[0].name;
+	assert!(library
+		.states_named(first_name)
+		.any(|found| found.path == load_path));
+	assert!(library
+		.files_with_dimensions(lights_icon.width, lights_icon.height)
+		.any(|file| file.path == load_path));
+}
+
+#[test]
+fn state_pattern_globs() {
+	use dmi::pattern::StatePattern;
+
+	assert!(StatePattern::glob("*_broken").matches("light_broken"));
+	assert!(StatePattern::glob("*_broken").matches("_broken"));
+	assert!(!StatePattern::glob("*_broken").matches("light_broken_off"));
+	assert!(StatePattern::glob("muzzle_*").matches("muzzle_laser"));
+	assert!(StatePattern::glob("a*b*c").matches("aXbYbZc"));
+	assert!(StatePattern::glob("tube?").matches("tube1"));
+	assert!(!StatePattern::glob("tube?").matches("tube"));
+	assert!(StatePattern::glob("*").matches(""));
+	assert!(!StatePattern::exact("tube").matches("tube1"));
+}
+
+#[test]
+fn icon_find_states() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	assert_eq!(icon.find_states("*").len(), icon.states.len());
+	let name = &icon.states[0].name;
+	let found = icon.find_states(format!("{}*", name).as_str());
+	assert!(found
+		.iter()
+		.all(|state| state.name.starts_with(name.as_str())));
+	assert!(icon.find_states("no_such_state_*").is_empty());
+}
+
+#[test]
+fn merge3_combines_independent_changes() {
+	use dmi::icon::IconState;
+	use dmi::merge::ConflictKind;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let sprite =
+		|value: u8| DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([value, 0, 0, 255])));
+	let state = |name: &str, value: u8| IconState {
+		name: name.to_string(),
+		images: vec![sprite(value)],
+		..Default::default()
+	};
+	let base = Icon {
+		width: 2,
+		height: 2,
+		states: vec![state("a", 0), state("b", 0), state("c", 0)],
+		..Default::default()
+	};
+
+	let mut ours = base.clone();
+	ours.states[0] = state("a", 1);
+	ours.states[1].movement = true;
+	ours.states[2] = state("c", 1);
+	let mut theirs = base.clone();
+	theirs.states[1] = state("b", 2);
+	theirs.states[2] = state("c", 2);
+	theirs.states.push(state("d", 0));
+
+	let merged = Icon::merge3(&base, &ours, &theirs).expect("Failed to merge");
+	assert_eq!(merged.conflicts.len(), 1);
+	assert_eq!(merged.conflicts[0].name, "c");
+	assert_eq!(merged.conflicts[0].kind, ConflictKind::BothModified);
+
+	let names: Vec<&str> = merged
+		.icon
+		.states
+		.iter()
+		.map(|state| state.name.as_str())
+		.collect();
+	assert_eq!(names, ["a", "b", "c", "d"]);
+	assert_eq!(merged.icon.states[0].images, vec![sprite(1)]);
+	assert_eq!(merged.icon.states[1].images, vec![sprite(2)]);
+	assert!(merged.icon.states[1].movement);
+}
+
+#[test]
+fn merge_driver_writes_over_ours() {
+	use dmi::icon::IconState;
+	use dmi::merge::merge_driver;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let state = |name: &str, value: u8| IconState {
+		name: name.to_string(),
+		images: vec![DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+			2,
+			2,
+			Rgba([value, 0, 0, 255]),
+		))],
+		..Default::default()
+	};
+	let icon = |states: Vec<IconState>| Icon {
+		width: 2,
+		height: 2,
+		states,
+		..Default::default()
+	};
+	let root = std::env::temp_dir().join(format!("dmi_merge_driver_{}", std::process::id()));
+	std::fs::create_dir_all(&root).unwrap();
+	let [base, ours, theirs] = ["base.dmi", "ours.dmi", "theirs.dmi"].map(|name| root.join(name));
+	let write = |path: &PathBuf, icon: Icon| icon.save(&mut File::create(path).unwrap()).unwrap();
+	let contents = |path: &PathBuf| -> Vec<(String, u8)> {
+		Icon::load(File::open(path).unwrap())
+			.unwrap()
+			.states
+			.iter()
+			.map(|state| (state.name.clone(), state.images[0].to_rgba8()[(0, 0)][0]))
+			.collect()
+	};
+
+	write(&base, icon(vec![state("a", 0), state("b", 0)]));
+	write(&ours, icon(vec![state("a", 1), state("b", 0)]));
+	write(
+		&theirs,
+		icon(vec![state("a", 0), state("b", 0), state("c", 2)]),
+	);
+	assert!(merge_driver(&base, &ours, &theirs).unwrap().is_empty());
+	assert_eq!(
+		contents(&ours),
+		[
+			("a".to_string(), 1),
+			("b".to_string(), 0),
+			("c".to_string(), 2)
+		]
+	);
+
+	// Conflicts still leave a valid file, holding our side of each conflicted state.
+	write(&ours, icon(vec![state("a", 0), state("b", 1)]));
+	write(&theirs, icon(vec![state("a", 0), state("b", 2)]));
+	let conflicts = merge_driver(&base, &ours, &theirs).unwrap();
+	assert_eq!(conflicts.len(), 1);
+	assert_eq!(conflicts[0].name, "b");
+	assert_eq!(
+		contents(&ours),
+		[("a".to_string(), 0), ("b".to_string(), 1)]
+	);
+
+	std::fs::remove_file(&theirs).unwrap();
+	assert!(merge_driver(&base, &ours, &theirs).is_err());
+	std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn edit_handle_rolls_back_broken_layouts() {
+	use dmi::icon::IconState;
+	use image::DynamicImage;
+
+	let mut state = IconState {
+		name: "door".to_string(),
+		images: vec![DynamicImage::new_rgba8(32, 32)],
+		..Default::default()
+	};
+
+	// Four dirs without the matching images: rolled back when the handle is dropped.
+	state.edit().set_layout(4, 1);
+	assert_eq!((state.dirs, state.images.len()), (1, 1));
+
+	let mut edit = state.edit();
+	edit.set_layout(4, 1);
+	edit
+		.images_mut()
+		.extend(std::iter::repeat_n(DynamicImage::new_rgba8(32, 32), 3));
+	edit.finish().expect("Consistent edit refused");
+	assert_eq!((state.dirs, state.images.len()), (4, 4));
+
+	// Animated without delays, which IconState::validate refuses.
+	let mut edit = state.edit();
+	edit.set_layout(1, 4);
+	assert!(edit.finish().is_err());
+	assert_eq!((state.dirs, state.frames), (4, 1));
+
+	let mut edit = state.edit();
+	edit.set_layout(1, 4).set_delay(Some(vec![1.0; 4]));
+	edit.finish().expect("Animated edit refused");
+	assert_eq!(state.delay, Some(vec![1.0; 4]));
+}
+
+#[test]
+fn images_are_addressed_frame_major() {
+	use dmi::dirs::Dirs;
+	use dmi::icon::IconState;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	// Each sprite is filled with its own position within `images`.
+	let state = IconState {
+		name: "walk".to_string(),
+		dirs: 4,
+		frames: 3,
+		images: (0..12)
+			.map(|index| DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([index, 0, 0, 255]))))
+			.collect(),
+		delay: Some(vec![1.0; 3]),
+		..Default::default()
+	};
+
+	let index_of =
+		|dir: &Dirs, frame: u32| state.get_image(dir, frame).unwrap().to_rgba8()[(0, 0)][0];
+	assert_eq!(index_of(&Dirs::SOUTH, 1), 0);
+	assert_eq!(index_of(&Dirs::NORTH, 1), 1);
+	assert_eq!(index_of(&Dirs::WEST, 1), 3);
+	assert_eq!(index_of(&Dirs::SOUTH, 2), 4);
+	assert_eq!(index_of(&Dirs::EAST, 3), 10);
+	assert!(state.get_image(&Dirs::SOUTHEAST, 1).is_err());
+	assert!(state.get_image(&Dirs::SOUTH, 0).is_err());
+	assert!(state.get_image(&Dirs::SOUTH, 4).is_err());
+
+	let east: Vec<_> = state.frames_for_dir(&Dirs::EAST).unwrap();
+	assert_eq!(
+		east,
+		[&state.images[2], &state.images[6], &state.images[10]]
+	);
+
+	let rewinding = IconState {
+		rewind: true,
+		..state.clone()
+	};
+	let east: Vec<_> = rewinding.frames_for_dir(&Dirs::EAST).unwrap();
+	assert_eq!(
+		east,
+		[
+			&state.images[2],
+			&state.images[6],
+			&state.images[10],
+			&state.images[6]
+		]
+	);
+}
+
+#[test]
+fn frames_and_dir_indices_address_images() {
+	use dmi::dirs::Dirs;
+	use dmi::icon::{DirIndex, Frame, IconState, DIR_ORDERING};
+	use image::DynamicImage;
+
+	assert_eq!(Frame::new(0), None);
+	assert_eq!(Frame::new(1), Some(Frame::FIRST));
+	let third = Frame::from_index(2).unwrap();
+	assert_eq!((third.get(), third.index()), (3, 2));
+	assert_eq!(third.to_string(), "3");
+	let last = Frame::from_index(u32::MAX - 1).unwrap();
+	assert_eq!((last.get(), last.index()), (u32::MAX, u32::MAX - 1));
+	assert_eq!(Frame::from_index(u32::MAX), None);
+	assert_eq!(Frame::new(u32::MAX), Some(last));
+
+	for (index, dir) in DIR_ORDERING.iter().enumerate() {
+		let dir_index = DirIndex::of(dir).unwrap();
+		assert_eq!(dir_index.get(), index);
+		assert_eq!(dir_index.dir(), *dir);
+		assert_eq!(DirIndex::new(index), Some(dir_index));
+	}
+	assert_eq!(DirIndex::new(8), None);
+	assert_eq!(DirIndex::of(&(Dirs::NORTH | Dirs::SOUTH)), None);
+
+	let mut state = IconState {
+		name: "walk".to_string(),
+		dirs: 4,
+		frames: 2,
+		images: vec![DynamicImage::new_rgba8(1, 1); 8],
+		delay: Some(vec![1.0; 2]),
+		..Default::default()
+	};
+	let west = DirIndex::of(&Dirs::WEST).unwrap();
+	assert_eq!(state.image_index(west, Frame::new(2).unwrap()).unwrap(), 7);
+	assert!(state.image_index(west, Frame::new(3).unwrap()).is_err());
+	let northeast = DirIndex::of(&Dirs::NORTHEAST).unwrap();
+	assert!(state.image_index(northeast, Frame::FIRST).is_err());
+	assert!(state.image_mut(west, Frame::FIRST).is_ok());
+	state.images.truncate(4);
+	assert!(state.image(west, Frame::new(2).unwrap()).is_err());
+}
+
+#[test]
+fn export_and_import_frames() {
+	use dmi::naming::NamingScheme;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+
+	let export_path = std::env::temp_dir().join(format!("dmi_frames_{}", std::process::id()));
+	let naming = NamingScheme::new("{state}-{dir}-{frame}");
+	let written = icon
+		.export_frames(&export_path, &naming)
+		.expect("Failed to export frames");
+	let sprite_count: usize = icon.states.iter().map(|state| state.images.len()).sum();
+	assert_eq!(written.len(), sprite_count);
+
+	let imported = Icon::import_frames(&export_path, &naming).expect("Failed to import frames");
+	std::fs::remove_dir_all(&export_path).unwrap();
+	assert_eq!((imported.width, imported.height), (icon.width, icon.height));
+	for icon_state in &icon.states {
+		let imported_state = imported
+			.states
+			.iter()
+			.find(|imported_state| imported_state.name == icon_state.name)
+			.expect("State lost in the round trip");
+		assert_eq!(imported_state.dirs, icon_state.dirs);
+		assert_eq!(imported_state.frames, icon_state.frames);
+	}
+}
+
+#[test]
+fn export_frames_names_and_orders_sprites() {
+	use dmi::dirs::Dirs;
+	use dmi::icon::{Frame, IconState};
+	use dmi::naming::NamingScheme;
+	use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+	let walk = IconState {
+		name: "walk".to_string(),
+		dirs: 4,
+		frames: 2,
+		images: (0..8)
+			.map(|index| DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([index, 0, 0, 255]))))
+			.collect(),
+		delay: Some(vec![1.0; 2]),
+		..Default::default()
+	};
+	let icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![walk.clone()],
+		..Default::default()
+	};
+	let export_path = std::env::temp_dir().join(format!("dmi_export_{}", std::process::id()));
+	let naming = NamingScheme::new("{state}-{dir}-{frame}");
+	let written = icon.export_frames(&export_path, &naming).unwrap();
+	let expected_name = |dir: &Dirs, frame: u32| {
+		export_path.join(format!(
+			"{}.png",
+			naming.name("walk", dir, Frame::new(frame).unwrap())
+		))
+	};
+	assert_eq!(written.len(), 8);
+	assert_eq!(written[0], expected_name(&Dirs::SOUTH, 1));
+	assert_eq!(written[1], expected_name(&Dirs::NORTH, 1));
+	assert_eq!(written[7], expected_name(&Dirs::WEST, 2));
+	let last = image::open(&written[7]).unwrap();
+	assert_eq!(last.get_pixel(0, 0), Rgba([7, 0, 0, 255]));
+	std::fs::remove_dir_all(&export_path).unwrap();
+
+	// Nothing is written when a name is unusable.
+	let clashing = Icon {
+		states: vec![walk.clone(), walk.clone()],
+		..icon.clone()
+	};
+	assert!(clashing.export_frames(&export_path, &naming).is_err());
+	let nested = Icon {
+		states: vec![IconState {
+			name: "sub/walk".to_string(),
+			..walk
+		}],
+		..icon
+	};
+	assert!(nested.export_frames(&export_path, &naming).is_err());
+	assert!(!export_path.exists());
+}
+
+#[test]
+fn naming_scheme_round_trips_dir_styles() {
+	use dmi::dirs::Dirs;
+	use dmi::icon::Frame;
+	use dmi::naming::{DirStyle, NamingScheme, SpriteName};
+
+	let frame = Frame::new(12).unwrap();
+	for dir_style in [
+		DirStyle::Lowercase,
+		DirStyle::Uppercase,
+		DirStyle::Abbreviated,
+		DirStyle::Byond,
+		DirStyle::Index,
+	] {
+		let naming = NamingScheme::new("{state}_{dir}{frame}").with_dir_style(dir_style);
+		let name = naming.name("open_door", &Dirs::SOUTHWEST, frame);
+		let expected = SpriteName {
+			state: "open_door".to_string(),
+			dir: Dirs::SOUTHWEST,
+			frame,
+		};
+		assert_eq!(naming.parse(&name), Some(expected), "{name}");
+	}
+	assert_eq!(
+		NamingScheme::default().name("idle", &Dirs::NORTH, Frame::FIRST),
+		"idle_north_1"
+	);
+}
+
+#[test]
+fn quick_fingerprint_matches_full_load() {
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let fingerprint = dmi::quick_fingerprint(&load_path).expect("Failed to fingerprint");
+	let raw_dmi = RawDmi::load(File::open(&load_path).unwrap()).unwrap();
+	let icon = Icon::load(File::open(&load_path).unwrap()).unwrap();
+
+	let idat_length: u64 = raw_dmi
+		.chunks_idat
+		.iter()
+		.map(|chunk| chunk.data.len() as u64)
+		.sum();
+	assert_eq!(fingerprint.idat_length, idat_length);
+	assert_eq!(fingerprint.width, raw_dmi.image_width().unwrap());
+	assert_eq!(fingerprint.height, raw_dmi.image_height().unwrap());
+	assert_eq!(fingerprint.width % icon.width, 0);
+	assert_eq!(fingerprint.height % icon.height, 0);
+}
+
+#[test]
+fn quick_fingerprint_rejects_forged_lengths() {
+	let path = std::env::temp_dir().join(format!("dmi_forged_{}.dmi", std::process::id()));
+	let mut forged = dmi::PNG_HEADER.to_vec();
+	// A zTXt chunk claiming nearly 4 GiB of data, followed by a few bytes.
+	forged.extend([0xFF, 0xFF, 0xFF, 0xF0]);
+	forged.extend(b"zTXt");
+	forged.extend([0; 16]);
+	std::fs::write(&path, &forged).unwrap();
+	assert!(dmi::quick_fingerprint(&path).is_err());
+
+	// Within the maximum length, but longer than the file.
+	forged[8..12].copy_from_slice(&0x7FFF_0000u32.to_be_bytes());
+	std::fs::write(&path, &forged).unwrap();
+	assert!(dmi::quick_fingerprint(&path).is_err());
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn load_collecting_skips_malformed_states() {
+	use dmi::icon::IconState;
+	use dmi::{ztxt, RawDmi};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let state = |name: &str, red: u8| IconState {
+		name: name.to_string(),
+		images: vec![DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+			1,
+			1,
+			Rgba([red, 0, 0, 255]),
+		))],
+		..Default::default()
+	};
+	let icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![state("first", 1), state("broken", 2), state("last", 3)],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	let text = String::from_utf8(raw_dmi.chunk_ztxt.unwrap().data.decode().unwrap()).unwrap();
+	let text = text.replace(
+		"state = \"broken\"\n",
+		"state = \"broken\"\n\tmovement = yes\n",
+	);
+	raw_dmi.chunk_ztxt = Some(ztxt::create_ztxt_chunk(text.as_bytes()).unwrap());
+	let mut broken = vec![];
+	raw_dmi.save(&mut broken).unwrap();
+
+	assert!(Icon::load(&broken[..]).is_err());
+	let (loaded, errors) = Icon::load_collecting(&broken[..]).expect("Failed to load partially");
+	assert_eq!(errors.len(), 1);
+	assert_eq!(loaded.states.len(), 2);
+	assert_eq!(loaded.states[1], icon.states[2]);
+}
+
+#[test]
+fn load_collecting_skips_every_sprite_of_malformed_states() {
+	use dmi::icon::IconState;
+	use dmi::{ztxt, RawDmi};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let sprite =
+		|red: u8| DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([red, 0, 0, 255])));
+	let icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![
+			IconState {
+				name: "broken".to_string(),
+				dirs: 4,
+				frames: 2,
+				images: (1..=8).map(sprite).collect(),
+				delay: Some(vec![1.0, 1.0]),
+				..Default::default()
+			},
+			IconState {
+				name: "last".to_string(),
+				images: vec![sprite(9)],
+				..Default::default()
+			},
+		],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	let text = String::from_utf8(raw_dmi.chunk_ztxt.unwrap().data.decode().unwrap()).unwrap();
+	let text = text.replace(
+		"state = \"broken\"\n",
+		"state = \"broken\"\n\tmovement = yes\n",
+	);
+	raw_dmi.chunk_ztxt = Some(ztxt::create_ztxt_chunk(text.as_bytes()).unwrap());
+	let mut broken = vec![];
+	raw_dmi.save(&mut broken).unwrap();
+
+	// The eight sprites of the skipped state are stepped over, not just one.
+	let (loaded, errors) = Icon::load_collecting(&broken[..]).expect("Failed to load partially");
+	assert_eq!(errors.len(), 1);
+	assert_eq!(loaded.states.len(), 1);
+	assert_eq!(loaded.states[0], icon.states[1]);
+}
+
+#[test]
+fn load_reports_chunks() {
+	use dmi::chunk::{ChunkHandling, ChunkRecord};
+	use dmi::options::LoadOptions;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut records: Vec<ChunkRecord> = vec![];
+	let options = LoadOptions::new().on_chunk(|record| records.push(*record));
+	Icon::load_with(File::open(load_path).unwrap(), options).expect("Unable to load lights dmi");
+
+	assert_eq!(&records[0].chunk_type, b"IHDR");
+	assert_eq!(&records[records.len() - 1].chunk_type, b"IEND");
+	assert!(records
+		.iter()
+		.filter(|record| &record.chunk_type == b"IDAT")
+		.all(|record| record.handling == ChunkHandling::Parsed));
+}
+
+#[test]
+fn save_carries_ancillary_chunks() {
+	use dmi::chunk::{ChunkPolicy, RawGenericChunk};
+	use dmi::options::SaveOptions;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let phys = RawGenericChunk::new(*b"pHYs", vec![0, 0, 11, 19, 0, 0, 11, 19, 1]).unwrap();
+	icon.ancillary_chunks.push(phys.clone());
+
+	let mut bytes = vec![];
+	icon.save(&mut bytes).expect("Failed to save lights dmi");
+	let reloaded = Icon::load(bytes.as_slice()).expect("Unable to reload lights dmi");
+	assert_eq!(reloaded.ancillary_chunks, vec![phys]);
+
+	let mut bytes = vec![];
+	let options = SaveOptions::new().chunk_policy(ChunkPolicy::KeepListed(vec![*b"tIME"]));
+	icon
+		.save_with(&mut bytes, &options)
+		.expect("Failed to save lights dmi");
+	let reloaded = Icon::load(bytes.as_slice()).expect("Unable to reload lights dmi");
+	assert!(reloaded.ancillary_chunks.is_empty());
+
+	icon.ancillary_chunks = vec![RawGenericChunk::new(*b"IHDR", vec![0; 13]).unwrap()];
+	assert!(icon.save(&mut vec![]).is_err());
+}
+
+#[test]
+fn raw_dmi_maps_chunk_offsets() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(load_path).unwrap();
+	let raw_dmi = dmi::RawDmi::load(bytes.as_slice()).expect("Unable to load lights dmi");
+	let source_map = raw_dmi.source_map.as_ref().unwrap();
+
+	assert_eq!(source_map.len(), raw_dmi.chunk_types().len());
+	assert_eq!(source_map[0].offset, 8);
+	for span in source_map {
+		let start = span.offset as usize;
+		assert_eq!(bytes[start + 4..start + 8], span.chunk_type);
+	}
+	let iend = source_map.last().unwrap();
+	assert_eq!(&iend.chunk_type, b"IEND");
+	assert_eq!(iend.offset + iend.length, bytes.len() as u64);
+}
+
+#[test]
+fn patch_metadata_in_place_keeps_file_length() {
+	use dmi::metadata::DmiMetadata;
+	use dmi::patch::patch_metadata_in_place;
+	use std::io::Cursor;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = dmi::RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let text =
+		String::from_utf8(raw_dmi.chunk_ztxt.as_ref().unwrap().data.decode().unwrap()).unwrap();
+	let with_states = |count: u32| {
+		let extra_states: String = (0..count)
+			.map(|index| {
+				format!(
+					"state = \"extra{}\"\n\tdirs = 1\n\tframes = 1\n",
+					index * 7919
+				)
+			})
+			.collect();
+		text.replace("# END DMI", &format!("{extra_states}# END DMI"))
+	};
+	let long_text = with_states(40);
+	raw_dmi.chunk_ztxt = Some(dmi::ztxt::create_ztxt_chunk(long_text.as_bytes()).unwrap());
+	let mut bytes = vec![];
+	raw_dmi.save(&mut bytes).unwrap();
+
+	let mut file = Cursor::new(bytes.clone());
+	assert!(!patch_metadata_in_place(&mut file, &with_states(80)).unwrap());
+	assert_eq!(file.get_ref(), &bytes);
+
+	assert!(patch_metadata_in_place(&mut file, &text).unwrap());
+	assert_eq!(file.get_ref().len(), bytes.len());
+	let patched = DmiMetadata::load(file.get_ref().as_slice()).unwrap();
+	assert_eq!(patched, DmiMetadata::parse(&text).unwrap());
+}
+
+#[test]
+fn patch_metadata_in_place_leaves_split_metadata_to_a_rewrite() {
+	use dmi::metadata::DmiMetadata;
+	use dmi::patch::patch_metadata_in_place;
+	use dmi::ztxt;
+	use std::io::Cursor;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = dmi::RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let text =
+		String::from_utf8(raw_dmi.chunk_ztxt.as_ref().unwrap().data.decode().unwrap()).unwrap();
+	let (first, continuations) = ztxt::create_split_ztxt_chunks("Description", &text, 40).unwrap();
+	assert!(!continuations.is_empty());
+	raw_dmi.chunk_ztxt = Some(first);
+	raw_dmi
+		.other_chunks
+		.get_or_insert_with(Vec::new)
+		.extend(continuations);
+	let mut bytes = vec![];
+	raw_dmi.save(&mut bytes).unwrap();
+	assert_eq!(
+		DmiMetadata::load(&bytes[..]).unwrap(),
+		DmiMetadata::parse(&text).unwrap()
+	);
+
+	let new_text = text.replacen("state = \"", "state = \"renamed_", 1);
+	assert_ne!(new_text, text);
+	let mut file = Cursor::new(bytes.clone());
+	assert!(!patch_metadata_in_place(&mut file, &new_text).unwrap());
+	assert_eq!(file.get_ref(), &bytes);
+
+	let mut rewritten = dmi::RawDmi::load(&bytes[..]).unwrap();
+	rewritten.replace_metadata(&new_text).unwrap();
+	let mut rewritten_bytes = vec![];
+	rewritten.save(&mut rewritten_bytes).unwrap();
+	assert_eq!(
+		DmiMetadata::load(&rewritten_bytes[..]).unwrap(),
+		DmiMetadata::parse(&new_text).unwrap()
+	);
+}
+
+#[test]
+fn memory_usage_counts_images() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let pixel_bytes: usize = icon
+		.states
+		.iter()
+		.flat_map(|state| &state.images)
+		.map(|image| image.as_bytes().len())
+		.sum();
+	assert!(icon.approx_memory_bytes() > pixel_bytes);
+
+	let before = icon.approx_memory_bytes();
+	let removed = icon.states.pop().unwrap();
+	assert_eq!(
+		icon.approx_memory_bytes(),
+		before - removed.approx_memory_bytes()
+	);
+}
+
+#[test]
+fn generate_thumbnails_for_many_files() {
+	use dmi::thumbnail::{generate_thumbnails, ThumbnailOptions};
+	use std::num::NonZeroUsize;
+	use std::path::Path;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(&load_path).unwrap()).expect("Unable to load lights dmi");
+	let paths = vec![load_path.clone(), PathBuf::from("missing.dmi"), load_path];
+	let options = ThumbnailOptions::new()
+		.max_size(icon.width / 2)
+		.threads(NonZeroUsize::new(2).unwrap());
+
+	let mut results = vec![];
+	generate_thumbnails(&paths, &options, |path, thumbnails| {
+		results.push((path.to_path_buf(), thumbnails))
+	});
+	assert_eq!(results.len(), 3);
+	for (path, thumbnails) in results {
+		if path == Path::new("missing.dmi") {
+			assert!(thumbnails.is_err());
+			continue;
+		}
+		let thumbnails = thumbnails.expect("Unable to thumbnail lights dmi");
+		assert_eq!(thumbnails.len(), icon.states.len());
+		assert_eq!(thumbnails[0].state_name, icon.states[0].name);
+		assert!(thumbnails
+			.iter()
+			.all(|thumbnail| thumbnail.image.width() <= icon.width / 2));
+	}
+}
+
+#[test]
+fn contact_sheet_lays_out_states() {
+	use dmi::contact_sheet::ContactSheetOptions;
+	use image::GenericImageView;
+	use std::num::NonZeroU32;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let options = ContactSheetOptions::new()
+		.columns(NonZeroU32::new(1).unwrap())
+		.scale(NonZeroU32::new(2).unwrap())
+		.spacing(4);
+	let sheet = icon.render_contact_sheet(&options).unwrap();
+
+	let rows = icon.states.len() as u32;
+	assert_eq!(sheet.image.width(), icon.width * 2 + 8);
+	assert_eq!(sheet.image.height(), rows * (icon.height * 2 + 4) + 4);
+	assert_eq!(sheet.cells.len(), icon.states.len());
+	let cell = &sheet.cells[1];
+	assert_eq!((cell.x, cell.y), (4, 4 + icon.height * 2 + 4));
+	let sprite = &icon.states[1].images[0];
+	let (x, y, pixel) = sprite
+		.pixels()
+		.find(|(_, _, pixel)| pixel[3] == 255)
+		.expect("No opaque pixel");
+	assert_eq!(sheet.image.get_pixel(cell.x + x * 2, cell.y + y * 2), pixel);
+
+	let options = ContactSheetOptions::new().scale(NonZeroU32::new(u32::MAX / 2).unwrap());
+	assert!(icon.render_contact_sheet(&options).is_err());
+	let options = ContactSheetOptions::new()
+		.columns(NonZeroU32::new(1).unwrap())
+		.spacing(u32::MAX / 4);
+	assert!(icon.render_contact_sheet(&options).is_err());
+}
+
+#[test]
+fn dirs_parse_and_display_names() {
+	use dmi::dirs::{Dirs, ALL_DIRS};
+
+	for dir in ALL_DIRS {
+		assert_eq!(dir.to_string().parse::<Dirs>().unwrap(), dir);
+		assert_eq!(dir.bits().to_string().parse::<Dirs>().unwrap(), dir);
+	}
+	assert_eq!("southWest".parse::<Dirs>().unwrap(), Dirs::SOUTHWEST);
+	assert_eq!(Dirs::SOUTHWEST.to_string(), "SOUTHWEST");
+	assert!("3".parse::<Dirs>().is_err());
+	assert!("up".parse::<Dirs>().is_err());
+
+	assert_eq!(Dirs::from_delta(0, 3), Some(Dirs::NORTH));
+	assert_eq!(Dirs::from_delta(-1, -5), Some(Dirs::SOUTHWEST));
+	assert_eq!(Dirs::from_delta(0, 0), None);
+}
+
+#[test]
+fn replace_color_within_tolerance() {
+	use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let mut image = RgbaImage::from_pixel(icon.width, icon.height, Rgba([10, 200, 10, 255]));
+	image.put_pixel(0, 0, Rgba([14, 196, 10, 255]));
+	image.put_pixel(1, 0, Rgba([30, 200, 10, 255]));
+	icon.states[0].images[0] = DynamicImage::ImageRgba8(image);
+
+	let red = Rgba([255, 0, 0, 255]);
+	let replaced = icon.replace_color(Rgba([10, 200, 10, 255]), red, 4);
+	assert!(replaced >= (icon.width * icon.height - 1) as usize);
+	let image = &icon.states[0].images[0];
+	assert_eq!(image.get_pixel(0, 0), red);
+	assert_eq!(image.get_pixel(1, 0), Rgba([30, 200, 10, 255]));
+}
+
+#[test]
+fn grayscale_keeps_alpha() {
+	use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let image = RgbaImage::from_pixel(icon.width, icon.height, Rgba([255, 0, 0, 128]));
+	icon.states[0].images[0] = DynamicImage::ImageRgba8(image);
+
+	let grayscale = icon.to_grayscale();
+	assert_eq!(
+		grayscale.states[0].images[0].get_pixel(3, 3),
+		Rgba([54, 54, 54, 128])
+	);
+	assert!(grayscale
+		.states
+		.iter()
+		.flat_map(|state| &state.images)
+		.flat_map(|image| image.pixels())
+		.all(|(_, _, pixel)| pixel[0] == pixel[1] && pixel[1] == pixel[2]));
+	assert_eq!(
+		icon.states[0].images[0].get_pixel(3, 3),
+		Rgba([255, 0, 0, 128])
+	);
+}
+
+#[test]
+fn shift_hsv_rotates_hues() {
+	use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let image = RgbaImage::from_pixel(icon.width, icon.height, Rgba([255, 0, 0, 200]));
+	icon.states[0].images[0] = DynamicImage::ImageRgba8(image);
+
+	icon.states[0].shift_hsv(120.0, 0.0, 0.0);
+	assert_eq!(
+		icon.states[0].images[0].get_pixel(0, 0),
+		Rgba([0, 255, 0, 200])
+	);
+	icon.shift_hsv(-480.0, -0.5, -0.5);
+	assert_eq!(
+		icon.states[0].images[0].get_pixel(0, 0),
+		Rgba([128, 64, 64, 200])
+	);
+}
+
+#[test]
+fn mask_recolor_tints_regions() {
+	use dmi::icon::IconState;
+	use dmi::recolor::MaskColors;
+	use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let (width, height) = (icon.width, icon.height);
+	for icon_state in &mut icon.states {
+		for image in &mut icon_state.images {
+			*image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+				width,
+				height,
+				Rgba([255, 255, 255, 255]),
+			));
+		}
+	}
+	let mut mask = RgbaImage::new(width, height);
+	mask.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+	icon.states.push(IconState {
+		name: "mask".to_string(),
+		images: vec![DynamicImage::ImageRgba8(mask)],
+		..Default::default()
+	});
+
+	let blue_team = MaskColors::from([(Rgba([255, 0, 0, 255]), Rgba([0, 0, 200, 255]))]);
+	let red_team = MaskColors::from([(Rgba([255, 0, 0, 255]), Rgba([200, 0, 0, 255]))]);
+	let variants = icon.mask_variants("mask", &[blue_team, red_team]).unwrap();
+	assert_eq!(variants.len(), 2);
+	assert!(variants[0].states.iter().all(|state| state.name != "mask"));
+	let image = &variants[0].states[0].images[0];
+	assert_eq!(image.get_pixel(0, 0), Rgba([0, 0, 200, 255]));
+	assert_eq!(image.get_pixel(1, 0), Rgba([255, 255, 255, 255]));
+	assert_eq!(
+		variants[1].states[0].images[0].get_pixel(0, 0),
+		Rgba([200, 0, 0, 255])
+	);
+	assert!(icon.mask_recolor("missing", &MaskColors::new()).is_err());
+}
+
+#[test]
+fn chunk_crc_hasher_matches_stored_crcs() {
+	use dmi::crc::ChunkCrcHasher;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let raw_dmi = dmi::RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	for chunk in raw_dmi.chunks_idat.iter().chain([&raw_dmi.chunk_ihdr]) {
+		let mut hasher = ChunkCrcHasher::new();
+		for part in chunk.data.chunks(7) {
+			hasher.update(part);
+		}
+		assert_eq!(
+			hasher.finalize_for(chunk.chunk_type),
+			u32::from_be_bytes(chunk.crc)
+		);
+		assert_eq!(
+			ChunkCrcHasher::checksum(chunk.chunk_type, &chunk.data),
+			u32::from_be_bytes(chunk.crc)
+		);
+	}
+	assert_eq!(ChunkCrcHasher::new().finalize_for(*b"IEND"), 0xae42_6082);
+	assert_eq!(ChunkCrcHasher::checksum(*b"IEND", &[]), 0xae42_6082);
+}
+
+#[test]
+fn states_by_name() {
+	use dmi::icon::IconState;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let name = icon.states[0].name.clone();
+	assert_eq!(icon[name.as_str()].frames, icon.states[0].frames);
+	assert!(icon.get_state("missing").is_none());
+
+	icon.states[0].movement = true;
+	icon.states.push(IconState {
+		name: name.clone(),
+		..icon.states[0].clone()
+	});
+	let last = icon.states.len() - 1;
+	icon.states[last].movement = false;
+	icon.get_state_mut(&name).unwrap().rewind = true;
+	assert!(icon.states[0].rewind);
+	assert!(!icon.states[last].rewind);
+	assert!(icon[name.as_str()].movement);
+
+	assert_eq!(icon.all_states_named(&name).count(), 2);
+	let states = icon.states.len();
+	let removed = icon.remove_state(&name).unwrap();
+	assert!(removed.movement);
+	assert_eq!(icon.states.len(), states - 1);
+	assert!(!icon[name.as_str()].movement);
+	assert!(icon.remove_state("missing").is_none());
+}
+
+#[test]
+fn duplicate_state_names_resolve_to_the_first() {
+	use dmi::icon::IconState;
+
+	let state = |movement: bool, frames: u32| IconState {
+		name: "walk".to_string(),
+		movement,
+		frames,
+		..Default::default()
+	};
+	let mut icon = Icon {
+		states: vec![state(true, 1), state(false, 2)],
+		..Default::default()
+	};
+
+	assert!(icon.get_state("walk").unwrap().movement);
+	assert!(icon["walk"].movement);
+	assert!(!icon.get_state_variant("walk", false).unwrap().movement);
+	assert_eq!(
+		icon
+			.all_states_named("walk")
+			.map(|state| state.frames)
+			.collect::<Vec<_>>(),
+		vec![1, 2]
+	);
+
+	let removed = icon.remove_state("walk").unwrap();
+	assert!(removed.movement);
+	assert_eq!(icon.states, vec![state(false, 2)]);
+	assert!(!icon["walk"].movement);
+}
+
+#[test]
+fn subset_states() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let name = icon.states[1].name.clone();
+
+	let filtered = icon.filtered(&[name.as_str(), "missing"]);
+	assert!(filtered.states.iter().all(|state| state.name == name));
+	assert!(!filtered.states.is_empty());
+	assert_eq!(filtered.width, icon.width);
+
+	icon.retain_states(|state| state.name != name);
+	assert!(icon.get_state(&name).is_none());
+}
+
+#[test]
+fn map_images_reaches_every_frame() {
+	use image::{GenericImageView, Rgba};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let image_count: usize = icon.states.iter().map(|state| state.images.len()).sum();
+
+	let mut visited = 0;
+	icon.map_images(|_| visited += 1);
+	assert_eq!(visited, image_count);
+
+	icon.par_map_images(|image| image.put_pixel(0, 0, Rgba([1, 2, 3, 4])));
+	assert!(icon
+		.states
+		.iter()
+		.flat_map(|state| &state.images)
+		.all(|image| image.get_pixel(0, 0) == Rgba([1, 2, 3, 4])));
+}
+
+#[test]
+fn load_ztxt_after_image_data() {
+	use dmi::metadata::DmiMetadata;
+	use dmi::options::LoadOptions;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = dmi::RawDmi::load(File::open(&load_path).unwrap()).unwrap();
+	let chunk_ztxt = raw_dmi.chunk_ztxt.take().unwrap();
+	let mut bytes = vec![];
+	raw_dmi.save(&mut bytes).unwrap();
+	let mut ztxt_bytes = vec![];
+	chunk_ztxt.save(&mut ztxt_bytes).unwrap();
+	// Right before the 12 bytes of the IEND chunk.
+	let iend_start = bytes.len() - 12;
+	bytes.splice(iend_start..iend_start, ztxt_bytes);
+
+	let icon = Icon::load(File::open(&load_path).unwrap()).unwrap();
+	assert_eq!(Icon::load(bytes.as_slice()).unwrap(), icon);
+	assert_eq!(
+		DmiMetadata::load(bytes.as_slice()).unwrap(),
+		DmiMetadata::load(File::open(&load_path).unwrap()).unwrap()
+	);
+	let strict = || LoadOptions::new().strict_chunk_order(true);
+	assert!(Icon::load_with(bytes.as_slice(), strict()).is_err());
+	assert!(Icon::load_with(File::open(&load_path).unwrap(), strict()).is_ok());
+}
+
+#[test]
+fn idat_payload() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let raw_dmi = dmi::RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let idat_length: usize = raw_dmi
+		.chunks_idat
+		.iter()
+		.map(|chunk| chunk.data.len())
+		.sum();
+	assert_eq!(raw_dmi.idat_bytes().len(), idat_length);
+
+	let width = raw_dmi.image_width().unwrap() as usize;
+	let height = raw_dmi.image_height().unwrap() as usize;
+	let bits_per_pixel = match raw_dmi.color_type().unwrap() {
+		0 => 1,
+		2 => 3,
+		3 => 1,
+		4 => 2,
+		_ => 4,
+	} * raw_dmi.bit_depth().unwrap() as usize;
+	let scanline_length = 1 + (width * bits_per_pixel).div_ceil(8);
+	assert_eq!(
+		raw_dmi.inflated_idat_bytes().unwrap().len(),
+		height * scanline_length
+	);
+}
+
+#[test]
+fn load_rejects_hostile_chunk_lengths() {
+	use dmi::error::DmiError;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(load_path).unwrap();
+	for data_length in [u32::MAX, bytes.len() as u32] {
+		let mut hostile = bytes.clone();
+		// The length of the IHDR chunk, right after the PNG header.
+		hostile[8..12].copy_from_slice(&data_length.to_be_bytes());
+		assert!(matches!(
+			dmi::RawDmi::load(hostile.as_slice()),
+			Err(DmiError::TruncatedChunk { offset: 8, .. })
+		));
+	}
+	assert!(dmi::RawDmi::load(&bytes[..bytes.len() - 20]).is_err());
+}
+
+#[test]
+fn duplicate_ztxt_policy() {
+	use dmi::chunk::DuplicateChunkPolicy;
+	use dmi::error::DmiError;
+	use dmi::metadata::DmiMetadata;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let raw_dmi = dmi::RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let mut bytes = vec![];
+	raw_dmi.save(&mut bytes).unwrap();
+	let first_text = "# BEGIN DMI\nversion = 4.0\n\twidth = 32\n\theight = 32\n# END DMI\n";
+	let mut ztxt_bytes = vec![];
+	dmi::ztxt::create_ztxt_chunk(first_text.as_bytes())
+		.unwrap()
+		.save(&mut ztxt_bytes)
+		.unwrap();
+	// Right after the PNG header and the 25 bytes of the IHDR chunk.
+	bytes.splice(33..33, ztxt_bytes);
+
+	let load = |duplicates| dmi::RawDmi::load_with_policy(bytes.as_slice(), duplicates);
+	let first = DmiMetadata::from_raw_dmi(&load(DuplicateChunkPolicy::First).unwrap()).unwrap();
+	assert!(first.states.is_empty());
+	let last = DmiMetadata::from_raw_dmi(&load(DuplicateChunkPolicy::Last).unwrap()).unwrap();
+	assert_eq!(last, DmiMetadata::from_raw_dmi(&raw_dmi).unwrap());
+	assert!(matches!(
+		load(DuplicateChunkPolicy::Error),
+		Err(DmiError::DuplicateChunk { chunk_type }) if &chunk_type == b"zTXt"
+	));
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn cache_round_trip() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let mut cache = vec![];
+	icon.write_cache(&mut cache).unwrap();
+	assert_eq!(Icon::read_cache(&mut cache.as_slice()).unwrap(), icon);
+	assert!(Icon::read_cache(&mut &cache[..cache.len() - 1]).is_err());
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn load_cached_writes_and_validates_sidecar() {
+	use dmi::cache::sidecar_path;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let dir = std::env::temp_dir().join(format!("dmi_sidecar_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("lights.dmi");
+	std::fs::copy(&load_path, &path).unwrap();
+	let sidecar = sidecar_path(&path);
+	assert_eq!(sidecar, dir.join("lights.dmi.cache"));
+
+	let icon = Icon::load_cached(&path).unwrap();
+	assert_eq!(icon, Icon::load(File::open(&load_path).unwrap()).unwrap());
+	assert!(sidecar.exists());
+	assert_eq!(Icon::load_cached(&path).unwrap(), icon);
+
+	// A cache made for another file is stale and gets replaced.
+	let mut other = icon.clone();
+	other.states.pop();
+	other.save(&mut File::create(&path).unwrap()).unwrap();
+	let reloaded = Icon::load_cached(&path).unwrap();
+	assert_eq!(reloaded.states.len(), other.states.len());
+
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "cache")]
+#[test]
+fn load_cached_misses_on_palette_edit() {
+	use dmi::encode::OutputColorType;
+	use dmi::icon::IconState;
+	use dmi::options::SaveOptions;
+	use dmi::RawDmi;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let icon = Icon {
+		width: 2,
+		height: 2,
+		states: vec![IconState {
+			name: "red".to_string(),
+			images: vec![DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+				2,
+				2,
+				Rgba([255, 0, 0, 255]),
+			))],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon
+		.save_with(
+			&mut saved,
+			&SaveOptions::new().color_type(OutputColorType::IndexedIfLossless),
+		)
+		.unwrap();
+	let dir = std::env::temp_dir().join(format!("dmi_sidecar_plte_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("red.dmi");
+	std::fs::write(&path, &saved).unwrap();
+	let cached = Icon::load_cached(&path).unwrap();
+	assert_eq!(
+		cached.states[0].images[0].to_rgba8().get_pixel(0, 0),
+		&Rgba([255, 0, 0, 255])
+	);
+
+	// Recoloring the palette keeps the size of every chunk.
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	let plte = raw_dmi.chunk_plte.as_ref().expect("Expected a palette");
+	let mut palette = plte.data.clone();
+	let entry = palette
+		.chunks(3)
+		.position(|color| color == [255, 0, 0])
+		.unwrap();
+	palette[entry * 3..entry * 3 + 3].copy_from_slice(&[0, 0, 255]);
+	raw_dmi.chunk_plte = Some(plte.with_data(palette).unwrap());
+	let mut recolored = vec![];
+	raw_dmi.save(&mut recolored).unwrap();
+	assert_eq!(recolored.len(), saved.len());
+	std::fs::write(&path, &recolored).unwrap();
+
+	let reloaded = Icon::load_cached(&path).unwrap();
+	assert_eq!(
+		reloaded.states[0].images[0].to_rgba8().get_pixel(0, 0),
+		&Rgba([0, 0, 255, 255])
+	);
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn atlas_packs_sprites_without_overlap() {
+	use dmi::atlas::AtlasBuilder;
+	use image::GenericImageView;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let sprite_count: usize = icon.states.iter().map(|state| state.images.len()).sum();
+	let max_size = icon.width * 3 + 2;
+	let atlas = AtlasBuilder::new(max_size)
+		.padding(1)
+		.add_icon("a.dmi", &icon)
+		.add_icon("b.dmi", &icon)
+		.build()
+		.unwrap();
+
+	assert_eq!(atlas.entries.len(), sprite_count * 2);
+	assert!(atlas
+		.pages
+		.iter()
+		.all(|page| page.width() <= max_size && page.height() <= max_size));
+	for (index, entry) in atlas.entries.iter().enumerate() {
+		assert!(entry
+			.uv
+			.iter()
+			.all(|coordinate| (0.0..=1.0).contains(coordinate)));
+		for other in &atlas.entries[index + 1..] {
+			let apart = entry.page != other.page
+				|| entry.x + entry.width <= other.x
+				|| other.x + other.width <= entry.x
+				|| entry.y + entry.height <= other.y
+				|| other.y + other.height <= entry.y;
+			assert!(apart, "{entry:?} overlaps {other:?}");
+		}
+	}
+	let first = &atlas.entries[0];
+	assert_eq!(first.file, "a.dmi");
+	assert_eq!(
+		atlas.pages[first.page]
+			.view(first.x, first.y, first.width, first.height)
+			.to_image(),
+		icon.states[0].images[0].to_rgba8()
+	);
+	assert!(AtlasBuilder::new(icon.width - 1)
+		.add_icon("a.dmi", &icon)
+		.build()
+		.is_err());
+}
+
+#[test]
+fn physical_dimensions_round_trip() {
+	use dmi::phys::{PhysicalDimensions, PhysicalUnit};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let dimensions = PhysicalDimensions::from_dpi(300.0);
+	assert_eq!(dimensions.pixels_per_unit_x, 11811);
+	assert_eq!(dimensions.unit, PhysicalUnit::Meter);
+	icon.set_physical_dimensions(Some(&dimensions)).unwrap();
+
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let mut raw_dmi = dmi::RawDmi::load(saved.as_slice()).unwrap();
+	let stored = raw_dmi.physical_dimensions().unwrap().unwrap();
+	assert_eq!(stored, dimensions);
+	assert!((stored.dpi().unwrap().0 - 300.0).abs() < 0.01);
+	assert_eq!(
+		Icon::load(saved.as_slice())
+			.unwrap()
+			.physical_dimensions()
+			.unwrap(),
+		Some(dimensions)
+	);
+
+	raw_dmi.set_physical_dimensions(None).unwrap();
+	assert_eq!(raw_dmi.physical_dimensions().unwrap(), None);
+}
+
+#[test]
+fn flatten_over_background() {
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let mut image = RgbaImage::from_pixel(icon.width, icon.height, Rgba([255, 255, 255, 0]));
+	image.put_pixel(1, 0, Rgba([255, 0, 0, 255]));
+	image.put_pixel(2, 0, Rgba([255, 255, 255, 128]));
+	icon.states[0].images[0] = DynamicImage::ImageRgba8(image);
+
+	icon.states[0].flatten(Rgba([0, 0, 0, 0]));
+	let flattened = icon.states[0].images[0].as_rgb8().unwrap();
+	assert_eq!(flattened.get_pixel(0, 0).0, [0, 0, 0]);
+	assert_eq!(flattened.get_pixel(1, 0).0, [255, 0, 0]);
+	assert_eq!(flattened.get_pixel(2, 0).0, [128, 128, 128]);
+}
+
+#[test]
+fn quantize_to_palette() {
+	use dmi::quantize::{quantize, Dithering, QuantizeMethod, QuantizeOptions};
+	use image::{Rgba, RgbaImage};
+
+	let gradient = RgbaImage::from_fn(64, 4, |x, y| {
+		if y == 0 {
+			Rgba([0, 0, 0, 0])
+		} else {
+			Rgba([(x * 4) as u8, 0, 255 - (x * 4) as u8, 255])
+		}
+	});
+	for dithering in [
+		Dithering::None,
+		Dithering::Ordered,
+		Dithering::FloydSteinberg,
+	] {
+		let options = QuantizeOptions::new().max_colors(8).dithering(dithering);
+		let quantized = quantize(&gradient, &options).unwrap();
+		assert_eq!(quantized.palette.len(), 8);
+		assert_eq!(quantized.indices.len(), 64 * 4);
+		assert_eq!(quantized.to_rgba8().get_pixel(5, 0).0, [0, 0, 0, 0]);
+	}
+
+	let neuquant = QuantizeOptions::new().method(QuantizeMethod::NeuQuant);
+	assert!(quantize(&gradient, &neuquant.clone().max_colors(8)).is_err());
+	// The 64 opaque colors of the gradient do not fit beside the transparent entry.
+	let quantized = quantize(&gradient, &neuquant.max_colors(64)).unwrap();
+	assert!(quantized.palette.len() <= 64);
+	let remapped = quantized.to_rgba8();
+	assert_eq!(remapped.get_pixel(5, 0).0, [0, 0, 0, 0]);
+	for (original, remapped) in gradient.pixels().zip(remapped.pixels()).skip(64) {
+		let distance: i32 = (0..4)
+			.map(|channel| (original[channel] as i32 - remapped[channel] as i32).abs())
+			.sum();
+		assert!(distance < 64, "{original:?} became {remapped:?}");
+	}
+
+	let few_colors = RgbaImage::from_fn(4, 4, |x, _| Rgba([x as u8, 0, 0, 255]));
+	let quantized = quantize(&few_colors, &QuantizeOptions::new()).unwrap();
+	assert_eq!(quantized.to_rgba8(), few_colors);
+	assert!(quantize(&few_colors, &QuantizeOptions::new().max_colors(0)).is_err());
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let palette = icon
+		.quantize(&QuantizeOptions::new().max_colors(4))
+		.unwrap();
+	assert!(palette.len() <= 4);
+	for image in icon.states.iter().flat_map(|state| state.images.iter()) {
+		assert!(image
+			.to_rgba8()
+			.pixels()
+			.all(|pixel| palette.contains(pixel)));
+	}
+}
+
+#[test]
+fn save_with_color_type() {
+	use dmi::encode::OutputColorType;
+	use dmi::options::SaveOptions;
+	use dmi::quantize::QuantizeOptions;
+	use dmi::RawDmi;
+	use image::Rgba;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	icon
+		.quantize(&QuantizeOptions::new().max_colors(16))
+		.unwrap();
+	// The color of fully transparent pixels is not kept by every format.
+	let pixels = |icon: &Icon| -> Vec<Vec<Rgba<u8>>> {
+		icon
+			.states
+			.iter()
+			.flat_map(|state| state.images.iter())
+			.map(|image| {
+				image
+					.to_rgba8()
+					.pixels()
+					.map(|&pixel| if pixel[3] == 0 { Rgba([0; 4]) } else { pixel })
+					.collect()
+			})
+			.collect()
+	};
+
+	let options = SaveOptions::new().color_type(OutputColorType::Indexed(QuantizeOptions::new()));
+	let mut indexed = vec![];
+	icon.save_with(&mut indexed, &options).unwrap();
+	let raw_dmi = RawDmi::load(&indexed[..]).unwrap();
+	assert_eq!(raw_dmi.color_type().unwrap(), 3);
+	assert!(raw_dmi.bit_depth().unwrap() <= 4);
+	let reloaded = Icon::load(&indexed[..]).unwrap();
+	assert_eq!(pixels(&reloaded), pixels(&icon));
+
+	let options = SaveOptions::new().color_type(OutputColorType::Rgb8Trns);
+	let mut translucent = icon.clone();
+	translucent.map_images(|image| image.put_pixel(0, 0, Rgba([255, 0, 0, 100])));
+	assert!(translucent.save_with(&mut vec![], &options).is_err());
+	icon.map_images(|image| {
+		for pixel in image.pixels_mut() {
+			if pixel[3] < 128 {
+				*pixel = Rgba([0, 0, 0, 0]);
+			} else {
+				pixel[3] = 255;
+			}
+		}
+	});
+	let mut rgb = vec![];
+	icon.save_with(&mut rgb, &options).unwrap();
+	assert_eq!(RawDmi::load(&rgb[..]).unwrap().color_type().unwrap(), 2);
+	let reloaded = Icon::load(&rgb[..]).unwrap();
+	assert_eq!(pixels(&reloaded), pixels(&icon));
+}
+
+#[test]
+fn blank_states_and_frames() {
+	use dmi::icon::Frame;
+	use image::DynamicImage;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	assert!(icon.blank_states().is_empty());
+
+	let (width, height) = (icon.width, icon.height);
+	let icon_state = &mut icon.states[0];
+	let dirs = icon_state.dirs as usize;
+	for image in &mut icon_state.images[..dirs] {
+		*image = DynamicImage::new_rgba8(width, height);
+	}
+	assert_eq!(icon_state.blank_frames(), vec![Frame::FIRST]);
+	assert_eq!(icon_state.is_blank(), icon_state.frames == 1);
+
+	for image in &mut icon.states[1].images {
+		*image = DynamicImage::new_rgba8(width, height);
+	}
+	let blank_names: Vec<&str> = icon
+		.blank_states()
+		.iter()
+		.map(|icon_state| icon_state.name.as_str())
+		.collect();
+	assert!(blank_names.contains(&icon.states[1].name.as_str()));
+}
+
+#[test]
+fn content_bounding_box() {
+	use dmi::analysis::BoundingBox;
+	use dmi::icon::{DirIndex, Frame};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let (width, height) = (icon.width, icon.height);
+	for image in icon
+		.states
+		.iter_mut()
+		.flat_map(|state| state.images.iter_mut())
+	{
+		*image = DynamicImage::new_rgba8(width, height);
+	}
+	assert_eq!(icon.content_bbox(), None);
+
+	let mut sprite = RgbaImage::new(width, height);
+	sprite.put_pixel(2, 3, Rgba([255, 0, 0, 255]));
+	sprite.put_pixel(5, 4, Rgba([255, 0, 0, 10]));
+	icon.states[0].images[0] = DynamicImage::ImageRgba8(sprite);
+	let mut other = RgbaImage::new(width, height);
+	other.put_pixel(7, 1, Rgba([0, 0, 255, 255]));
+	*icon.states[1].images.last_mut().unwrap() = DynamicImage::ImageRgba8(other);
+
+	let bbox = icon.states[0]
+		.content_bbox(DirIndex::new(0).unwrap(), Frame::FIRST)
+		.unwrap();
+	assert_eq!(
+		bbox,
+		Some(BoundingBox {
+			x: 2,
+			y: 3,
+			width: 4,
+			height: 2
+		})
+	);
+	assert_eq!(
+		icon.content_bbox(),
+		Some(BoundingBox {
+			x: 2,
+			y: 1,
+			width: 6,
+			height: 4
+		})
+	);
+}
+
+#[test]
+fn unique_colors_of_state() {
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	let (width, height) = (icon.width, icon.height);
+	let icon_state = &mut icon.states[0];
+	for image in &mut icon_state.images {
+		let mut sprite = RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 0]));
+		sprite.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+		*image = DynamicImage::ImageRgba8(sprite);
+	}
+
+	let colors = icon_state.unique_colors();
+	let sprites = icon_state.images.len() as u64;
+	assert_eq!(colors.len(), 2);
+	assert_eq!(colors[&Rgba([255, 0, 0, 255])], sprites);
+	assert_eq!(
+		colors[&Rgba([0, 0, 0, 0])],
+		sprites * (width * height - 1) as u64
+	);
+}
+
+#[test]
+fn color_census_of_icon() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+
+	let census = icon.color_census();
+	assert_eq!(census.states.len(), icon.states.len());
+	let total_pixels: u64 = icon
+		.states
+		.iter()
+		.flat_map(|state| state.images.iter())
+		.map(|image| (image.width() * image.height()) as u64)
+		.sum();
+	assert_eq!(census.counts.values().sum::<u64>(), total_pixels);
+	for (icon_state, counts) in icon.states.iter().zip(&census.states) {
+		assert_eq!(&icon_state.unique_colors(), counts);
+		assert!(counts
+			.iter()
+			.all(|(color, &count)| census.counts[color] >= count));
+	}
+}
+
+#[test]
+fn library_reports_progress() {
+	let mut resources_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	resources_path.push("tests/resources");
+	let mut library = dmi::library::DmiLibrary::new();
+	let mut reports = vec![];
+	library
+		.add_dir_with_progress(&resources_path, |progress| {
+			reports.push((
+				progress.files_done,
+				progress.files_total,
+				progress.bytes_done,
+				progress.path.to_path_buf(),
+			));
+		})
+		.expect("Failed to scan resources");
+
+	assert!(!reports.is_empty());
+	for (index, (files_done, files_total, bytes_done, path)) in reports.iter().enumerate() {
+		assert_eq!(*files_done, index + 1);
+		assert_eq!(*files_total, reports.len());
+		assert!(*bytes_done > 0);
+		assert!(path.starts_with(&resources_path));
+	}
+	assert_eq!(
+		library.files().len() + library.failures().len(),
+		reports.len()
+	);
+}
+
+#[test]
+fn batch_operations_report_progress() {
+	use dmi::convert::{convert_png_dir_with_progress, ConvertOptions};
+	use dmi::thumbnail::{generate_thumbnails_with_progress, ThumbnailOptions};
+	use image::{Rgba, RgbaImage};
+
+	let mut resources_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	resources_path.push("tests/resources");
+	let library = dmi::library::DmiLibrary::load_dir(&resources_path).unwrap();
+	let mut files_done = vec![];
+	library.find_duplicates_with_progress(|progress| files_done.push(progress.files_done));
+	assert_eq!(files_done, (1..=library.files().len()).collect::<Vec<_>>());
+
+	let load_path = resources_path.join("load_test.dmi");
+	let paths = vec![load_path.clone(), load_path.clone()];
+	let mut files_done = vec![];
+	generate_thumbnails_with_progress(
+		&paths,
+		&ThumbnailOptions::new(),
+		|_, _| {},
+		|progress| {
+			assert_eq!(progress.files_total, 2);
+			files_done.push(progress.files_done);
+		},
+	);
+	assert_eq!(files_done, vec![1, 2]);
+
+	let icon = Icon::load(File::open(&load_path).unwrap()).unwrap();
+	let mut rows = vec![];
+	icon
+		.save_streaming_with_progress(&mut vec![], |progress| {
+			rows.push((progress.rows_done, progress.rows_total))
+		})
+		.unwrap();
+	let (rows_done, rows_total) = rows[rows.len() - 1];
+	assert_eq!(rows_done, rows_total);
+	assert!(rows.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+	let root = std::env::temp_dir().join(format!("dmi_convert_progress_{}", std::process::id()));
+	for directory in ["items", "mobs"] {
+		std::fs::create_dir_all(root.join(directory)).unwrap();
+		for name in ["a", "b"] {
+			RgbaImage::from_pixel(4, 4, Rgba([1, 0, 0, 255]))
+				.save(root.join(directory).join(format!("{name}.png")))
+				.unwrap();
+		}
+	}
+	let mut reports = vec![];
+	convert_png_dir_with_progress(&root, &ConvertOptions::new(), |progress| {
+		reports.push((
+			progress.files_done,
+			progress.files_total,
+			progress.path.to_path_buf(),
+		))
+	})
+	.unwrap();
+	std::fs::remove_dir_all(&root).unwrap();
+	assert_eq!(
+		reports,
+		vec![(2, 4, root.join("items")), (4, 4, root.join("mobs"))]
+	);
+}
+
+#[test]
+fn convert_png_directories() {
+	use dmi::convert::{convert_png_dir, ConvertOptions};
+	use dmi::naming::NamingScheme;
+	use image::{Rgba, RgbaImage};
+	use std::path::Path;
+
+	let root = std::env::temp_dir().join(format!("dmi_convert_{}", std::process::id()));
+	let mobs = root.join("mobs");
+	let broken = root.join("broken");
+	std::fs::create_dir_all(&mobs).unwrap();
+	std::fs::create_dir_all(&broken).unwrap();
+	let sprite = |size: u32, red: u8| RgbaImage::from_pixel(size, size, Rgba([red, 0, 0, 255]));
+	sprite(32, 1).save(root.join("crate.png")).unwrap();
+	sprite(32, 2).save(root.join("locker.png")).unwrap();
+	for (index, dir) in ["south", "north", "east", "west"].iter().enumerate() {
+		for frame in 1..=2 {
+			sprite(16, (index * 2 + frame) as u8)
+				.save(mobs.join(format!("cat_{dir}_{frame}.png")))
+				.unwrap();
+		}
+	}
+	std::fs::write(mobs.join("notes.txt"), "not a sprite").unwrap();
+	sprite(16, 1).save(broken.join("small.png")).unwrap();
+	sprite(8, 1).save(broken.join("tiny.png")).unwrap();
+
+	let converted = convert_png_dir(&root, &ConvertOptions::new()).unwrap();
+	let root_name = root.file_name().unwrap();
+	let outputs: Vec<&Path> = converted.iter().map(|icon| icon.output.as_path()).collect();
+	assert_eq!(
+		outputs,
+		vec![
+			Path::new(root_name).with_extension("dmi").as_path(),
+			Path::new("broken.dmi"),
+			Path::new("mobs.dmi"),
+		]
+	);
+	let items = converted[0].icon.as_ref().unwrap();
+	assert_eq!((items.width, items.height), (32, 32));
+	let names: Vec<&str> = items
+		.states
+		.iter()
+		.map(|state| state.name.as_str())
+		.collect();
+	assert_eq!(names, vec!["crate", "locker"]);
+	assert!(converted[1].icon.is_err());
+	// The default naming takes the whole file name as the state name.
+	assert_eq!(converted[2].icon.as_ref().unwrap().states.len(), 8);
+
+	let mobs_naming = ConvertOptions::new().naming(NamingScheme::new("{state}_{dir}_{frame}"));
+	let converted = convert_png_dir(&mobs, &mobs_naming).unwrap();
+	let cat = &converted[0].icon.as_ref().unwrap().states[0];
+	assert_eq!((cat.name.as_str(), cat.dirs, cat.frames), ("cat", 4, 2));
+	assert_eq!(cat.delay, Some(vec![1.0, 1.0]));
+
+	let output_root = root.join("out");
+	let written = converted[0].save_to(&output_root).unwrap();
+	assert_eq!(written, output_root.join("mobs.dmi"));
+	let reloaded = Icon::load(File::open(&written).unwrap()).unwrap();
+	std::fs::remove_dir_all(&root).unwrap();
+	assert_eq!(&reloaded.states[0], cat);
+}
+
+#[cfg(unix)]
+#[test]
+fn convert_png_dir_skips_symlinked_directories() {
+	use dmi::convert::{convert_png_dir, ConvertOptions};
+	use image::{Rgba, RgbaImage};
+
+	let root = std::env::temp_dir().join(format!("dmi_convert_links_{}", std::process::id()));
+	let items = root.join("items");
+	std::fs::create_dir_all(&items).unwrap();
+	RgbaImage::from_pixel(4, 4, Rgba([1, 0, 0, 255]))
+		.save(items.join("crate.png"))
+		.unwrap();
+	// A link back to the root, which would be walked forever if followed.
+	std::os::unix::fs::symlink(&root, items.join("loop")).unwrap();
+
+	let converted = convert_png_dir(&root, &ConvertOptions::new()).unwrap();
+	std::fs::remove_dir_all(&root).unwrap();
+	assert_eq!(converted.len(), 1);
+	assert_eq!(converted[0].source, items);
+}
+
+#[test]
+fn provenance_round_trip() {
+	use dmi::text::Provenance;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	assert_eq!(icon.provenance(), Provenance::new());
+
+	let provenance = Provenance::new()
+		.author("Rohesie")
+		.copyright("CC BY-SA 3.0")
+		.software("dmi-rust");
+	icon.set_provenance(&provenance).unwrap();
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	assert_eq!(raw_dmi.provenance(), provenance);
+	assert_eq!(raw_dmi.text("Author").as_deref(), Some("Rohesie"));
+	assert_eq!(Icon::load(&saved[..]).unwrap().provenance(), provenance);
+
+	let relicensed = Provenance::new().copyright("MIT");
+	raw_dmi.set_provenance(&relicensed).unwrap();
+	assert_eq!(raw_dmi.provenance(), relicensed);
+	icon.set_provenance(&relicensed).unwrap();
+	assert_eq!(icon.provenance(), relicensed);
+	assert_eq!(icon.ancillary_chunks.len(), 1);
+}
+
+#[test]
+fn frame_as_data_uri() {
+	use dmi::icon::{DirIndex, Frame};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	icon.states[0].images[0] =
+		DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 255])));
+
+	let south = DirIndex::new(0).unwrap();
+	let uri = icon.states[0].frame_data_uri(south, Frame::FIRST).unwrap();
+	let payload = uri.strip_prefix("data:image/png;base64,").unwrap();
+	assert_eq!(payload.len() % 4, 0);
+	// The PNG signature, base64 encoded.
+	assert!(payload.starts_with("iVBORw0KGgo"));
+	assert!(icon.states[0]
+		.frame_data_uri(south, Frame::new(icon.states[0].frames + 1).unwrap())
+		.is_err());
+}
+
+#[test]
+fn load_from_byte_slices() {
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(load_path).unwrap();
+	let icon = Icon::try_from(&bytes[..]).unwrap();
+	assert_eq!(icon, Icon::load(&bytes[..]).unwrap());
+	assert_eq!(
+		RawDmi::try_from(&bytes[..]).unwrap(),
+		RawDmi::load(&bytes[..]).unwrap()
+	);
+	assert!(Icon::try_from(&bytes[..40]).is_err());
+
+	#[cfg(feature = "bytes")]
+	{
+		let shared = bytes::Bytes::from(bytes);
+		assert_eq!(Icon::try_from(shared.clone()).unwrap(), icon);
+		assert!(RawDmi::try_from(shared).is_ok());
+	}
+}
+
+#[test]
+fn streaming_save_matches_save() {
+	use dmi::icon::IconState;
+	use dmi::RawDmi;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).expect("Unable to load lights dmi");
+	icon.app_data.insert("editor".to_string(), vec![1, 2, 3]);
+
+	let mut streamed = vec![];
+	let written = icon.save_streaming(&mut streamed).unwrap();
+	assert_eq!(written, streamed.len());
+	assert_eq!(Icon::load(&streamed[..]).unwrap(), icon);
+
+	// Noise barely compresses, so the image data spans several IDAT chunks.
+	let mut seed = 1u32;
+	let noise = RgbaImage::from_fn(128, 128, |_, _| {
+		seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+		Rgba(seed.to_be_bytes())
+	});
+	let big = Icon {
+		width: 128,
+		height: 128,
+		states: vec![IconState {
+			name: "noise".to_string(),
+			images: vec![DynamicImage::ImageRgba8(noise)],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	let mut streamed = vec![];
+	big.save_streaming(&mut streamed).unwrap();
+	assert!(RawDmi::load(&streamed[..]).unwrap().chunks_idat.len() > 1);
+	assert_eq!(Icon::load(&streamed[..]).unwrap(), big);
+}
+
+#[test]
+fn quirks_mode_accepts_nonstandard_metadata() {
+	use dmi::icon::{Hotspot, IconState, Looping};
+	use dmi::quirks::Quirk;
+	use dmi::warning::DmiWarning;
+	use dmi::{ztxt, RawDmi};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let sprite =
+		|red: u8| DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([red, 0, 0, 255])));
+	let icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![
+			IconState {
+				name: "pair".to_string(),
+				frames: 2,
+				images: vec![sprite(1), sprite(2)],
+				delay: Some(vec![1.0, 1.0]),
+				..Default::default()
+			},
+			IconState {
+				name: "last".to_string(),
+				images: vec![sprite(3)],
+				..Default::default()
+			},
+		],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	let text = String::from_utf8(raw_dmi.chunk_ztxt.unwrap().data.decode().unwrap()).unwrap();
+	let quirky_text = text.replace(
+		"\tdirs = 1\n\tframes = 2\n\tdelay = 1,1\n",
+		"\tdirs = 2\n\tframes = 1\n\tloop = 3\n\tloop = -1\n\thotspot = 3,4\n",
+	);
+	assert_ne!(text, quirky_text);
+	raw_dmi.chunk_ztxt = Some(ztxt::create_ztxt_chunk(quirky_text.as_bytes()).unwrap());
+	let mut quirky = vec![];
+	raw_dmi.save(&mut quirky).unwrap();
+
+	assert!(Icon::load(&quirky[..]).is_err());
+	let (loaded, warnings) = Icon::load_with_quirks(&quirky[..]).expect("Failed to load with quirks");
+	let pair = &loaded.states[0];
+	assert_eq!(pair.dirs, 4);
+	assert_eq!(
+		pair.images,
+		vec![sprite(1), sprite(2), sprite(1), sprite(1)]
+	);
+	assert_eq!(pair.loop_flag, Looping::Indefinitely);
+	assert_eq!(pair.hotspot, Some(Hotspot { x: 3, y: 4 }));
+	assert_eq!(loaded.states[1], icon.states[1]);
+
+	let quirks: Vec<&Quirk> = warnings
+		.iter()
+		.filter_map(|warning| match warning {
+			DmiWarning::MetadataQuirk { state, quirk } if state == "pair" => Some(quirk),
+			_ => None,
+		})
+		.collect();
+	assert_eq!(
+		quirks,
+		vec![
+			&Quirk::DuplicateSetting {
+				key: "loop".to_string()
+			},
+			&Quirk::NegativeLoop,
+			&Quirk::TwoValueHotspot,
+			&Quirk::DirsRounded { from: 2, to: 4 },
+		]
+	);
+}
+
+#[test]
+fn dir_and_frame_of_flat_indices() {
+	use dmi::dirs::Dirs;
+	use dmi::icon::{dir_to_dmi_index, dmi_index_to_dir, DirIndex, Frame, SpritePositions};
+
+	for dir_count in [1, 4, 8] {
+		for index in 0..dir_count as usize {
+			let dir = dmi_index_to_dir(index, dir_count).unwrap();
+			assert_eq!(dir_to_dmi_index(&dir), Some(index));
+		}
+		assert_eq!(dmi_index_to_dir(dir_count as usize, dir_count), None);
+	}
+	assert_eq!(dmi_index_to_dir(2, 4), Some(Dirs::EAST));
+
+	let positions: Vec<(DirIndex, Frame)> = SpritePositions::new(4, 2).unwrap().collect();
+	assert_eq!(positions.len(), 8);
+	assert_eq!(
+		positions[5],
+		(DirIndex::of(&Dirs::NORTH).unwrap(), Frame::new(2).unwrap())
+	);
+	assert!(SpritePositions::new(0, 2).is_none());
+	assert!(SpritePositions::new(9, 2).is_none());
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	for icon_state in &icon.states {
+		for (index, (dir, frame)) in icon_state.sprite_positions().unwrap().enumerate() {
+			assert_eq!(icon_state.image_index(dir, frame).unwrap(), index);
+		}
+	}
+}
+
+#[test]
+fn frame_grid_indexing() {
+	use dmi::dirs::Dirs;
+	use dmi::grid::FrameGrid;
+	use dmi::icon::{DirIndex, Frame, IconState};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let sprite =
+		|red: u8| DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([red, 0, 0, 255])));
+	assert!(FrameGrid::new(2, 1, vec![sprite(0), sprite(1)]).is_err());
+	assert!(FrameGrid::new(4, 2, vec![sprite(0); 7]).is_err());
+
+	let mut grid = FrameGrid::new(4, 2, (0..8).map(sprite).collect()).unwrap();
+	let second = Frame::new(2).unwrap();
+	assert_eq!(grid[(Dirs::WEST, second)], sprite(7));
+	assert_eq!(
+		grid.get(DirIndex::of(&Dirs::NORTH).unwrap(), Frame::FIRST),
+		Some(&sprite(1))
+	);
+	assert_eq!(
+		grid.get(DirIndex::of(&Dirs::SOUTHEAST).unwrap(), Frame::FIRST),
+		None
+	);
+	grid[(Dirs::SOUTH, second)] = sprite(9);
+	assert_eq!(
+		grid.iter().nth(4),
+		Some((DirIndex::of(&Dirs::SOUTH).unwrap(), second, &sprite(9)))
+	);
+
+	let mut icon_state = IconState {
+		name: "grid".to_string(),
+		images: vec![sprite(0)],
+		..Default::default()
+	};
+	icon_state.set_grid(grid.clone());
+	assert_eq!((icon_state.dirs, icon_state.frames), (4, 2));
+	assert_eq!(
+		icon_state
+			.image(DirIndex::of(&Dirs::SOUTH).unwrap(), second)
+			.unwrap(),
+		&sprite(9)
+	);
+	assert_eq!(icon_state.to_grid().unwrap(), grid);
+}
+
+#[test]
+fn set_image_checks_dimensions() {
+	use dmi::dirs::Dirs;
+	use dmi::icon::IconState;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let sprite = |size: u32, red: u8| {
+		DynamicImage::ImageRgba8(RgbaImage::from_pixel(size, size, Rgba([red, 0, 0, 255])))
+	};
+	let mut icon = Icon {
+		width: 2,
+		height: 2,
+		states: vec![IconState {
+			name: "walk".to_string(),
+			dirs: 4,
+			images: vec![sprite(2, 0); 4],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+
+	icon
+		.set_image("walk", &Dirs::EAST, 1, sprite(2, 1))
+		.unwrap();
+	assert_eq!(
+		icon["walk"].get_image(&Dirs::EAST, 1).unwrap(),
+		&sprite(2, 1)
+	);
+	assert!(icon
+		.set_image("walk", &Dirs::EAST, 1, sprite(3, 1))
+		.is_err());
+	assert!(icon.set_image("run", &Dirs::EAST, 1, sprite(2, 1)).is_err());
+
+	let icon_state = icon.get_state_mut("walk").unwrap();
+	assert!(icon_state.set_image(&Dirs::NORTH, 1, sprite(1, 2)).is_err());
+	assert!(icon_state.set_image(&Dirs::NORTH, 2, sprite(2, 2)).is_err());
+	icon_state.set_image(&Dirs::NORTH, 1, sprite(2, 2)).unwrap();
+	*icon_state.get_image_mut(&Dirs::WEST, 1).unwrap() = sprite(2, 3);
+	assert_eq!(icon_state.images[1], sprite(2, 2));
+	assert_eq!(icon_state.images[3], sprite(2, 3));
+}
+
+#[test]
+fn duplicate_state_names() {
+	use dmi::icon::IconState;
+	use dmi::options::SaveOptions;
+	use image::{DynamicImage, RgbaImage};
+
+	let state = |name: &str, movement: bool, frames: u32| IconState {
+		name: name.to_string(),
+		movement,
+		frames,
+		images: vec![DynamicImage::ImageRgba8(RgbaImage::new(1, 1)); frames as usize],
+		delay: (frames > 1).then(|| vec![1.0; frames as usize]),
+		..Default::default()
+	};
+	let mut icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![state("walk", true, 2), state("walk", false, 1)],
+		..Default::default()
+	};
+	assert_eq!(icon.get_state_variant("walk", true).unwrap().frames, 2);
+	assert_eq!(icon.get_state_variant("walk", false).unwrap().frames, 1);
+	assert!(icon.get_state_variant("run", false).is_none());
+	icon.get_state_variant_mut("walk", true).unwrap().rewind = true;
+	assert!(icon.states[0].rewind);
+	assert!(icon.duplicate_states().is_empty());
+
+	let strict = SaveOptions::new().reject_duplicate_states(true);
+	icon.save_with(&mut vec![], &strict).unwrap();
+	icon.states.push(state("walk", false, 3));
+	assert_eq!(icon.duplicate_states(), vec![("walk", false)]);
+	assert_eq!(icon.all_states_named("walk").count(), 3);
+	assert!(icon.save_with(&mut vec![], &strict).is_err());
+	icon.save_with(&mut vec![], &SaveOptions::new()).unwrap();
+}
+
+#[test]
+fn metadata_only_save_keeps_image_data() {
+	use dmi::source::SourceSheet;
+	use dmi::RawDmi;
+	use image::DynamicImage;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let original = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let mut icon = Icon::try_from(
+		&{
+			let mut bytes = vec![];
+			original.save(&mut bytes).unwrap();
+			bytes
+		}[..],
+	)
+	.unwrap();
+
+	icon.states[0].name = "renamed".to_string();
+	let mut saved = vec![];
+	icon.save_metadata_only(&original, &mut saved).unwrap();
+	let resaved = RawDmi::load(&saved[..]).unwrap();
+	assert_eq!(resaved.chunks_idat, original.chunks_idat);
+	assert_eq!(Icon::load(&saved[..]).unwrap(), icon);
+
+	let mut raw_dmi = original.clone();
+	assert!(raw_dmi
+		.replace_metadata("# BEGIN DMI\nversion = 4.0\n\twidth = 7\n\theight = 7\n# END DMI\n")
+		.is_err());
+	assert_eq!(raw_dmi, original);
+
+	// Without the hashes of the source, the original sheet is decoded to compare the sprites.
+	let mut detached = icon.clone();
+	detached.source = SourceSheet::default();
+	let mut saved = vec![];
+	detached.save_metadata_only(&original, &mut saved).unwrap();
+	assert_eq!(
+		RawDmi::load(&saved[..]).unwrap().chunks_idat,
+		original.chunks_idat
+	);
+
+	let width = icon.width;
+	let height = icon.height;
+	icon.states[0].images[0] = DynamicImage::new_rgba8(width, height);
+	assert!(icon.save_metadata_only(&original, &mut vec![]).is_err());
+	detached.states[0].images[0] = DynamicImage::new_rgba8(width, height);
+	assert!(detached.save_metadata_only(&original, &mut vec![]).is_err());
+}
+
+#[test]
+fn unmodified_sprites_reuse_image_data() {
+	use dmi::RawDmi;
+	use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(load_path).unwrap();
+	let original = RawDmi::try_from(&bytes[..]).unwrap();
+	let mut icon = Icon::try_from(&bytes[..]).unwrap();
+	assert!(!icon.source.is_empty());
+	assert!(!icon.pixels_modified());
+
+	icon.states[0].name = "renamed".to_string();
+	icon.states[1].hotspot = Some(dmi::icon::Hotspot { x: 3, y: 4 });
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	assert_eq!(
+		RawDmi::load(&saved[..]).unwrap().chunks_idat,
+		original.chunks_idat
+	);
+	assert_eq!(Icon::load(&saved[..]).unwrap(), icon);
+
+	let image = &mut icon.states[0].images[0];
+	let pixel = image.get_pixel(0, 0);
+	image.put_pixel(0, 0, Rgba([pixel[0] ^ 1, pixel[1], pixel[2], 255]));
+	assert!(icon.pixels_modified());
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	assert_ne!(
+		RawDmi::load(&saved[..]).unwrap().chunks_idat,
+		original.chunks_idat
+	);
+	assert_eq!(Icon::load(&saved[..]).unwrap(), icon);
+
+	let memory_bytes = icon.approx_memory_bytes();
+	icon.source = dmi::source::SourceSheet::default();
+	assert!(icon.approx_memory_bytes() < memory_bytes);
+
+	let built = Icon {
+		width: 1,
+		height: 1,
+		states: vec![dmi::icon::IconState {
+			name: "built".to_string(),
+			images: vec![DynamicImage::new_rgba8(1, 1)],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	assert!(built.source.is_empty());
+	assert!(built.pixels_modified());
+}
+
+#[test]
+fn save_keeps_palette_of_unchanged_sheet() {
+	use dmi::encode::{OutputColorType, TRNS_TYPE};
+	use dmi::options::SaveOptions;
+	use dmi::quantize::QuantizeOptions;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	let options = SaveOptions::new().color_type(OutputColorType::Indexed(QuantizeOptions::new()));
+	let mut indexed = vec![];
+	icon.save_with(&mut indexed, &options).unwrap();
+	let original = RawDmi::load(&indexed[..]).unwrap();
+
+	let mut loaded = Icon::load(&indexed[..]).unwrap();
+	loaded.states[0].name = "renamed".to_string();
+	for save in [
+		|icon: &Icon, bytes: &mut Vec<u8>| icon.save(bytes).map(|_| ()),
+		|icon: &Icon, bytes: &mut Vec<u8>| icon.save_with(bytes, &SaveOptions::new()).map(|_| ()),
+	] {
+		let mut saved = vec![];
+		save(&loaded, &mut saved).unwrap();
+		let raw_dmi = RawDmi::load(&saved[..]).unwrap();
+		assert_eq!(raw_dmi.chunk_plte, original.chunk_plte);
+		assert!(raw_dmi
+			.other_chunks
+			.iter()
+			.flatten()
+			.any(|chunk| chunk.chunk_type == TRNS_TYPE));
+		assert_eq!(Icon::load(&saved[..]).unwrap(), loaded);
+	}
+
+	let mut reencoded = vec![];
+	loaded
+		.save_with(&mut reencoded, &SaveOptions::new().reencode(true))
+		.unwrap();
+	let raw_dmi = RawDmi::load(&reencoded[..]).unwrap();
+	assert!(raw_dmi.chunk_plte.is_none());
+	assert_eq!(raw_dmi.color_type().unwrap(), 6);
+}
+
+#[test]
+fn save_options_shape_the_file() {
+	use dmi::encode::{CompressionLevel, FilterStrategy, SheetLayout};
+	use dmi::icon::Hotspot;
+	use dmi::options::SaveOptions;
+	use dmi::RawDmi;
+	use std::num::NonZeroU32;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	icon.states[0].hotspot = Some(Hotspot { x: 1, y: 2 });
+	let sprite_count: usize = icon.states.iter().map(|state| state.images.len()).sum();
+
+	let save = |options: SaveOptions| {
+		let mut saved = vec![];
+		icon.save_with(&mut saved, &options).unwrap();
+		saved
+	};
+
+	let column = save(SaveOptions::new().layout(SheetLayout::Columns(NonZeroU32::MIN)));
+	let raw_dmi = RawDmi::load(&column[..]).unwrap();
+	assert_eq!(raw_dmi.image_width().unwrap(), icon.width);
+	assert_eq!(
+		raw_dmi.image_height().unwrap(),
+		icon.height * sprite_count as u32
+	);
+	assert_eq!(Icon::load(&column[..]).unwrap(), icon);
+
+	let fast = save(
+		SaveOptions::new()
+			.compression(CompressionLevel::Fast)
+			.filter(FilterStrategy::None),
+	);
+	let best = save(SaveOptions::new().compression(CompressionLevel::Best));
+	assert!(best.len() <= fast.len());
+	assert_eq!(Icon::load(&fast[..]).unwrap(), icon);
+
+	let keyword = save(SaveOptions::new().ztxt_keyword("Comment"));
+	let raw_dmi = RawDmi::load(&keyword[..]).unwrap();
+	assert_eq!(raw_dmi.chunk_ztxt.unwrap().data.keyword, b"Comment");
+	assert!(icon
+		.save_with(&mut vec![], &SaveOptions::new().ztxt_keyword(""))
+		.is_err());
+
+	let short_hotspot = save(SaveOptions::new().hotspot_third_value(false));
+	assert!(Icon::load(&short_hotspot[..]).is_err());
+	let (loaded, _) = Icon::load_with_quirks(&short_hotspot[..]).unwrap();
+	assert_eq!(loaded.states[0].hotspot, Some(Hotspot { x: 1, y: 2 }));
+}
+
+#[cfg(feature = "optimize")]
+#[test]
+fn optimize_dmi() {
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(&load_path).unwrap()).unwrap();
+
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let mut optimized = vec![];
+	icon.save_optimized(&mut optimized).unwrap();
+	assert!(optimized.len() <= saved.len());
+	assert_eq!(Icon::load(&optimized[..]).unwrap(), icon);
+
+	// Optimizing again finds nothing smaller.
+	let mut raw_dmi = RawDmi::load(&optimized[..]).unwrap();
+	assert!(!raw_dmi.optimize().unwrap());
+}
+
+#[test]
+fn save_indexed_if_lossless() {
+	use dmi::encode::OutputColorType;
+	use dmi::options::SaveOptions;
+	use dmi::quantize::QuantizeOptions;
+	use dmi::RawDmi;
+	use image::Rgba;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	let options = SaveOptions::new().color_type(OutputColorType::IndexedIfLossless);
+
+	icon
+		.quantize(&QuantizeOptions::new().max_colors(200))
+		.unwrap();
+	icon.map_images(|image| image.put_pixel(0, 0, Rgba([0, 0, 0, 0])));
+	let mut indexed = vec![];
+	icon.save_with(&mut indexed, &options).unwrap();
+	assert_eq!(RawDmi::load(&indexed[..]).unwrap().color_type().unwrap(), 3);
+	let mut rgba = vec![];
+	icon
+		.save_with(&mut rgba, &SaveOptions::new().reencode(true))
+		.unwrap();
+	assert!(indexed.len() < rgba.len());
+	assert_eq!(Icon::load(&indexed[..]).unwrap(), icon);
+
+	icon.map_images(|image| {
+		for (x, y, pixel) in image.enumerate_pixels_mut() {
+			*pixel = Rgba([x as u8, y as u8, 0, 255]);
+		}
+	});
+	let mut too_many_colors = vec![];
+	icon.save_with(&mut too_many_colors, &options).unwrap();
+	let raw_dmi = RawDmi::load(&too_many_colors[..]).unwrap();
+	assert_eq!(raw_dmi.color_type().unwrap(), 6);
+	assert_eq!(Icon::load(&too_many_colors[..]).unwrap(), icon);
+}
+
+#[test]
+fn save_in_source_format() {
+	use dmi::encode::OutputColorType;
+	use dmi::options::SaveOptions;
+	use dmi::RawDmi;
+	use image::{DynamicImage, Rgba};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let original = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	// The same file, with its sheet converted to greyscale with alpha.
+	let mut sheet = vec![];
+	original.save(&mut sheet).unwrap();
+	let grey = image::load_from_memory(&sheet).unwrap().to_luma_alpha8();
+	let mut grey_png = vec![];
+	DynamicImage::ImageLumaA8(grey)
+		.write_to(
+			&mut std::io::Cursor::new(&mut grey_png),
+			image::ImageFormat::Png,
+		)
+		.unwrap();
+	let mut grey_dmi = RawDmi::load(&grey_png[..]).unwrap();
+	grey_dmi.chunk_ztxt = original.chunk_ztxt.clone();
+	let mut grey_file = vec![];
+	grey_dmi.save(&mut grey_file).unwrap();
+
+	let mut icon = Icon::load(&grey_file[..]).unwrap();
+	let format = icon.source.format().unwrap();
+	assert_eq!((format.color_type, format.bit_depth), (4, 8));
+	let options = SaveOptions::new().color_type(OutputColorType::Source);
+	let pixels = |icon: &Icon| -> Vec<Vec<u8>> {
+		icon
+			.states
+			.iter()
+			.flat_map(|state| state.images.iter())
+			.map(|image| image.to_rgba8().into_raw())
+			.collect()
+	};
+	let save = |icon: &Icon| {
+		let mut saved = vec![];
+		icon.save_with(&mut saved, &options).unwrap();
+		saved
+	};
+
+	icon.map_images(|image| image.put_pixel(0, 0, Rgba([80, 80, 80, 128])));
+	let saved = save(&icon);
+	assert_eq!(RawDmi::load(&saved[..]).unwrap().color_type().unwrap(), 4);
+	assert_eq!(pixels(&Icon::load(&saved[..]).unwrap()), pixels(&icon));
+
+	icon.map_images(|image| image.put_pixel(0, 0, Rgba([255, 0, 0, 255])));
+	let saved = save(&icon);
+	assert_eq!(RawDmi::load(&saved[..]).unwrap().color_type().unwrap(), 6);
+	assert_eq!(pixels(&Icon::load(&saved[..]).unwrap()), pixels(&icon));
+}
+
+#[test]
+fn load_16_bit_dmi() {
+	use dmi::options::LoadOptions;
+	use dmi::RawDmi;
+	use image::DynamicImage;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let original = RawDmi::load(File::open(&load_path).unwrap()).unwrap();
+	let icon = Icon::load(File::open(&load_path).unwrap()).unwrap();
+	let mut sheet = vec![];
+	original.save(&mut sheet).unwrap();
+	let sheet16 = image::load_from_memory(&sheet).unwrap().to_rgba16();
+	let mut png16 = vec![];
+	DynamicImage::ImageRgba16(sheet16)
+		.write_to(
+			&mut std::io::Cursor::new(&mut png16),
+			image::ImageFormat::Png,
+		)
+		.unwrap();
+	let mut dmi16 = RawDmi::load(&png16[..]).unwrap();
+	assert_eq!(dmi16.bit_depth().unwrap(), 16);
+	dmi16.chunk_ztxt = original.chunk_ztxt.clone();
+	let mut file16 = vec![];
+	dmi16.save(&mut file16).unwrap();
+
+	let loaded = Icon::load(&file16[..]).unwrap();
+	for (state, original_state) in loaded.states.iter().zip(&icon.states) {
+		for (image, original_image) in state.images.iter().zip(&original_state.images) {
+			assert!(image.as_rgba8().is_some());
+			assert_eq!(image.to_rgba8(), original_image.to_rgba8());
+		}
+	}
+	assert!(Icon::load_with(&file16[..], LoadOptions::new().reject_16_bit(true)).is_err());
+	assert!(Icon::load_with(&file16[..], LoadOptions::new()).is_ok());
+}
+
+#[test]
+fn load_text_description() {
+	use dmi::metadata::DmiMetadata;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = RawDmi::load(File::open(&load_path).unwrap()).unwrap();
+	let icon = Icon::load(File::open(&load_path).unwrap()).unwrap();
+	let description = raw_dmi.chunk_ztxt.take().unwrap().data.decode().unwrap();
+	raw_dmi
+		.set_text("Description", &String::from_utf8(description).unwrap())
+		.unwrap();
+	let mut text_file = vec![];
+	raw_dmi.save(&mut text_file).unwrap();
+
+	let metadata = DmiMetadata::load(&text_file[..]).unwrap();
+	assert_eq!(metadata.states.len(), icon.states.len());
+	let loaded = Icon::load(&text_file[..]).unwrap();
+	assert_eq!(loaded, icon);
+	assert!(loaded.ancillary_chunks.is_empty());
+
+	raw_dmi.remove_text("Description");
+	let mut no_metadata = vec![];
+	raw_dmi.save(&mut no_metadata).unwrap();
+	assert!(DmiMetadata::load(&no_metadata[..]).is_err());
+}
+
+#[test]
+fn save_metadata_as_itxt() {
+	use dmi::itxt::{create_itxt_chunk, read_itxt_chunk, MetadataChunk};
+	use dmi::metadata::DmiMetadata;
+	use dmi::options::SaveOptions;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	icon.states[0].name = "état ☃".to_string();
+
+	let mut saved = vec![];
+	icon
+		.save_with(
+			&mut saved,
+			&SaveOptions::new().metadata_chunk(MetadataChunk::Itxt),
+		)
+		.unwrap();
+	let raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	assert!(raw_dmi.chunk_ztxt.is_none());
+	assert!(raw_dmi.itxt("Description").unwrap().contains("état ☃"));
+	let metadata = DmiMetadata::load(&saved[..]).unwrap();
+	assert_eq!(metadata.states[0].name, "état ☃");
+	let loaded = Icon::load(&saved[..]).unwrap();
+	assert_eq!(loaded, icon);
+	assert!(loaded.ancillary_chunks.is_empty());
+
+	let chunk = create_itxt_chunk("Comment", "ünïcode", false).unwrap();
+	assert_eq!(
+		read_itxt_chunk(&chunk).unwrap(),
+		("Comment".to_string(), "ünïcode".to_string())
+	);
+	assert!(create_itxt_chunk("", "text", true).is_err());
+}
+
+#[test]
+fn custom_metadata_keyword_round_trips() {
+	use dmi::error::DmiError;
+	use dmi::itxt::MetadataChunk;
+	use dmi::options::SaveOptions;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+
+	let mut saved = vec![];
+	icon
+		.save_with(&mut saved, &SaveOptions::new().ztxt_keyword("Metadata"))
+		.unwrap();
+	assert_eq!(Icon::load(&saved[..]).unwrap(), icon);
+
+	// iTXt metadata is only looked up under the Description keyword, so it cannot be saved elsewhere.
+	let options = SaveOptions::new()
+		.ztxt_keyword("Metadata")
+		.metadata_chunk(MetadataChunk::Itxt);
+	assert!(matches!(
+		icon.save_with(&mut vec![], &options),
+		Err(DmiError::InvalidArgument(_))
+	));
+	let mut saved = vec![];
+	icon
+		.save_with(
+			&mut saved,
+			&options.ztxt_keyword(dmi::ztxt::DESCRIPTION_KEYWORD),
+		)
+		.unwrap();
+	assert_eq!(Icon::load(&saved[..]).unwrap(), icon);
+}
+
+#[test]
+fn split_metadata_across_ztxt_chunks() {
+	use dmi::metadata::DmiMetadata;
+	use dmi::options::SaveOptions;
+	use dmi::{ztxt, RawDmi};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let icon = Icon::load(File::open(load_path).unwrap()).unwrap();
+	let continuations = |file: &[u8]| {
+		let raw_dmi = RawDmi::load(file).unwrap();
+		raw_dmi
+			.other_chunks
+			.iter()
+			.flatten()
+			.filter(|chunk| ztxt::is_continuation(chunk, ztxt::DESCRIPTION_KEYWORD.as_bytes()))
+			.count()
+	};
+
+	let mut whole = vec![];
+	icon.save(&mut whole).unwrap();
+	let mut split = vec![];
+	icon
+		.save_with(
+			&mut split,
+			&SaveOptions::new().split_metadata_above(Some(40)),
+		)
+		.unwrap();
+	assert_eq!(continuations(&whole), 0);
+	assert!(continuations(&split) > 1);
+	assert_eq!(
+		DmiMetadata::load(&split[..]).unwrap(),
+		DmiMetadata::load(&whole[..]).unwrap()
+	);
+	let loaded = Icon::load(&split[..]).unwrap();
+	assert_eq!(loaded, icon);
+
+	let mut resaved = vec![];
+	loaded.save(&mut resaved).unwrap();
+	assert_eq!(continuations(&resaved), 0);
+	assert_eq!(Icon::load(&resaved[..]).unwrap(), icon);
+}
+
+#[test]
+fn continuations_match_the_primary_keyword() {
+	use dmi::chunk::RawGenericChunk;
+	use dmi::{ztxt, RawDmi};
+
+	let ztxt_chunk = |keyword: &str, text: &str| {
+		let chunk = ztxt::create_ztxt_chunk_with_keyword(keyword, text.as_bytes()).unwrap();
+		let mut data = vec![];
+		chunk.data.save(&mut data).unwrap();
+		RawGenericChunk::new(ztxt::ZTXT_TYPE, data).unwrap()
+	};
+	let continuation = ztxt_chunk("Description continued", "state = \"more\"\n");
+	let story = ztxt_chunk("Story continued", "once upon a time");
+	assert!(ztxt::is_continuation(&continuation, b"Description"));
+	assert!(!ztxt::is_continuation(&continuation, b"Desc"));
+	assert!(!ztxt::is_continuation(&story, b"Description"));
+	assert!(ztxt::is_continuation(&story, b"Story"));
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	raw_dmi.other_chunks = Some(vec![continuation.clone()]);
+	let mut saved = vec![];
+	raw_dmi.save(&mut saved).unwrap();
+	let loaded = RawDmi::load(&saved[..]).unwrap();
+	assert_eq!(loaded.chunk_ztxt, raw_dmi.chunk_ztxt);
+	assert_eq!(loaded.other_chunks, Some(vec![continuation.clone()]));
+
+	// Only the continuations of the replaced metadata are dropped.
+	raw_dmi.other_chunks = Some(vec![story.clone(), continuation]);
+	let text =
+		String::from_utf8(raw_dmi.chunk_ztxt.as_ref().unwrap().data.decode().unwrap()).unwrap();
+	raw_dmi.replace_metadata(&text).unwrap();
+	assert_eq!(raw_dmi.other_chunks, Some(vec![story]));
+}
+
+#[test]
+fn read_chunks_one_at_a_time() {
+	use dmi::reader::ChunkReader;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(&load_path).unwrap();
+	let raw_dmi = RawDmi::load(File::open(&load_path).unwrap()).unwrap();
+	assert_eq!(raw_dmi, RawDmi::try_from(&bytes[..]).unwrap());
+
+	let mut spans = vec![];
+	let mut reader = ChunkReader::new(&bytes[..]).unwrap();
+	while let Some(span) = reader.next_span().unwrap() {
+		spans.push(span);
+	}
+	assert_eq!(Some(spans), raw_dmi.source_map);
+	assert_eq!(reader.offset(), bytes.len() as u64);
+
+	let mut reader = ChunkReader::new(&bytes[..]).unwrap();
+	let mut chunk_count = 0;
+	while reader.next_chunk().unwrap().is_some() {
+		chunk_count += 1;
+	}
+	assert_eq!(chunk_count, raw_dmi.chunk_types().len());
+
+	let mut reader = ChunkReader::new(&bytes[..bytes.len() - 20]).unwrap();
+	assert!(std::iter::from_fn(|| Some(reader.next_span()))
+		.find(|span| !matches!(span, Ok(Some(_))))
+		.unwrap()
+		.is_err());
+	assert!(ChunkReader::new(&b"not a png"[..]).is_err());
+}
+
+#[test]
+fn iterate_chunks() {
+	use dmi::chunk::{ChunkRef, ChunkType};
+	use dmi::reader::ChunkReader;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(&load_path).unwrap();
+	let raw_dmi = RawDmi::load(&bytes[..]).unwrap();
+
+	let read: Vec<_> = ChunkReader::new(&bytes[..])
+		.unwrap()
+		.collect::<Result<_, _>>()
+		.unwrap();
+	let borrowed: Vec<ChunkRef> = raw_dmi.chunks().collect();
+	assert_eq!(read.len(), borrowed.len());
+	for (read, borrowed) in read.iter().zip(&borrowed) {
+		assert_eq!(read.chunk_type, borrowed.chunk_type());
+		assert_eq!(read.data.len() as u32, borrowed.data_length());
+	}
+	assert!(matches!(borrowed.first(), Some(ChunkRef::Generic(_))));
+	assert!(matches!(borrowed.last(), Some(ChunkRef::Iend(_))));
+	let ztxt_count = borrowed
+		.iter()
+		.filter(|chunk| ChunkType::from(chunk.chunk_type()) == ChunkType::Ztxt)
+		.count();
+	assert_eq!(ztxt_count, 1);
+
+	let mut truncated = ChunkReader::new(&bytes[..bytes.len() - 20]).unwrap();
+	assert!(truncated.any(|chunk| chunk.is_err()));
+	assert!(truncated.next().is_none());
+}
+
+#[test]
+fn edit_other_chunks() {
+	use dmi::chunk::RawGenericChunk;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let chunk =
+		|chunk_type: &[u8; 4], data: &[u8]| RawGenericChunk::new(*chunk_type, data.to_vec()).unwrap();
+	let types = |raw_dmi: &RawDmi| -> Vec<[u8; 4]> {
+		raw_dmi
+			.other_chunks
+			.iter()
+			.flatten()
+			.map(|chunk| chunk.chunk_type)
+			.collect()
+	};
+	let before = types(&raw_dmi);
+
+	raw_dmi
+		.insert_chunk_before_idat(chunk(b"prOj", b"one"))
+		.unwrap();
+	raw_dmi
+		.insert_chunk_before_idat(chunk(b"teSt", b""))
+		.unwrap();
+	raw_dmi
+		.insert_chunk_before_idat(chunk(b"prOj", b"two"))
+		.unwrap();
+	raw_dmi.upsert_chunk(chunk(b"prOj", b"three")).unwrap();
+	let mut expected = before.clone();
+	expected.extend([*b"prOj", *b"teSt"]);
+	assert_eq!(types(&raw_dmi), expected);
+	assert!(raw_dmi
+		.other_chunks
+		.iter()
+		.flatten()
+		.any(|chunk| chunk.data == b"three"));
+
+	let mut saved = vec![];
+	raw_dmi.save(&mut saved).unwrap();
+	assert_eq!(RawDmi::load(&saved[..]).unwrap(), {
+		let mut reloaded = raw_dmi.clone();
+		reloaded.source_map = RawDmi::load(&saved[..]).unwrap().source_map;
+		reloaded
+	});
+
+	assert_eq!(raw_dmi.remove_chunks_of_type(*b"prOj"), 1);
+	assert_eq!(raw_dmi.remove_chunks_of_type(*b"prOj"), 0);
+	assert!(raw_dmi
+		.insert_chunk_before_idat(chunk(b"IDAT", b""))
+		.is_err());
+	raw_dmi.chunk_plte = Some(chunk(b"PLTE", &[0, 0, 0]));
+	assert!(raw_dmi
+		.upsert_chunk(chunk(b"gAMA", &[0, 0, 177, 143]))
+		.is_err());
+	raw_dmi.chunk_plte = None;
+	assert!(raw_dmi
+		.upsert_chunk(chunk(b"gAMA", &[0, 0, 177, 143]))
+		.is_ok());
+}
+
+#[test]
+fn convert_between_raw_dmi_and_icon() {
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let raw_dmi = RawDmi::load(File::open(&load_path).unwrap()).unwrap();
+	let icon = Icon::try_from(raw_dmi).unwrap();
+	assert_eq!(icon, Icon::load(File::open(&load_path).unwrap()).unwrap());
+
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let converted = RawDmi::try_from(&icon).unwrap();
+	let mut converted_bytes = vec![];
+	converted.save(&mut converted_bytes).unwrap();
+	assert_eq!(converted_bytes, saved);
+
+	let mut edited = icon.clone();
+	edited.states.truncate(1);
+	let edited_back = Icon::try_from(RawDmi::try_from(&edited).unwrap()).unwrap();
+	assert_eq!(edited_back, edited);
+}
+
+#[test]
+fn parse_ihdr() {
+	use dmi::chunk::RawGenericChunk;
+	use dmi::ihdr::IhdrData;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let raw_dmi = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let ihdr = raw_dmi.ihdr().unwrap();
+	assert_eq!(ihdr.width, raw_dmi.image_width().unwrap());
+	assert_eq!(ihdr.height, raw_dmi.image_height().unwrap());
+	assert_eq!(ihdr.bit_depth, raw_dmi.bit_depth().unwrap());
+	assert_eq!(ihdr.color_type, raw_dmi.color_type().unwrap());
+	assert_eq!(ihdr.interlace_method, 0);
+	assert_eq!(IhdrData::try_from(&raw_dmi.chunk_ihdr).unwrap(), ihdr);
+
+	assert!(IhdrData::try_from(&raw_dmi.chunk_ihdr.data[..12]).is_err());
+	let not_ihdr = RawGenericChunk::new(*b"teSt", raw_dmi.chunk_ihdr.data.clone()).unwrap();
+	assert!(IhdrData::try_from(&not_ihdr).is_err());
+}
+
+#[test]
+fn ihdr_accessors_read_each_field() {
+	use dmi::icon::IconState;
+	use dmi::RawDmi;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let icon = Icon {
+		width: 3,
+		height: 2,
+		states: vec![IconState {
+			name: "wide".to_string(),
+			images: vec![DynamicImage::ImageRgba8(RgbaImage::from_fn(
+				3,
+				2,
+				|x, y| Rgba([x as u8 * 80, y as u8 * 80, 7, 100 + x as u8]),
+			))],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	assert_eq!(raw_dmi.image_width().unwrap(), 3);
+	assert_eq!(raw_dmi.image_height().unwrap(), 2);
+	// Translucent pixels of many colors are stored as 8 bits RGBA.
+	assert_eq!(raw_dmi.color_type().unwrap(), 6);
+	assert_eq!(raw_dmi.bit_depth().unwrap(), 8);
+
+	// Each accessor only needs the bytes of its own field.
+	raw_dmi.chunk_ihdr.data.truncate(9);
+	assert_eq!(raw_dmi.image_height().unwrap(), 2);
+	assert_eq!(raw_dmi.bit_depth().unwrap(), 8);
+	assert!(raw_dmi.color_type().is_err());
+	assert!(raw_dmi.ihdr().is_err());
+}
+
+#[test]
+fn load_meta_past_image_data() {
+	use dmi::metadata::DmiMetadata;
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let raw_dmi = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	// The metadata after the image data, as some optimizers write it.
+	let mut reordered = dmi::PNG_HEADER.to_vec();
+	raw_dmi.chunk_ihdr.save(&mut reordered).unwrap();
+	for chunk in &raw_dmi.chunks_idat {
+		chunk.save(&mut reordered).unwrap();
+	}
+	raw_dmi
+		.chunk_ztxt
+		.as_ref()
+		.unwrap()
+		.save(&mut reordered)
+		.unwrap();
+	raw_dmi.chunk_iend.save(&mut reordered).unwrap();
+
+	let meta = RawDmi::load_meta(&reordered[..]).unwrap();
+	assert!(meta.chunks_idat.is_empty());
+	assert_eq!(meta.chunk_ztxt, raw_dmi.chunk_ztxt);
+	assert_eq!(
+		meta.source_map.unwrap().len(),
+		raw_dmi.chunks_idat.len() + 3
+	);
+	let mut original = vec![];
+	raw_dmi.save(&mut original).unwrap();
+	assert_eq!(
+		DmiMetadata::load(&reordered[..]).unwrap(),
+		DmiMetadata::load(&original[..]).unwrap()
+	);
+
+	let mut without_idat = dmi::PNG_HEADER.to_vec();
+	raw_dmi.chunk_ihdr.save(&mut without_idat).unwrap();
+	raw_dmi
+		.chunk_ztxt
+		.as_ref()
+		.unwrap()
+		.save(&mut without_idat)
+		.unwrap();
+	raw_dmi.chunk_iend.save(&mut without_idat).unwrap();
+	assert!(RawDmi::load_meta(&without_idat[..]).is_err());
+}
+
+#[test]
+fn fingerprint_without_seek() {
+	use dmi::fingerprint::{quick_fingerprint, quick_fingerprint_reader};
+	use dmi::RawDmi;
+	use std::io::Read;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(&load_path).unwrap();
+	// Split in two, so that nothing can seek across it.
+	let (start, end) = bytes.split_at(100);
+	let stream = || start.chain(end);
+
+	assert_eq!(
+		quick_fingerprint_reader(stream()).unwrap(),
+		quick_fingerprint(&load_path).unwrap()
+	);
+	let meta = RawDmi::load_meta(stream()).unwrap();
+	assert_eq!(
+		meta.chunk_ztxt,
+		RawDmi::load(&bytes[..]).unwrap().chunk_ztxt
+	);
+}
+
+#[test]
+fn lenient_load_collects_warnings() {
+	use dmi::chunk::RawGenericChunk;
+	use dmi::icon::IconState;
+	use dmi::options::LoadOptions;
+	use dmi::quirks::Quirk;
+	use dmi::warning::DmiWarning;
+	use dmi::{ztxt, RawDmi};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let sprite =
+		|red: u8| DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 32, Rgba([red, 0, 0, 255])));
+	let icon = Icon {
+		width: 32,
+		height: 32,
+		states: vec![IconState {
+			name: "pair".to_string(),
+			frames: 2,
+			images: vec![sprite(1), sprite(2)],
+			delay: Some(vec![1.0, 1.0]),
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	let text = String::from_utf8(raw_dmi.chunk_ztxt.unwrap().data.decode().unwrap()).unwrap();
+	let broken_text = text.replace("\twidth = 32\n\theight = 32\n", "").replace(
+		"\tdirs = 1\n\tframes = 2\n\tdelay = 1,1\n",
+		"\tdirs = 2\n\tframes = 1\n",
+	);
+	raw_dmi.chunk_ztxt = Some(ztxt::create_ztxt_chunk(broken_text.as_bytes()).unwrap());
+	let mut comment = RawGenericChunk::new(*b"tEXt", b"Comment\0hello".to_vec()).unwrap();
+	comment.crc[0] ^= 0xFF;
+	raw_dmi.other_chunks = Some(vec![comment]);
+	let mut broken = vec![];
+	raw_dmi.save(&mut broken).unwrap();
+
+	assert!(Icon::load_with(&broken[..], LoadOptions::new()).is_err());
+
+	let mut warnings = vec![];
+	let loaded = Icon::load_with(&broken[..], LoadOptions::new().lenient(&mut warnings))
+		.expect("Failed to load leniently");
+	assert_eq!((loaded.width, loaded.height), (32, 32));
+	assert_eq!(loaded.states[0].dirs, 4);
+	assert_eq!(
+		warnings,
+		vec![
+			DmiWarning::CorruptChunk {
+				chunk_type: *b"tEXt"
+			},
+			DmiWarning::MissingSize {
+				key: "width".to_string(),
+				assumed: 32
+			},
+			DmiWarning::MissingSize {
+				key: "height".to_string(),
+				assumed: 32
+			},
+			DmiWarning::MetadataQuirk {
+				state: "pair".to_string(),
+				quirk: Quirk::DirsRounded { from: 2, to: 4 }
+			},
+		]
+	);
+}
+
+#[test]
+fn find_duplicates_groups_exact_and_near_states() {
+	use dmi::icon::IconState;
+	use dmi::library::{DmiLibrary, DuplicateGroup, StateLocation};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let root = std::env::temp_dir().join(format!("dmi_duplicates_{}", std::process::id()));
+	std::fs::create_dir_all(&root).unwrap();
+	let save = |file: &str, name: &str, color: [u8; 4], hidden: [u8; 4]| {
+		let mut image = RgbaImage::from_pixel(2, 1, Rgba(color));
+		image.put_pixel(1, 0, Rgba(hidden));
+		let icon = Icon {
+			width: 2,
+			height: 1,
+			states: vec![IconState {
+				name: name.to_string(),
+				images: vec![DynamicImage::ImageRgba8(image)],
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		icon
+			.save(&mut File::create(root.join(file)).unwrap())
+			.unwrap();
+	};
+	save("a.dmi", "crate", [200, 0, 0, 255], [0, 0, 0, 0]);
+	save("b.dmi", "crate_copy", [200, 0, 0, 255], [0, 0, 0, 0]);
+	save("c.dmi", "crate_tinted", [203, 0, 0, 255], [50, 50, 50, 0]);
+	// Mean colors on either side of a bucket boundary.
+	save("d.dmi", "gray", [7, 7, 7, 255], [0, 0, 0, 0]);
+	save("e.dmi", "gray_light", [8, 8, 8, 255], [0, 0, 0, 0]);
+	save("f.dmi", "unrelated", [100, 0, 200, 255], [0, 0, 0, 0]);
+	save("g.dmi", "removed", [200, 0, 0, 255], [0, 0, 0, 0]);
+
+	let library = DmiLibrary::load_dir(&root).unwrap();
+	std::fs::remove_file(root.join("g.dmi")).unwrap();
+	let duplicates = library.find_duplicates();
+	std::fs::remove_dir_all(&root).unwrap();
+
+	let locations = |files: &[usize]| -> Vec<StateLocation> {
+		files
+			.iter()
+			.map(|&file| StateLocation { file, state: 0 })
+			.collect()
+	};
+	assert_eq!(
+		duplicates.groups,
+		vec![
+			DuplicateGroup {
+				states: locations(&[0, 1]),
+				exact: true,
+			},
+			DuplicateGroup {
+				states: locations(&[0, 1, 2]),
+				exact: false,
+			},
+			DuplicateGroup {
+				states: locations(&[3, 4]),
+				exact: false,
+			},
+		]
+	);
+	assert_eq!(duplicates.failures.len(), 1);
+	assert_eq!(duplicates.failures[0].0, root.join("g.dmi"));
+}
+
+#[test]
+fn find_duplicates_compares_more_files_than_it_keeps_decoded() {
+	use dmi::icon::IconState;
+	use dmi::library::{DmiLibrary, DuplicateGroup, StateLocation, RELOADED_FILES};
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	let root = std::env::temp_dir().join(format!("dmi_many_duplicates_{}", std::process::id()));
+	std::fs::create_dir_all(&root).unwrap();
+	// Each shade is near the next one, so they all end up in a single group.
+	let file_count = RELOADED_FILES * 2;
+	for index in 0..file_count {
+		let red = 100 + index as u8 * 3;
+		let icon = Icon {
+			width: 1,
+			height: 1,
+			states: vec![IconState {
+				name: format!("shade_{index}"),
+				images: vec![DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+					1,
+					1,
+					Rgba([red, 0, 0, 255]),
+				))],
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let path = root.join(format!("{index:02}.dmi"));
+		icon.save(&mut File::create(path).unwrap()).unwrap();
+	}
+
+	let library = DmiLibrary::load_dir(&root).unwrap();
+	let duplicates = library.find_duplicates();
+	std::fs::remove_dir_all(&root).unwrap();
+
+	assert!(duplicates.failures.is_empty());
+	let mut groups = duplicates.groups;
+	assert_eq!(groups.len(), 1);
+	groups[0].states.sort_by_key(|location| location.file);
+	assert_eq!(
+		groups[0],
+		DuplicateGroup {
+			states: (0..file_count)
+				.map(|file| StateLocation { file, state: 0 })
+				.collect(),
+			exact: false,
+		}
+	);
+}
+
+#[test]
+fn metadata_ignores_extra_indentation() {
+	use dmi::metadata::DmiMetadata;
+	use std::collections::HashMap;
+
+	let text = "# BEGIN DMI\nversion = 4.0\n\twidth = 32\n\theight = 32\nstate = \"door\"\n\t\tdirs = 4\n\t  frames = 1\n\t\tcustom = yes\n# END DMI\n";
+	let metadata = DmiMetadata::parse(text).unwrap();
+	let state = &metadata.states[0];
+	assert_eq!((state.dirs, state.frames), (4, 1));
+	assert_eq!(
+		state.unknown_settings,
+		Some(HashMap::from([("custom".to_string(), "yes".to_string())]))
+	);
+	assert!(metadata
+		.dump()
+		.contains("state \"door\"\n\tdirs = 4\n\tframes = 1\n"));
+	assert!(metadata.dump().ends_with("\tcustom = yes\n"));
+}
+
+#[test]
+fn state_names_with_escapes_round_trip() {
+	use dmi::icon::IconState;
+	use dmi::metadata::{quote_state_name, unquote_state_name};
+	use image::DynamicImage;
+
+	let names = [
+		"say \"hi\"",
+		"back\\slash",
+		"trailing\\",
+		"\\\"",
+		"\"\"",
+		"tab\tand\rcarriage return",
+	];
+	for name in names {
+		assert_eq!(unquote_state_name(&quote_state_name(name)).unwrap(), name);
+	}
+
+	let state = |name: &str| IconState {
+		name: name.to_string(),
+		images: vec![DynamicImage::new_rgba8(1, 1)],
+		..Default::default()
+	};
+	let icon = Icon {
+		width: 1,
+		height: 1,
+		states: names.iter().map(|name| state(name)).collect(),
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let loaded = Icon::load(&saved[..]).unwrap();
+	let loaded_names: Vec<&str> = loaded
+		.states
+		.iter()
+		.map(|state| state.name.as_str())
+		.collect();
+	assert_eq!(loaded_names, names);
+
+	let broken = Icon {
+		states: vec![state("two\nlines")],
+		..icon
+	};
+	assert!(broken.save(&mut vec![]).is_err());
+	assert!(broken.states[0].validate(1, 1).is_err());
+}
+
+#[test]
+fn oversized_layout_fails_to_slice() {
+	use dmi::error::DmiError;
+	use dmi::icon::IconState;
+	use dmi::{ztxt, RawDmi};
+	use image::DynamicImage;
+
+	let icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![IconState {
+			name: "huge".to_string(),
+			images: vec![DynamicImage::new_rgba8(1, 1)],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+
+	// dirs * frames overflows a u32.
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	let text = String::from_utf8(raw_dmi.chunk_ztxt.unwrap().data.decode().unwrap()).unwrap();
+	let text = text.replace(
+		"\tdirs = 1\n\tframes = 1\n",
+		&format!("\tdirs = 8\n\tframes = {}\n", u32::MAX),
+	);
+	raw_dmi.chunk_ztxt = Some(ztxt::create_ztxt_chunk(text.as_bytes()).unwrap());
+	let mut broken = vec![];
+	raw_dmi.save(&mut broken).unwrap();
+
+	match Icon::load(&broken[..]) {
+		Err(DmiError::TileSlicing {
+			image_width,
+			image_height,
+			tile_width,
+			tile_height,
+			required,
+		}) => {
+			assert_eq!((image_width, image_height), (1, 1));
+			assert_eq!((tile_width, tile_height), (1, 1));
+			assert_eq!(required, 8 * u32::MAX as u64);
+		}
+		other => panic!("Expected a tile slicing error, got {other:?}"),
+	}
+}
+
+#[test]
+fn display_summarizes_icons() {
+	use dmi::icon::IconState;
+	use image::DynamicImage;
+
+	let icon = Icon {
+		width: 32,
+		height: 16,
+		states: vec![
+			IconState {
+				name: "idle".to_string(),
+				images: vec![DynamicImage::new_rgba8(32, 16)],
+				..Default::default()
+			},
+			IconState {
+				name: "walk".to_string(),
+				dirs: 4,
+				frames: 2,
+				movement: true,
+				..Default::default()
+			},
+		],
+		..Default::default()
+	};
+	assert_eq!(
+		icon.to_string(),
+		"DMI v4.0, 32x16, 2 states\n\t\"idle\": 1 dir, 1 frame\n\t\"walk\": 4 dirs, 2 frames, movement"
+	);
+	let single = Icon {
+		states: vec![icon.states[0].clone()],
+		..icon
+	};
+	assert!(single.to_string().starts_with("DMI v4.0, 32x16, 1 state\n"));
+}
+
+#[test]
+fn errors_are_classified() {
+	use dmi::error::DmiError;
+	use std::io::{self, ErrorKind};
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut corrupted = std::fs::read(load_path).unwrap();
+	// Flips a byte of the IHDR data, which breaks its CRC.
+	corrupted[20] ^= 0xff;
+	let error = Icon::load(&corrupted[..]).unwrap_err();
+	assert!(error.is_data_corruption());
+	assert!(error.is_recoverable());
+
+	let missing = DmiError::from(io::Error::from(ErrorKind::NotFound));
+	assert!(!missing.is_data_corruption());
+	assert!(missing.is_recoverable());
+
+	let disk_full = DmiError::from(io::Error::from(ErrorKind::StorageFull));
+	assert!(!disk_full.is_data_corruption());
+	assert!(!disk_full.is_recoverable());
+
+	let truncated = Icon::load(&[0x89, b'P', b'N'][..]).unwrap_err();
+	assert!(truncated.is_data_corruption());
+	assert!(truncated.is_recoverable());
+}
+
+#[test]
+fn misuse_is_not_data_corruption() {
+	use dmi::chunk::RawGenericChunk;
+	use dmi::error::DmiError;
+	use dmi::icon::IconState;
+	use dmi::{itxt, ztxt, RawDmi};
+	use image::DynamicImage;
+
+	let mut icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![IconState {
+			name: "two\nlines".to_string(),
+			images: vec![DynamicImage::new_rgba8(1, 1)],
+			..Default::default()
+		}],
+		..Default::default()
+	};
+	let mut errors = vec![icon.save(&mut vec![]).unwrap_err()];
+	icon.states[0].name = "one line".to_string();
+	icon.ancillary_chunks = vec![RawGenericChunk::new(*b"CRIt", vec![]).unwrap()];
+	errors.push(icon.save(&mut vec![]).unwrap_err());
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	let critical = RawGenericChunk::new(*b"CRIt", vec![]).unwrap();
+	errors.push(raw_dmi.upsert_chunk(critical).unwrap_err());
+	errors.push(ztxt::create_ztxt_chunk_with_keyword("", b"text").unwrap_err());
+	errors.push(itxt::create_itxt_chunk("", "text", false).unwrap_err());
+
+	for error in errors {
+		assert!(matches!(error, DmiError::InvalidArgument(_)), "{error:?}");
+		assert!(!error.is_data_corruption());
+		assert!(error.is_recoverable());
+	}
+}
+
+#[test]
+fn prune_chunks_follows_policy() {
+	use dmi::chunk::{ChunkPolicy, RawGenericChunk};
+	use dmi::RawDmi;
+
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let mut raw_dmi = RawDmi::load(File::open(load_path).unwrap()).unwrap();
+	raw_dmi.other_chunks = Some(
+		[*b"pHYs", *b"tIME", *b"prVt", *b"CRIt"]
+			.into_iter()
+			.map(|chunk_type| RawGenericChunk::new(chunk_type, vec![0; 4]).unwrap())
+			.collect(),
+	);
+	let chunk_types = |raw_dmi: &RawDmi| -> Vec<[u8; 4]> {
+		raw_dmi
+			.other_chunks
+			.iter()
+			.flatten()
+			.map(|chunk| chunk.chunk_type)
+			.collect()
+	};
+
+	let mut kept = raw_dmi.clone();
+	assert_eq!(kept.prune_chunks(&ChunkPolicy::KeepAll), 0);
+	assert_eq!(chunk_types(&kept).len(), 4);
+
+	// Critical chunks are kept whatever the policy.
+	let mut listed = raw_dmi.clone();
+	assert_eq!(
+		listed.prune_chunks(&ChunkPolicy::KeepListed(vec![*b"pHYs"])),
+		2
+	);
+	assert_eq!(chunk_types(&listed), [*b"pHYs", *b"CRIt"]);
+
+	let mut dropped = raw_dmi.clone();
+	assert_eq!(dropped.prune_chunks(&ChunkPolicy::DropAll), 3);
+	assert_eq!(chunk_types(&dropped), [*b"CRIt"]);
+
+	raw_dmi.other_chunks.as_mut().unwrap().pop();
+	assert_eq!(raw_dmi.prune_chunks(&ChunkPolicy::DropAll), 3);
+	assert_eq!(raw_dmi.other_chunks, None);
+	let mut saved = vec![];
+	raw_dmi.save(&mut saved).unwrap();
+	assert!(Icon::load(&saved[..]).is_ok());
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn webp_import_converts_frames() {
+	use dmi::icon::{IconState, Looping};
+	use image::codecs::webp::WebPEncoder;
+	use image::{ExtendedColorType, Rgba, RgbaImage};
+	use std::io::Cursor;
+	use std::num::NonZeroU32;
+
+	let chunk = |chunk_type: &[u8; 4], data: &[u8]| {
+		let mut chunk = chunk_type.to_vec();
+		chunk.extend((data.len() as u32).to_le_bytes());
+		chunk.extend(data);
+		if data.len() % 2 == 1 {
+			chunk.push(0);
+		}
+		chunk
+	};
+	let u24 = |value: u32| value.to_le_bytes()[..3].to_vec();
+	let riff = |chunks: Vec<u8>| {
+		let mut file = b"RIFF".to_vec();
+		file.extend((chunks.len() as u32 + 4).to_le_bytes());
+		file.extend(b"WEBP");
+		file.extend(chunks);
+		file
+	};
+	// A lossless still WebP of a 2x2 sprite filled with `red`.
+	let still = |red: u8| {
+		let sprite = RgbaImage::from_pixel(2, 2, Rgba([red, 0, 0, 255]));
+		let mut encoded = vec![];
+		WebPEncoder::new_lossless(&mut encoded)
+			.encode(sprite.as_raw(), 2, 2, ExtendedColorType::Rgba8)
+			.unwrap();
+		encoded
+	};
+
+	// The animation container wraps the VP8L chunk of each still frame.
+	let mut chunks = chunk(
+		b"VP8X",
+		&[[0x12, 0, 0, 0].as_slice(), &u24(1), &u24(1)].concat(),
+	);
+	chunks.extend(chunk(b"ANIM", &[0, 0, 0, 0, 3, 0]));
+	for (red, duration) in [(10, 200), (20, 50)] {
+		let mut frame = [u24(0), u24(0), u24(1), u24(1), u24(duration)].concat();
+		// Not blended with the previous frame.
+		frame.push(0x02);
+		frame.extend(&still(red)[12..]);
+		chunks.extend(chunk(b"ANMF", &frame));
+	}
+	let animated = riff(chunks);
+
+	let state = IconState::from_webp("blink", Cursor::new(animated)).unwrap();
+	assert_eq!((state.dirs, state.frames), (1, 2));
+	assert_eq!(state.delay, Some(vec![2.0, 0.5]));
+	assert_eq!(
+		state.loop_flag,
+		Looping::NTimes(NonZeroU32::new(3).unwrap())
+	);
+	let reds: Vec<u8> = state
+		.images
+		.iter()
+		.map(|image| image.to_rgba8()[(0, 0)][0])
+		.collect();
+	assert_eq!(reds, [10, 20]);
+
+	let state = IconState::from_webp("still", Cursor::new(still(30))).unwrap();
+	assert_eq!((state.frames, state.delay), (1, None));
+	assert_eq!(state.images[0].to_rgba8()[(1, 1)], Rgba([30, 0, 0, 255]));
+}
+
+#[test]
+fn load_with_warnings_reports_oddities() {
+	use dmi::chunk::RawGenericChunk;
+	use dmi::icon::IconState;
+	use dmi::metadata::DmiMetadata;
+	use dmi::warning::DmiWarning;
+	use dmi::{ztxt, RawDmi};
+	use image::DynamicImage;
+
+	let icon = Icon {
+		width: 1,
+		height: 1,
+		states: vec![
+			IconState {
+				name: "pulse".to_string(),
+				images: vec![DynamicImage::new_rgba8(1, 1)],
+				..Default::default()
+			},
+			IconState {
+				name: "pair".to_string(),
+				frames: 2,
+				images: vec![DynamicImage::new_rgba8(1, 1); 2],
+				delay: Some(vec![1.0; 2]),
+				..Default::default()
+			},
+		],
+		..Default::default()
+	};
+	let mut saved = vec![];
+	icon.save(&mut saved).unwrap();
+	let mut raw_dmi = RawDmi::load(&saved[..]).unwrap();
+	let text = String::from_utf8(raw_dmi.chunk_ztxt.unwrap().data.decode().unwrap()).unwrap();
+	let text = text
+		.replace(
+			"state = \"pulse\"\n\tdirs = 1\n\tframes = 1\n",
+			"state = \"pulse\"\n\tdirs = 1\n\tframes = 1\n\tdelay = 2\n\tglow = 1\n",
+		)
+		.replace("\tdirs = 1\n\tframes = 2\n", "\tdirs = 2\n\tframes = 1\n");
+	raw_dmi.chunk_ztxt = Some(ztxt::create_ztxt_chunk(text.as_bytes()).unwrap());
+	raw_dmi.other_chunks = Some(vec![RawGenericChunk::new(*b"tIME", vec![0; 7]).unwrap()]);
+	let mut odd = vec![];
+	raw_dmi.save(&mut odd).unwrap();
+
+	let expected = vec![
+		DmiWarning::StillStateAnimationSettings {
+			state: "pulse".to_string(),
+		},
+		DmiWarning::UnknownSetting {
+			state: "pulse".to_string(),
+			key: "glow".to_string(),
+		},
+		DmiWarning::UnusualDirs {
+			state: "pair".to_string(),
+			dirs: 2,
+		},
+		DmiWarning::StillStateAnimationSettings {
+			state: "pair".to_string(),
+		},
+		DmiWarning::IgnoredChunk {
+			chunk_type: *b"tIME",
+		},
+	];
+	let (loaded, warnings) = Icon::load_with_warnings(&odd[..]).unwrap();
+	assert_eq!(warnings, expected);
+	assert_eq!(loaded.states.len(), 2);
+	let (_, warnings) = DmiMetadata::load_with_warnings(&odd[..]).unwrap();
+	assert_eq!(warnings, expected);
+	assert_eq!(
+		expected[2].to_string(),
+		"icon_state \"pair\" has 2 dirs, expected 1, 4 or 8"
+	);
+
+	let (_, warnings) = Icon::load_with_warnings(&saved[..]).unwrap();
+	assert!(warnings.is_empty());
+}
+
+#[test]
+fn new_chunks_carry_length_and_crc() {
+	use dmi::chunk::RawGenericChunk;
+	use dmi::error::DmiError;
+
+	let chunk = RawGenericChunk::new(*b"teSt", b"hello".to_vec()).unwrap();
+	assert_eq!(chunk.data_length, 5u32.to_be_bytes());
+	// The chunk survives a save and reload, which checks its CRC.
+	let mut saved = vec![];
+	chunk.save(&mut saved).unwrap();
+	assert_eq!(saved.len(), 12 + 5);
+	assert_eq!(RawGenericChunk::load(&mut &saved[..]).unwrap(), chunk);
+
+	let replaced = chunk.with_data(b"longer data".to_vec()).unwrap();
+	assert_eq!(replaced.chunk_type, *b"teSt");
+	assert_eq!(replaced.data_length, 11u32.to_be_bytes());
+	assert_ne!(replaced.crc, chunk.crc);
+	let mut saved = vec![];
+	replaced.save(&mut saved).unwrap();
+	assert_eq!(RawGenericChunk::load(&mut &saved[..]).unwrap(), replaced);
+
+	let empty = RawGenericChunk::new(*b"IEND", vec![]).unwrap();
+	assert_eq!(empty.data_length, [0; 4]);
+	assert_eq!(empty.crc, [0xAE, 0x42, 0x60, 0x82]);
+
+	for chunk_type in [*b"te5t", *b"te t", [b't', b'e', 0xC3, b't']] {
+		assert!(matches!(
+			RawGenericChunk::new(chunk_type, vec![]),
+			Err(DmiError::InvalidChunkType { chunk_type: rejected }) if rejected == chunk_type
+		));
+	}
+}
+
+#[test]
+fn chunk_types_convert_from_bytes() {
+	use dmi::chunk::ChunkType;
+
+	let known = [
+		(*b"IHDR", ChunkType::Ihdr),
+		(*b"PLTE", ChunkType::Plte),
+		(*b"IDAT", ChunkType::Idat),
+		(*b"IEND", ChunkType::Iend),
+		(*b"zTXt", ChunkType::Ztxt),
+		(*b"tEXt", ChunkType::Text),
+		(*b"iTXt", ChunkType::Itxt),
+	];
+	for (bytes, chunk_type) in known {
+		assert_eq!(ChunkType::from(bytes), chunk_type);
+		assert_eq!(<[u8; 4]>::from(chunk_type), bytes);
+		assert_eq!(chunk_type.to_string(), String::from_utf8_lossy(&bytes));
+	}
+
+	// Unknown types keep their bytes, and known ones are never wrapped in Other.
+	assert_eq!(ChunkType::from(*b"pHYs"), ChunkType::Other(*b"pHYs"));
+	assert_eq!(ChunkType::Other(*b"pHYs").bytes(), *b"pHYs");
+	assert_ne!(ChunkType::from(*b"IHDR"), ChunkType::Other(*b"IHDR"));
+	// Type names are case sensitive.
+	assert_eq!(ChunkType::from(*b"ztxt"), ChunkType::Other(*b"ztxt"));
+}
+
+#[test]
+fn chunk_type_properties_follow_letter_case() {
+	use dmi::chunk::ChunkType;
+
+	let ihdr = ChunkType::Ihdr;
+	assert!(ihdr.is_critical() && !ihdr.is_ancillary());
+	assert!(ihdr.is_public());
+	assert!(!ihdr.is_safe_to_copy());
+	assert!(!ihdr.is_kept_by_icon());
+
+	let text = ChunkType::Text;
+	assert!(text.is_ancillary() && !text.is_critical());
+	assert!(text.is_public() && text.is_safe_to_copy());
+	assert!(text.is_kept_by_icon());
+
+	// sBIT depends on the image data, so it is not safe to copy.
+	let sbit = ChunkType::from(*b"sBIT");
+	assert!(sbit.is_ancillary() && sbit.is_public());
+	assert!(!sbit.is_safe_to_copy());
+	assert!(!sbit.is_kept_by_icon());
+
+	let private = ChunkType::from(*b"prIV");
+	assert!(private.is_ancillary() && !private.is_public());
+	assert!(!private.is_safe_to_copy());
+	let private_copyable = ChunkType::from(*b"prIv");
+	assert!(private_copyable.is_safe_to_copy());
+	assert!(private_copyable.is_kept_by_icon());
+
+	// zTXt and application data are parsed into the icon instead of kept as chunks.
+	assert!(ChunkType::Ztxt.is_ancillary() && ChunkType::Ztxt.is_safe_to_copy());
+	assert!(!ChunkType::Ztxt.is_kept_by_icon());
+	let app_data = ChunkType::from(dmi::appdata::APP_DATA_TYPE);
+	assert!(app_data.is_ancillary() && app_data.is_safe_to_copy());
+	assert!(!app_data.is_kept_by_icon());
+}
@@ -0,0 +1,27 @@
+//! Runs without the `images` feature as well, covering what the crate offers without decoding
+//! sprites.
+
+use dmi::metadata::DmiMetadata;
+use dmi::RawDmi;
+use std::fs::File;
+use std::path::PathBuf;
+
+#[test]
+fn raw_and_metadata_load_without_images() {
+	let mut load_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	load_path.push("tests/resources/load_test.dmi");
+	let bytes = std::fs::read(&load_path).unwrap();
+
+	let raw_dmi = RawDmi::load(&bytes[..]).expect("Unable to load lights dmi");
+	let mut saved = vec![];
+	raw_dmi.save(&mut saved).unwrap();
+	assert_eq!(RawDmi::load(&saved[..]).unwrap(), raw_dmi);
+
+	let metadata = DmiMetadata::load(File::open(&load_path).unwrap()).unwrap();
+	assert_eq!((metadata.width, metadata.height), (160, 160));
+	assert!(!metadata.states.is_empty());
+	assert!(metadata
+		.states
+		.iter()
+		.all(|state| matches!(state.dirs, 1 | 4 | 8) && state.frames > 0));
+}
@@ -0,0 +1,14 @@
+use dmi::icon::Icon;
+use proptest::prelude::*;
+
+proptest! {
+	#![proptest_config(ProptestConfig::with_cases(32))]
+
+	#[test]
+	fn save_then_load_is_lossless(icon in any::<Icon>()) {
+		let mut saved = vec![];
+		icon.save(&mut saved).expect("Failed to save generated icon");
+		let loaded = Icon::load(&saved[..]).expect("Failed to load generated icon");
+		prop_assert_eq!(loaded, icon);
+	}
+}